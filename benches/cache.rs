@@ -0,0 +1,117 @@
+//! Benchmarks of the hot paths, for comparing redesigns before and after.
+//!
+//! Run with `cargo bench`; pass a filter such as `cargo bench concurrent` to
+//! run one group.
+
+use std::hint::black_box;
+use std::sync::{Arc, Barrier};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use ttr_cache::{EvictionPolicy, RefreshPolicy, TTRCache, TTRCacheBuilder};
+
+type Fetch = fn(&u64) -> Option<u64>;
+
+const KEYS: u64 = 1024;
+
+fn double(key: &u64) -> Option<u64> {
+    Some(key * 2)
+}
+
+fn warmed(ttl: impl Into<RefreshPolicy>, shards: usize) -> TTRCache<u64, u64, Fetch> {
+    let cache = TTRCacheBuilder::new(ttl)
+        .shards(shards)
+        .build(double as Fetch);
+    cache.warm(0..KEYS);
+    cache
+}
+
+fn fresh_hit(c: &mut Criterion) {
+    let cache = warmed(Duration::from_secs(3600), 1);
+    c.bench_function("get/fresh_hit", |b| {
+        let mut key = 0;
+        b.iter(|| {
+            key = (key + 1) % KEYS;
+            black_box(cache.get(&key))
+        });
+    });
+}
+
+fn stale_refresh(c: &mut Criterion) {
+    // Every lookup finds the entry stale and refreshes it on the caller.
+    let cache = warmed(RefreshPolicy::Always, 1);
+    c.bench_function("get/stale_refresh", |b| {
+        let mut key = 0;
+        b.iter(|| {
+            key = (key + 1) % KEYS;
+            black_box(cache.get(&key))
+        });
+    });
+}
+
+fn concurrent(c: &mut Criterion) {
+    const LOOKUPS: u64 = 10_000;
+
+    let mut group = c.benchmark_group("concurrent");
+    for shards in [1, 16] {
+        for threads in [1, 4, 16] {
+            let cache = Arc::new(warmed(Duration::from_secs(3600), shards));
+            group.throughput(Throughput::Elements(LOOKUPS * threads));
+            group.bench_with_input(
+                BenchmarkId::new(format!("{shards}_shards"), threads),
+                &threads,
+                |b, &threads| {
+                    b.iter_custom(|iters| {
+                        let start = Arc::new(Barrier::new(threads as usize + 1));
+                        let handles: Vec<_> = (0..threads)
+                            .map(|thread| {
+                                let cache = Arc::clone(&cache);
+                                let start = Arc::clone(&start);
+                                thread::spawn(move || {
+                                    start.wait();
+                                    for i in 0..iters * LOOKUPS {
+                                        black_box(cache.get(&((i + thread * 97) % KEYS)));
+                                    }
+                                })
+                            })
+                            .collect();
+                        start.wait();
+                        let started = Instant::now();
+                        for handle in handles {
+                            handle.join().unwrap();
+                        }
+                        started.elapsed()
+                    });
+                },
+            );
+        }
+    }
+    group.finish();
+}
+
+fn eviction(c: &mut Criterion) {
+    let mut group = c.benchmark_group("eviction");
+    for (name, policy) in [
+        ("lru", EvictionPolicy::Lru),
+        ("tiny_lfu", EvictionPolicy::TinyLfu),
+    ] {
+        // Cycling over eight times the capacity misses and evicts on most
+        // lookups.
+        let cache: TTRCache<u64, u64, Fetch> = TTRCacheBuilder::new(Duration::from_secs(3600))
+            .max_capacity(KEYS as usize)
+            .eviction_policy(policy)
+            .build(double as Fetch);
+        group.bench_function(name, |b| {
+            let mut key = 0;
+            b.iter(|| {
+                key = (key + 1) % (KEYS * 8);
+                black_box(cache.get(&key))
+            });
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, fresh_hit, stale_refresh, concurrent, eviction);
+criterion_main!(benches);
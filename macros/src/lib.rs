@@ -0,0 +1,353 @@
+//! The `#[ttr_cached]` attribute, re-exported by `ttr-cache` with its
+//! `macros` feature.
+
+use proc_macro::TokenStream;
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use quote::quote;
+use syn::punctuated::Punctuated;
+use syn::{
+    parse_macro_input, Expr, ExprLit, FnArg, GenericArgument, ItemFn, Lit, MetaNameValue, Pat,
+    PathArguments, ReturnType, Token, Type,
+};
+
+/// Caches a function's results in a `TTRCache`, keyed by its arguments.
+///
+/// The first call with some arguments runs the function; later calls with
+/// the same arguments return the cached result until it is `ttl` old. After
+/// that, the next call runs the function again while concurrent calls with
+/// the same arguments get the stale result. With `max_stale`, a result
+/// more than that past its TTR is no longer served, and calls wait for the
+/// new one.
+///
+/// The function returns `Option<V>` or `V`. With `Option<V>`, only `Some`
+/// results are cached, so a `None` is recomputed on the next call, and a
+/// refresh that returns `None` keeps the stale value.
+///
+/// Durations are a whole number followed by `ms`, `s`, `m`, `h` or `d`.
+/// Arguments are taken by value and together form the key, so they must be
+/// `Clone + Eq + Hash`, and `Send + Sync` like the result; generic
+/// functions, methods and `async` functions are not supported. There is one
+/// cache per function, created on the first call and shared by all threads.
+/// A panic in the function reaches the call that ran it, unless a stale
+/// result can be served instead.
+///
+/// ```rust
+/// use std::sync::atomic::{AtomicU32, Ordering};
+/// use ttr_cache::ttr_cached;
+///
+/// static QUERIES: AtomicU32 = AtomicU32::new(0);
+///
+/// #[ttr_cached(ttl = "300s", max_stale = "1h")]
+/// fn user_name(id: u32, tenant: String) -> Option<String> {
+///     QUERIES.fetch_add(1, Ordering::Relaxed);
+///     (id != 0).then(|| format!("{tenant}/user {id}"))
+/// }
+///
+/// assert_eq!(user_name(7, "acme".into()).as_deref(), Some("acme/user 7"));
+/// assert_eq!(user_name(7, "acme".into()).as_deref(), Some("acme/user 7"));
+/// assert_eq!(QUERIES.load(Ordering::Relaxed), 1);
+/// assert_eq!(user_name(0, "acme".into()), None);
+/// ```
+///
+/// ```rust
+/// use ttr_cache::ttr_cached;
+///
+/// #[ttr_cached(ttl = "60s")]
+/// fn port(name: String) -> u16 {
+///     match name.as_str() {
+///         "https" => 443,
+///         _ => panic!("unknown service"),
+///     }
+/// }
+///
+/// assert_eq!(port("https".into()), 443);
+/// let panic = std::panic::catch_unwind(|| port("gopher".into())).unwrap_err();
+/// assert_eq!(panic.downcast_ref::<&str>(), Some(&"unknown service"));
+/// ```
+///
+/// Arguments that are borrowed or `impl Trait` are rejected:
+///
+/// ```compile_fail
+/// # use ttr_cache::ttr_cached;
+/// #[ttr_cached(ttl = "60s")]
+/// fn length(text: &str) -> usize {
+///     text.len()
+/// }
+/// ```
+///
+/// ```compile_fail
+/// # use ttr_cache::ttr_cached;
+/// #[ttr_cached(ttl = "60s")]
+/// fn length(text: impl AsRef<str>) -> usize {
+///     text.as_ref().len()
+/// }
+/// ```
+///
+/// As are `async` functions and a missing `ttl`:
+///
+/// ```compile_fail
+/// # use ttr_cache::ttr_cached;
+/// #[ttr_cached(ttl = "60s")]
+/// async fn length(text: String) -> usize {
+///     text.len()
+/// }
+/// ```
+///
+/// ```compile_fail
+/// # use ttr_cache::ttr_cached;
+/// #[ttr_cached(max_stale = "60s")]
+/// fn length(text: String) -> usize {
+///     text.len()
+/// }
+/// ```
+#[proc_macro_attribute]
+pub fn ttr_cached(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let options =
+        parse_macro_input!(attr with Punctuated::<MetaNameValue, Token![,]>::parse_terminated);
+    let function = parse_macro_input!(item as ItemFn);
+    expand(options, function)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+fn expand(
+    options: Punctuated<MetaNameValue, Token![,]>,
+    mut function: ItemFn,
+) -> syn::Result<TokenStream2> {
+    let mut ttl = None;
+    let mut max_stale = None;
+    for option in &options {
+        if option.path.is_ident("ttl") {
+            ttl = Some(duration(&option.value)?);
+        } else if option.path.is_ident("max_stale") {
+            max_stale = Some(duration(&option.value)?);
+        } else {
+            return Err(syn::Error::new_spanned(
+                &option.path,
+                "expected `ttl` or `max_stale`",
+            ));
+        }
+    }
+    let Some((ttl_secs, ttl_nanos)) = ttl else {
+        return Err(syn::Error::new(
+            Span::call_site(),
+            "missing `ttl`, such as `ttl = \"300s\"`",
+        ));
+    };
+    if ttl_secs == 0 && ttl_nanos == 0 {
+        return Err(syn::Error::new_spanned(
+            &options,
+            "`ttl` must be more than zero",
+        ));
+    }
+
+    let sig = &function.sig;
+    if let Some(asyncness) = &sig.asyncness {
+        return Err(syn::Error::new_spanned(
+            asyncness,
+            "`ttr_cached` does not support async functions",
+        ));
+    }
+    if !sig.generics.params.is_empty() {
+        return Err(syn::Error::new_spanned(
+            &sig.generics,
+            "`ttr_cached` does not support generic functions",
+        ));
+    }
+    let (value, optional) = match &sig.output {
+        ReturnType::Default => {
+            return Err(syn::Error::new_spanned(
+                sig,
+                "`ttr_cached` needs a function that returns a value",
+            ))
+        }
+        ReturnType::Type(_, ty) => match option_inner(ty) {
+            Some(inner) => (inner.clone(), true),
+            None => ((**ty).clone(), false),
+        },
+    };
+
+    let mut names = Vec::new();
+    let mut types = Vec::new();
+    for input in &sig.inputs {
+        let FnArg::Typed(arg) = input else {
+            return Err(syn::Error::new_spanned(
+                input,
+                "`ttr_cached` does not support methods",
+            ));
+        };
+        let Pat::Ident(pat) = &*arg.pat else {
+            return Err(syn::Error::new_spanned(
+                &arg.pat,
+                "`ttr_cached` needs plain argument names",
+            ));
+        };
+        if pat.by_ref.is_some() || pat.subpat.is_some() {
+            return Err(syn::Error::new_spanned(
+                &arg.pat,
+                "`ttr_cached` needs plain argument names",
+            ));
+        }
+        match &*arg.ty {
+            Type::Reference(_) => {
+                return Err(syn::Error::new_spanned(
+                    &arg.ty,
+                    "`ttr_cached` arguments form the key, so they must be owned",
+                ))
+            }
+            Type::ImplTrait(_) => return Err(syn::Error::new_spanned(
+                &arg.ty,
+                "`ttr_cached` arguments form the key, so they need a named type, not `impl Trait`",
+            )),
+            _ => {}
+        }
+        names.push(pat.ident.clone());
+        types.push((*arg.ty).clone());
+    }
+
+    // The original function, run on misses and refreshes.
+    let uncached_inputs = &sig.inputs;
+    let output = &sig.output;
+    let block = &function.block;
+    let uncached = quote! {
+        fn __ttr_cached_uncached(#uncached_inputs) #output #block
+    };
+
+    // The wrapper takes the same arguments, without `mut`.
+    for input in &mut function.sig.inputs {
+        if let FnArg::Typed(arg) = input {
+            if let Pat::Ident(pat) = &mut *arg.pat {
+                pat.mutability = None;
+            }
+        }
+    }
+    let fetched = if optional {
+        quote! { fetched }
+    } else {
+        quote! { ::core::option::Option::Some(fetched) }
+    };
+    // The cache treats a panicking fetch as a failed one, so the panic is
+    // kept for the call that ran it to raise again.
+    let ident = &function.sig.ident;
+    let served = if optional {
+        quote! { served }
+    } else {
+        quote! {
+            served.unwrap_or_else(|| {
+                ::core::panic!(
+                    "`{}` panicked computing this value on another thread",
+                    ::core::stringify!(#ident),
+                )
+            })
+        }
+    };
+    let max_stale = max_stale.map(|(secs, nanos)| {
+        quote! { .max_stale(::core::time::Duration::new(#secs, #nanos)) }
+    });
+
+    let attrs = &function.attrs;
+    let vis = &function.vis;
+    let sig = &function.sig;
+    Ok(quote! {
+        #(#attrs)*
+        #vis #sig {
+            #uncached
+
+            type __TtrCachedKey = (#(#types,)*);
+            type __TtrCachedFetch = fn(&__TtrCachedKey) -> ::core::option::Option<#value>;
+
+            ::std::thread_local! {
+                static __TTR_CACHED_PANIC: ::core::cell::Cell<
+                    ::core::option::Option<::std::boxed::Box<dyn ::core::any::Any + ::core::marker::Send>>,
+                > = const { ::core::cell::Cell::new(::core::option::Option::None) };
+            }
+
+            static __TTR_CACHED: ::std::sync::LazyLock<
+                ::ttr_cache::TTRCache<__TtrCachedKey, #value, __TtrCachedFetch>,
+            > = ::std::sync::LazyLock::new(|| {
+                let fetch: __TtrCachedFetch = |key| {
+                    let (#(#names,)*) = ::core::clone::Clone::clone(key);
+                    match ::std::panic::catch_unwind(::std::panic::AssertUnwindSafe(move || {
+                        __ttr_cached_uncached(#(#names),*)
+                    })) {
+                        ::core::result::Result::Ok(fetched) => #fetched,
+                        ::core::result::Result::Err(panic) => {
+                            __TTR_CACHED_PANIC.with(|slot| slot.set(::core::option::Option::Some(panic)));
+                            ::core::option::Option::None
+                        }
+                    }
+                };
+                ::ttr_cache::TTRCacheBuilder::new(::core::time::Duration::new(#ttl_secs, #ttl_nanos))
+                    #max_stale
+                    .build(fetch)
+            });
+
+            let served = __TTR_CACHED.get(&(#(#names,)*));
+            if let ::core::option::Option::Some(panic) = __TTR_CACHED_PANIC.with(::core::cell::Cell::take) {
+                if served.is_none() {
+                    ::std::panic::resume_unwind(panic);
+                }
+            }
+            #served
+        }
+    })
+}
+
+/// Parses a duration such as `"300s"` into seconds and nanoseconds.
+fn duration(value: &Expr) -> syn::Result<(u64, u32)> {
+    let Expr::Lit(ExprLit {
+        lit: Lit::Str(literal),
+        ..
+    }) = value
+    else {
+        return Err(syn::Error::new_spanned(
+            value,
+            "expected a duration such as \"300s\"",
+        ));
+    };
+    let text = literal.value();
+    let split = text
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(text.len());
+    let (amount, unit) = text.split_at(split);
+    let nanos_per_unit: u128 = match unit {
+        "ms" => 1_000_000,
+        "s" => 1_000_000_000,
+        "m" => 60 * 1_000_000_000,
+        "h" => 60 * 60 * 1_000_000_000,
+        "d" => 24 * 60 * 60 * 1_000_000_000,
+        _ => {
+            return Err(syn::Error::new_spanned(
+                literal,
+                "expected a whole number followed by `ms`, `s`, `m`, `h` or `d`",
+            ))
+        }
+    };
+    amount
+        .parse::<u128>()
+        .ok()
+        .and_then(|amount| amount.checked_mul(nanos_per_unit))
+        .and_then(|nanos| {
+            let secs = u64::try_from(nanos / 1_000_000_000).ok()?;
+            Some((secs, (nanos % 1_000_000_000) as u32))
+        })
+        .ok_or_else(|| syn::Error::new_spanned(literal, "invalid duration"))
+}
+
+/// The `T` of an `Option<T>` return type.
+fn option_inner(ty: &Type) -> Option<&Type> {
+    let Type::Path(path) = ty else {
+        return None;
+    };
+    let last = path.path.segments.last()?;
+    if last.ident != "Option" {
+        return None;
+    }
+    let PathArguments::AngleBracketed(arguments) = &last.arguments else {
+        return None;
+    };
+    match arguments.args.first()? {
+        GenericArgument::Type(inner) if arguments.args.len() == 1 => Some(inner),
+        _ => None,
+    }
+}
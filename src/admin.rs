@@ -0,0 +1,188 @@
+//! JSON admin endpoints over a cache, for any HTTP framework.
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hash};
+
+use http::header::{HeaderValue, CONTENT_TYPE};
+use http::{Method, Request, Response, StatusCode};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::shared::SharedTTRCache;
+
+/// Hot keys listed when the request does not say how many.
+const DEFAULT_HOT_KEYS: usize = 10;
+
+/// Serves a cache's stats, hot keys and entries as JSON, and invalidates
+/// keys on request.
+///
+/// [`handle`](Self::handle) takes and returns `http` types, so the response
+/// can be returned from an axum handler as it is and converted in a line for
+/// actix or hyper. Routes are matched on the last path segment, so the admin
+/// can be mounted under any prefix:
+///
+/// - `GET stats`: the [`CacheStats`](crate::CacheStats).
+/// - `GET hot-keys?limit=N`: the [top keys](crate::TTRCache::top_keys), ten
+///   by default.
+/// - `GET entries`: every entry, as the [dump](crate::TTRCache::dump) does.
+/// - `GET entry?key=K`: one entry's [metadata](crate::EntryInfo), with the
+///   key as URL-encoded JSON.
+/// - `POST invalidate`: marks the key in the body, as JSON, stale.
+///
+/// With the `http-admin` feature.
+///
+/// ```rust
+/// use std::time::Duration;
+/// use http::{Request, StatusCode};
+/// use ttr_cache::{CacheAdmin, SharedTTRCache, TTRCache};
+///
+/// let cache = SharedTTRCache::new(TTRCache::new(Duration::from_secs(60), |id: &u32| {
+///     Some(format!("user {id}"))
+/// }));
+/// cache.get(&7);
+/// let admin = CacheAdmin::new(cache.clone());
+///
+/// let stats = admin.handle(&Request::get("/admin/cache/stats").body("").unwrap());
+/// assert_eq!(stats.status(), StatusCode::OK);
+/// assert!(stats.body().contains("\"entry_count\":1"));
+///
+/// let invalidate = Request::post("/admin/cache/invalidate").body("7").unwrap();
+/// assert_eq!(admin.handle(&invalidate).body(), "{\"invalidated\":true}");
+/// assert!(cache.entry(&7).unwrap().is_stale());
+///
+/// let entry = admin.handle(&Request::get("/admin/cache/entry?key=8").body("").unwrap());
+/// assert_eq!(entry.status(), StatusCode::NOT_FOUND);
+/// ```
+pub struct CacheAdmin<K, V, F, S = RandomState>
+where
+    K: Eq + Hash,
+{
+    cache: SharedTTRCache<K, V, F, S>,
+}
+
+impl<K, V, F, S> CacheAdmin<K, V, F, S>
+where
+    K: Eq + Hash + Clone + Serialize + DeserializeOwned,
+    V: Clone + Serialize,
+    S: BuildHasher,
+{
+    /// Serves `cache`.
+    pub fn new(cache: SharedTTRCache<K, V, F, S>) -> Self {
+        CacheAdmin { cache }
+    }
+
+    /// Answers an admin request, with a JSON body.
+    ///
+    /// Unknown routes get a 404, known ones with the wrong method a 405, and
+    /// keys that do not parse a 400, each with an `error` message.
+    pub fn handle<B: AsRef<[u8]>>(&self, request: &Request<B>) -> Response<String> {
+        let route = request.uri().path().trim_end_matches('/');
+        let route = route.rsplit('/').next().unwrap_or_default();
+        let query = request.uri().query().unwrap_or_default();
+        let method = request.method();
+        match (route, method) {
+            ("stats", &Method::GET) => json(StatusCode::OK, &self.cache.stats()),
+            ("hot-keys", &Method::GET) => {
+                let limit = match param(query, "limit").map(|limit| limit.parse()) {
+                    None => DEFAULT_HOT_KEYS,
+                    Some(Ok(limit)) => limit,
+                    Some(Err(_)) => return error(StatusCode::BAD_REQUEST, "invalid limit"),
+                };
+                json(StatusCode::OK, &self.cache.top_keys(limit))
+            }
+            ("entries", &Method::GET) => json(StatusCode::OK, &self.cache.dump()),
+            ("entry", &Method::GET) => {
+                let Some(key) = param(query, "key") else {
+                    return error(StatusCode::BAD_REQUEST, "missing key");
+                };
+                let Ok(key) = serde_json::from_str::<K>(&key) else {
+                    return error(StatusCode::BAD_REQUEST, "invalid key");
+                };
+                match self.cache.entry(&key) {
+                    Some(info) => json(StatusCode::OK, &info),
+                    None => error(StatusCode::NOT_FOUND, "key not in cache"),
+                }
+            }
+            ("invalidate", &Method::POST) => {
+                let Ok(key) = serde_json::from_slice::<K>(request.body().as_ref()) else {
+                    return error(StatusCode::BAD_REQUEST, "invalid key");
+                };
+                let invalidated = self.cache.invalidate(&key);
+                json(
+                    StatusCode::OK,
+                    &serde_json::json!({ "invalidated": invalidated }),
+                )
+            }
+            ("stats" | "hot-keys" | "entries" | "entry" | "invalidate", _) => {
+                error(StatusCode::METHOD_NOT_ALLOWED, "method not allowed")
+            }
+            _ => error(StatusCode::NOT_FOUND, "no such admin route"),
+        }
+    }
+}
+
+impl<K, V, F, S> Clone for CacheAdmin<K, V, F, S>
+where
+    K: Eq + Hash,
+{
+    fn clone(&self) -> Self {
+        CacheAdmin {
+            cache: self.cache.clone(),
+        }
+    }
+}
+
+fn json(status: StatusCode, body: &impl Serialize) -> Response<String> {
+    match serde_json::to_string(body) {
+        Ok(body) => response(status, body),
+        Err(_) => error(StatusCode::INTERNAL_SERVER_ERROR, "serialization failed"),
+    }
+}
+
+fn error(status: StatusCode, message: &str) -> Response<String> {
+    response(status, serde_json::json!({ "error": message }).to_string())
+}
+
+fn response(status: StatusCode, body: String) -> Response<String> {
+    let mut response = Response::new(body);
+    *response.status_mut() = status;
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    response
+}
+
+/// The percent-decoded value of `name` in a query string.
+fn param(query: &str, name: &str) -> Option<String> {
+    query.split('&').find_map(|pair| {
+        let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+        (key == name).then(|| decode(value))
+    })
+}
+
+fn decode(encoded: &str) -> String {
+    let bytes = encoded.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (b'+', _) => {
+                decoded.push(b' ');
+                i += 1;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
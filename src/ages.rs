@@ -0,0 +1,62 @@
+//! Ages of the values served, for staleness objectives.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, PoisonError};
+use std::time::Duration;
+
+use hdrhistogram::Histogram;
+
+/// Significant figures kept for each recorded age.
+const PRECISION: u8 = 3;
+
+/// A histogram of served-value ages, in milliseconds, and a count of those
+/// older than a threshold.
+pub(crate) struct ServedAges {
+    threshold: Duration,
+    histogram: Mutex<Histogram<u64>>,
+    over_threshold: AtomicU64,
+}
+
+impl ServedAges {
+    pub(crate) fn new(threshold: Duration) -> Self {
+        ServedAges {
+            threshold,
+            histogram: Mutex::new(Histogram::new(PRECISION).expect("valid precision")),
+            over_threshold: AtomicU64::new(0),
+        }
+    }
+
+    pub(crate) fn record(&self, age: Duration) {
+        if age > self.threshold {
+            self.over_threshold.fetch_add(1, Ordering::Relaxed);
+        }
+        let millis = u64::try_from(age.as_millis()).unwrap_or(u64::MAX);
+        let mut histogram = self
+            .histogram
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        // Grows to fit; only ages too large to track are capped.
+        if histogram.record(millis).is_err() {
+            histogram.saturating_record(millis);
+        }
+    }
+
+    /// The age at or under which `quantile` of the reads were served, or
+    /// `None` before the first read.
+    pub(crate) fn percentile(&self, quantile: f64) -> Option<Duration> {
+        let histogram = self
+            .histogram
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if histogram.is_empty() {
+            return None;
+        }
+        Some(Duration::from_millis(
+            histogram.value_at_quantile(quantile.clamp(0.0, 1.0)),
+        ))
+    }
+
+    pub(crate) fn over_threshold(&self) -> u64 {
+        self.over_threshold.load(Ordering::Relaxed)
+    }
+}
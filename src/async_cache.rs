@@ -0,0 +1,1273 @@
+//! Async variant of the cache for fetchers backed by async I/O.
+
+use std::borrow::Borrow;
+use std::cell::Cell;
+use std::collections::hash_map::RandomState;
+use std::future::{self, Future};
+use std::hash::{BuildHasher, Hash};
+use std::ops::RangeBounds;
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::{pin, Pin};
+use std::sync::mpsc::Receiver;
+use std::sync::Arc;
+use std::task::Poll;
+use std::time::Duration;
+
+#[cfg(feature = "age-histogram")]
+use crate::ages::ServedAges;
+use crate::builder::TTRCacheBuilder;
+use crate::context::FetchContext;
+use crate::entry::{CacheLookup, EntryInfo, EntryState, HotKey, KeyStats};
+use crate::error::Error;
+use crate::fetcher::FetchOutcome;
+use crate::flight::{Claim, FlightGuard};
+use crate::listener::{LookupOutcome, LookupSample, RefreshEvent};
+use crate::policy::{Freshness, RefreshPolicy, TtlPolicy};
+use crate::snapshot::{EntrySnapshot, Snapshot};
+use crate::spawn::{Spawner, Spawning};
+use crate::stats::CacheStats;
+use crate::store::{BoxedLookupSampler, Lookup, Store};
+use crate::time::{Instant, SystemTime};
+use crate::warm::{KeyRefresh, RefreshReport, WarmReport};
+use crate::watch::Watch;
+
+/// Async data source interface for fetching entities.
+pub trait AsyncEntityFetcher<K, V> {
+    /// Fetches an entity by key.
+    ///
+    /// Resolves to `Some(V)` if found, `None` otherwise.
+    fn fetch_entity(&self, key: &K) -> impl Future<Output = Option<V>> + Send;
+
+    /// Refetches an entity the cache already holds as `current`.
+    ///
+    /// See [`EntityFetcher::fetch_if_modified`](crate::EntityFetcher::fetch_if_modified).
+    /// Defaults to an unconditional [`fetch_entity`](Self::fetch_entity).
+    fn fetch_if_modified(
+        &self,
+        key: &K,
+        current: &V,
+    ) -> impl Future<Output = FetchOutcome<V>> + Send {
+        let _ = current;
+        let fetch = self.fetch_entity(key);
+        async move { fetch.await.into() }
+    }
+
+    /// Fetches for a cache, with a [`FetchContext`] telling when the cache
+    /// gives up on the result.
+    ///
+    /// See [`EntityFetcher::fetch_with_context`](crate::EntityFetcher::fetch_with_context).
+    /// Futures can race their work against [`FetchContext::cancelled`], and
+    /// time it out at the context's deadline. Defaults to
+    /// [`fetch_if_modified`](Self::fetch_if_modified) with `current`, or
+    /// [`fetch_entity`](Self::fetch_entity) without.
+    fn fetch_with_context(
+        &self,
+        key: &K,
+        current: Option<&V>,
+        context: &FetchContext,
+    ) -> impl Future<Output = FetchOutcome<V>> + Send {
+        let _ = context;
+        let refetch = current.map(|current| self.fetch_if_modified(key, current));
+        let fetch = refetch.is_none().then(|| self.fetch_entity(key));
+        async move {
+            match (refetch, fetch) {
+                (Some(refetch), _) => refetch.await,
+                (None, Some(fetch)) => fetch.await.into(),
+                (None, None) => FetchOutcome::NotFound,
+            }
+        }
+    }
+}
+
+/// Cache that refreshes stale entries using an async fetcher.
+///
+/// Behaves like [`TTRCache`](crate::TTRCache), but `get` awaits the refresh,
+/// unless the cache was built with a [`Spawner`] to run refreshes as tasks.
+/// No lock is held across the await.
+///
+/// ```rust
+/// use std::time::Duration;
+/// use ttr_cache::{AsyncEntityFetcher, AsyncTTRCache};
+///
+/// struct MyDataSource;
+///
+/// impl AsyncEntityFetcher<String, u64> for MyDataSource {
+///     async fn fetch_entity(&self, _key: &String) -> Option<u64> {
+///         Some(42)
+///     }
+/// }
+///
+/// let cache = AsyncTTRCache::new(Duration::from_secs(300), MyDataSource);
+/// ```
+pub struct AsyncTTRCache<K, V, F, S = RandomState>
+where
+    K: Eq + Hash,
+    F: AsyncEntityFetcher<K, V>,
+{
+    store: Arc<Store<K, V, S>>,
+    fetcher: Arc<F>,
+    spawning: Option<Spawning<StaleRefresh<K, V>>>,
+}
+
+/// A refresh claimed by a stale read, for a spawned task to fetch.
+struct StaleRefresh<K: Eq + Hash, V> {
+    key: K,
+    stale: V,
+    guard: FlightGuard<K, V>,
+}
+
+impl<K, V, F> AsyncTTRCache<K, V, F>
+where
+    K: Eq + Hash + Clone,
+    V: Clone,
+    F: AsyncEntityFetcher<K, V>,
+{
+    /// Creates a new cache with given TTL and fetcher.
+    pub fn new(ttl: impl Into<RefreshPolicy>, fetcher: F) -> Self {
+        Self::with_store(TTRCacheBuilder::new(ttl).into_store(), fetcher)
+    }
+
+    /// Creates a new cache whose per-entry TTR is decided by `policy`.
+    ///
+    /// `ttl` is used for entries the policy has no opinion on.
+    pub fn with_ttl_policy<P>(ttl: impl Into<RefreshPolicy>, fetcher: F, policy: P) -> Self
+    where
+        P: TtlPolicy<K, V> + Send + Sync + 'static,
+    {
+        TTRCacheBuilder::new(ttl)
+            .ttl_policy(policy)
+            .build_async(fetcher)
+    }
+
+    /// Creates a new cache holding at most `max_entries`, evicting the least
+    /// recently used.
+    pub fn with_capacity(ttl: impl Into<RefreshPolicy>, fetcher: F, max_entries: usize) -> Self {
+        TTRCacheBuilder::new(ttl)
+            .max_capacity(max_entries)
+            .build_async(fetcher)
+    }
+}
+
+impl<K, V, F, S> AsyncTTRCache<K, V, F, S>
+where
+    K: Eq + Hash + Clone,
+    V: Clone,
+    F: AsyncEntityFetcher<K, V>,
+    S: BuildHasher,
+{
+    /// Creates a new cache with given TTL and fetcher, hashing keys with
+    /// `hasher`.
+    ///
+    /// See [`TTRCacheBuilder::hasher`].
+    pub fn with_hasher(ttl: impl Into<RefreshPolicy>, fetcher: F, hasher: S) -> Self
+    where
+        S: Clone,
+    {
+        TTRCacheBuilder::new(ttl)
+            .hasher(hasher)
+            .build_async(fetcher)
+    }
+
+    pub(crate) fn with_store(store: Store<K, V, S>, fetcher: F) -> Self {
+        AsyncTTRCache {
+            store: Arc::new(store),
+            fetcher: Arc::new(fetcher),
+            spawning: None,
+        }
+    }
+
+    /// Like [`with_store`](Self::with_store), with stale reads refreshed by
+    /// tasks handed to `spawner`.
+    pub(crate) fn with_spawner(
+        store: Store<K, V, S>,
+        fetcher: F,
+        spawner: impl Spawner + 'static,
+    ) -> Self
+    where
+        K: Send + Sync + 'static,
+        V: Send + Sync + 'static,
+        F: Send + Sync + 'static,
+        S: Send + Sync + 'static,
+    {
+        let mut cache = Self::with_store(store, fetcher);
+        let store = Arc::clone(&cache.store);
+        let fetcher = Arc::clone(&cache.fetcher);
+        cache.spawning = Some(Spawning::new(spawner, move |refresh| {
+            // Tasks refresh through a handle of their own, which spawns
+            // nothing further.
+            let handle = AsyncTTRCache {
+                store: Arc::clone(&store),
+                fetcher: Arc::clone(&fetcher),
+                spawning: None,
+            };
+            async move { handle.refresh_claimed(refresh).await }
+        }));
+        cache
+    }
+
+    /// Awaits `fetch` and stores the result.
+    ///
+    /// When nothing is found, the entry is handled as configured with
+    /// `on_missing_refresh`.
+    /// The result is dropped if `context` is cancelled in the meantime.
+    async fn load_with(
+        &self,
+        key: &K,
+        context: &FetchContext,
+        fetch: impl Future<Output = FetchOutcome<V>>,
+    ) -> Option<V> {
+        let Ok(_lock) = self.store.lock_fetch(key) else {
+            #[cfg(feature = "tracing")]
+            tracing::debug!(key = ?self.store.trace_key(key), "fetched by another process");
+            return self.store.get(key);
+        };
+        if !self.store.admit_fetch(key) {
+            #[cfg(feature = "tracing")]
+            tracing::debug!(key = ?self.store.trace_key(key), "fetch skipped");
+            return self.store.get(key);
+        }
+        #[cfg(feature = "tracing")]
+        let span = tracing::debug_span!("fetch", key = ?self.store.trace_key(key));
+        #[cfg(feature = "tracing")]
+        let fetch = tracing::Instrument::instrument(fetch, span.clone());
+        let generation = self.store.generation(key);
+        let permit = self.store.fetch_permit_async().await;
+        let started = Instant::now();
+        let mut fetch = pin!(fetch);
+        let fetched = future::poll_fn(|cx| {
+            match panic::catch_unwind(AssertUnwindSafe(|| fetch.as_mut().poll(cx))) {
+                Ok(poll) => poll.map(Ok),
+                Err(panic) => Poll::Ready(Err(panic)),
+            }
+        })
+        .await;
+        drop(permit);
+        // A panicking fetcher fails the fetch, not the caller.
+        let Ok(fetched) = fetched else {
+            self.store.record_fetch(started.elapsed(), false);
+            self.store.fetch_panicked(key);
+            return self.store.get(key);
+        };
+        let rejected =
+            matches!(&fetched, FetchOutcome::Modified(entity) if self.store.rejects(key, entity));
+        let succeeded = !rejected && !matches!(fetched, FetchOutcome::NotFound);
+        let latency = started.elapsed();
+        #[cfg(feature = "tracing")]
+        span.in_scope(|| tracing::debug!(elapsed = ?latency, succeeded, "fetched"));
+        self.store.record_fetch(latency, succeeded);
+        if rejected {
+            self.store.fetch_failed(key);
+            return self.store.get(key);
+        }
+        // The key was removed during the fetch.
+        if context.was_cancelled() {
+            return self.store.get(key);
+        }
+
+        match fetched {
+            FetchOutcome::Modified(entity) => {
+                let fetched_at = self.store.now();
+                let stored = self.store.refresh(
+                    key.clone(),
+                    fetched_at,
+                    generation,
+                    entity.clone(),
+                    Some(latency),
+                );
+                #[cfg(feature = "tracing")]
+                if stored.is_none() {
+                    span.in_scope(|| tracing::debug!("discarded, entry changed during fetch"));
+                }
+                Some(stored.unwrap_or(entity))
+            }
+            FetchOutcome::NotModified => self
+                .store
+                .renew(key, generation)
+                .or_else(|| self.store.get(key)),
+            FetchOutcome::NotFound => {
+                self.store.fetch_failed(key);
+                self.store.refresh_missing(key, generation)
+            }
+        }
+    }
+
+    /// Fetches a key now, awaiting any fetch of it already in flight first so
+    /// the result is no older than this call.
+    async fn load_now<Fut>(
+        &self,
+        key: &K,
+        context: &FetchContext,
+        fetch: impl FnOnce() -> Fut,
+    ) -> Option<V>
+    where
+        Fut: Future<Output = FetchOutcome<V>>,
+    {
+        let guard = loop {
+            match self.store.claim(key) {
+                Claim::Leader(guard) => break guard,
+                Claim::Follower(flight) => {
+                    flight.wait_async().await;
+                }
+            }
+        };
+
+        let entity = self.load_with(key, context, fetch()).await;
+        guard.complete(entity.clone());
+        entity
+    }
+
+    /// Loads a key that has nothing to serve, joining a fetch already in
+    /// flight instead of starting another.
+    ///
+    /// If the fetch being waited on fails, this caller fetches for itself.
+    async fn load_missing<Fut>(
+        &self,
+        key: &K,
+        context: &FetchContext,
+        fetch: impl FnOnce() -> Fut,
+    ) -> Option<V>
+    where
+        Fut: Future<Output = FetchOutcome<V>>,
+    {
+        let generation = self.store.generation(key);
+        let guard = loop {
+            match self.store.claim(key) {
+                Claim::Leader(guard) => break guard,
+                Claim::Follower(flight) => {
+                    if let Some(entity) = Arc::clone(&flight).wait_async().await {
+                        if let Some(entity) = &entity {
+                            self.store.adopt(key, generation, &flight, entity);
+                        }
+                        return entity;
+                    }
+                }
+            }
+        };
+
+        let entity = self.load_with(key, context, fetch()).await;
+        guard.complete(entity.clone());
+        entity
+    }
+
+    /// Refreshes a stale key, or serves `stale` if another caller already is.
+    async fn load_stale<Fut>(
+        &self,
+        key: &K,
+        stale: &V,
+        context: &FetchContext,
+        fetch: impl FnOnce() -> Fut,
+    ) -> Option<V>
+    where
+        Fut: Future<Output = FetchOutcome<V>>,
+    {
+        match self.store.claim(key) {
+            Claim::Leader(guard) => {
+                let entity = self.load_with(key, context, fetch()).await;
+                guard.complete(entity.clone());
+                entity
+            }
+            Claim::Follower(_) => Some(stale.clone()),
+        }
+    }
+
+    /// Fetches the refresh a stale read claimed, in a spawned task.
+    ///
+    /// Until it completes, other reads of the key get the stale value.
+    async fn refresh_claimed(&self, refresh: StaleRefresh<K, V>) {
+        let StaleRefresh { key, stale, guard } = refresh;
+        let context = self.store.fetch_context(&key);
+        let fetch = self
+            .fetcher
+            .fetch_with_context(&key, Some(&stale), &context);
+        let refreshed = self.load_with(&key, &context, fetch).await;
+        guard.complete(refreshed);
+    }
+
+    /// Gets a value, awaiting a refresh if stale.
+    ///
+    /// Only one caller fetches a given key at a time. While a stale key is
+    /// being refreshed, other callers get the stale value; while a missing
+    /// key is being fetched, other callers await the result. With a
+    /// [`Spawner`], a stale value is returned at once and refreshed by a
+    /// spawned task instead.
+    ///
+    /// Like [`TTRCache::get`](crate::TTRCache::get), the key can be any
+    /// borrowed form of `K`.
+    ///
+    /// Returns `Some(V)` if found, `None` otherwise.
+    pub async fn get<Q>(&self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    {
+        let sampler = self.store.lookup_sampler();
+        if sampler.is_some() || self.store.tracks_served_ages() {
+            let key = self.store.canonical_owned(key.to_owned());
+            return self.serve_observed(&key, sampler).await;
+        }
+        if self.store.normalizes_keys() {
+            let key = self.store.canonical_owned(key.to_owned());
+            let lookup = self.store.lookup(&key);
+            return self.serve(&key, lookup).await;
+        }
+        if let Some(entity) = self.store.get_swapped(key) {
+            return Some(entity);
+        }
+        let (key, lookup) = match self.store.lookup_cached(key) {
+            Some(Lookup::Fresh(entity) | Lookup::Expiring(entity) | Lookup::Retrying(entity)) => {
+                return Some(entity)
+            }
+            Some(lookup) => (key.to_owned(), lookup),
+            None => {
+                let key = key.to_owned();
+                let lookup = self.store.lookup(&key);
+                (key, lookup)
+            }
+        };
+        self.serve(&key, lookup).await
+    }
+
+    /// Looks `key` up and serves it like [`serve`](Self::serve), passing
+    /// the lookup to `sampler`, if any, and recording the served value's age.
+    async fn serve_observed(&self, key: &K, sampler: Option<&BoxedLookupSampler<K>>) -> Option<V> {
+        let age = self.store.refresh_timing(key).map(|(age, _)| age);
+        let lookup = self.store.lookup(key);
+        let outcome = lookup.outcome();
+        let started = Instant::now();
+        let entity = self.serve(key, lookup).await;
+        if let Some(sampler) = sampler {
+            sampler(&LookupSample {
+                key,
+                outcome,
+                age,
+                fetch_latency: (outcome != LookupOutcome::Hit).then(|| started.elapsed()),
+            });
+        }
+        if entity.is_some() {
+            self.store.record_served_age(key);
+        }
+        entity
+    }
+
+    /// Returns the value `lookup` found, refreshing or fetching as needed.
+    async fn serve(&self, key: &K, lookup: Lookup<V>) -> Option<V> {
+        match lookup {
+            Lookup::Fresh(entity) | Lookup::Expiring(entity) | Lookup::Retrying(entity) => {
+                Some(entity)
+            }
+            Lookup::Absent => None,
+            Lookup::Stale(entity) => {
+                if let Some(spawning) = &self.spawning {
+                    // Only the read that claims the refresh spawns it.
+                    if let Claim::Leader(guard) = self.store.claim(key) {
+                        spawning.spawn(StaleRefresh {
+                            key: key.clone(),
+                            stale: entity.clone(),
+                            guard,
+                        });
+                    }
+                    return Some(entity);
+                }
+                let context = self.store.fetch_context(key);
+                self.load_stale(key, &entity, &context, || {
+                    self.fetcher
+                        .fetch_with_context(key, Some(&entity), &context)
+                })
+                .await
+            }
+            Lookup::Revalidate(entity) => {
+                let context = self.store.fetch_context(key);
+                self.load_missing(key, &context, || {
+                    self.fetcher
+                        .fetch_with_context(key, Some(&entity), &context)
+                })
+                .await
+            }
+            Lookup::Missing if !self.store.fetches_on_miss(key) => None,
+            Lookup::Missing => {
+                let context = self.store.fetch_context(key);
+                self.load_missing(key, &context, || {
+                    self.fetcher.fetch_with_context(key, None, &context)
+                })
+                .await
+            }
+        }
+    }
+
+    /// Gets a value no older than `freshness` allows, awaiting a refresh
+    /// otherwise.
+    ///
+    /// See [`TTRCache::get_with`](crate::TTRCache::get_with).
+    pub async fn get_with(&self, key: &K, freshness: Freshness) -> Option<V> {
+        let key = &*self.store.canonical(key);
+        if freshness == Freshness::Stale {
+            return self.get(key).await;
+        }
+        if let Some(entity) = self.store.get_if(key, |info| freshness.accepts(info)) {
+            return Some(entity);
+        }
+
+        let current = self.store.entry_info(key).map(EntryInfo::into_value);
+        let context = self.store.fetch_context(key);
+        self.load_missing(key, &context, || {
+            self.fetcher
+                .fetch_with_context(key, current.as_ref(), &context)
+        })
+        .await;
+        self.store
+            .entry_info(key)
+            .filter(|info| freshness.accepts(info))
+            .map(EntryInfo::into_value)
+    }
+
+    /// Gets a value younger than `max_age`, refreshing it otherwise.
+    ///
+    /// Same as [`get_with`](Self::get_with) with [`Freshness::MaxAge`]; see
+    /// [`TTRCache::get_if_fresher_than`](crate::TTRCache::get_if_fresher_than).
+    pub async fn get_if_fresher_than(&self, key: &K, max_age: Duration) -> Option<V> {
+        self.get_with(key, Freshness::MaxAge(max_age)).await
+    }
+
+    /// Gets the cached value at once, along with a future for the refreshed
+    /// one.
+    ///
+    /// The first element is what [`get`](Self::get) would serve without
+    /// waiting: the value even if stale, or `None` if nothing is cached. The
+    /// future resolves to the same value if it was fresh, and otherwise
+    /// fetches, or waits for a fetch of the key already in flight. Nothing
+    /// is fetched until it is awaited, so dropping it skips the refresh.
+    ///
+    /// ```rust
+    /// # use std::future::Future;
+    /// # use std::pin::pin;
+    /// # use std::sync::Arc;
+    /// # use std::task::{Context, Poll, Wake, Waker};
+    /// # struct Noop;
+    /// # impl Wake for Noop { fn wake(self: Arc<Self>) {} }
+    /// # fn block_on<F: Future>(future: F) -> F::Output {
+    /// #     let waker = Waker::from(Arc::new(Noop));
+    /// #     let mut cx = Context::from_waker(&waker);
+    /// #     let mut future = pin!(future);
+    /// #     loop {
+    /// #         if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+    /// #             return output;
+    /// #         }
+    /// #     }
+    /// # }
+    /// use std::time::Duration;
+    /// use ttr_cache::{AsyncEntityFetcher, AsyncTTRCache, RefreshPolicy};
+    ///
+    /// struct Quotes;
+    ///
+    /// impl AsyncEntityFetcher<&'static str, u32> for Quotes {
+    ///     async fn fetch_entity(&self, _key: &&'static str) -> Option<u32> {
+    ///         Some(105)
+    ///     }
+    /// }
+    ///
+    /// let cache = AsyncTTRCache::new(RefreshPolicy::Always, Quotes);
+    /// cache.insert("ACME", 100);
+    ///
+    /// let (shown, refreshed) = cache.get_with_refresh(&"ACME");
+    /// assert_eq!(shown, Some(100));
+    /// assert_eq!(block_on(refreshed), Some(105));
+    /// ```
+    pub fn get_with_refresh<'a>(
+        &'a self,
+        key: &'a K,
+    ) -> (Option<V>, impl Future<Output = Option<V>> + 'a) {
+        let key = self.store.canonical(key);
+        let lookup = self.store.lookup(&key);
+        let current = match &lookup {
+            Lookup::Fresh(entity)
+            | Lookup::Expiring(entity)
+            | Lookup::Retrying(entity)
+            | Lookup::Stale(entity) => Some(entity.clone()),
+            Lookup::Revalidate(_) | Lookup::Absent | Lookup::Missing => None,
+        };
+
+        let refreshed = async move {
+            let key = &*key;
+            match lookup {
+                Lookup::Fresh(entity) | Lookup::Expiring(entity) | Lookup::Retrying(entity) => {
+                    Some(entity)
+                }
+                Lookup::Absent => None,
+                Lookup::Stale(entity) => match self.store.claim(key) {
+                    Claim::Leader(guard) => {
+                        let context = self.store.fetch_context(key);
+                        let fetch = self
+                            .fetcher
+                            .fetch_with_context(key, Some(&entity), &context);
+                        let refreshed = self.load_with(key, &context, fetch).await;
+                        guard.complete(refreshed.clone());
+                        refreshed
+                    }
+                    Claim::Follower(flight) => flight.wait_async().await.unwrap_or(Some(entity)),
+                },
+                Lookup::Revalidate(entity) => {
+                    let context = self.store.fetch_context(key);
+                    self.load_missing(key, &context, || {
+                        self.fetcher
+                            .fetch_with_context(key, Some(&entity), &context)
+                    })
+                    .await
+                }
+                Lookup::Missing if !self.store.fetches_on_miss(key) => None,
+                Lookup::Missing => {
+                    let context = self.store.fetch_context(key);
+                    self.load_missing(key, &context, || {
+                        self.fetcher.fetch_with_context(key, None, &context)
+                    })
+                    .await
+                }
+            }
+        };
+        (current, refreshed)
+    }
+
+    /// Fetches `keys` and stores the results, for preloading the cache before
+    /// traffic arrives.
+    ///
+    /// Every key is fetched, even if it is already cached. Keys are fetched
+    /// one after another; to fetch concurrently, join several `warm` calls
+    /// over disjoint keys.
+    pub async fn warm(&self, keys: impl IntoIterator<Item = K>) -> WarmReport<K> {
+        let mut report = WarmReport::new();
+        for key in keys {
+            let key = self.store.canonical_owned(key);
+            let context = self.store.fetch_context(&key);
+            let entity = self
+                .load_missing(&key, &context, || {
+                    self.fetcher.fetch_with_context(&key, None, &context)
+                })
+                .await;
+            report.record(key, entity.is_some());
+        }
+        report
+    }
+
+    /// Refreshes a key now, whatever its TTR, and returns the new value.
+    ///
+    /// See [`TTRCache::force_refresh`](crate::TTRCache::force_refresh).
+    pub async fn force_refresh(&self, key: &K) -> Option<V> {
+        let key = &*self.store.canonical(key);
+        let current = self.store.entry_info(key).map(EntryInfo::into_value);
+        let context = self.store.fetch_context(key);
+        self.load_now(key, &context, || {
+            self.fetcher
+                .fetch_with_context(key, current.as_ref(), &context)
+        })
+        .await
+    }
+
+    /// Refreshes every cached key now, one after another, like
+    /// [`force_refresh`](Self::force_refresh).
+    ///
+    /// The report lists the keys still cached afterwards as loaded and the
+    /// rest as missing. To refresh concurrently, use
+    /// [`refresh_keys`](Self::refresh_keys).
+    pub async fn refresh_all(&self) -> WarmReport<K> {
+        let mut report = WarmReport::new();
+        for key in self.keys() {
+            let refreshed = self.force_refresh(&key).await;
+            report.record(key, refreshed.is_some());
+        }
+        report
+    }
+
+    /// Refreshes `keys` now, with up to `concurrency` fetches in flight at
+    /// once, and reports how each went.
+    ///
+    /// See [`TTRCache::refresh_keys`](crate::TTRCache::refresh_keys).
+    ///
+    /// # Panics
+    ///
+    /// Panics if `concurrency` is zero.
+    pub async fn refresh_keys(
+        &self,
+        keys: impl IntoIterator<Item = K>,
+        concurrency: usize,
+    ) -> Vec<RefreshReport<K>> {
+        self.refresh_keys_with_progress(keys, concurrency, |_| {})
+            .await
+    }
+
+    /// Like [`refresh_keys`](Self::refresh_keys), calling `progress` with
+    /// each report as its key finishes.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `concurrency` is zero.
+    pub async fn refresh_keys_with_progress(
+        &self,
+        keys: impl IntoIterator<Item = K>,
+        concurrency: usize,
+        mut progress: impl FnMut(&RefreshReport<K>),
+    ) -> Vec<RefreshReport<K>> {
+        assert!(
+            concurrency > 0,
+            "refresh_keys needs a concurrency of at least one"
+        );
+        let mut keys = keys.into_iter();
+        let mut running: Vec<Pin<Box<dyn Future<Output = RefreshReport<K>> + '_>>> = Vec::new();
+        let mut reports = Vec::new();
+        future::poll_fn(|cx| loop {
+            while running.len() < concurrency {
+                let Some(key) = keys.next() else { break };
+                running.push(Box::pin(self.refresh_report(key)));
+            }
+            if running.is_empty() {
+                return Poll::Ready(());
+            }
+            let before = running.len();
+            let mut i = 0;
+            while i < running.len() {
+                match running[i].as_mut().poll(cx) {
+                    Poll::Ready(report) => {
+                        drop(running.swap_remove(i));
+                        progress(&report);
+                        reports.push(report);
+                    }
+                    Poll::Pending => i += 1,
+                }
+            }
+            if running.len() == before {
+                return Poll::Pending;
+            }
+        })
+        .await;
+        reports
+    }
+
+    /// Force-refreshes `key`, reporting how the fetch went.
+    async fn refresh_report(&self, key: K) -> RefreshReport<K> {
+        let canonical = self.store.canonical_owned(key.clone());
+        let current = self.store.entry_info(&canonical).map(EntryInfo::into_value);
+        let context = self.store.fetch_context(&canonical);
+        let outcome = Cell::new(KeyRefresh::Failed);
+        self.load_now(&canonical, &context, || async {
+            let fetched = self
+                .fetcher
+                .fetch_with_context(&canonical, current.as_ref(), &context)
+                .await;
+            outcome.set(KeyRefresh::fetched(&fetched, |value| {
+                self.store.validates(&canonical, value)
+            }));
+            fetched
+        })
+        .await;
+        RefreshReport {
+            key,
+            outcome: outcome.get(),
+        }
+    }
+
+    /// Gets a value, awaiting `init` instead of the fetcher if the entry is
+    /// stale or missing.
+    ///
+    /// A value returned by `init` is stored like a fetched one, so its TTR
+    /// starts now. If `init` resolves to `None`, the stale value is kept and
+    /// returned.
+    pub async fn get_or_insert_with<Fut>(&self, key: K, init: impl FnOnce() -> Fut) -> Option<V>
+    where
+        Fut: Future<Output = Option<V>>,
+    {
+        let key = self.store.canonical_owned(key);
+        let init = || async { init().await.into() };
+        match self.store.lookup(&key) {
+            Lookup::Fresh(entity) | Lookup::Expiring(entity) | Lookup::Retrying(entity) => {
+                Some(entity)
+            }
+            Lookup::Stale(entity) => {
+                let context = self.store.fetch_context(&key);
+                self.load_stale(&key, &entity, &context, init).await
+            }
+            Lookup::Revalidate(_) | Lookup::Absent | Lookup::Missing => {
+                let context = self.store.fetch_context(&key);
+                self.load_missing(&key, &context, init).await
+            }
+        }
+    }
+
+    /// Returns a cached value with its age and staleness, without fetching.
+    ///
+    /// Does not count as a use for eviction or in the stats.
+    pub fn entry(&self, key: &K) -> Option<EntryInfo<V>> {
+        self.store.entry_info(&self.store.canonical(key))
+    }
+
+    /// Returns the cached value, stale or not, without ever fetching.
+    ///
+    /// See [`TTRCache::peek`](crate::TTRCache::peek).
+    pub fn peek(&self, key: &K) -> Option<V> {
+        self.entry(key).map(EntryInfo::into_value)
+    }
+
+    /// Time left before `key` goes stale, zero if it already is.
+    ///
+    /// See [`TTRCache::time_to_refresh`](crate::TTRCache::time_to_refresh).
+    pub fn time_to_refresh(&self, key: &K) -> Option<Duration> {
+        let (_, left) = self.store.refresh_timing(&self.store.canonical(key))?;
+        Some(left)
+    }
+
+    /// Time since `key` was last fetched or inserted.
+    ///
+    /// See [`TTRCache::time_since_refresh`](crate::TTRCache::time_since_refresh).
+    pub fn time_since_refresh(&self, key: &K) -> Option<Duration> {
+        let (age, _) = self.store.refresh_timing(&self.store.canonical(key))?;
+        Some(age)
+    }
+
+    /// Returns the last value of `key` that passed validation.
+    ///
+    /// See [`TTRCache::get_last_known_good`](crate::TTRCache::get_last_known_good).
+    pub fn get_last_known_good(&self, key: &K) -> Option<V> {
+        self.store.last_known_good(&self.store.canonical(key))
+    }
+
+    /// Returns the `n` most read keys, most read first, with their hit
+    /// counts and ages.
+    ///
+    /// See [`TTRCache::top_keys`](crate::TTRCache::top_keys).
+    pub fn top_keys(&self, n: usize) -> Vec<HotKey<K>> {
+        self.store.top_keys(n)
+    }
+
+    /// See [`TTRCache::key_stats`](crate::TTRCache::key_stats).
+    pub fn key_stats(&self, key: &K) -> Option<KeyStats> {
+        self.store.key_stats(&self.store.canonical(key))
+    }
+
+    /// See [`TTRCache::entry_state`](crate::TTRCache::entry_state).
+    pub fn entry_state(&self, key: &K) -> Option<EntryState> {
+        self.store.entry_state(&self.store.canonical(key), false)
+    }
+
+    /// Looks up a key without ever fetching it.
+    ///
+    /// See [`TTRCache::lookup`](crate::TTRCache::lookup).
+    pub fn lookup(&self, key: &K) -> CacheLookup<V> {
+        self.store.lookup(&self.store.canonical(key)).into()
+    }
+
+    /// Subscribes to changes of `key`'s value.
+    ///
+    /// See [`TTRCache::subscribe`](crate::TTRCache::subscribe).
+    pub fn subscribe(&self, key: &K) -> Receiver<RefreshEvent<K, V>>
+    where
+        V: PartialEq,
+    {
+        self.store.subscribe(self.store.canonical(key).into_owned())
+    }
+
+    /// Watches `key`'s value, yielding the cached value and then every
+    /// value a refresh or insert changes it to.
+    ///
+    /// The watch does not fetch: for a key that is not cached yet the first
+    /// [`changed`](Watch::changed) waits for someone to load it. Refreshes that
+    /// return an equal value are not yielded. A [`Watch`] yields the
+    /// latest value and skips those it missed; wrap it with
+    /// `futures::stream::unfold` for a `Stream`.
+    ///
+    /// ```rust
+    /// # use std::future::Future;
+    /// # use std::pin::pin;
+    /// # use std::sync::Arc;
+    /// # use std::task::{Context, Poll, Wake, Waker};
+    /// # struct Noop;
+    /// # impl Wake for Noop { fn wake(self: Arc<Self>) {} }
+    /// # fn block_on<F: Future>(future: F) -> F::Output {
+    /// #     let waker = Waker::from(Arc::new(Noop));
+    /// #     let mut cx = Context::from_waker(&waker);
+    /// #     let mut future = pin!(future);
+    /// #     loop {
+    /// #         if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+    /// #             return output;
+    /// #         }
+    /// #     }
+    /// # }
+    /// use std::time::Duration;
+    /// use ttr_cache::{AsyncEntityFetcher, AsyncTTRCache};
+    ///
+    /// struct Nothing;
+    ///
+    /// impl AsyncEntityFetcher<&'static str, u32> for Nothing {
+    ///     async fn fetch_entity(&self, _key: &&'static str) -> Option<u32> {
+    ///         None
+    ///     }
+    /// }
+    ///
+    /// let cache = AsyncTTRCache::new(Duration::from_secs(60), Nothing);
+    /// cache.insert("price", 10);
+    /// let mut prices = cache.watch(&"price");
+    ///
+    /// cache.insert("price", 11);
+    /// cache.insert("price", 12);
+    /// assert_eq!(block_on(prices.changed()), Some(12));
+    ///
+    /// drop(cache);
+    /// assert_eq!(block_on(prices.changed()), None);
+    /// ```
+    pub fn watch(&self, key: &K) -> Watch<V>
+    where
+        V: PartialEq,
+    {
+        self.store.watch(self.store.canonical(key).into_owned())
+    }
+
+    /// Iterates over a snapshot of the cached entries.
+    ///
+    /// Stale entries are included as they are; nothing is fetched, and
+    /// entries past the hard expiry are skipped. Iterating does not count as
+    /// a use for eviction or in the stats.
+    pub fn iter(&self) -> impl Iterator<Item = (K, V)> {
+        self.store.entries().into_iter()
+    }
+
+    /// Iterates over a snapshot of the cached keys.
+    ///
+    /// See [`iter`](Self::iter).
+    pub fn keys(&self) -> impl Iterator<Item = K> {
+        self.iter().map(|(key, _)| key)
+    }
+
+    /// The cached keys whose value maps to `value` by a secondary index. See
+    /// [`TTRCache::get_by_index`](crate::TTRCache::get_by_index).
+    pub fn get_by_index<I>(&self, value: &I) -> Vec<K>
+    where
+        K: 'static,
+        V: 'static,
+        I: Eq + Hash + 'static,
+    {
+        self.store.keys_by_index(value)
+    }
+
+    /// Iterates over a snapshot of the entries whose keys are within
+    /// `range`, in key order. See [`TTRCache::range`](crate::TTRCache::range).
+    pub fn range<R>(&self, range: R) -> impl Iterator<Item = (K, V)>
+    where
+        K: Ord + 'static,
+        R: RangeBounds<K>,
+    {
+        self.store
+            .entries_of(self.store.keys_in_range(&range))
+            .into_iter()
+    }
+
+    /// Iterates over a snapshot of the cached values.
+    ///
+    /// See [`iter`](Self::iter).
+    pub fn values(&self) -> impl Iterator<Item = V> {
+        self.iter().map(|(_, value)| value)
+    }
+
+    /// Returns the number of cached entries, stale or not.
+    pub fn len(&self) -> usize {
+        self.store.len()
+    }
+
+    /// Returns `true` if nothing is cached.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Copies the cached entries and their timestamps, for [`restore`](Self::restore).
+    ///
+    /// Entries past the hard expiry are left out.
+    pub fn snapshot(&self) -> Snapshot<K, V> {
+        self.store.snapshot()
+    }
+
+    /// Copies every cached entry with all its timestamps.
+    ///
+    /// See [`TTRCache::dump`](crate::TTRCache::dump).
+    pub fn dump(&self) -> Vec<(K, EntrySnapshot<V>)> {
+        self.store.dump()
+    }
+
+    /// Stores dumped entries, keeping their timestamps and TTR.
+    ///
+    /// See [`TTRCache::import`](crate::TTRCache::import).
+    pub fn import(&self, entries: impl IntoIterator<Item = (K, EntrySnapshot<V>)>) -> usize {
+        let store = &self.store;
+        store.import(
+            entries
+                .into_iter()
+                .map(|(key, entry)| (store.canonical_owned(key), entry)),
+        )
+    }
+
+    /// Stores the entries of a snapshot, keeping their age and TTR.
+    ///
+    /// Entries that went stale since the snapshot was taken are refreshed as
+    /// usual. Returns the number of entries stored, which leaves out those
+    /// evicted again to make room.
+    pub fn restore(&self, snapshot: Snapshot<K, V>) -> usize {
+        self.store.restore(snapshot)
+    }
+
+    /// Writes a [`snapshot`](Self::snapshot) to `path` as JSON.
+    ///
+    /// Fails with [`Error::Poisoned`], writing nothing, if a panic poisoned
+    /// one of the cache's locks.
+    #[cfg(feature = "serde")]
+    pub fn save_to(&self, path: impl AsRef<std::path::Path>) -> Result<(), Error>
+    where
+        K: serde::Serialize,
+        V: serde::Serialize,
+    {
+        if self.store.is_poisoned() {
+            return Err(Error::Poisoned);
+        }
+        crate::snapshot::save(&self.snapshot(), path.as_ref())
+    }
+
+    /// Restores a snapshot written by [`save_to`](Self::save_to).
+    ///
+    /// Returns the number of entries stored.
+    #[cfg(feature = "serde")]
+    pub fn load_from(&self, path: impl AsRef<std::path::Path>) -> Result<usize, Error>
+    where
+        K: serde::de::DeserializeOwned,
+        V: serde::de::DeserializeOwned,
+    {
+        Ok(self.restore(crate::snapshot::load(path.as_ref())?))
+    }
+
+    /// See [`TTRCache::age_percentile`](crate::TTRCache::age_percentile).
+    #[cfg(feature = "age-histogram")]
+    pub fn age_percentile(&self, quantile: f64) -> Option<Duration> {
+        self.store.served_ages()?.percentile(quantile)
+    }
+
+    /// See [`TTRCache::reads_over_age_threshold`](crate::TTRCache::reads_over_age_threshold).
+    #[cfg(feature = "age-histogram")]
+    pub fn reads_over_age_threshold(&self) -> u64 {
+        self.store
+            .served_ages()
+            .map_or(0, ServedAges::over_threshold)
+    }
+
+    /// Returns a snapshot of the cache's counters.
+    pub fn stats(&self) -> CacheStats {
+        self.store.stats()
+    }
+
+    /// Estimated bytes held by the cached entries.
+    ///
+    /// See [`TTRCache::memory_usage`](crate::TTRCache::memory_usage).
+    pub fn memory_usage(&self) -> u64 {
+        self.store.memory_usage()
+    }
+
+    /// Inserts a value as if it had just been fetched.
+    ///
+    /// The entry's TTR comes from the policy, if any, like a fetched value.
+    pub fn insert(&self, key: K, value: V) {
+        self.store
+            .insert(self.store.canonical_owned(key), self.store.now(), value);
+    }
+
+    /// Inserts a value that stays fresh for `ttl`, overriding the cache's TTR.
+    ///
+    /// The override lasts until the entry is next refreshed.
+    pub fn insert_with_ttl(&self, key: K, value: V, ttl: Duration) {
+        let key = self.store.canonical_owned(key);
+        self.store
+            .insert_with_ttl(key, self.store.now(), value, ttl);
+    }
+
+    /// Inserts a value as if it had been fetched at `fetched_at`, on the
+    /// cache's clock. See
+    /// [`TTRCache::insert_with_timestamp`](crate::TTRCache::insert_with_timestamp).
+    pub fn insert_with_timestamp(&self, key: K, value: V, fetched_at: Instant) {
+        let key = self.store.canonical_owned(key);
+        let now = self.store.now();
+        self.store.insert(key, fetched_at.min(now), value);
+    }
+
+    /// Inserts precomputed values as if they had just been fetched. See
+    /// [`TTRCache::populate`](crate::TTRCache::populate).
+    pub fn populate(&self, entries: impl IntoIterator<Item = (K, V)>) -> usize {
+        let store = &self.store;
+        let entries = entries
+            .into_iter()
+            .map(|(key, value)| (store.canonical_owned(key), value, None));
+        store.populate(entries)
+    }
+
+    /// Inserts precomputed values as if they had been fetched at the
+    /// wall-clock times given. See
+    /// [`TTRCache::populate_at`](crate::TTRCache::populate_at).
+    pub fn populate_at(&self, entries: impl IntoIterator<Item = (K, V, SystemTime)>) -> usize {
+        let store = &self.store;
+        let entries = entries
+            .into_iter()
+            .map(|(key, value, fetched_at)| (store.canonical_owned(key), value, Some(fetched_at)));
+        store.populate(entries)
+    }
+
+    /// Replaces the cache-wide TTR without rebuilding the cache. See
+    /// [`TTRCache::set_ttl`](crate::TTRCache::set_ttl).
+    pub fn set_ttl(&self, ttl: impl Into<RefreshPolicy>) -> Result<(), Error> {
+        self.store.set_ttl(ttl.into().checked_ttl()?);
+        Ok(())
+    }
+
+    /// Stops fetching until [`resume_refresh`](Self::resume_refresh). See
+    /// [`TTRCache::pause_refresh`](crate::TTRCache::pause_refresh).
+    pub fn pause_refresh(&self) {
+        self.store.pause_fetches(true);
+    }
+
+    /// Resumes fetching after [`pause_refresh`](Self::pause_refresh).
+    pub fn resume_refresh(&self) {
+        self.store.pause_fetches(false);
+    }
+
+    /// Whether fetching is paused by [`pause_refresh`](Self::pause_refresh).
+    pub fn is_refresh_paused(&self) -> bool {
+        self.store.fetches_paused()
+    }
+
+    /// Marks an entry stale so the next `get` refreshes it.
+    ///
+    /// The stale value keeps being served in the meantime, subject to the
+    /// refresh mode. Returns `false` if the key is not cached.
+    pub fn invalidate(&self, key: &K) -> bool {
+        self.store.invalidate(&self.store.canonical(key))
+    }
+
+    /// Records that the value of `dependent` is derived from `base`.
+    ///
+    /// See [`TTRCache::add_dependency`](crate::TTRCache::add_dependency).
+    pub fn add_dependency(&self, dependent: K, base: K) {
+        let store = &self.store;
+        store.add_dependency(
+            store.canonical_owned(dependent),
+            store.canonical_owned(base),
+        );
+    }
+
+    /// Forgets every key `dependent` was registered as derived from.
+    ///
+    /// Returns `false` if it had no dependencies.
+    pub fn remove_dependencies(&self, dependent: &K) -> bool {
+        self.store
+            .remove_dependencies(&self.store.canonical(dependent))
+    }
+
+    /// Exempts an entry from eviction and hard expiry.
+    ///
+    /// See [`TTRCache::pin`](crate::TTRCache::pin). Returns `false` if the
+    /// key is not cached.
+    pub fn pin(&self, key: &K) -> bool {
+        self.store.set_pinned(&self.store.canonical(key), true)
+    }
+
+    /// Makes a pinned entry subject to eviction and hard expiry again.
+    ///
+    /// Returns `false` if the key is not cached.
+    pub fn unpin(&self, key: &K) -> bool {
+        self.store.set_pinned(&self.store.canonical(key), false)
+    }
+
+    /// Marks every entry stale at once, returning the new cache-wide
+    /// generation.
+    ///
+    /// See [`TTRCache::bump_generation`](crate::TTRCache::bump_generation).
+    pub fn bump_generation(&self) -> u64 {
+        self.store.bump_generation()
+    }
+
+    /// The cache-wide generation.
+    ///
+    /// See [`TTRCache::generation`](crate::TTRCache::generation).
+    pub fn generation(&self) -> u64 {
+        self.store.bulk_generation()
+    }
+
+    /// Marks every entry matching `predicate` stale, in one pass. Returns how
+    /// many were marked.
+    pub fn invalidate_if(&self, predicate: impl FnMut(&K, &V) -> bool) -> usize {
+        self.store.invalidate_if(predicate)
+    }
+
+    /// Marks every entry whose key starts with `prefix` stale. Returns how
+    /// many were marked.
+    pub fn invalidate_prefix(&self, prefix: &str) -> usize
+    where
+        K: AsRef<str>,
+    {
+        self.invalidate_if(|key, _| key.as_ref().starts_with(prefix))
+    }
+
+    /// Marks every entry whose key is within `range` stale. Returns how many
+    /// were marked.
+    ///
+    /// See [`TTRCache::invalidate_range`](crate::TTRCache::invalidate_range).
+    pub fn invalidate_range<R>(&self, range: R) -> usize
+    where
+        K: Ord + 'static,
+        R: RangeBounds<K>,
+    {
+        self.store
+            .keys_in_range(&range)
+            .iter()
+            .filter(|key| self.invalidate(key))
+            .count()
+    }
+
+    /// Removes every entry whose key is within `range`, from the second tier
+    /// too. Returns how many were removed.
+    pub fn remove_range<R>(&self, range: R) -> usize
+    where
+        K: Ord + 'static,
+        R: RangeBounds<K>,
+    {
+        self.store
+            .keys_in_range(&range)
+            .iter()
+            .filter(|key| self.remove(key).is_some())
+            .count()
+    }
+
+    /// Removes every entry matching `predicate`, from the second tier too.
+    /// Returns how many were removed.
+    pub fn remove_if(&self, predicate: impl FnMut(&K, &V) -> bool) -> usize {
+        self.store.remove_if(predicate)
+    }
+
+    /// Removes an entry, returning its value.
+    pub fn remove(&self, key: &K) -> Option<V> {
+        self.store.remove(&self.store.canonical(key))
+    }
+
+    /// Removes every entry.
+    pub fn clear(&self) {
+        self.store.clear();
+    }
+
+    /// Refreshes handed to the [`Spawner`] that have not finished, zero
+    /// without one.
+    pub fn running_refreshes(&self) -> usize {
+        self.spawning.as_ref().map_or(0, Spawning::running)
+    }
+
+    /// Removes entries past their hard expiry or idle for too long.
+    ///
+    /// See [`TTRCache::purge`](crate::TTRCache::purge). Async caches have no
+    /// janitor thread; call this periodically from a task instead.
+    pub fn purge(&self) -> usize {
+        self.store.purge()
+    }
+
+    /// Removes the entries past their hard expiry and yields them.
+    ///
+    /// See [`TTRCache::drain_expired`](crate::TTRCache::drain_expired).
+    pub fn drain_expired(&self) -> impl Iterator<Item = (K, V)> {
+        self.store.drain_expired().into_iter()
+    }
+}
@@ -0,0 +1,189 @@
+//! Caches a command's output on disk, rerunning the command once its TTR is
+//! up.
+//!
+//! ```text
+//! ttr-cache [--ttr SECS] [--max-stale SECS] [--file PATH] [--key KEY] [--refresh] -- COMMAND [ARGS...]
+//! ```
+//!
+//! The first run executes the command and prints its output; later runs
+//! print the cached output until it is `--ttr` seconds old (60 by default),
+//! then run the command again. If the command fails on one of those runs,
+//! the last output is printed instead, for up to `--max-stale` seconds past
+//! its TTR if that is set. Only the standard output of runs that exit
+//! successfully is cached; standard error is passed through.
+//!
+//! Entries are keyed by the command line, as a JSON array of its arguments,
+//! or by `--key`, and kept together in one file,
+//! `$XDG_CACHE_HOME/ttr-cache.json` or `~/.cache/ttr-cache.json` by default.
+//! The file is a cache snapshot as written by `TTRCache::save_to`: a JSON
+//! object whose `entries` each hold the `key`, the output as a byte array in
+//! `value`, the wall-clock `fetched_at`, the `ttl` and whether the entry was
+//! `invalidated`. Runs sharing a file hold a `FileLock` on it from loading
+//! it to writing it back, so they take turns rather than lose each other's
+//! entries.
+//!
+//! With the `cli` feature.
+
+use std::env;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::process::{Command, ExitCode, Stdio};
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use ttr_cache::{Error, FileLock, TTRCache, TTRCacheBuilder};
+
+const USAGE: &str = "usage: ttr-cache [--ttr SECS] [--max-stale SECS] [--file PATH] [--key KEY] [--refresh] -- COMMAND [ARGS...]";
+
+/// How long a run may hold the cache file before others take it to have
+/// died and go ahead.
+const LOCK_LEASE: Duration = Duration::from_secs(600);
+
+struct Options {
+    ttr: Duration,
+    max_stale: Option<Duration>,
+    file: PathBuf,
+    key: Option<String>,
+    refresh: bool,
+    command: Vec<String>,
+}
+
+fn main() -> ExitCode {
+    let options = match parse(env::args().skip(1)) {
+        Ok(Some(options)) => options,
+        Ok(None) => {
+            println!("{USAGE}");
+            return ExitCode::SUCCESS;
+        }
+        Err(message) => {
+            eprintln!("ttr-cache: {message}\n{USAGE}");
+            return ExitCode::from(2);
+        }
+    };
+    let key = options.key.clone().unwrap_or_else(|| {
+        serde_json::to_string(&options.command).expect("arguments serialize as JSON")
+    });
+
+    // The exit code of a failed run, passed on if nothing is cached; zero
+    // while no run failed.
+    let failed = Arc::new(AtomicI32::new(0));
+    let run = {
+        let failed = Arc::clone(&failed);
+        let command = options.command.clone();
+        move |_: &String| match Command::new(&command[0])
+            .args(&command[1..])
+            .stderr(Stdio::inherit())
+            .output()
+        {
+            Ok(output) if output.status.success() => Some(output.stdout),
+            Ok(output) => {
+                failed.store(output.status.code().unwrap_or(1), Ordering::Relaxed);
+                None
+            }
+            Err(error) => {
+                eprintln!("ttr-cache: cannot run {}: {error}", command[0]);
+                failed.store(127, Ordering::Relaxed);
+                None
+            }
+        }
+    };
+    let mut builder = TTRCacheBuilder::new(options.ttr);
+    if let Some(max_stale) = options.max_stale {
+        builder = builder.max_stale(max_stale);
+    }
+    let cache: TTRCache<String, Vec<u8>, _> = builder.build(run);
+
+    if let Some(parent) = options.file.parent() {
+        let _ = std::fs::create_dir_all(parent);
+    }
+    let lock = FileLock::acquire(&options.file, LOCK_LEASE);
+    match cache.load_from(&options.file) {
+        Ok(_) => {}
+        Err(Error::Io(error)) if error.kind() == io::ErrorKind::NotFound => {}
+        Err(error) => eprintln!("ttr-cache: ignoring {}: {error}", options.file.display()),
+    }
+    if options.refresh {
+        cache.invalidate(&key);
+    }
+    let output = cache.get(&key);
+    if let Err(error) = cache.save_to(&options.file) {
+        eprintln!(
+            "ttr-cache: cannot write {}: {error}",
+            options.file.display()
+        );
+    }
+    drop(lock);
+
+    let failed = failed.load(Ordering::Relaxed);
+    let Some(output) = output else {
+        return ExitCode::from(
+            u8::try_from(failed)
+                .ok()
+                .filter(|code| *code != 0)
+                .unwrap_or(1),
+        );
+    };
+    if failed != 0 {
+        eprintln!("ttr-cache: command failed, printing the last output");
+    }
+    match io::stdout().write_all(&output) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(_) => ExitCode::FAILURE,
+    }
+}
+
+/// Parses the arguments, or returns `None` if help was asked for.
+fn parse(mut args: impl Iterator<Item = String>) -> Result<Option<Options>, String> {
+    let mut options = Options {
+        ttr: Duration::from_secs(60),
+        max_stale: None,
+        file: default_file(),
+        key: None,
+        refresh: false,
+        command: Vec::new(),
+    };
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("{arg} needs a value"));
+        match arg.as_str() {
+            "--ttr" => options.ttr = seconds(&value()?)?,
+            "--max-stale" => options.max_stale = Some(seconds(&value()?)?),
+            "--file" => options.file = PathBuf::from(value()?),
+            "--key" => options.key = Some(value()?),
+            "--refresh" => options.refresh = true,
+            "-h" | "--help" => return Ok(None),
+            "--" => {
+                options.command = args.collect();
+                break;
+            }
+            _ if arg.starts_with('-') => return Err(format!("unknown option {arg}")),
+            _ => {
+                options.command = std::iter::once(arg).chain(args).collect();
+                break;
+            }
+        }
+    }
+    if options.command.is_empty() {
+        return Err("no command given".to_string());
+    }
+    if options.ttr.is_zero() {
+        return Err("--ttr must be more than zero".to_string());
+    }
+    Ok(Some(options))
+}
+
+fn seconds(value: &str) -> Result<Duration, String> {
+    value
+        .parse()
+        .ok()
+        .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok())
+        .ok_or_else(|| format!("not a number of seconds: {value}"))
+}
+
+fn default_file() -> PathBuf {
+    let dir = env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))
+        .unwrap_or_else(env::temp_dir);
+    dir.join("ttr-cache.json")
+}
@@ -0,0 +1,114 @@
+//! Circuit breaker that stops calling a failing backend for a while.
+
+use std::sync::{Mutex, PoisonError};
+use std::time::Duration;
+
+use crate::time::Instant;
+
+/// Whether a cache's circuit breaker lets fetches through, as reported in
+/// [`CacheStats::breaker_state`](crate::CacheStats::breaker_state).
+///
+/// See [`TTRCacheBuilder::circuit_breaker`](crate::TTRCacheBuilder::circuit_breaker).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum BreakerState {
+    /// Fetches go through, as without a breaker.
+    #[default]
+    Closed,
+    /// Too many fetches failed in a row; none are made until the cool-down
+    /// is over and stale values are served instead.
+    Open,
+    /// The cool-down is over and the next fetch is a trial: it closes the
+    /// breaker if it succeeds and opens it again if it fails.
+    HalfOpen,
+}
+
+impl BreakerState {
+    /// The more severe of two states, for stats merged across caches.
+    pub(crate) fn worst(self, other: BreakerState) -> BreakerState {
+        match (self, other) {
+            (BreakerState::Open, _) | (_, BreakerState::Open) => BreakerState::Open,
+            (BreakerState::HalfOpen, _) | (_, BreakerState::HalfOpen) => BreakerState::HalfOpen,
+            _ => BreakerState::Closed,
+        }
+    }
+}
+
+enum Phase {
+    Closed {
+        failures: u32,
+    },
+    Open {
+        since: Instant,
+    },
+    /// A trial fetch started at `since` and has not finished.
+    Trial {
+        since: Instant,
+    },
+}
+
+/// Counts consecutive fetch failures and opens after `threshold` of them.
+pub(crate) struct Breaker {
+    threshold: u32,
+    cool_down: Duration,
+    phase: Mutex<Phase>,
+}
+
+impl Breaker {
+    pub(crate) fn new(threshold: u32, cool_down: Duration) -> Self {
+        Breaker {
+            threshold,
+            cool_down,
+            phase: Mutex::new(Phase::Closed { failures: 0 }),
+        }
+    }
+
+    /// Whether a fetch may go ahead at `now`, starting a trial if the
+    /// cool-down is over.
+    ///
+    /// A trial that never reports back, such as a dropped future, is given
+    /// up on after another cool-down.
+    pub(crate) fn admit(&self, now: Instant) -> bool {
+        let mut phase = self.phase.lock().unwrap_or_else(PoisonError::into_inner);
+        match *phase {
+            Phase::Closed { .. } => true,
+            Phase::Open { since } | Phase::Trial { since }
+                if now.saturating_duration_since(since) >= self.cool_down =>
+            {
+                *phase = Phase::Trial { since: now };
+                true
+            }
+            Phase::Open { .. } | Phase::Trial { .. } => false,
+        }
+    }
+
+    /// Records how a fetch went.
+    pub(crate) fn record(&self, succeeded: bool, now: Instant) {
+        let mut phase = self.phase.lock().unwrap_or_else(PoisonError::into_inner);
+        match *phase {
+            _ if succeeded => *phase = Phase::Closed { failures: 0 },
+            Phase::Closed { failures } if failures.saturating_add(1) < self.threshold => {
+                *phase = Phase::Closed {
+                    failures: failures + 1,
+                }
+            }
+            // Late failures of fetches started before it opened.
+            Phase::Open { .. } => {}
+            Phase::Closed { .. } | Phase::Trial { .. } => {
+                #[cfg(feature = "tracing")]
+                tracing::warn!(cool_down = ?self.cool_down, "circuit breaker opened");
+                *phase = Phase::Open { since: now };
+            }
+        }
+    }
+
+    pub(crate) fn state(&self, now: Instant) -> BreakerState {
+        match *self.phase.lock().unwrap_or_else(PoisonError::into_inner) {
+            Phase::Closed { .. } => BreakerState::Closed,
+            Phase::Open { since } if now.saturating_duration_since(since) < self.cool_down => {
+                BreakerState::Open
+            }
+            Phase::Open { .. } | Phase::Trial { .. } => BreakerState::HalfOpen,
+        }
+    }
+}
@@ -0,0 +1,166 @@
+//! A memory limit shared by several caches.
+
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, PoisonError, Weak};
+
+/// A cap on the estimated memory of every cache built with it, shared
+/// instead of split into per-cache limits.
+///
+/// Caches register with [`TTRCacheBuilder::memory_budget`](crate::TTRCacheBuilder::memory_budget)
+/// and count their [`memory_usage`](crate::TTRCache::memory_usage) against
+/// the budget. When a write takes the total over the limit, each cache is
+/// given a share of the limit in proportion to what it holds, and evicts its
+/// least recently used entries down to that share: the writing cache at
+/// once, the others on their next write or purge. The total can overshoot
+/// while a cache with more than its share is idle, so caches that are rarely
+/// written should run a [janitor](crate::TTRCacheBuilder::janitor).
+///
+/// Clones share the same budget. A cache's usage is released when it is
+/// dropped.
+///
+/// ```rust
+/// use std::time::Duration;
+/// use ttr_cache::{MemoryBudget, TTRCache, TTRCacheBuilder};
+///
+/// let budget = MemoryBudget::new(4096);
+/// let build = || -> TTRCache<u32, Vec<u8>, _> {
+///     TTRCacheBuilder::new(Duration::from_secs(60))
+///         .weigher(|_: &u32, blob: &Vec<u8>| blob.len() as u64)
+///         .memory_budget(budget.clone())
+///         .build(|len: &u32| Some(vec![0; *len as usize]))
+/// };
+/// let (images, thumbnails) = (build(), build());
+///
+/// for len in [1000, 1001, 1002] {
+///     images.get(&len);
+/// }
+/// thumbnails.get(&500);
+/// assert_eq!(budget.used(), 3503);
+///
+/// // Over the limit, the images hold most of it and shed the most.
+/// images.get(&1003);
+/// assert!(budget.used() <= 4096);
+/// assert_eq!(thumbnails.memory_usage(), 500);
+/// ```
+#[derive(Clone)]
+pub struct MemoryBudget {
+    state: Arc<BudgetState>,
+}
+
+struct BudgetState {
+    max: u64,
+    used: AtomicU64,
+    /// Set once the total went over and the shares were handed out, until
+    /// it is back within the limit.
+    pressure: AtomicBool,
+    accounts: Mutex<Vec<Weak<Account>>>,
+}
+
+impl MemoryBudget {
+    /// A budget of `max_bytes` for all its caches together.
+    pub fn new(max_bytes: u64) -> Self {
+        MemoryBudget {
+            state: Arc::new(BudgetState {
+                max: max_bytes,
+                used: AtomicU64::new(0),
+                pressure: AtomicBool::new(false),
+                accounts: Mutex::new(Vec::new()),
+            }),
+        }
+    }
+
+    /// The limit, in bytes.
+    pub fn max(&self) -> u64 {
+        self.state.max
+    }
+
+    /// The estimated bytes held by all its caches.
+    pub fn used(&self) -> u64 {
+        self.state.used.load(Ordering::Relaxed)
+    }
+
+    /// Opens an account for a new cache.
+    pub(crate) fn register(&self) -> Arc<Account> {
+        let account = Arc::new(Account {
+            budget: Arc::clone(&self.state),
+            used: AtomicU64::new(0),
+            share: AtomicU64::new(u64::MAX),
+        });
+        let mut accounts = self
+            .state
+            .accounts
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        accounts.retain(|account| account.strong_count() > 0);
+        accounts.push(Arc::downgrade(&account));
+        account
+    }
+}
+
+/// One cache's usage of a [`MemoryBudget`].
+pub(crate) struct Account {
+    budget: Arc<BudgetState>,
+    used: AtomicU64,
+    /// The most the cache may hold while the budget is over.
+    share: AtomicU64,
+}
+
+impl Account {
+    pub(crate) fn add(&self, bytes: u64) {
+        self.used.fetch_add(bytes, Ordering::Relaxed);
+        self.budget.used.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub(crate) fn sub(&self, bytes: u64) {
+        self.used.fetch_sub(bytes, Ordering::Relaxed);
+        self.budget.used.fetch_sub(bytes, Ordering::Relaxed);
+    }
+
+    /// Whether the budget is over its limit and the cache holds more than
+    /// its share, handing out the shares first if it just went over.
+    pub(crate) fn is_over(&self) -> bool {
+        let budget = &self.budget;
+        if budget.used.load(Ordering::Relaxed) <= budget.max {
+            budget.pressure.store(false, Ordering::Relaxed);
+            return false;
+        }
+        if !budget.pressure.swap(true, Ordering::Relaxed) {
+            budget.apportion();
+        }
+        self.used.load(Ordering::Relaxed) > self.share.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for Account {
+    fn drop(&mut self) {
+        self.budget
+            .used
+            .fetch_sub(*self.used.get_mut(), Ordering::Relaxed);
+    }
+}
+
+impl BudgetState {
+    /// Gives every cache a share of the limit in proportion to its usage.
+    fn apportion(&self) {
+        let accounts: Vec<Arc<Account>> = self
+            .accounts
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .iter()
+            .filter_map(Weak::upgrade)
+            .collect();
+        let total: u128 = accounts
+            .iter()
+            .map(|account| u128::from(account.used.load(Ordering::Relaxed)))
+            .sum();
+        for account in &accounts {
+            let used = u128::from(account.used.load(Ordering::Relaxed));
+            let share = (used * u128::from(self.max))
+                .checked_div(total)
+                .unwrap_or(0);
+            account
+                .share
+                .store(u64::try_from(share).unwrap_or(u64::MAX), Ordering::Relaxed);
+        }
+    }
+}
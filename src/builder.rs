@@ -0,0 +1,2408 @@
+//! Builder for configuring caches.
+
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hash};
+use std::marker::PhantomData;
+use std::path::PathBuf;
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+
+#[cfg(feature = "age-histogram")]
+use crate::ages::ServedAges;
+#[cfg(feature = "async")]
+use crate::async_cache::{AsyncEntityFetcher, AsyncTTRCache};
+use crate::budget::MemoryBudget;
+use crate::bus::InvalidationBus;
+use crate::cache::{TTRCache, THREADS};
+use crate::clock::{Clock, SystemClock};
+use crate::codec::ValueCodec;
+use crate::error::Error;
+use crate::flight::FlightGroup;
+use crate::index::Indexes;
+use crate::listener::{CacheEventListener, LookupSample, ValueLifecycle};
+use crate::lock_file::LockDir;
+use crate::namespace::{NamespaceTtls, NamespacedKey};
+#[cfg(feature = "snapshot-swap")]
+use crate::policy::Engine;
+use crate::policy::{
+    value_ttl, ClockJump, EvictionPolicy, HttpCachePolicy, KeyNormalizer, MissPolicy,
+    OnMissingRefresh, RefreshMode, RefreshPolicy, RefreshStrategy, RetryPolicy, StaleIfError,
+    TtlFromValue, TtlPolicy, Validator, Weigher, ZERO_TTL,
+};
+#[cfg(feature = "async")]
+use crate::spawn::Spawner;
+use crate::storage::Storage;
+use crate::store::{AdaptiveTtl, Config, Entry as StoredEntry, Store, WeakValues};
+use crate::tiered::CacheStore;
+use crate::write_behind::{WriteBehind, WriteBehindQueue};
+
+/// Builder for [`TTRCache`] and, with the `async` feature, `AsyncTTRCache`.
+///
+/// ```rust
+/// use std::time::Duration;
+/// use ttr_cache::{EntityFetcher, RefreshMode, TTRCache, TTRCacheBuilder};
+///
+/// struct MyDataSource;
+///
+/// impl EntityFetcher<String, u64> for MyDataSource {
+///     fn fetch_entity(&self, _key: &String) -> Option<u64> {
+///         Some(42)
+///     }
+/// }
+///
+/// let cache: TTRCache<String, u64, _> = TTRCacheBuilder::new(Duration::from_secs(300))
+///     .max_stale(Duration::from_secs(3600))
+///     .max_capacity(10_000)
+///     .refresh_mode(RefreshMode::Background)
+///     .build_background(MyDataSource);
+/// ```
+pub struct TTRCacheBuilder<K, V, S = RandomState, M = NoWorkers> {
+    config: Config<K, V>,
+    refresh_mode: RefreshMode,
+    janitor: Option<Duration>,
+    /// The window and number of slices of the refresh sweep.
+    sweep: Option<(Duration, u32)>,
+    /// How recently an entry must have been read to be refreshed ahead
+    /// without a read in the lead.
+    ahead_of_reads: Option<Duration>,
+    refreshers: usize,
+    refresh_budget: Option<(usize, Duration)>,
+    refresh_batching: Option<(Duration, usize)>,
+    /// The cache-wide TTR was given as a zero `Duration`.
+    ambiguous_ttl: bool,
+    /// The first other setting found invalid.
+    invalid: Option<&'static str>,
+    hasher: S,
+    workers: PhantomData<M>,
+}
+
+/// A [`TTRCacheBuilder`] with no settings that need a worker thread, which
+/// can build caches that do all their work on the threads calling them.
+pub struct NoWorkers;
+
+/// A [`TTRCacheBuilder`] with a setting that needs a worker thread, such as
+/// [`RefreshMode::Background`], which only
+/// [`build_background`](TTRCacheBuilder::build_background) can build.
+///
+/// ```compile_fail
+/// use std::time::Duration;
+/// use ttr_cache::{RefreshMode, TTRCache, TTRCacheBuilder};
+///
+/// let cache: TTRCache<u32, u32, _> = TTRCacheBuilder::new(Duration::from_secs(60))
+///     .refresh_mode(RefreshMode::Background)
+///     .build(|id: &u32| Some(*id));
+/// ```
+pub struct WithWorkers;
+
+impl<K, V> TTRCacheBuilder<K, V>
+where
+    K: Eq + Hash + Clone,
+    V: Clone,
+{
+    /// Creates a builder with the given TTR and no other options set.
+    ///
+    /// A zero `Duration` is rejected when building; see
+    /// [`RefreshPolicy::After`].
+    pub fn new(ttl: impl Into<RefreshPolicy>) -> Self {
+        let refresh = ttl.into();
+        TTRCacheBuilder {
+            config: Config {
+                ttl: refresh.ttl(),
+                ttl_policy: None,
+                max_stale: None,
+                max_idle: None,
+                expire_after_idle: None,
+                max_concurrent_fetches: None,
+                circuit_breaker: None,
+                max_capacity: None,
+                eviction_policy: EvictionPolicy::Lru,
+                weigher: None,
+                key_normalizer: None,
+                max_weight: None,
+                memory_budget: None,
+                validator: None,
+                entry_size: std::mem::size_of::<(K, V)>() as u64,
+                refresh_ahead: None,
+                refresh_strategy: None,
+                ttl_jitter: None,
+                fetch_cost_ttl: None,
+                retry_policy: None,
+                stale_if_error: None,
+                negative_ttl: None,
+                miss_filter: None,
+                min_fetch_interval: None,
+                stale_while_revalidate: None,
+                on_missing_refresh: OnMissingRefresh::KeepStale,
+                miss_policy: MissPolicy::Fetch,
+                namespace_ttls: None,
+                listener: None,
+                lifecycle: None,
+                codec: None,
+                weak_values: None,
+                dedupe_refreshes: None,
+                adaptive_ttl: None,
+                keep_last_known_good: false,
+                shards: 1,
+                storage: None,
+                second_tier: None,
+                write_behind: None,
+                refresh_locks: None,
+                invalidation_bus: None,
+                lookup_sampler: None,
+                #[cfg(feature = "age-histogram")]
+                served_ages: None,
+                #[cfg(feature = "snapshot-swap")]
+                engine: Engine::Sharded,
+                clock_jump: None,
+                flights: None,
+                fetch_timeout: None,
+                indexes: Indexes::new(),
+                clock: Arc::new(SystemClock),
+                #[cfg(feature = "tracing")]
+                trace_keys: None,
+            },
+            refresh_mode: RefreshMode::Blocking,
+            janitor: None,
+            sweep: None,
+            ahead_of_reads: None,
+            refreshers: 1,
+            refresh_budget: None,
+            refresh_batching: None,
+            ambiguous_ttl: refresh.is_ambiguous(),
+            invalid: None,
+            hasher: RandomState::new(),
+            workers: PhantomData,
+        }
+    }
+
+    /// A preset for name resolution and similar lookups: a 30 second TTR,
+    /// names that do not resolve remembered for 5 seconds, and stale answers
+    /// served for at most 5 minutes past their TTR.
+    ///
+    /// Like every preset, it is an ordinary builder, so any of its settings
+    /// can be overridden before building.
+    ///
+    /// ```rust
+    /// use std::net::{IpAddr, Ipv4Addr};
+    /// use ttr_cache::{TTRCache, TTRCacheBuilder};
+    ///
+    /// let cache: TTRCache<_, _, _> = TTRCacheBuilder::dns().build(|host: &&str| {
+    ///     (*host == "localhost").then_some(IpAddr::V4(Ipv4Addr::LOCALHOST))
+    /// });
+    /// assert!(cache.get(&"localhost").is_some());
+    /// assert_eq!(cache.get(&"nowhere"), None);
+    /// assert_eq!(cache.get(&"nowhere"), None);
+    /// assert_eq!(cache.stats().refreshes, 2);
+    /// ```
+    pub fn dns() -> Self {
+        TTRCacheBuilder::new(Duration::from_secs(30))
+            .negative_ttl(Duration::from_secs(5))
+            .max_stale(Duration::from_secs(300))
+    }
+
+    /// A preset for access tokens and other values that carry their own
+    /// expiry: each value's TTR comes from [`TtlFromValue`], 5 minutes for
+    /// values without one, tokens read in their last minute are refreshed
+    /// ahead, and a token is never served past its TTR. Build it with
+    /// [`build_background`](Self::build_background).
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    /// use ttr_cache::{TTRCache, TTRCacheBuilder, TtlFromValue};
+    ///
+    /// #[derive(Clone)]
+    /// struct Token {
+    ///     expires_in: Duration,
+    /// }
+    ///
+    /// impl TtlFromValue for Token {
+    ///     fn ttl(&self) -> Option<Duration> {
+    ///         Some(self.expires_in)
+    ///     }
+    /// }
+    ///
+    /// let cache: TTRCache<_, _, _> = TTRCacheBuilder::oauth_token()
+    ///     .build_background(|_: &&str| Some(Token { expires_in: Duration::from_secs(3600) }));
+    /// cache.get(&"read");
+    /// assert_eq!(cache.entry(&"read").unwrap().ttl(), Duration::from_secs(3600));
+    /// ```
+    pub fn oauth_token() -> TTRCacheBuilder<K, V, RandomState, WithWorkers>
+    where
+        K: 'static,
+        V: TtlFromValue + 'static,
+    {
+        TTRCacheBuilder::new(Duration::from_secs(300))
+            .ttl_from_value()
+            .refresh_ahead(Duration::from_secs(60))
+            .max_stale(Duration::ZERO)
+    }
+
+    /// A preset for feature flags and configuration: every key is refreshed
+    /// in the background each `interval` once read, and the last value is
+    /// served for as long as the backend is unavailable. Build it with
+    /// [`build_background`](Self::build_background).
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    /// use ttr_cache::{TTRCache, TTRCacheBuilder};
+    ///
+    /// let cache: TTRCache<_, _, _> = TTRCacheBuilder::feature_flags(Duration::from_secs(30))
+    ///     .build_background(|_: &&str| Some(true));
+    /// assert_eq!(cache.get(&"new_checkout"), Some(true));
+    /// ```
+    pub fn feature_flags(interval: Duration) -> TTRCacheBuilder<K, V, RandomState, WithWorkers> {
+        TTRCacheBuilder::new(interval)
+            .refresh_strategy(move |_: &K| RefreshStrategy::Periodic(interval))
+    }
+}
+
+impl<K, V, S, M> TTRCacheBuilder<K, V, S, M>
+where
+    K: Eq + Hash + Clone,
+    V: Clone,
+    S: BuildHasher + Clone,
+{
+    /// Replaces the cache-wide TTR.
+    pub fn ttl(mut self, ttl: impl Into<RefreshPolicy>) -> Self {
+        let refresh = ttl.into();
+        self.config.ttl = refresh.ttl();
+        self.ambiguous_ttl = refresh.is_ambiguous();
+        self
+    }
+
+    /// Spreads out refreshes by varying each entry's TTR randomly by up to
+    /// `fraction` of itself.
+    ///
+    /// With a fraction of `0.1`, an entry with a TTR of 100 seconds goes
+    /// stale between 90 and 110 seconds after it is fetched, so keys that
+    /// were fetched together do not all go stale together. Applies to TTRs
+    /// from the policy too, but not to [`insert_with_ttl`](TTRCache::insert_with_ttl).
+    ///
+    /// # Panics
+    ///
+    /// Panics if `fraction` is not between `0.0` and `1.0`.
+    pub fn ttl_jitter(mut self, fraction: f64) -> Self {
+        assert!(
+            (0.0..=1.0).contains(&fraction),
+            "TTR jitter must be between 0.0 and 1.0, got {fraction}"
+        );
+        self.config.ttl_jitter = Some(fraction);
+        self
+    }
+
+    /// Decides the TTR per entry, falling back to the builder's TTR.
+    pub fn ttl_policy<P>(mut self, policy: P) -> Self
+    where
+        P: TtlPolicy<K, V> + Send + Sync + 'static,
+    {
+        self.config.ttl_policy = Some(Box::new(policy));
+        self
+    }
+
+    /// Scales the TTR of each fetched value by how long its fetch took, so
+    /// expensive keys are refreshed less often than cheap ones.
+    ///
+    /// `scale` is given the TTR the entry would have, from the
+    /// [policy](Self::ttl_policy) or the default, and the fetch latency, and
+    /// returns the TTR to use before [jitter](Self::ttl_jitter). Values
+    /// fetched in a batch share the batch's latency. Inserted values keep
+    /// their TTR, and scaled entries no longer follow
+    /// [`set_ttl`](TTRCache::set_ttl) until they are next refreshed.
+    ///
+    /// ```rust
+    /// use std::thread;
+    /// use std::time::Duration;
+    /// use ttr_cache::{TTRCache, TTRCacheBuilder};
+    ///
+    /// let cache: TTRCache<_, _, _> = TTRCacheBuilder::new(Duration::from_secs(60))
+    ///     // One more minute of TTR per 10ms the fetch took, up to an hour.
+    ///     .ttl_by_fetch_cost(|ttl, latency| {
+    ///         let extra = Duration::from_secs(60) * (latency.as_millis() / 10) as u32;
+    ///         (ttl + extra).min(Duration::from_secs(3600))
+    ///     })
+    ///     .build(|query: &&str| {
+    ///         if query.starts_with("report") {
+    ///             thread::sleep(Duration::from_millis(30));
+    ///         }
+    ///         Some(query.len())
+    ///     });
+    ///
+    /// cache.get(&"user");
+    /// cache.get(&"report:yearly");
+    /// assert!(cache.entry(&"user").unwrap().ttl() < Duration::from_secs(120));
+    /// assert!(cache.entry(&"report:yearly").unwrap().ttl() >= Duration::from_secs(240));
+    /// ```
+    pub fn ttl_by_fetch_cost(
+        mut self,
+        scale: impl Fn(Duration, Duration) -> Duration + Send + Sync + 'static,
+    ) -> Self {
+        self.config.fetch_cost_ttl = Some(Box::new(scale));
+        self
+    }
+
+    /// Takes each entry's TTR from its value, through [`TtlFromValue`].
+    ///
+    /// This is a [`ttl_policy`](Self::ttl_policy), and replaces any set
+    /// before.
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    /// use ttr_cache::{TTRCache, TTRCacheBuilder, TtlFromValue};
+    ///
+    /// #[derive(Clone)]
+    /// struct Token {
+    ///     secret: String,
+    ///     expires_in: u64,
+    /// }
+    ///
+    /// impl TtlFromValue for Token {
+    ///     fn ttl(&self) -> Option<Duration> {
+    ///         // Refresh a minute before the token expires.
+    ///         Some(Duration::from_secs(self.expires_in.saturating_sub(60).max(1)))
+    ///     }
+    /// }
+    ///
+    /// let cache: TTRCache<_, _, _> = TTRCacheBuilder::new(Duration::from_secs(300))
+    ///     .ttl_from_value()
+    ///     .build(|scope: &&str| {
+    ///         Some(Token {
+    ///             secret: format!("token for {scope}"),
+    ///             expires_in: 3600,
+    ///         })
+    ///     });
+    ///
+    /// assert_eq!(cache.get(&"read").unwrap().secret, "token for read");
+    /// assert_eq!(cache.entry(&"read").unwrap().ttl(), Duration::from_secs(3540));
+    /// ```
+    pub fn ttl_from_value(self) -> Self
+    where
+        K: 'static,
+        V: TtlFromValue + 'static,
+    {
+        self.ttl_policy(value_ttl::<K, V> as fn(&K, &V) -> Option<Duration>)
+    }
+
+    /// Stops serving stale entries once they are `max_stale` past their TTR.
+    ///
+    /// Expired entries are fetched on the calling thread like missing keys,
+    /// and `get` returns `None` if that fetch fails. By default, stale values
+    /// are served indefinitely.
+    pub fn max_stale(mut self, max_stale: Duration) -> Self {
+        self.config.max_stale = Some(max_stale);
+        self
+    }
+
+    /// Serves stale entries while they are refreshed only for `window` past
+    /// their TTR.
+    ///
+    /// After that, a lookup waits for the refresh, as for a missing key, and
+    /// gets the stale value only if the refresh finds nothing. Mirrors HTTP's
+    /// `stale-while-revalidate`; see [`http_policy`](Self::http_policy). By
+    /// default, stale values are served while they are refreshed for as long
+    /// as they are cached.
+    pub fn stale_while_revalidate(mut self, window: Duration) -> Self {
+        self.config.stale_while_revalidate = Some(window);
+        self
+    }
+
+    /// Decides freshness like an HTTP cache following `policy`.
+    ///
+    /// Sets the TTR to the policy's `max-age`,
+    /// [`stale_while_revalidate`](Self::stale_while_revalidate) to its
+    /// `stale-while-revalidate` window, and
+    /// [`max_stale`](Self::max_stale) to the longer of its two windows, so
+    /// the value is served past `stale-while-revalidate` only while
+    /// refreshes fail, and never past both windows. Use
+    /// [`RefreshMode::Background`] for stale values within the
+    /// `stale-while-revalidate` window to be served without waiting.
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    /// use ttr_cache::{HttpCachePolicy, MockClock, TTRCache, TTRCacheBuilder};
+    ///
+    /// let policy = HttpCachePolicy::from_cache_control("max-age=60, stale-if-error=600").unwrap();
+    /// let clock = MockClock::new();
+    /// let cache: TTRCache<_, _, _> = TTRCacheBuilder::new(Duration::ZERO)
+    ///     .http_policy(&policy)
+    ///     .clock(clock.clone())
+    ///     .build(|_: &&str| None::<u32>);
+    ///
+    /// cache.insert("page", 1);
+    /// clock.advance(Duration::from_secs(300));
+    /// // The backend is down, so the stale page is served.
+    /// assert_eq!(cache.get(&"page"), Some(1));
+    /// clock.advance(Duration::from_secs(400));
+    /// assert_eq!(cache.get(&"page"), None);
+    /// ```
+    pub fn http_policy(mut self, policy: &HttpCachePolicy) -> Self {
+        self.config.ttl = policy.max_age;
+        // `max-age=0` asks for revalidation on every read.
+        self.ambiguous_ttl = false;
+        self.config.stale_while_revalidate = Some(policy.stale_while_revalidate);
+        self.config.max_stale = Some(policy.stale_while_revalidate.max(policy.stale_if_error));
+        self
+    }
+
+    /// Lets [`purge`](TTRCache::purge) remove entries nobody has read or
+    /// written for `max_idle`.
+    ///
+    /// Idle entries are only removed when the cache is purged, either by
+    /// calling `purge` or by the [`janitor`](Self::janitor); until then they
+    /// are served as usual.
+    pub fn max_idle(mut self, max_idle: Duration) -> Self {
+        self.config.max_idle = Some(max_idle);
+        self
+    }
+
+    /// Expires entries nobody has read or written for `idle`, however
+    /// fresh they are.
+    ///
+    /// Refreshes do not count as uses, so keys that stop being read are
+    /// dropped instead of being refreshed forever by
+    /// [`refresh_strategy`](Self::refresh_strategy) or
+    /// [`refresh_sweep`](Self::refresh_sweep). Unlike
+    /// [`max_idle`](Self::max_idle), an idle entry is no longer served: the
+    /// next read treats the key as missing, and background refreshes skip
+    /// it. Idle entries are removed as they are found, or by
+    /// [`purge`](TTRCache::purge). Pinned entries never expire.
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    /// use ttr_cache::{MockClock, TTRCache, TTRCacheBuilder};
+    ///
+    /// let clock = MockClock::new();
+    /// let cache: TTRCache<_, _, _> = TTRCacheBuilder::new(Duration::from_secs(10))
+    ///     .expire_after_idle(Duration::from_secs(60))
+    ///     .clock(clock.clone())
+    ///     .build(|id: &u32| Some(id * 10));
+    ///
+    /// cache.get(&1);
+    /// cache.get(&2);
+    /// clock.advance(Duration::from_secs(40));
+    /// cache.get(&1);
+    /// clock.advance(Duration::from_secs(40));
+    /// assert_eq!(cache.purge(), 1);
+    /// assert!(cache.entry(&1).is_some());
+    /// assert!(cache.entry(&2).is_none());
+    /// ```
+    pub fn expire_after_idle(mut self, idle: Duration) -> Self {
+        self.config.expire_after_idle = Some(idle);
+        self
+    }
+
+    /// Purges the cache every `interval` on a worker thread.
+    ///
+    /// The worker removes entries past their hard expiry or, with
+    /// [`max_idle`](Self::max_idle), idle ones, so keys that are never read
+    /// again do not hold memory forever. It exits when the cache is dropped.
+    /// Only applies to [`TTRCache`]; async caches can call `purge` from a
+    /// task of their own.
+    ///
+    /// ```rust
+    /// use std::thread;
+    /// use std::time::Duration;
+    /// use ttr_cache::{EntityFetcher, TTRCache, TTRCacheBuilder};
+    ///
+    /// struct Nothing;
+    ///
+    /// impl EntityFetcher<&'static str, u32> for Nothing {
+    ///     fn fetch_entity(&self, _key: &&'static str) -> Option<u32> {
+    ///         None
+    ///     }
+    /// }
+    ///
+    /// let cache: TTRCache<_, _, _> = TTRCacheBuilder::new(Duration::from_secs(60))
+    ///     .max_idle(Duration::from_millis(20))
+    ///     .janitor(Duration::from_millis(10))
+    ///     .build_background(Nothing);
+    ///
+    /// cache.insert("session", 1);
+    /// thread::sleep(Duration::from_millis(200));
+    /// assert!(cache.is_empty());
+    /// ```
+    pub fn janitor(mut self, interval: Duration) -> TTRCacheBuilder<K, V, S, WithWorkers> {
+        if interval.is_zero() {
+            self.reject("the janitor interval must not be zero");
+        }
+        self.janitor = Some(interval);
+        self.with_workers()
+    }
+
+    /// Refreshes every cached key once per `window`, whether or not it is
+    /// read, in `slices` even parts spread over the window.
+    ///
+    /// For caches of configuration and other snapshots that should be
+    /// pulled again as a whole every so often, without a burst of fetches:
+    /// a dedicated thread wakes every `window / slices` and refreshes the
+    /// keys in the next slice, one after another, as `get` would refresh
+    /// them. Keys are split into slices by hash, so each is refreshed once a
+    /// window. A window of the TTR keeps reads from finding keys stale;
+    /// for example a 60-second window in 60 slices refreshes a sixtieth of
+    /// the keys every second. Refreshes start after the first read through
+    /// the fetcher. The window is in wall time, whatever the
+    /// [clock](Self::clock). Only applies to [`TTRCache`].
+    ///
+    /// ```rust
+    /// use std::sync::atomic::{AtomicU32, Ordering};
+    /// use std::thread;
+    /// use std::time::Duration;
+    /// use ttr_cache::{TTRCache, TTRCacheBuilder};
+    ///
+    /// static VERSION: AtomicU32 = AtomicU32::new(1);
+    ///
+    /// let cache: TTRCache<_, _, _> = TTRCacheBuilder::new(Duration::from_secs(3600))
+    ///     .refresh_sweep(Duration::from_millis(200), 4)
+    ///     .build_background(|key: &&str| {
+    ///         Some(format!("{key} v{}", VERSION.load(Ordering::SeqCst)))
+    ///     });
+    ///
+    /// for key in ["db.url", "db.pool", "feature.x"] {
+    ///     cache.get(&key);
+    /// }
+    /// VERSION.store(2, Ordering::SeqCst);
+    /// // `peek` never refreshes; the sweep does.
+    /// while ["db.url", "db.pool", "feature.x"]
+    ///     .iter()
+    ///     .any(|key| !cache.peek(key).is_some_and(|value| value.ends_with("v2")))
+    /// {
+    ///     thread::sleep(Duration::from_millis(10));
+    /// }
+    /// ```
+    pub fn refresh_sweep(
+        mut self,
+        window: Duration,
+        slices: u32,
+    ) -> TTRCacheBuilder<K, V, S, WithWorkers> {
+        if slices == 0 {
+            self.reject("the refresh sweep needs at least one slice");
+        }
+        if (window / slices.max(1)).is_zero() {
+            self.reject("the refresh sweep window must not be zero");
+        }
+        self.sweep = Some((window, slices));
+        self.with_workers()
+    }
+
+    /// Limits the cache to `max_entries`, evicting the least recently used.
+    ///
+    /// Both reads and fetches count as use. Evicting scans every entry, which
+    /// keeps reads cheap at the cost of slower inserts into a full cache.
+    ///
+    /// ```rust
+    /// use std::sync::atomic::{AtomicUsize, Ordering};
+    /// use std::time::Duration;
+    /// use ttr_cache::{EntityFetcher, TTRCache, TTRCacheBuilder};
+    ///
+    /// #[derive(Default)]
+    /// struct Counting(AtomicUsize);
+    ///
+    /// impl EntityFetcher<&'static str, usize> for Counting {
+    ///     fn fetch_entity(&self, _key: &&'static str) -> Option<usize> {
+    ///         Some(self.0.fetch_add(1, Ordering::SeqCst))
+    ///     }
+    /// }
+    ///
+    /// let cache: TTRCache<_, _, _> = TTRCacheBuilder::new(Duration::from_secs(300))
+    ///     .max_capacity(2)
+    ///     .build(Counting::default());
+    ///
+    /// assert_eq!(cache.get(&"a"), Some(0));
+    /// assert_eq!(cache.get(&"b"), Some(1));
+    /// assert_eq!(cache.get(&"a"), Some(0));
+    ///
+    /// // "b" is the least recently used, so it makes room for "c".
+    /// assert_eq!(cache.get(&"c"), Some(2));
+    /// assert_eq!(cache.get(&"a"), Some(0));
+    /// assert_eq!(cache.get(&"b"), Some(3));
+    /// ```
+    pub fn max_capacity(mut self, max_entries: usize) -> Self {
+        self.config.max_capacity = Some(max_entries);
+        self
+    }
+
+    /// Chooses which entries make room when the cache is full.
+    ///
+    /// Only applies with [`max_capacity`](Self::max_capacity) or
+    /// [`max_weight`](Self::max_weight). Defaults to [`EvictionPolicy::Lru`].
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    /// use ttr_cache::{EntityFetcher, EvictionPolicy, TTRCache, TTRCacheBuilder};
+    ///
+    /// struct Backend;
+    ///
+    /// impl EntityFetcher<u32, u32> for Backend {
+    ///     fn fetch_entity(&self, key: &u32) -> Option<u32> {
+    ///         Some(*key)
+    ///     }
+    /// }
+    ///
+    /// let cache: TTRCache<_, _, _> = TTRCacheBuilder::new(Duration::from_secs(300))
+    ///     .max_capacity(2)
+    ///     .eviction_policy(EvictionPolicy::TinyLfu)
+    ///     .build(Backend);
+    ///
+    /// for _ in 0..3 {
+    ///     cache.get(&1);
+    ///     cache.get(&2);
+    /// }
+    /// // A scan reads each of these once.
+    /// for key in 100..110 {
+    ///     cache.get(&key);
+    /// }
+    /// assert!(cache.entry(&1).is_some());
+    /// assert!(cache.entry(&2).is_some());
+    /// ```
+    pub fn eviction_policy(mut self, policy: EvictionPolicy) -> Self {
+        self.config.eviction_policy = policy;
+        self
+    }
+
+    /// Limits the total weight of the cache, evicting the least recently used.
+    ///
+    /// Entries weigh 1 unless a [`weigher`](Self::weigher) is set. An entry
+    /// heavier than `max_weight` on its own is evicted as soon as it is
+    /// stored. Can be combined with [`max_capacity`](Self::max_capacity).
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    /// use ttr_cache::{EntityFetcher, TTRCache, TTRCacheBuilder};
+    ///
+    /// struct Blobs;
+    ///
+    /// impl EntityFetcher<usize, Vec<u8>> for Blobs {
+    ///     fn fetch_entity(&self, len: &usize) -> Option<Vec<u8>> {
+    ///         Some(vec![0; *len])
+    ///     }
+    /// }
+    ///
+    /// let cache: TTRCache<_, _, _> = TTRCacheBuilder::new(Duration::from_secs(300))
+    ///     .weigher(|_: &usize, blob: &Vec<u8>| blob.len() as u64)
+    ///     .max_weight(1024)
+    ///     .build(Blobs);
+    ///
+    /// cache.get(&512);
+    /// cache.get(&256);
+    /// cache.get(&768);
+    /// assert_eq!(cache.stats().entry_count, 2);
+    /// ```
+    pub fn max_weight(mut self, max_weight: u64) -> Self {
+        self.config.max_weight = Some(max_weight);
+        self
+    }
+
+    /// Counts the cache's [`memory_usage`](TTRCache::memory_usage) against a
+    /// budget shared with other caches, evicting the least recently used
+    /// entries when it is over. See [`MemoryBudget`].
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    /// use ttr_cache::{MemoryBudget, TTRCache, TTRCacheBuilder};
+    ///
+    /// let budget = MemoryBudget::new(1024);
+    /// let cache: TTRCache<_, _, _> = TTRCacheBuilder::new(Duration::from_secs(60))
+    ///     .entry_size(256)
+    ///     .memory_budget(budget.clone())
+    ///     .build(|id: &u32| Some(format!("user {id}")));
+    ///
+    /// for id in 0..5 {
+    ///     cache.get(&id);
+    /// }
+    /// assert_eq!(budget.used(), 1024);
+    /// assert_eq!(cache.stats().entry_count, 4);
+    /// ```
+    pub fn memory_budget(mut self, budget: MemoryBudget) -> Self {
+        self.config.memory_budget = Some(budget);
+        self
+    }
+
+    /// Measures entries for [`max_weight`](Self::max_weight).
+    ///
+    /// Entries are weighed once, when they are stored.
+    pub fn weigher<W>(mut self, weigher: W) -> Self
+    where
+        W: Weigher<K, V> + Send + Sync + 'static,
+    {
+        self.config.weigher = Some(Box::new(weigher));
+        self
+    }
+
+    /// Estimates each entry at `bytes` for
+    /// [`memory_usage`](TTRCache::memory_usage), instead of the size of a key
+    /// and value, `size_of::<(K, V)>()`.
+    ///
+    /// For values that own heap memory, such as strings. Ignored with a
+    /// [`weigher`](Self::weigher), whose weights are taken as bytes.
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    /// use ttr_cache::{TTRCache, TTRCacheBuilder};
+    ///
+    /// let cache: TTRCache<_, _, _> = TTRCacheBuilder::new(Duration::from_secs(60))
+    ///     .entry_size(256)
+    ///     .build(|id: &u32| Some(format!("user {id}")));
+    /// cache.get(&1);
+    /// cache.get(&2);
+    /// cache.remove(&1);
+    ///
+    /// assert_eq!(cache.memory_usage(), 256);
+    /// assert_eq!(cache.stats().peak_memory_usage, 512);
+    /// ```
+    pub fn entry_size(mut self, bytes: u64) -> Self {
+        self.config.entry_size = bytes;
+        self
+    }
+
+    /// Checks every fetched value with `validator` before caching it.
+    ///
+    /// A rejected value is dropped and the fetch counted as failed: the
+    /// previous entry, if any, stays cached and is served as before, subject
+    /// to the [retry policy](Self::retry_policy) and
+    /// [`stale_if_error`](Self::stale_if_error). Values stored with `insert`
+    /// are not checked.
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    /// use ttr_cache::{RefreshPolicy, TTRCache, TTRCacheBuilder};
+    ///
+    /// // A backend that sometimes returns truncated, empty responses.
+    /// let cache: TTRCache<_, _, _> = TTRCacheBuilder::new(RefreshPolicy::Always)
+    ///     .validator(|_: &&str, body: &String| !body.is_empty())
+    ///     .build(|_: &&str| Some(String::new()));
+    ///
+    /// cache.insert("profile", "{\"name\":\"Ada\"}".to_string());
+    /// assert_eq!(cache.get(&"profile").as_deref(), Some("{\"name\":\"Ada\"}"));
+    /// assert_eq!(cache.get(&"missing"), None);
+    /// assert_eq!(cache.stats().refresh_failures, 2);
+    /// ```
+    pub fn validator<W>(mut self, validator: W) -> Self
+    where
+        W: Validator<K, V> + Send + Sync + 'static,
+    {
+        self.config.validator = Some(Box::new(validator));
+        self
+    }
+
+    /// Keeps each key's last value that passed the
+    /// [validator](Self::validator), for
+    /// [`get_last_known_good`](TTRCache::get_last_known_good).
+    ///
+    /// Without a validator every stored value passes. The value is kept
+    /// through failed refreshes, [inserts](TTRCache::insert) the validator
+    /// rejects and hard expiry, until the key is removed or evicted for
+    /// capacity. Holds a second copy of each value.
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    /// use ttr_cache::{TTRCache, TTRCacheBuilder};
+    ///
+    /// let cache: TTRCache<_, _, _> = TTRCacheBuilder::new(Duration::from_secs(60))
+    ///     .validator(|_: &&str, body: &String| !body.is_empty())
+    ///     .keep_last_known_good()
+    ///     .build(|_: &&str| Some("{\"rate\":1.1}".to_string()));
+    ///
+    /// cache.get(&"eur");
+    /// // A bad value pushed past the validator is served, but not kept.
+    /// cache.insert("eur", String::new());
+    /// assert_eq!(cache.get(&"eur").as_deref(), Some(""));
+    /// assert_eq!(cache.get_last_known_good(&"eur").as_deref(), Some("{\"rate\":1.1}"));
+    /// ```
+    pub fn keep_last_known_good(mut self) -> Self {
+        self.config.keep_last_known_good = true;
+        self
+    }
+
+    /// Stores every key in the form `normalizer` gives it, so that forms of
+    /// the same logical key share one entry.
+    ///
+    /// Keys are normalized before every lookup, insert, invalidation and
+    /// fetch, and fetchers, listeners and iteration see the normalized form.
+    /// Reads of borrowed keys clone the key first, so they skip the
+    /// in-place fast paths of [`get`](TTRCache::get) and
+    /// [`get_guarded`](TTRCache::get_guarded).
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    /// use ttr_cache::{TTRCache, TTRCacheBuilder};
+    ///
+    /// let cache: TTRCache<_, _, _> = TTRCacheBuilder::new(Duration::from_secs(60))
+    ///     .key_normalizer(|path: &String| path.trim_end_matches('/').to_lowercase())
+    ///     .build(|path: &String| Some(format!("page {path}")));
+    ///
+    /// assert_eq!(cache.get("/Docs/").as_deref(), Some("page /docs"));
+    /// assert_eq!(cache.get("/docs").as_deref(), Some("page /docs"));
+    /// cache.insert("/ABOUT".to_string(), "about".to_string());
+    /// assert_eq!(cache.get("/about").as_deref(), Some("about"));
+    /// assert_eq!(cache.len(), 2);
+    /// assert_eq!(cache.stats().misses, 1);
+    /// ```
+    pub fn key_normalizer<N>(mut self, normalizer: N) -> Self
+    where
+        N: KeyNormalizer<K> + Send + Sync + 'static,
+    {
+        self.config.key_normalizer = Some(Box::new(normalizer));
+        self
+    }
+
+    /// Backs off refreshing an entry after its refresh fails.
+    ///
+    /// Without a retry policy, every read of a stale entry whose refresh
+    /// failed tries again. Missing keys have nothing to serve and are always
+    /// fetched; see [`negative_ttl`](Self::negative_ttl) for those.
+    ///
+    /// ```rust
+    /// use std::sync::atomic::{AtomicUsize, Ordering};
+    /// use std::time::Duration;
+    /// use ttr_cache::{EntityFetcher, RetryPolicy, TTRCache, TTRCacheBuilder};
+    ///
+    /// #[derive(Default)]
+    /// struct Down(AtomicUsize);
+    ///
+    /// impl EntityFetcher<&'static str, u32> for Down {
+    ///     fn fetch_entity(&self, _key: &&'static str) -> Option<u32> {
+    ///         self.0.fetch_add(1, Ordering::SeqCst);
+    ///         None
+    ///     }
+    /// }
+    ///
+    /// let cache: TTRCache<_, _, _> = TTRCacheBuilder::new(Duration::from_secs(60))
+    ///     .retry_policy(RetryPolicy::exponential(Duration::from_secs(5)))
+    ///     .build(Down::default());
+    ///
+    /// cache.insert("config", 1);
+    /// cache.invalidate(&"config");
+    /// assert_eq!(cache.get(&"config"), Some(1));
+    /// assert_eq!(cache.get(&"config"), Some(1));
+    /// assert_eq!(cache.stats().refreshes, 1);
+    /// ```
+    pub fn retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.config.retry_policy = Some(retry_policy);
+        self
+    }
+
+    /// Stops serving a stale entry once its refreshes have failed for too
+    /// long.
+    ///
+    /// Complements [`max_stale`](Self::max_stale), which limits how long a
+    /// stale entry is served regardless of why it was not refreshed.
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    /// use ttr_cache::{EntityFetcher, StaleIfError, TTRCache, TTRCacheBuilder};
+    ///
+    /// struct Down;
+    ///
+    /// impl EntityFetcher<&'static str, u32> for Down {
+    ///     fn fetch_entity(&self, _key: &&'static str) -> Option<u32> {
+    ///         None
+    ///     }
+    /// }
+    ///
+    /// let cache: TTRCache<_, _, _> = TTRCacheBuilder::new(Duration::from_secs(60))
+    ///     .stale_if_error(StaleIfError::new(Duration::from_secs(600)).max_failures(2))
+    ///     .build(Down);
+    ///
+    /// cache.insert("config", 1);
+    /// cache.invalidate(&"config");
+    /// // The first failure still serves the stale value, the second does not.
+    /// assert_eq!(cache.get(&"config"), Some(1));
+    /// assert_eq!(cache.get(&"config"), None);
+    /// ```
+    pub fn stale_if_error(mut self, policy: StaleIfError) -> Self {
+        self.config.stale_if_error = Some(policy);
+        self
+    }
+
+    /// Gives up on fetches that take longer than `timeout`.
+    ///
+    /// `get` then serves the stale value, if any, or `None`, and the fetch is
+    /// counted as failed and reported to
+    /// [`on_fetch_timeout`](CacheEventListener::on_fetch_timeout). To enforce
+    /// the timeout, each fetch runs on a thread of its own; one that timed out
+    /// has its [`FetchContext`](crate::FetchContext) cancelled and its result
+    /// dropped, but keeps running until the fetcher checks the context.
+    ///
+    /// Only enforced for [`TTRCache`] lookups through an [`EntityFetcher`](crate::EntityFetcher).
+    /// Async fetchers get the timeout as their context's deadline, and can
+    /// apply it with their runtime's timer.
+    ///
+    /// ```rust
+    /// use std::thread;
+    /// use std::time::Duration;
+    /// use ttr_cache::{EntityFetcher, RefreshPolicy, TTRCache, TTRCacheBuilder};
+    ///
+    /// struct Hung;
+    ///
+    /// impl EntityFetcher<&'static str, u32> for Hung {
+    ///     fn fetch_entity(&self, _key: &&'static str) -> Option<u32> {
+    ///         thread::sleep(Duration::from_secs(5));
+    ///         Some(2)
+    ///     }
+    /// }
+    ///
+    /// let cache: TTRCache<_, _, _> = TTRCacheBuilder::new(RefreshPolicy::Always)
+    ///     .fetch_timeout(Duration::from_millis(50))
+    ///     .build_background(Hung);
+    ///
+    /// cache.insert("config", 1);
+    /// assert_eq!(cache.get(&"config"), Some(1));
+    /// assert_eq!(cache.stats().fetch_timeouts, 1);
+    /// ```
+    pub fn fetch_timeout(mut self, timeout: Duration) -> TTRCacheBuilder<K, V, S, WithWorkers> {
+        if timeout.is_zero() {
+            self.reject("the fetch timeout must not be zero");
+        }
+        self.config.fetch_timeout = Some(timeout);
+        self.with_workers()
+    }
+
+    /// Chooses whether stale entries are refreshed on the calling thread or
+    /// on a worker.
+    ///
+    /// Only applies to [`TTRCache`]; async caches always await the refresh.
+    /// [`RefreshMode::Background`] needs
+    /// [`build_background`](Self::build_background).
+    pub fn refresh_mode(
+        mut self,
+        refresh_mode: RefreshMode,
+    ) -> TTRCacheBuilder<K, V, S, WithWorkers> {
+        self.refresh_mode = refresh_mode;
+        self.with_workers()
+    }
+
+    /// Lets at most `max` fetcher calls run at once across the cache.
+    ///
+    /// Covers every fetch, on the calling thread and in the background, so
+    /// a burst of keys going stale together cannot flood the backend. Fetches
+    /// beyond the limit wait for a running one to finish. A batch fetch counts
+    /// as one call.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max` is zero.
+    ///
+    /// ```rust
+    /// use std::sync::atomic::{AtomicUsize, Ordering};
+    /// use std::thread;
+    /// use std::time::Duration;
+    /// use ttr_cache::{EntityFetcher, TTRCache, TTRCacheBuilder};
+    ///
+    /// static RUNNING: AtomicUsize = AtomicUsize::new(0);
+    /// static PEAK: AtomicUsize = AtomicUsize::new(0);
+    ///
+    /// struct Backend;
+    ///
+    /// impl EntityFetcher<u32, u32> for Backend {
+    ///     fn fetch_entity(&self, key: &u32) -> Option<u32> {
+    ///         let running = RUNNING.fetch_add(1, Ordering::SeqCst) + 1;
+    ///         PEAK.fetch_max(running, Ordering::SeqCst);
+    ///         thread::sleep(Duration::from_millis(10));
+    ///         RUNNING.fetch_sub(1, Ordering::SeqCst);
+    ///         Some(*key)
+    ///     }
+    /// }
+    ///
+    /// let cache: TTRCache<_, _, _> = TTRCacheBuilder::new(Duration::from_secs(60))
+    ///     .max_concurrent_fetches(2)
+    ///     .build(Backend);
+    ///
+    /// thread::scope(|scope| {
+    ///     for key in 0..8 {
+    ///         let cache = &cache;
+    ///         scope.spawn(move || cache.get(&key));
+    ///     }
+    /// });
+    /// assert!(PEAK.load(Ordering::SeqCst) <= 2);
+    /// ```
+    pub fn max_concurrent_fetches(mut self, max: usize) -> Self {
+        assert!(max > 0, "a cache needs to allow at least one fetch");
+        self.config.max_concurrent_fetches = Some(max);
+        self
+    }
+
+    /// Stops calling the fetcher for `cool_down` after `failures` fetches in
+    /// a row failed, across all keys.
+    ///
+    /// While the breaker is open, stale values are served as they are and
+    /// missing keys return `None`, as if fetching was
+    /// [paused](TTRCache::pause_refresh). After the cool-down the next fetch
+    /// is let through as a trial, closing the breaker if it succeeds and
+    /// opening it again if it fails. Fetches count as failed as they do for
+    /// [`CacheStats::refresh_failures`](crate::CacheStats::refresh_failures).
+    /// The state is reported in
+    /// [`CacheStats::breaker_state`](crate::CacheStats::breaker_state).
+    ///
+    /// ```rust
+    /// use std::sync::atomic::{AtomicBool, Ordering};
+    /// use std::time::Duration;
+    /// use ttr_cache::{BreakerState, MockClock, RefreshPolicy, TTRCache, TTRCacheBuilder};
+    ///
+    /// static DOWN: AtomicBool = AtomicBool::new(false);
+    ///
+    /// let clock = MockClock::new();
+    /// let cache: TTRCache<_, _, _> = TTRCacheBuilder::new(RefreshPolicy::Always)
+    ///     .clock(clock.clone())
+    ///     .circuit_breaker(3, Duration::from_secs(30))
+    ///     .build(|id: &u32| (!DOWN.load(Ordering::SeqCst)).then(|| id * 10));
+    ///
+    /// cache.get(&1);
+    /// DOWN.store(true, Ordering::SeqCst);
+    /// for _ in 0..5 {
+    ///     assert_eq!(cache.get(&1), Some(10));
+    /// }
+    /// let stats = cache.stats();
+    /// assert_eq!(stats.breaker_state, BreakerState::Open);
+    /// assert_eq!(stats.refreshes, 4);
+    ///
+    /// DOWN.store(false, Ordering::SeqCst);
+    /// clock.advance(Duration::from_secs(30));
+    /// assert_eq!(cache.stats().breaker_state, BreakerState::HalfOpen);
+    /// cache.get(&2);
+    /// assert_eq!(cache.stats().breaker_state, BreakerState::Closed);
+    /// ```
+    pub fn circuit_breaker(mut self, failures: u32, cool_down: Duration) -> Self {
+        assert!(failures > 0, "a circuit breaker needs to allow one failure");
+        self.config.circuit_breaker = Some((failures, cool_down));
+        self
+    }
+
+    /// Runs up to `max` background refreshes at once.
+    ///
+    /// Pending refreshes are picked by their [`Priority`](crate::Priority),
+    /// then in order of how often their keys were read while waiting, so hot
+    /// keys are refreshed before cold ones. Only applies with
+    /// [`RefreshMode::Background`] or [`refresh_ahead`](Self::refresh_ahead).
+    /// Defaults to 1.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max` is zero.
+    pub fn max_concurrent_refreshes(mut self, max: usize) -> Self {
+        assert!(max > 0, "a cache needs at least one refresh worker");
+        self.refreshers = max;
+        self
+    }
+
+    /// Starts at most `max` background refreshes every `per`.
+    ///
+    /// Refreshes beyond the budget wait for the next interval, and each
+    /// interval goes to the keys read most often while waiting, the most
+    /// recently read first on ties. Keys that are never picked keep being
+    /// served stale. Only applies with [`RefreshMode::Background`] or
+    /// [`refresh_ahead`](Self::refresh_ahead).
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max` is zero.
+    ///
+    /// ```rust
+    /// use std::thread;
+    /// use std::time::Duration;
+    /// use ttr_cache::{CacheLookup, MockClock, RefreshMode, TTRCache, TTRCacheBuilder};
+    ///
+    /// let clock = MockClock::new();
+    /// let cache: TTRCache<_, _, _> = TTRCacheBuilder::new(Duration::from_secs(60))
+    ///     .clock(clock.clone())
+    ///     .refresh_mode(RefreshMode::Background)
+    ///     .refresh_budget(1, Duration::from_secs(3600))
+    ///     .build_background(|id: &u32| Some(id * 10));
+    ///
+    /// cache.insert(1, 0);
+    /// cache.insert(2, 0);
+    /// clock.advance(Duration::from_secs(90));
+    ///
+    /// assert_eq!(cache.get(&1), Some(0));
+    /// while cache.lookup(&1) != CacheLookup::Fresh(10) {
+    ///     thread::sleep(Duration::from_millis(10));
+    /// }
+    ///
+    /// // The budget for this hour is spent, so key 2 stays stale.
+    /// assert_eq!(cache.get(&2), Some(0));
+    /// thread::sleep(Duration::from_millis(100));
+    /// assert_eq!(cache.lookup(&2), CacheLookup::Stale(0));
+    /// ```
+    pub fn refresh_budget(mut self, max: usize, per: Duration) -> Self {
+        assert!(
+            max > 0,
+            "a refresh budget needs to allow at least one refresh"
+        );
+        if per.is_zero() {
+            self.reject("the refresh budget interval must not be zero");
+        }
+        self.refresh_budget = Some((max, per));
+        self
+    }
+
+    /// Collects background refreshes for `window` and fetches them together,
+    /// up to `max_batch` keys per call to the fetcher's
+    /// [`fetch_entities`](crate::BatchEntityFetcher::fetch_entities).
+    ///
+    /// Only refreshes queued by [`get_many`](TTRCache::get_many) are batched,
+    /// since that is where the cache knows the fetcher is a
+    /// [`BatchEntityFetcher`](crate::BatchEntityFetcher); others are fetched
+    /// one key at a time as before. Only applies with
+    /// [`RefreshMode::Background`] or [`refresh_ahead`](Self::refresh_ahead).
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max_batch` is zero.
+    ///
+    /// ```rust
+    /// use std::collections::HashMap;
+    /// use std::sync::atomic::{AtomicUsize, Ordering};
+    /// use std::sync::Arc;
+    /// use std::thread;
+    /// use std::time::Duration;
+    /// use ttr_cache::{BatchEntityFetcher, CacheLookup, MockClock, RefreshMode, TTRCache, TTRCacheBuilder};
+    ///
+    /// struct Users(Arc<AtomicUsize>);
+    ///
+    /// impl BatchEntityFetcher<u32, u32> for Users {
+    ///     fn fetch_entities(&self, keys: &[u32]) -> HashMap<u32, u32> {
+    ///         self.0.fetch_add(1, Ordering::SeqCst);
+    ///         keys.iter().map(|id| (*id, id * 10)).collect()
+    ///     }
+    /// }
+    ///
+    /// let calls = Arc::new(AtomicUsize::new(0));
+    /// let clock = MockClock::new();
+    /// let cache: TTRCache<_, _, _> = TTRCacheBuilder::new(Duration::from_secs(60))
+    ///     .clock(clock.clone())
+    ///     .refresh_mode(RefreshMode::Background)
+    ///     .max_concurrent_refreshes(1)
+    ///     .refresh_batching(Duration::from_millis(50), 100)
+    ///     .build_background(Users(calls.clone()));
+    ///
+    /// for id in 1..=3 {
+    ///     cache.insert(id, 0);
+    /// }
+    /// clock.advance(Duration::from_secs(90));
+    ///
+    /// assert_eq!(cache.get_many(&[1, 2, 3]).len(), 3);
+    /// while cache.lookup(&3) != CacheLookup::Fresh(30) {
+    ///     thread::sleep(Duration::from_millis(10));
+    /// }
+    /// assert_eq!(cache.lookup(&1), CacheLookup::Fresh(10));
+    /// assert_eq!(calls.load(Ordering::SeqCst), 1);
+    /// ```
+    pub fn refresh_batching(mut self, window: Duration, max_batch: usize) -> Self {
+        assert!(
+            max_batch > 0,
+            "a refresh batch needs to hold at least one key"
+        );
+        self.refresh_batching = Some((window, max_batch));
+        self
+    }
+
+    /// Refreshes entries that are read within `lead` of going stale.
+    ///
+    /// The read still returns the fresh value, and the refresh runs on a
+    /// worker thread, so keys that are read regularly are replaced before
+    /// anyone has to wait for them or see them stale. Keys nobody reads in
+    /// that window go stale as usual; see
+    /// [`refresh_ahead_of_reads`](Self::refresh_ahead_of_reads) to refresh
+    /// them without a read in the lead. Only applies to [`TTRCache`].
+    pub fn refresh_ahead(mut self, lead: Duration) -> TTRCacheBuilder<K, V, S, WithWorkers> {
+        self.config.refresh_ahead = Some(lead);
+        self.with_workers()
+    }
+
+    /// Refreshes entries read within `window` once they are within `lead`
+    /// of going stale, whether or not they are read again in time.
+    ///
+    /// Like [`refresh_ahead`](Self::refresh_ahead), which this also sets,
+    /// but a read in the lead is not needed: a dedicated thread looks for
+    /// such entries every half lead, in wall time, and refreshes them one
+    /// after another, so hot keys are almost never served stale. Keys not
+    /// read for `window` go stale as usual. Only applies to [`TTRCache`].
+    ///
+    /// ```rust
+    /// use std::sync::atomic::{AtomicU32, Ordering};
+    /// use std::thread;
+    /// use std::time::Duration;
+    /// use ttr_cache::{MockClock, TTRCache, TTRCacheBuilder};
+    ///
+    /// static VERSION: AtomicU32 = AtomicU32::new(1);
+    ///
+    /// let clock = MockClock::new();
+    /// let cache: TTRCache<_, _, _> = TTRCacheBuilder::new(Duration::from_secs(60))
+    ///     .clock(clock.clone())
+    ///     .refresh_ahead_of_reads(Duration::from_millis(200), Duration::from_secs(300))
+    ///     .build_background(|key: &&str| Some(VERSION.load(Ordering::SeqCst)));
+    ///
+    /// assert_eq!(cache.get(&"flags"), Some(1));
+    /// VERSION.store(2, Ordering::SeqCst);
+    /// clock.advance(Duration::from_millis(59_900));
+    /// // `peek` is not a read; the refresh needs none in the lead.
+    /// while cache.peek(&"flags") != Some(2) {
+    ///     thread::sleep(Duration::from_millis(10));
+    /// }
+    /// ```
+    pub fn refresh_ahead_of_reads(
+        mut self,
+        lead: Duration,
+        window: Duration,
+    ) -> TTRCacheBuilder<K, V, S, WithWorkers> {
+        if (lead / 2).is_zero() {
+            self.reject("the refresh-ahead lead is too short to check for");
+        }
+        self.config.refresh_ahead = Some(lead);
+        self.ahead_of_reads = Some(window);
+        self.with_workers()
+    }
+
+    /// Chooses per key whether it is only refreshed when read stale, or also
+    /// in the background, every interval or as soon as it goes stale.
+    ///
+    /// Scheduled keys are kept on a hierarchical timer wheel with a
+    /// resolution of 100ms, so setting and firing a timer takes the same
+    /// time however many keys there are. A dedicated thread sleeps until the
+    /// next timer is due and refreshes the keys on it as `get` would refresh
+    /// them; they start after the first read through the fetcher. Each key's
+    /// strategy is asked for whenever a value is cached for it. The timers
+    /// run in wall time, whatever the [clock](Self::clock). Only applies to
+    /// [`TTRCache`].
+    ///
+    /// ```rust
+    /// use std::sync::atomic::{AtomicU32, Ordering};
+    /// use std::thread;
+    /// use std::time::Duration;
+    /// use ttr_cache::{RefreshStrategy, TTRCache, TTRCacheBuilder};
+    ///
+    /// static PRICE: AtomicU32 = AtomicU32::new(100);
+    ///
+    /// let cache: TTRCache<_, _, _> = TTRCacheBuilder::new(Duration::from_secs(3600))
+    ///     .refresh_strategy(|symbol: &&str| match *symbol {
+    ///         "BTC" => RefreshStrategy::Periodic(Duration::from_millis(100)),
+    ///         _ => RefreshStrategy::OnRead,
+    ///     })
+    ///     .build_background(|_: &&str| Some(PRICE.load(Ordering::SeqCst)));
+    ///
+    /// assert_eq!(cache.get(&"BTC"), Some(100));
+    /// assert_eq!(cache.get(&"ETH"), Some(100));
+    /// PRICE.store(120, Ordering::SeqCst);
+    /// while cache.get(&"BTC") != Some(120) {
+    ///     thread::sleep(Duration::from_millis(10));
+    /// }
+    /// // Refreshed only once read stale, an hour from now.
+    /// assert_eq!(cache.get(&"ETH"), Some(100));
+    ///
+    /// // Every key refreshed once its TTR of 100ms is up, read or not.
+    /// let cache: TTRCache<_, _, _> = TTRCacheBuilder::new(Duration::from_millis(100))
+    ///     .refresh_strategy(|_: &&str| RefreshStrategy::WhenStale)
+    ///     .build_background(|_: &&str| Some(PRICE.load(Ordering::SeqCst)));
+    /// assert_eq!(cache.get(&"ETH"), Some(120));
+    /// PRICE.store(130, Ordering::SeqCst);
+    /// // `peek` never refreshes; the timer thread does.
+    /// while cache.peek(&"ETH") != Some(130) {
+    ///     thread::sleep(Duration::from_millis(10));
+    /// }
+    /// ```
+    pub fn refresh_strategy(
+        mut self,
+        strategy: impl Fn(&K) -> RefreshStrategy + Send + Sync + 'static,
+    ) -> TTRCacheBuilder<K, V, S, WithWorkers> {
+        self.config.refresh_strategy = Some(Box::new(strategy));
+        self.with_workers()
+    }
+
+    /// Remembers keys the fetcher found nothing for, for `negative_ttl`.
+    ///
+    /// Until then, `get` returns `None` for those keys without calling the
+    /// fetcher again. Fetch errors are not remembered. Inserting, removing or
+    /// invalidating a key forgets it early.
+    ///
+    /// ```rust
+    /// use std::sync::atomic::{AtomicUsize, Ordering};
+    /// use std::time::Duration;
+    /// use ttr_cache::{EntityFetcher, TTRCache, TTRCacheBuilder};
+    ///
+    /// #[derive(Default)]
+    /// struct Nothing(AtomicUsize);
+    ///
+    /// impl EntityFetcher<u32, String> for Nothing {
+    ///     fn fetch_entity(&self, _key: &u32) -> Option<String> {
+    ///         self.0.fetch_add(1, Ordering::SeqCst);
+    ///         None
+    ///     }
+    /// }
+    ///
+    /// let cache: TTRCache<_, _, _> = TTRCacheBuilder::new(Duration::from_secs(300))
+    ///     .negative_ttl(Duration::from_secs(30))
+    ///     .build(Nothing::default());
+    ///
+    /// assert_eq!(cache.get(&404), None);
+    /// assert_eq!(cache.get(&404), None);
+    /// let stats = cache.stats();
+    /// assert_eq!((stats.refreshes, stats.absent_hits, stats.hits), (1, 1, 0));
+    /// ```
+    pub fn negative_ttl(mut self, negative_ttl: Duration) -> Self {
+        self.config.negative_ttl = Some(negative_ttl);
+        self
+    }
+
+    /// Records keys the fetcher found nothing for in a Bloom filter, so
+    /// repeated lookups of them return `None` without calling the fetcher or
+    /// taking the lock of [`negative_ttl`](Self::negative_ttl).
+    ///
+    /// For keys probed over and over that will never exist, such as made-up
+    /// ids. The filter is sized for `expected_keys` missing keys at about 1%
+    /// false positives, ten bits a key: a key it mistakes for one of them
+    /// is reported missing without a fetch, until the filter is cleared.
+    /// It is cleared every `reset_every`, by the cache's
+    /// [clock](Self::clock), and whenever one of its keys is cached,
+    /// removed or invalidated.
+    ///
+    /// ```rust
+    /// use std::sync::atomic::{AtomicUsize, Ordering};
+    /// use std::time::Duration;
+    /// use ttr_cache::{MockClock, TTRCache, TTRCacheBuilder};
+    ///
+    /// static FETCHES: AtomicUsize = AtomicUsize::new(0);
+    ///
+    /// let clock = MockClock::new();
+    /// let cache: TTRCache<_, _, _> = TTRCacheBuilder::new(Duration::from_secs(300))
+    ///     .miss_filter(100_000, Duration::from_secs(600))
+    ///     .clock(clock.clone())
+    ///     .build(|id: &u32| {
+    ///         FETCHES.fetch_add(1, Ordering::SeqCst);
+    ///         (*id < 1000).then(|| format!("user {id}"))
+    ///     });
+    ///
+    /// for _ in 0..3 {
+    ///     assert_eq!(cache.get(&999_999), None);
+    /// }
+    /// assert_eq!(FETCHES.load(Ordering::SeqCst), 1);
+    ///
+    /// clock.advance(Duration::from_secs(600));
+    /// assert_eq!(cache.get(&999_999), None);
+    /// assert_eq!(FETCHES.load(Ordering::SeqCst), 2);
+    /// ```
+    pub fn miss_filter(mut self, expected_keys: usize, reset_every: Duration) -> Self {
+        self.config.miss_filter = Some((expected_keys, reset_every));
+        self
+    }
+
+    /// Fetches each key at most once per `interval`, whatever the outcome.
+    ///
+    /// Within the interval, a key that would be fetched is served as if the
+    /// fetch found nothing: stale values are kept and missing keys return
+    /// `None`. Unlike [`negative_ttl`](Self::negative_ttl), this also limits
+    /// keys whose fetches fail or whose values keep being removed.
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    /// use ttr_cache::{MockClock, TTRCache, TTRCacheBuilder};
+    ///
+    /// let clock = MockClock::new();
+    /// let cache: TTRCache<_, _, _> = TTRCacheBuilder::new(Duration::from_secs(300))
+    ///     .min_fetch_interval(Duration::from_secs(1))
+    ///     .clock(clock.clone())
+    ///     .build(|_: &u32| None::<String>);
+    ///
+    /// for _ in 0..100 {
+    ///     assert_eq!(cache.get(&404), None);
+    /// }
+    /// assert_eq!(cache.stats().refreshes, 1);
+    ///
+    /// clock.advance(Duration::from_secs(1));
+    /// cache.get(&404);
+    /// assert_eq!(cache.stats().refreshes, 2);
+    /// ```
+    pub fn min_fetch_interval(mut self, interval: Duration) -> Self {
+        self.config.min_fetch_interval = Some(interval);
+        self
+    }
+
+    /// Sets what a refresh that finds nothing does to the cached entry.
+    ///
+    /// By default the stale value is kept and the key is fetched again on
+    /// every lookup. Fetch errors are not affected: they always keep the
+    /// stale value.
+    ///
+    /// ```rust
+    /// use std::sync::{Arc, Mutex};
+    /// use std::time::Duration;
+    /// use ttr_cache::{OnMissingRefresh, RefreshPolicy, TTRCache, TTRCacheBuilder};
+    ///
+    /// let users = Arc::new(Mutex::new(vec![1, 2]));
+    /// let backend = Arc::clone(&users);
+    /// let cache: TTRCache<_, _, _> = TTRCacheBuilder::new(RefreshPolicy::Always)
+    ///     .on_missing_refresh(OnMissingRefresh::Remove)
+    ///     .build(move |id: &u32| backend.lock().unwrap().contains(id).then(|| format!("user {id}")));
+    ///
+    /// assert_eq!(cache.get(&2).as_deref(), Some("user 2"));
+    /// users.lock().unwrap().retain(|id| *id != 2);
+    /// assert_eq!(cache.get(&2), None);
+    /// assert!(cache.entry(&2).is_none());
+    /// ```
+    ///
+    /// A tombstone also keeps the deletion for a while, so lookups in the
+    /// meantime do not ask the backend again:
+    ///
+    /// ```rust
+    /// use std::sync::atomic::{AtomicBool, Ordering};
+    /// use std::sync::Arc;
+    /// use std::time::Duration;
+    /// use ttr_cache::{OnMissingRefresh, RefreshPolicy, TTRCache, TTRCacheBuilder};
+    ///
+    /// let deleted = Arc::new(AtomicBool::new(false));
+    /// let backend = Arc::clone(&deleted);
+    /// let cache: TTRCache<_, _, _> = TTRCacheBuilder::new(RefreshPolicy::Always)
+    ///     .on_missing_refresh(OnMissingRefresh::Tombstone(Duration::from_secs(60)))
+    ///     .build(move |id: &u32| (!backend.load(Ordering::SeqCst)).then(|| format!("user {id}")));
+    ///
+    /// assert_eq!(cache.get(&2).as_deref(), Some("user 2"));
+    /// deleted.store(true, Ordering::SeqCst);
+    /// assert_eq!(cache.get(&2), None);
+    /// assert_eq!(cache.get(&2), None);
+    /// assert_eq!(cache.stats().refreshes, 2);
+    ///
+    /// // Restored upstream; invalidating the key lifts the tombstone.
+    /// deleted.store(false, Ordering::SeqCst);
+    /// cache.invalidate(&2);
+    /// assert_eq!(cache.get(&2).as_deref(), Some("user 2"));
+    /// ```
+    pub fn on_missing_refresh(mut self, behavior: OnMissingRefresh) -> Self {
+        self.config.on_missing_refresh = behavior;
+        self
+    }
+
+    /// Chooses what lookups do for keys with nothing cached: fetch them, the
+    /// default, or return `None` for caches that are preloaded and should
+    /// never call the backend on the request path.
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    /// use ttr_cache::{Error, MissPolicy, TTRCache, TTRCacheBuilder};
+    ///
+    /// let cache: TTRCache<_, _, _> = TTRCacheBuilder::new(Duration::from_secs(60))
+    ///     .miss_policy(MissPolicy::Error)
+    ///     .build(|id: &u32| Some(id * 10));
+    /// cache.warm([1]);
+    ///
+    /// assert_eq!(cache.get(&1), Some(10));
+    /// assert_eq!(cache.get(&2), None);
+    /// assert!(matches!(cache.get_strict(&2), Err(Error::UnexpectedMiss)));
+    /// ```
+    pub fn miss_policy(mut self, policy: MissPolicy) -> Self {
+        self.config.miss_policy = policy;
+        self
+    }
+
+    /// Notifies `listener` of inserts, refreshes, evictions and expiries,
+    /// and of every value leaving the cache with the reason why.
+    ///
+    /// ```rust
+    /// use std::sync::{Arc, Mutex};
+    /// use std::time::Duration;
+    /// use ttr_cache::{CacheEventListener, EntityFetcher, TTRCache, TTRCacheBuilder};
+    ///
+    /// struct Identity;
+    ///
+    /// impl EntityFetcher<u32, u32> for Identity {
+    ///     fn fetch_entity(&self, key: &u32) -> Option<u32> {
+    ///         Some(*key)
+    ///     }
+    /// }
+    ///
+    /// #[derive(Clone, Default)]
+    /// struct Evictions(Arc<Mutex<Vec<u32>>>);
+    ///
+    /// impl CacheEventListener<u32, u32> for Evictions {
+    ///     fn on_evict(&self, key: &u32, _value: &u32) {
+    ///         self.0.lock().unwrap().push(*key);
+    ///     }
+    /// }
+    ///
+    /// let evictions = Evictions::default();
+    /// let cache: TTRCache<_, _, _> = TTRCacheBuilder::new(Duration::from_secs(60))
+    ///     .max_capacity(1)
+    ///     .event_listener(evictions.clone())
+    ///     .build(Identity);
+    ///
+    /// cache.get(&1);
+    /// cache.get(&2);
+    /// assert_eq!(*evictions.0.lock().unwrap(), vec![1]);
+    /// ```
+    pub fn event_listener<L>(mut self, listener: L) -> Self
+    where
+        L: CacheEventListener<K, V> + Send + Sync + 'static,
+    {
+        self.config.listener = Some(Box::new(listener));
+        self
+    }
+
+    /// Hands values to `lifecycle` as the cache lets go of them, so the
+    /// resources they hold can be released. See [`ValueLifecycle`].
+    pub fn value_lifecycle<L>(mut self, lifecycle: L) -> Self
+    where
+        L: ValueLifecycle<V> + Send + Sync + 'static,
+    {
+        self.config.lifecycle = Some(Box::new(lifecycle));
+        self
+    }
+
+    /// Passes a random `rate` of lookups through `get` to `sampler`, with how
+    /// each was served, the age of the cached value and how long serving it
+    /// took.
+    ///
+    /// For feeding per-key cache efficiency into analytics without logging
+    /// every read; a rate of `0.01` samples one lookup in a hundred. The
+    /// sampler runs on the thread that made the lookup, once it is served.
+    ///
+    /// ```rust
+    /// use std::sync::{Arc, Mutex};
+    /// use std::time::Duration;
+    /// use ttr_cache::{LookupOutcome, TTRCache, TTRCacheBuilder};
+    ///
+    /// let samples = Arc::new(Mutex::new(Vec::new()));
+    /// let sink = Arc::clone(&samples);
+    /// let cache: TTRCache<_, _, _> = TTRCacheBuilder::new(Duration::from_secs(60))
+    ///     .sample_lookups(1.0, move |sample| {
+    ///         sink.lock().unwrap().push((*sample.key, sample.outcome));
+    ///     })
+    ///     .build(|id: &u32| Some(id * 10));
+    ///
+    /// cache.get(&1);
+    /// cache.get(&1);
+    /// assert_eq!(
+    ///     *samples.lock().unwrap(),
+    ///     [(1, LookupOutcome::Miss), (1, LookupOutcome::Hit)]
+    /// );
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if `rate` is not between `0.0` and `1.0`.
+    pub fn sample_lookups(
+        mut self,
+        rate: f64,
+        sampler: impl Fn(&LookupSample<'_, K>) + Send + Sync + 'static,
+    ) -> Self {
+        assert!(
+            (0.0..=1.0).contains(&rate),
+            "lookup sample rate must be between 0.0 and 1.0, got {rate}"
+        );
+        self.config.lookup_sampler = Some((rate, Box::new(sampler)));
+        self
+    }
+
+    /// Records the age of every value served by `get` in a histogram, for
+    /// staleness objectives such as "99% of reads under ten minutes old".
+    ///
+    /// Values fetched for the read count as zero seconds old. Query the
+    /// histogram with [`age_percentile`](TTRCache::age_percentile) and the
+    /// reads that were older than `threshold` with
+    /// [`reads_over_age_threshold`](TTRCache::reads_over_age_threshold).
+    /// With the `age-histogram` feature.
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    /// use ttr_cache::{MockClock, TTRCache, TTRCacheBuilder};
+    ///
+    /// let clock = MockClock::new();
+    /// let cache: TTRCache<_, _, _> = TTRCacheBuilder::new(Duration::from_secs(600))
+    ///     .clock(clock.clone())
+    ///     .track_served_ages(Duration::from_secs(60))
+    ///     .build(|id: &u32| Some(id * 10));
+    ///
+    /// cache.get(&1);
+    /// clock.advance(Duration::from_secs(120));
+    /// cache.get(&1);
+    /// assert_eq!(cache.age_percentile(0.5), Some(Duration::ZERO));
+    /// let oldest = cache.age_percentile(1.0).unwrap();
+    /// assert!(oldest >= Duration::from_secs(120) && oldest < Duration::from_secs(121));
+    /// assert_eq!(cache.reads_over_age_threshold(), 1);
+    /// ```
+    #[cfg(feature = "age-histogram")]
+    pub fn track_served_ages(mut self, threshold: Duration) -> Self {
+        self.config.served_ages = Some(ServedAges::new(threshold));
+        self
+    }
+
+    /// Keeps the cached keys in order, in a `BTreeSet` next to the entries,
+    /// so [`range`](TTRCache::range) and the range invalidations find the
+    /// keys of a range without scanning the whole cache.
+    ///
+    /// For keys read in contiguous runs, such as time series keyed by
+    /// `(metric, bucket)`. Each new key is also cloned into the set, while
+    /// its shard is locked.
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    /// use ttr_cache::{TTRCache, TTRCacheBuilder};
+    ///
+    /// let samples: TTRCache<(&str, u32), f64, ()> = TTRCacheBuilder::new(Duration::from_secs(60))
+    ///     .ordered_keys()
+    ///     .build_cache_aside();
+    /// for bucket in 0..4 {
+    ///     samples.insert(("cpu", bucket), f64::from(bucket));
+    ///     samples.insert(("mem", bucket), 0.5);
+    /// }
+    ///
+    /// let window: Vec<_> = samples.range(("cpu", 1)..("cpu", 3)).collect();
+    /// assert_eq!(window, [(("cpu", 1), 1.0), (("cpu", 2), 2.0)]);
+    /// ```
+    pub fn ordered_keys(mut self) -> Self
+    where
+        K: Ord + Clone + Send + 'static,
+    {
+        self.config.indexes.order_keys();
+        self
+    }
+
+    /// Indexes cached keys by what `extract` returns for their value, for
+    /// reverse lookups with [`get_by_index`](TTRCache::get_by_index).
+    ///
+    /// The index is kept up to date as entries are written and removed. One
+    /// index can be registered per type `I`; wrap the type in a newtype to
+    /// index by several fields of the same type. `extract` runs on every
+    /// write while the entry is locked, so it should be quick.
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    /// use ttr_cache::{TTRCache, TTRCacheBuilder};
+    ///
+    /// #[derive(Clone)]
+    /// struct Session {
+    ///     user_id: u64,
+    /// }
+    ///
+    /// let sessions: TTRCache<&str, Session, ()> = TTRCacheBuilder::new(Duration::from_secs(60))
+    ///     .secondary_index(|session: &Session| session.user_id)
+    ///     .build_cache_aside();
+    /// sessions.insert("a", Session { user_id: 1 });
+    /// sessions.insert("b", Session { user_id: 2 });
+    /// sessions.insert("c", Session { user_id: 1 });
+    ///
+    /// let mut found = sessions.get_by_index(&1u64);
+    /// found.sort();
+    /// assert_eq!(found, ["a", "c"]);
+    ///
+    /// sessions.remove(&"a");
+    /// sessions.insert("c", Session { user_id: 2 });
+    /// assert!(sessions.get_by_index(&1u64).is_empty());
+    /// ```
+    pub fn secondary_index<I>(mut self, extract: impl Fn(&V) -> I + Send + Sync + 'static) -> Self
+    where
+        K: Eq + Hash + Clone + Send + 'static,
+        V: 'static,
+        I: Eq + Hash + Clone + Send + 'static,
+    {
+        self.config.indexes.register(extract);
+        self
+    }
+
+    /// Keeps a second copy of every entry in `store`, consulted when a key is
+    /// not in memory.
+    ///
+    /// Fetched and inserted values are written through to `store`, and
+    /// removing a key removes it from both. Entries evicted from memory stay
+    /// in `store`, and are brought back with their original age, so only
+    /// those that went stale in the meantime are fetched again. `clear` only
+    /// empties memory.
+    ///
+    /// `store` is called on the thread doing the lookup, even for async
+    /// caches, so it should be quick or do its own caching.
+    pub fn second_tier<T>(mut self, store: T) -> Self
+    where
+        T: CacheStore<K, V> + Send + Sync + 'static,
+    {
+        self.config.second_tier = Some(Box::new(store));
+        self
+    }
+
+    /// Writes refreshed values to `sink` on a worker thread, in batches of
+    /// up to `max_batch`, so a slow durable store does not hold up lookups.
+    ///
+    /// Values are queued after each successful refresh, keeping the latest
+    /// per key, and written once `max_batch` keys are queued or `interval`
+    /// after the first one was. Failed batches are retried with backoff
+    /// starting at `interval`. Inserts are not written. Queued values are
+    /// written before [`shutdown`](TTRCache::shutdown) returns, and in the
+    /// background once the cache is dropped.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max_batch` is zero.
+    pub fn write_behind<W>(mut self, sink: W, max_batch: usize, interval: Duration) -> Self
+    where
+        K: Send + 'static,
+        V: Send + 'static,
+        W: WriteBehind<K, V> + Send + Sync + 'static,
+    {
+        assert!(
+            max_batch > 0,
+            "a write-behind batch needs to hold at least one value"
+        );
+        self.config.write_behind =
+            Some(WriteBehindQueue::spawn(Box::new(sink), max_batch, interval));
+        self
+    }
+
+    /// Shares invalidations with other instances of the cache through `bus`.
+    ///
+    /// Keys passed to [`invalidate`](TTRCache::invalidate) are published,
+    /// and keys the other instances publish are marked stale here as if
+    /// invalidated locally, without being published again. See
+    /// [`InvalidationBus`]. Only applies to [`TTRCache`].
+    pub fn invalidation_bus(
+        mut self,
+        bus: impl InvalidationBus<K> + Send + Sync + 'static,
+    ) -> TTRCacheBuilder<K, V, S, WithWorkers> {
+        self.config.invalidation_bus = Some(Box::new(bus));
+        self.with_workers()
+    }
+
+    /// Coordinates fetches with other processes through lock files in `dir`,
+    /// so that only one of them refreshes a key at a time.
+    ///
+    /// For tools that share an on-disk cache, such as CLI invocations
+    /// restoring the same [snapshot](TTRCache::snapshot). While another
+    /// process holds a key's lock, this one serves its cached value, stale or
+    /// not, instead of fetching; keys with nothing cached are fetched
+    /// regardless. Lock files are named by a hash of the key, so the
+    /// processes should run the same build, and one older than `lease` is
+    /// taken to be left behind by a process that died and is broken. If a
+    /// lock file cannot be created, such as when `dir` does not exist, the
+    /// fetch goes ahead uncoordinated. Batched refreshes are not coordinated.
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    /// use ttr_cache::{TTRCache, TTRCacheBuilder};
+    ///
+    /// let dir = std::env::temp_dir().join("ttr-cache-refresh-locks");
+    /// std::fs::create_dir_all(&dir).unwrap();
+    /// let cache: TTRCache<_, _, _> = TTRCacheBuilder::new(Duration::from_secs(60))
+    ///     .refresh_lock_dir(&dir, Duration::from_secs(30))
+    ///     .build(|id: &u32| Some(id * 10));
+    /// assert_eq!(cache.get(&1), Some(10));
+    /// ```
+    pub fn refresh_lock_dir(mut self, dir: impl Into<PathBuf>, lease: Duration) -> Self {
+        self.config.refresh_locks = Some(LockDir::new(dir.into(), lease));
+        self
+    }
+
+    /// Coalesces fetches with every cache built with a clone of `group`, so
+    /// a key missing from several of them is fetched once. See
+    /// [`FlightGroup`].
+    ///
+    /// The caches should fetch from the same source, since each stores what
+    /// the others fetched.
+    pub fn flight_group(mut self, group: FlightGroup<K, V>) -> Self {
+        self.config.flights = Some(group);
+        self
+    }
+
+    /// Splits the cache into `shards` independently locked maps.
+    ///
+    /// Keys are spread across shards by hash, so writes to keys in different
+    /// shards do not wait for each other. Reads only ever take a shared lock,
+    /// so sharding mostly helps write-heavy workloads. Capacity and weight
+    /// limits still apply to the cache as a whole, but evicting has to scan
+    /// every shard. Defaults to 1.
+    ///
+    /// This is the layout of concurrent maps such as `dashmap`, so one is
+    /// not needed for concurrent reads: with enough shards, a `get` and a
+    /// refresh only contend when their keys share a shard, and a refresh
+    /// only holds its shard's lock to store the fetched value, never while
+    /// fetching. Around the number of threads using the cache, rounded up to
+    /// a power of two, is a good start.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `shards` is zero.
+    pub fn shards(mut self, shards: usize) -> Self {
+        assert!(shards > 0, "a cache needs at least one shard");
+        self.config.shards = shards;
+        self
+    }
+
+    /// Stores each shard's entries in the map `new` returns, instead of a
+    /// `HashMap`.
+    ///
+    /// With a `BTreeMap`, [`range`](TTRCache::range) and the range
+    /// invalidations find the keys of a range without scanning every entry
+    /// or keeping [`ordered_keys`](Self::ordered_keys). See [`Storage`].
+    pub fn storage<B>(mut self, new: impl Fn() -> B + Send + Sync + 'static) -> Self
+    where
+        B: Storage<K, StoredEntry<V>> + Send + Sync + 'static,
+    {
+        self.config.storage = Some(Box::new(move || Box::new(new())));
+        self
+    }
+
+    /// Re-dates entries as `policy` says when the monotonic clock jumps ahead
+    /// of the wall clock by at least `threshold`, as it can across a suspend,
+    /// instead of letting every entry go stale or expire at once on resume.
+    ///
+    /// Lookups and [janitor](Self::janitor) runs compare how far the
+    /// [`Clock`]'s `now` and `wall_time` moved since the last one, so a cache
+    /// sitting unused, with both moving together, re-dates nothing. Setting
+    /// the wall clock back by `threshold` or more looks like a jump.
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    /// use ttr_cache::{CacheLookup, ClockJump, MockClock, TTRCache, TTRCacheBuilder};
+    ///
+    /// let clock = MockClock::new();
+    /// let cache: TTRCache<_, _, _> = TTRCacheBuilder::new(Duration::from_secs(60))
+    ///     .clock(clock.clone())
+    ///     .on_clock_jump(Duration::from_secs(600), ClockJump::Rearm)
+    ///     .build(|id: &u32| Some(id * 10));
+    ///
+    /// cache.get(&1);
+    /// clock.advance(Duration::from_secs(50));
+    /// cache.get(&1);
+    /// // Eight hours asleep.
+    /// clock.jump(Duration::from_secs(8 * 3600));
+    /// cache.get(&1);
+    /// assert_eq!(cache.stats().misses, 1);
+    /// let entry = cache.entry(&1).unwrap();
+    /// assert!(!entry.is_stale());
+    /// assert_eq!(entry.age(), Duration::from_secs(50));
+    ///
+    /// // Eight hours unused, awake.
+    /// clock.advance(Duration::from_secs(8 * 3600));
+    /// assert_eq!(cache.lookup(&1), CacheLookup::Stale(10));
+    /// let entry = cache.entry(&1).unwrap();
+    /// assert!(entry.is_stale());
+    /// assert_eq!(entry.age(), Duration::from_secs(8 * 3600 + 50));
+    /// ```
+    pub fn on_clock_jump(mut self, threshold: Duration, policy: ClockJump) -> Self {
+        self.config.clock_jump = Some((threshold, policy));
+        self
+    }
+
+    /// Chooses how entries are stored. Defaults to [`Engine::Sharded`].
+    ///
+    /// With [`Engine::SnapshotSwap`], a fresh value is served by `get`
+    /// from an immutable copy of the cache, without taking a lock; any other
+    /// lookup goes through the shards as usual. With the `snapshot-swap`
+    /// feature.
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    /// use ttr_cache::{Engine, TTRCache, TTRCacheBuilder};
+    ///
+    /// let cache: TTRCache<_, _, _> = TTRCacheBuilder::new(Duration::from_secs(60))
+    ///     .engine(Engine::SnapshotSwap)
+    ///     .build(|flag: &&str| Some(flag.len() > 3));
+    ///
+    /// assert_eq!(cache.get(&"beta"), Some(true));
+    /// assert_eq!(cache.get(&"beta"), Some(true));
+    /// cache.insert("beta", false);
+    /// assert_eq!(cache.get(&"beta"), Some(false));
+    /// assert_eq!(cache.stats().hits, 2);
+    /// assert_eq!(cache.key_stats(&"beta").unwrap().hits, 2);
+    /// ```
+    #[cfg(feature = "snapshot-swap")]
+    pub fn engine(mut self, engine: Engine) -> Self {
+        self.config.engine = engine;
+        self
+    }
+
+    /// Stores values encoded by `codec`, decoding them on every read.
+    ///
+    /// See [`ValueCodec`].
+    pub fn codec<C>(mut self, codec: C) -> Self
+    where
+        C: ValueCodec<V> + Send + Sync + 'static,
+    {
+        self.config.codec = Some(Box::new(codec));
+        self
+    }
+
+    /// Replaces the time source used to age entries.
+    ///
+    /// Mostly useful with [`MockClock`](crate::MockClock) in tests.
+    pub fn clock<C>(mut self, clock: C) -> Self
+    where
+        C: Clock + Send + Sync + 'static,
+    {
+        self.config.clock = Arc::new(clock);
+        self
+    }
+
+    /// Hashes keys with `hasher` instead of [`RandomState`].
+    ///
+    /// Like [`HashMap::with_hasher`](std::collections::HashMap::with_hasher),
+    /// this trades the default's resistance to hash flooding for speed when
+    /// keys are trusted, or the other way around.
+    pub fn hasher<H>(self, hasher: H) -> TTRCacheBuilder<K, V, H, M>
+    where
+        H: BuildHasher + Clone,
+    {
+        TTRCacheBuilder {
+            config: self.config,
+            refresh_mode: self.refresh_mode,
+            janitor: self.janitor,
+            sweep: self.sweep,
+            ahead_of_reads: self.ahead_of_reads,
+            refreshers: self.refreshers,
+            refresh_budget: self.refresh_budget,
+            refresh_batching: self.refresh_batching,
+            ambiguous_ttl: self.ambiguous_ttl,
+            invalid: self.invalid,
+            hasher,
+            workers: PhantomData,
+        }
+    }
+
+    /// Moves on to a builder that needs worker threads.
+    fn with_workers(self) -> TTRCacheBuilder<K, V, S, WithWorkers> {
+        TTRCacheBuilder {
+            config: self.config,
+            refresh_mode: self.refresh_mode,
+            janitor: self.janitor,
+            sweep: self.sweep,
+            ahead_of_reads: self.ahead_of_reads,
+            refreshers: self.refreshers,
+            refresh_budget: self.refresh_budget,
+            refresh_batching: self.refresh_batching,
+            ambiguous_ttl: self.ambiguous_ttl,
+            invalid: self.invalid,
+            hasher: self.hasher,
+            workers: PhantomData,
+        }
+    }
+
+    /// Records a setting as invalid, keeping the first reason given.
+    fn reject(&mut self, reason: &'static str) {
+        self.invalid.get_or_insert(reason);
+    }
+
+    fn validate(&self) -> Result<(), Error> {
+        if self.ambiguous_ttl {
+            return Err(Error::InvalidConfig(ZERO_TTL));
+        }
+        self.invalid
+            .map_or(Ok(()), |reason| Err(Error::InvalidConfig(reason)))
+    }
+
+    pub(crate) fn into_store(self) -> Store<K, V, S> {
+        if let Err(error) = self.validate() {
+            panic!("{error}");
+        }
+        Store::new(self.config, self.hasher)
+    }
+
+    /// [`build_background`](Self::build_background), or returns why the
+    /// configuration is invalid.
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    /// use ttr_cache::{Error, TTRCacheBuilder};
+    ///
+    /// let result = TTRCacheBuilder::<u32, u32>::new(Duration::from_secs(60))
+    ///     .fetch_timeout(Duration::ZERO)
+    ///     .try_build_background(|id: &u32| Some(*id));
+    /// assert!(matches!(result, Err(Error::InvalidConfig(_))));
+    /// ```
+    pub fn try_build_background<F>(self, fetcher: F) -> Result<TTRCache<K, V, F, S>, Error>
+    where
+        K: Send + Sync + 'static,
+        V: Send + Sync + 'static,
+        F: Send + Sync + 'static,
+        S: Send + Sync + 'static,
+    {
+        self.validate()?;
+        Ok(self.build_background(fetcher))
+    }
+
+    /// Builds a cache backed by `fetcher`, starting the worker threads its
+    /// settings need.
+    ///
+    /// Where threads are unavailable, such as `wasm32-unknown-unknown`,
+    /// settings that need a worker thread are ignored: stale entries are
+    /// refreshed on the calling thread, the cache is only purged when
+    /// [`purge`](TTRCache::purge) is called, and fetches are not timed out.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the configuration is invalid, such as with a zero TTR; see
+    /// [`try_build_background`](Self::try_build_background).
+    pub fn build_background<F>(self, fetcher: F) -> TTRCache<K, V, F, S>
+    where
+        K: Send + Sync + 'static,
+        V: Send + Sync + 'static,
+        F: Send + Sync + 'static,
+        S: Send + Sync + 'static,
+    {
+        let refresh_mode = self.refresh_mode;
+        let refreshes_ahead = self.config.refresh_ahead.is_some();
+        let periodic = self.config.refresh_strategy.is_some();
+        let janitor = self.janitor;
+        let sweep = self.sweep;
+        let ahead_of_reads = self.ahead_of_reads.zip(self.config.refresh_ahead);
+        let fetch_timeout = self.config.fetch_timeout;
+        let refreshers = self.refreshers;
+        let refresh_budget = self.refresh_budget;
+        let refresh_batching = self.refresh_batching;
+        let mut cache = TTRCache::with_store(self.into_store(), fetcher);
+        cache.subscribe_invalidations();
+        if !THREADS {
+            return cache;
+        }
+        if refresh_mode == RefreshMode::Background || refreshes_ahead {
+            cache.spawn_refreshers(refresh_mode, refreshers, refresh_budget, refresh_batching);
+        }
+        if let Some(interval) = janitor {
+            cache.spawn_janitor(interval);
+        }
+        if periodic {
+            cache.spawn_timer();
+        }
+        if let Some((window, slices)) = sweep {
+            cache.spawn_sweeper(window, slices);
+        }
+        if let Some((window, lead)) = ahead_of_reads {
+            cache.spawn_ahead_refresher(lead, window);
+        }
+        if let Some(timeout) = fetch_timeout {
+            cache.set_fetch_timeout(timeout);
+        }
+        cache
+    }
+
+    /// Builds a cache-aside cache, with no fetcher.
+    ///
+    /// The cache never loads values itself: callers insert them and use
+    /// [`lookup`](TTRCache::lookup) to learn whether a value is fresh, stale
+    /// or missing. A [`janitor`](Self::janitor) still runs if configured;
+    /// background refresh settings have no effect.
+    pub fn build_cache_aside(self) -> TTRCache<K, V, (), S>
+    where
+        K: Send + Sync + 'static,
+        V: Send + Sync + 'static,
+        S: Send + Sync + 'static,
+    {
+        let janitor = self.janitor;
+        let mut cache = TTRCache::with_store(self.into_store(), ());
+        cache.subscribe_invalidations();
+        if let Some(interval) = janitor.filter(|_| THREADS) {
+            cache.spawn_janitor(interval);
+        }
+        cache
+    }
+}
+
+impl<K, V, S> TTRCacheBuilder<K, V, S, NoWorkers>
+where
+    K: Eq + Hash + Clone,
+    V: Clone,
+    S: BuildHasher + Clone,
+{
+    /// Builds a cache backed by `fetcher`, or returns why the configuration
+    /// is invalid.
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    /// use ttr_cache::{Error, TTRCacheBuilder};
+    ///
+    /// let result = TTRCacheBuilder::<u32, u32>::new(Duration::ZERO)
+    ///     .try_build(|id: &u32| Some(*id));
+    /// assert!(matches!(result, Err(Error::InvalidConfig(_))));
+    /// ```
+    pub fn try_build<F>(self, fetcher: F) -> Result<TTRCache<K, V, F, S>, Error> {
+        self.validate()?;
+        Ok(self.build(fetcher))
+    }
+
+    /// Builds a cache backed by `fetcher` that does all its work on the
+    /// threads calling it.
+    ///
+    /// Neither the keys, the values nor the fetcher need to be shared with
+    /// other threads, so a fetcher can hold an `Rc`. Settings that need a
+    /// worker thread, such as [`RefreshMode::Background`], a
+    /// [`janitor`](Self::janitor) or a [`fetch_timeout`](Self::fetch_timeout),
+    /// need [`build_background`](Self::build_background) instead.
+    ///
+    /// ```rust
+    /// use std::cell::Cell;
+    /// use std::rc::Rc;
+    /// use std::time::Duration;
+    /// use ttr_cache::{TTRCache, TTRCacheBuilder};
+    ///
+    /// let calls = Rc::new(Cell::new(0));
+    /// let fetches = Rc::clone(&calls);
+    /// let cache: TTRCache<_, _, _> = TTRCacheBuilder::new(Duration::from_secs(60))
+    ///     .build(move |id: &u32| {
+    ///         fetches.set(fetches.get() + 1);
+    ///         Some(*id)
+    ///     });
+    ///
+    /// cache.get(&7);
+    /// cache.get(&7);
+    /// assert_eq!(calls.get(), 1);
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if the configuration is invalid, such as with a zero TTR; see
+    /// [`try_build`](Self::try_build).
+    pub fn build<F>(self, fetcher: F) -> TTRCache<K, V, F, S> {
+        TTRCache::with_store(self.into_store(), fetcher)
+    }
+
+    /// Builds an async cache backed by `fetcher`.
+    #[cfg(feature = "async")]
+    pub fn build_async<F>(self, fetcher: F) -> AsyncTTRCache<K, V, F, S>
+    where
+        F: AsyncEntityFetcher<K, V>,
+    {
+        AsyncTTRCache::with_store(self.into_store(), fetcher)
+    }
+
+    /// Builds an async cache backed by `fetcher` that refreshes stale
+    /// entries in tasks handed to `spawner`, serving the stale value in the
+    /// meantime.
+    ///
+    /// The cache spawns nothing by itself: each refresh is a [`RefreshTask`](crate::RefreshTask)
+    /// the spawner owns, on a runtime or in a task group of its choice. A
+    /// stale key has at most one task at a time, spawned by the read that
+    /// claims its refresh. Tasks still running when the cache is dropped finish at once without
+    /// refreshing, and [`running_refreshes`](AsyncTTRCache::running_refreshes)
+    /// counts the unfinished ones.
+    ///
+    /// ```rust
+    /// # use std::future::Future;
+    /// # use std::pin::pin;
+    /// # use std::task::{Context, Poll, Wake, Waker};
+    /// # struct Noop;
+    /// # impl Wake for Noop { fn wake(self: Arc<Self>) {} }
+    /// # fn block_on<F: Future>(future: F) -> F::Output {
+    /// #     let waker = Waker::from(Arc::new(Noop));
+    /// #     let mut cx = Context::from_waker(&waker);
+    /// #     let mut future = pin!(future);
+    /// #     loop {
+    /// #         if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+    /// #             return output;
+    /// #         }
+    /// #     }
+    /// # }
+    /// use std::sync::{Arc, Mutex};
+    /// use ttr_cache::{AsyncEntityFetcher, RefreshPolicy, RefreshTask, TTRCacheBuilder};
+    ///
+    /// struct Prices;
+    ///
+    /// impl AsyncEntityFetcher<&'static str, u32> for Prices {
+    ///     async fn fetch_entity(&self, _key: &&'static str) -> Option<u32> {
+    ///         Some(105)
+    ///     }
+    /// }
+    ///
+    /// // The tasks are the caller's to run, here one after another.
+    /// let tasks = Arc::new(Mutex::new(Vec::<RefreshTask>::new()));
+    /// let spawner = {
+    ///     let tasks = Arc::clone(&tasks);
+    ///     move |task| tasks.lock().unwrap().push(task)
+    /// };
+    /// let cache = TTRCacheBuilder::new(RefreshPolicy::Always).build_async_with_spawner(Prices, spawner);
+    /// cache.insert("ACME", 100);
+    ///
+    /// assert_eq!(block_on(cache.get(&"ACME")), Some(100));
+    /// assert_eq!(cache.running_refreshes(), 1);
+    /// for task in tasks.lock().unwrap().drain(..) {
+    ///     block_on(task);
+    /// }
+    /// assert_eq!(cache.running_refreshes(), 0);
+    /// assert_eq!(cache.peek(&"ACME"), Some(105));
+    ///
+    /// // Dropping the cache ends the refreshes it left behind.
+    /// cache.invalidate(&"ACME");
+    /// block_on(cache.get(&"ACME"));
+    /// drop(cache);
+    /// for task in tasks.lock().unwrap().drain(..) {
+    ///     block_on(task);
+    /// }
+    /// ```
+    #[cfg(feature = "async")]
+    pub fn build_async_with_spawner<F>(
+        self,
+        fetcher: F,
+        spawner: impl Spawner + 'static,
+    ) -> AsyncTTRCache<K, V, F, S>
+    where
+        K: Send + Sync + 'static,
+        V: Send + Sync + 'static,
+        F: AsyncEntityFetcher<K, V> + Send + Sync + 'static,
+        S: Send + Sync + 'static,
+    {
+        AsyncTTRCache::with_spawner(self.into_store(), fetcher, spawner)
+    }
+}
+
+impl<K, V, S, M> TTRCacheBuilder<NamespacedKey<K>, V, S, M> {
+    /// Gives entries in `namespace` their own TTR, instead of the
+    /// cache-wide one.
+    ///
+    /// A [`ttl_policy`](Self::ttl_policy) that returns a TTR still takes
+    /// precedence. See [`TTRCache::namespace`].
+    pub fn namespace_ttl(mut self, namespace: &str, ttl: impl Into<RefreshPolicy>) -> Self {
+        let refresh = ttl.into();
+        if refresh.is_ambiguous() {
+            self.invalid.get_or_insert(ZERO_TTL);
+        }
+        self.config
+            .namespace_ttls
+            .get_or_insert_with(|| NamespaceTtls {
+                namespace_of: NamespacedKey::namespace,
+                ttls: HashMap::new(),
+            })
+            .ttls
+            .insert(namespace.into(), refresh.ttl());
+        self
+    }
+}
+
+impl<K, V, S, M> TTRCacheBuilder<K, V, S, M>
+where
+    V: PartialEq,
+{
+    /// Keeps the cached value when a refresh returns an equal one.
+    ///
+    /// Only the entry's TTR restarts, as if the fetcher had reported the
+    /// value unchanged: references to it stay valid, and no refresh event,
+    /// listener call or watch update is sent. Explicit inserts still replace
+    /// the value.
+    ///
+    /// ```rust
+    /// use std::sync::Arc;
+    /// use std::time::Duration;
+    /// use ttr_cache::{EntityFetcher, RefreshPolicy, TTRCache, TTRCacheBuilder};
+    ///
+    /// struct Settings;
+    ///
+    /// impl EntityFetcher<&'static str, Arc<String>> for Settings {
+    ///     fn fetch_entity(&self, _key: &&'static str) -> Option<Arc<String>> {
+    ///         Some(Arc::new("dark".to_string()))
+    ///     }
+    /// }
+    ///
+    /// let cache: TTRCache<_, _, _> = TTRCacheBuilder::new(RefreshPolicy::Always)
+    ///     .dedupe_refreshes()
+    ///     .build(Settings);
+    ///
+    /// let first = cache.get(&"theme").unwrap();
+    /// let second = cache.get(&"theme").unwrap();
+    /// assert!(Arc::ptr_eq(&first, &second));
+    /// ```
+    pub fn dedupe_refreshes(mut self) -> Self {
+        self.config.dedupe_refreshes = Some(V::eq);
+        self
+    }
+
+    /// Adapts each entry's TTR to how often its value changes, between `min`
+    /// and `max`.
+    ///
+    /// A key's first value gets the TTR it would otherwise, brought within
+    /// the bounds. After that, each refresh that finds the value unchanged,
+    /// equal or reported not modified, doubles the key's TTR, and each one
+    /// that finds it changed halves it, so values that never change are
+    /// fetched rarely and ones that do are kept close to the source.
+    ///
+    /// ```rust
+    /// use std::sync::atomic::{AtomicU32, Ordering};
+    /// use std::sync::Arc;
+    /// use std::time::Duration;
+    /// use ttr_cache::{MockClock, TTRCache, TTRCacheBuilder};
+    ///
+    /// let version = Arc::new(AtomicU32::new(1));
+    /// let backend = Arc::clone(&version);
+    /// let clock = MockClock::new();
+    /// let cache: TTRCache<_, _, _> = TTRCacheBuilder::new(Duration::from_secs(60))
+    ///     .adaptive_ttl(Duration::from_secs(30), Duration::from_secs(240))
+    ///     .clock(clock.clone())
+    ///     .build(move |_: &&str| Some(backend.load(Ordering::SeqCst)));
+    /// let ttl = || cache.entry(&"flags").unwrap().ttl().as_secs();
+    ///
+    /// cache.get(&"flags");
+    /// for expected in [120, 240, 240] {
+    ///     clock.advance(Duration::from_secs(ttl()));
+    ///     cache.get(&"flags");
+    ///     assert_eq!(ttl(), expected);
+    /// }
+    ///
+    /// version.store(2, Ordering::SeqCst);
+    /// clock.advance(Duration::from_secs(ttl()));
+    /// assert_eq!(cache.get(&"flags"), Some(2));
+    /// assert_eq!(ttl(), 120);
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if `min` is zero or greater than `max`.
+    pub fn adaptive_ttl(mut self, min: Duration, max: Duration) -> Self {
+        assert!(
+            !min.is_zero() && min <= max,
+            "adaptive TTR bounds must be non-zero and in order, got {min:?} to {max:?}"
+        );
+        self.config.adaptive_ttl = Some(AdaptiveTtl {
+            eq: V::eq,
+            min,
+            max,
+        });
+        self
+    }
+}
+
+#[cfg(feature = "tracing")]
+impl<K, V, S, M> TTRCacheBuilder<K, V, S, M>
+where
+    K: std::fmt::Debug,
+{
+    /// Includes keys, formatted with `Debug`, in the cache's trace output.
+    ///
+    /// Without this, trace events and spans mark where a key goes but do
+    /// not show it, since keys may hold data that should not reach logs.
+    pub fn trace_keys(mut self) -> Self {
+        self.config.trace_keys = Some(<K as std::fmt::Debug>::fmt);
+        self
+    }
+}
+
+impl<K, T, S, M> TTRCacheBuilder<K, Arc<T>, S, M>
+where
+    T: Send + Sync + 'static,
+{
+    /// Holds cached values through [`Weak`] references, so a value is dropped
+    /// once no caller holds its `Arc` any more.
+    ///
+    /// Entries whose value was dropped are treated as missing and fetched
+    /// again on the next access. They keep counting towards the capacity
+    /// until then, or until the cache is purged. Values are not passed
+    /// through a [`codec`](Self::codec) in this mode.
+    ///
+    /// ```rust
+    /// use std::sync::Arc;
+    /// use std::time::Duration;
+    /// use ttr_cache::{EntityFetcher, TTRCache, TTRCacheBuilder};
+    ///
+    /// struct Backend;
+    ///
+    /// impl EntityFetcher<u32, Arc<String>> for Backend {
+    ///     fn fetch_entity(&self, key: &u32) -> Option<Arc<String>> {
+    ///         Some(Arc::new(key.to_string()))
+    ///     }
+    /// }
+    ///
+    /// let cache: TTRCache<_, _, _> = TTRCacheBuilder::new(Duration::from_secs(60))
+    ///     .weak_values()
+    ///     .build(Backend);
+    ///
+    /// let held = cache.get(&1).unwrap();
+    /// assert!(cache.entry(&1).is_some());
+    /// drop(held);
+    /// assert!(cache.entry(&1).is_none());
+    /// ```
+    pub fn weak_values(mut self) -> Self {
+        self.config.weak_values = Some(WeakValues {
+            downgrade: |value| Box::new(Arc::downgrade(value)),
+            upgrade: |weak| weak.downcast_ref::<Weak<T>>()?.upgrade(),
+        });
+        self
+    }
+}
@@ -0,0 +1,107 @@
+//! Invalidations shared between cache instances.
+
+use std::sync::Weak;
+
+/// Carries invalidations between instances of a cache, such as replicas of
+/// a service each holding their own copy.
+///
+/// A cache built with [`TTRCacheBuilder::invalidation_bus`](crate::TTRCacheBuilder::invalidation_bus)
+/// publishes every key passed to [`invalidate`](crate::TTRCache::invalidate)
+/// and subscribes once, marking keys the other instances publish stale.
+/// Implementations should not hand an instance back its own invalidations.
+/// With the `redis` feature, [`RedisInvalidationBus`](crate::RedisInvalidationBus)
+/// uses Redis pub/sub.
+///
+/// ```rust
+/// use std::sync::{Arc, Mutex};
+/// use std::time::Duration;
+/// use ttr_cache::{InvalidationBus, InvalidationSink, TTRCache, TTRCacheBuilder};
+///
+/// /// Connects caches in one process, skipping the publisher.
+/// #[derive(Clone, Default)]
+/// struct LocalBus(Arc<Mutex<Vec<InvalidationSink<u32>>>>, usize);
+///
+/// impl InvalidationBus<u32> for LocalBus {
+///     fn publish(&self, key: &u32) {
+///         for (i, sink) in self.0.lock().unwrap().iter().enumerate() {
+///             if i != self.1 {
+///                 sink.invalidate(key);
+///             }
+///         }
+///     }
+///
+///     fn subscribe(&self, sink: InvalidationSink<u32>) {
+///         self.0.lock().unwrap().push(sink);
+///     }
+/// }
+///
+/// let sinks = Arc::default();
+/// let build = |i| -> TTRCache<_, _, _> {
+///     TTRCacheBuilder::new(Duration::from_secs(60))
+///         .invalidation_bus(LocalBus(Arc::clone(&sinks), i))
+///         .build_background(|id: &u32| Some(id * 10))
+/// };
+/// let (first, second) = (build(0), build(1));
+/// first.get(&1);
+/// second.get(&1);
+///
+/// first.invalidate(&1);
+/// assert!(second.entry(&1).unwrap().is_stale());
+/// ```
+pub trait InvalidationBus<K> {
+    /// Tells the other instances that `key` was invalidated.
+    ///
+    /// Called on the thread that invalidated the key; failures should be
+    /// logged rather than retried at length.
+    fn publish(&self, key: &K);
+
+    /// Starts passing the keys other instances publish to `sink`.
+    ///
+    /// Called once, when the cache is built. Implementations that listen on
+    /// a thread of their own should stop once the sink is
+    /// [closed](InvalidationSink::is_closed).
+    fn subscribe(&self, sink: InvalidationSink<K>);
+}
+
+/// Something that can be told of a remote invalidation.
+pub(crate) trait Invalidate<K> {
+    fn invalidate_remote(&self, key: &K);
+}
+
+/// Where an [`InvalidationBus`] delivers the invalidations of other
+/// instances, marking the keys stale in one cache.
+///
+/// Does not keep the cache alive.
+pub struct InvalidationSink<K> {
+    cache: Weak<dyn Invalidate<K> + Send + Sync>,
+}
+
+impl<K> InvalidationSink<K> {
+    pub(crate) fn new(cache: Weak<dyn Invalidate<K> + Send + Sync>) -> Self {
+        InvalidationSink { cache }
+    }
+
+    /// Marks `key` stale without publishing it again.
+    ///
+    /// Returns `false` if the cache has been dropped.
+    pub fn invalidate(&self, key: &K) -> bool {
+        let Some(cache) = self.cache.upgrade() else {
+            return false;
+        };
+        cache.invalidate_remote(key);
+        true
+    }
+
+    /// Returns `true` once the cache has been dropped.
+    pub fn is_closed(&self) -> bool {
+        self.cache.strong_count() == 0
+    }
+}
+
+impl<K> Clone for InvalidationSink<K> {
+    fn clone(&self) -> Self {
+        InvalidationSink {
+            cache: Weak::clone(&self.cache),
+        }
+    }
+}
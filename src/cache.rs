@@ -0,0 +1,3369 @@
+//! Synchronous cache.
+
+use std::borrow::Borrow;
+use std::collections::hash_map::RandomState;
+use std::collections::{HashMap, HashSet};
+use std::convert::Infallible;
+use std::hash::{BuildHasher, Hash};
+use std::ops::RangeBounds;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, OnceLock, PoisonError};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+#[cfg(feature = "age-histogram")]
+use crate::ages::ServedAges;
+use crate::builder::TTRCacheBuilder;
+use crate::bus::{Invalidate, InvalidationSink};
+use crate::context::FetchContext;
+use crate::entry::{
+    CacheLookup, EntryInfo, EntryState, GetResult, HitFreshness, HotKey, KeyStats, ValueGuard,
+};
+use crate::error::{Error, FetchError};
+use crate::fetcher::{
+    BatchEntityFetcher, EntityFetcher, EntityWriter, FanOutFetcher, FetchOutcome, FetchResult,
+    TryEntityFetcher,
+};
+use crate::flight::{Claim, RefreshHandle, RefreshSender};
+use crate::listener::{LookupOutcome, LookupSample, RefreshEvent};
+use crate::policy::{Freshness, MissPolicy, Priority, RefreshMode, RefreshPolicy, TtlPolicy};
+use crate::queue::RefreshQueue;
+use crate::snapshot::{EntrySnapshot, Snapshot};
+use crate::stats::{CacheStats, StatsCounter};
+use crate::store::{BoxedLookupSampler, Generation, Lookup, Store};
+use crate::time::{Instant, SystemTime};
+use crate::warm::{KeyRefresh, RefreshReport, WarmReport};
+use crate::watch::Watch;
+
+/// State shared between the cache handle and its background workers.
+struct Shared<K, V, F, S> {
+    store: Store<K, V, S>,
+    fetcher: F,
+    queue: Mutex<Refreshes<K, V, F>>,
+    /// Signalled when a refresh is queued or the workers should stop.
+    queued: Condvar,
+    /// Set once the workers should stop picking up new work.
+    stopping: AtomicBool,
+    /// How periodic refreshes fetch, known from the first read through the
+    /// fetcher.
+    periodic_loader: OnceLock<Loader<K, V, F>>,
+}
+
+impl<K, V, F, S> Invalidate<K> for Shared<K, V, F, S>
+where
+    K: Eq + Hash + Clone,
+    V: Clone,
+    S: BuildHasher,
+{
+    fn invalidate_remote(&self, key: &K) {
+        self.store.invalidate(&self.store.canonical(key));
+    }
+}
+
+impl<K, V, F, S> Shared<K, V, F, S> {
+    fn queue(&self) -> MutexGuard<'_, Refreshes<K, V, F>> {
+        self.queue.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Tells the refresh workers to exit once their current refresh is done.
+    fn stop(&self) {
+        {
+            let _queue = self.queue();
+            self.stopping.store(true, Ordering::Relaxed);
+        }
+        self.queued.notify_all();
+        self.store.wake_timers();
+    }
+}
+
+impl<K, V, F, S> Shared<K, V, F, S>
+where
+    K: Eq + Hash + Clone,
+    V: Clone,
+    S: BuildHasher,
+{
+    /// Fetches a key with `fetch` and stores the result, unless the entry is
+    /// written, invalidated or removed in the meantime.
+    ///
+    /// When nothing is found, the entry is handled as configured with
+    /// `on_missing_refresh`.
+    fn load_with<E>(
+        &self,
+        key: &K,
+        fetch: impl FnOnce(&F, &K, &FetchContext) -> Result<FetchOutcome<V>, E>,
+    ) -> Result<Option<V>, E> {
+        self.load_at(key, self.store.generation(key), fetch)
+    }
+
+    /// Refreshes a key off the request path, the way reads have fetched so
+    /// far, unless nothing was read through the fetcher yet.
+    fn reload(&self, key: &K, generation: Generation) {
+        if let Some(&loader) = self.periodic_loader.get() {
+            let current = self.store.entry_info(key).map(EntryInfo::into_value);
+            let Ok(_) = self.load_at(key, generation, |fetcher, key, context| {
+                Ok::<_, Infallible>(loader(fetcher, key, current.as_ref(), context))
+            });
+        }
+    }
+
+    /// Fetches like [`load_with`](Self::load_with), storing the result only
+    /// if the entry is still at `generation`.
+    fn load_at<E>(
+        &self,
+        key: &K,
+        generation: Generation,
+        fetch: impl FnOnce(&F, &K, &FetchContext) -> Result<FetchOutcome<V>, E>,
+    ) -> Result<Option<V>, E> {
+        let Ok(_lock) = self.store.lock_fetch(key) else {
+            #[cfg(feature = "tracing")]
+            tracing::debug!(key = ?self.store.trace_key(key), "fetched by another process");
+            return Ok(self.store.get(key));
+        };
+        if !self.store.admit_fetch(key) {
+            #[cfg(feature = "tracing")]
+            tracing::debug!(key = ?self.store.trace_key(key), "fetch skipped");
+            return Ok(self.store.get(key));
+        }
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!("fetch", key = ?self.store.trace_key(key)).entered();
+        let context = self.store.fetch_context(key);
+        let permit = self.store.fetch_permit();
+        let started = Instant::now();
+        let result = panic::catch_unwind(AssertUnwindSafe(|| fetch(&self.fetcher, key, &context)));
+        drop(permit);
+        // A panicking fetcher fails the fetch, not the caller.
+        let Ok(result) = result else {
+            self.store.record_fetch(started.elapsed(), false);
+            self.store.fetch_panicked(key);
+            return Ok(self.store.get(key));
+        };
+        let rejected = matches!(&result, Ok(FetchOutcome::Modified(entity)) if self.store.rejects(key, entity));
+        let succeeded = !rejected
+            && matches!(
+                result,
+                Ok(FetchOutcome::Modified(_) | FetchOutcome::NotModified)
+            );
+        let latency = started.elapsed();
+        #[cfg(feature = "tracing")]
+        tracing::debug!(elapsed = ?latency, succeeded, "fetched");
+        self.store.record_fetch(latency, succeeded);
+        if !succeeded {
+            self.store.fetch_failed(key);
+        }
+        if rejected {
+            return Ok(self.store.get(key));
+        }
+        // The key was removed or the cache shut down during the fetch.
+        if result.is_ok() && context.was_cancelled() {
+            return Ok(self.store.get(key));
+        }
+
+        match result? {
+            FetchOutcome::Modified(entity) => {
+                let fetched_at = self.store.now();
+                let stored = self.store.refresh(
+                    key.clone(),
+                    fetched_at,
+                    generation,
+                    entity.clone(),
+                    Some(latency),
+                );
+                #[cfg(feature = "tracing")]
+                if stored.is_none() {
+                    tracing::debug!("discarded, entry changed during fetch");
+                }
+                Ok(Some(stored.unwrap_or(entity)))
+            }
+            FetchOutcome::NotModified => Ok(self
+                .store
+                .renew(key, generation)
+                .or_else(|| self.store.get(key))),
+            FetchOutcome::NotFound => Ok(self.store.refresh_missing(key, generation)),
+        }
+    }
+
+    /// Fetches `keys` in one call with `batch` and stores what was found,
+    /// returning what is left to serve for each key.
+    ///
+    /// Keys whose fetched value was rejected are left out, as are all of them
+    /// if the fetcher panicked. Keys returned without being asked for are
+    /// inserted.
+    fn load_batch(
+        &self,
+        keys: &[K],
+        batch: impl FnOnce(&F, &[K]) -> HashMap<K, V>,
+    ) -> HashMap<K, Option<V>> {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!("fetch_batch", keys = keys.len()).entered();
+        let generations: HashMap<&K, Generation> = keys
+            .iter()
+            .map(|key| (key, self.store.generation(key)))
+            .collect();
+        let permit = self.store.fetch_permit();
+        let started = Instant::now();
+        let fetched = panic::catch_unwind(AssertUnwindSafe(|| batch(&self.fetcher, keys)));
+        drop(permit);
+        let Ok(fetched) = fetched else {
+            self.store.record_fetch(started.elapsed(), false);
+            for key in keys {
+                self.store.fetch_panicked(key);
+            }
+            return HashMap::new();
+        };
+        let latency = started.elapsed();
+        #[cfg(feature = "tracing")]
+        tracing::debug!(elapsed = ?latency, found = fetched.len(), "fetched");
+        self.store.record_fetch(latency, !fetched.is_empty());
+        let fetched_at = self.store.now();
+
+        let mut loaded = HashMap::with_capacity(keys.len());
+        for key in keys {
+            if !fetched.contains_key(key) {
+                self.store.fetch_failed(key);
+                let entity = self.store.refresh_missing(key, generations[key]);
+                loaded.insert(key.clone(), entity);
+            }
+        }
+        for (key, entity) in fetched {
+            if self.store.rejects(&key, &entity) {
+                self.store.fetch_failed(&key);
+                continue;
+            }
+            let entity = match generations.get(&key) {
+                Some(generation) => self
+                    .store
+                    .refresh(
+                        key.clone(),
+                        fetched_at,
+                        *generation,
+                        entity.clone(),
+                        Some(latency),
+                    )
+                    .unwrap_or(entity),
+                None => {
+                    self.store.insert(key.clone(), fetched_at, entity.clone());
+                    entity
+                }
+            };
+            loaded.insert(key, Some(entity));
+        }
+        loaded
+    }
+
+    /// Refreshes a key taken off the queue, handing the result to its
+    /// waiters.
+    fn run_refresh(&self, key: K, loader: Loader<K, V, F>, waiters: Vec<RefreshSender<V>>) {
+        // Keys removed since they were queued are not brought back.
+        let generation = self.store.generation(&key);
+        let current = self.store.entry_info(&key).map(EntryInfo::into_value);
+        let mut refreshed = None;
+        if generation.is_cached() {
+            let Ok(entity) = self.load_at(&key, generation, |fetcher, key, context| {
+                Ok::<_, Infallible>(loader(fetcher, key, current.as_ref(), context))
+            });
+            refreshed = entity;
+        }
+        for waiter in waiters {
+            waiter.complete(refreshed.clone());
+        }
+        self.finish_refresh(&key);
+    }
+
+    /// Waits out the batching window, then refreshes `key` together with up
+    /// to `max` keys queued for a batch in the meantime, in one fetch.
+    fn run_batch(
+        &self,
+        key: K,
+        waiters: Vec<RefreshSender<V>>,
+        batch: BatchLoader<K, V, F>,
+        (window, max): (Duration, usize),
+    ) {
+        thread::sleep(window);
+        let mut refreshes = vec![(key, waiters)];
+        {
+            let mut queue = self.queue();
+            while refreshes.len() < max && queue.exhausted().is_none() {
+                let Some((key, _, waiters)) = queue.pop_if(|load| load.batch.is_some()) else {
+                    break;
+                };
+                refreshes.push((key, waiters));
+            }
+        }
+
+        // Keys removed since they were queued are not brought back.
+        let keys: Vec<K> = refreshes
+            .iter()
+            .map(|(key, _)| key.clone())
+            .filter(|key| self.store.generation(key).is_cached() && self.store.admit_fetch(key))
+            .collect();
+        let mut loaded = if keys.is_empty() {
+            HashMap::new()
+        } else {
+            self.load_batch(&keys, batch)
+        };
+        for (key, waiters) in refreshes {
+            let refreshed = loaded.remove(&key).unwrap_or_else(|| self.store.get(&key));
+            for waiter in waiters {
+                waiter.complete(refreshed.clone());
+            }
+            self.finish_refresh(&key);
+        }
+    }
+
+    /// Marks a queued refresh done, waking a worker if it was queued again.
+    fn finish_refresh(&self, key: &K) {
+        if self.queue().finish(key) {
+            self.queued.notify_one();
+        }
+    }
+
+    /// Loads a key that has nothing to serve, joining a fetch already in
+    /// flight instead of starting another.
+    ///
+    /// If the fetch being waited on fails, this caller fetches for itself.
+    fn load_missing<E>(
+        &self,
+        key: &K,
+        fetch: impl FnOnce(&F, &K, &FetchContext) -> Result<FetchOutcome<V>, E>,
+    ) -> Result<Option<V>, E> {
+        let generation = self.store.generation(key);
+        let guard = loop {
+            match self.store.claim(key) {
+                Claim::Leader(guard) => break guard,
+                Claim::Follower(flight) => {
+                    if let Some(entity) = flight.wait() {
+                        if let Some(entity) = &entity {
+                            self.store.adopt(key, generation, &flight, entity);
+                        }
+                        return Ok(entity);
+                    }
+                }
+            }
+        };
+
+        let entity = self.load_with(key, fetch)?;
+        guard.complete(entity.clone());
+        Ok(entity)
+    }
+
+    /// Fetches a key now, waiting for any fetch of it already in flight to
+    /// finish first so the result is no older than this call.
+    fn load_now<E>(
+        &self,
+        key: &K,
+        fetch: impl FnOnce(&F, &K, &FetchContext) -> Result<FetchOutcome<V>, E>,
+    ) -> Result<Option<V>, E> {
+        let guard = loop {
+            match self.store.claim(key) {
+                Claim::Leader(guard) => break guard,
+                Claim::Follower(flight) => {
+                    flight.wait();
+                }
+            }
+        };
+
+        let entity = self.load_with(key, fetch)?;
+        guard.complete(entity.clone());
+        Ok(entity)
+    }
+
+    /// Blocks until a refresh is queued and takes it.
+    ///
+    /// Returns `None` once the workers should stop.
+    fn next_refresh(&self) -> Option<Refresh<K, V, F>> {
+        let mut queue = self.queue();
+        loop {
+            if self.stopping.load(Ordering::Relaxed) {
+                return None;
+            }
+            if let Some(renewed_in) = queue.exhausted() {
+                queue = self
+                    .queued
+                    .wait_timeout(queue, renewed_in)
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .0;
+                continue;
+            }
+            if let Some(refresh) = queue.pop() {
+                return Some(refresh);
+            }
+            queue = self
+                .queued
+                .wait(queue)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+    }
+
+    /// Refreshes a stale key, or serves `stale` if another caller already is.
+    fn load_stale<E>(
+        &self,
+        key: &K,
+        stale: &V,
+        fetch: impl FnOnce(&F, &K, &FetchContext) -> Result<FetchOutcome<V>, E>,
+    ) -> Result<Option<V>, E> {
+        match self.store.claim(key) {
+            Claim::Leader(guard) => {
+                let entity = self.load_with(key, fetch)?;
+                guard.complete(entity.clone());
+                Ok(entity)
+            }
+            Claim::Follower(_) => Ok(Some(stale.clone())),
+        }
+    }
+}
+
+/// Cache that refreshes stale entries while serving them.
+///
+/// All methods take `&self`, so the cache can be shared between threads via
+/// `Arc` without an outer lock. Lookups return owned clones of the cached
+/// values, so no lock is held once they return and values can be kept across
+/// awaits. For values that are expensive to clone, cache an `Arc<T>`: each
+/// lookup then only bumps a reference count.
+///
+/// Types:
+/// - `K`: Key type (must be `Eq + Hash`)
+/// - `V`: Value type (must be `Clone`; consider `Arc<T>` for large values)
+/// - `F`: Fetcher implementing `EntityFetcher<K, V>`
+/// - `S`: Hasher for keys, [`RandomState`] by default
+///
+/// ```rust
+/// use std::sync::Arc;
+/// use std::thread;
+/// use std::time::Duration;
+/// use ttr_cache::{EntityFetcher, TTRCache};
+///
+/// struct Doubler;
+///
+/// impl EntityFetcher<u64, u64> for Doubler {
+///     fn fetch_entity(&self, key: &u64) -> Option<u64> {
+///         Some(key * 2)
+///     }
+/// }
+///
+/// let cache = Arc::new(TTRCache::new(Duration::from_secs(60), Doubler));
+/// let handles: Vec<_> = (0..4)
+///     .map(|i| {
+///         let cache = Arc::clone(&cache);
+///         thread::spawn(move || cache.get(&i))
+///     })
+///     .collect();
+///
+/// for (i, handle) in handles.into_iter().enumerate() {
+///     assert_eq!(handle.join().unwrap(), Some(i as u64 * 2));
+/// }
+/// ```
+///
+/// # Global caches
+///
+/// The cache is `Send + Sync` whenever its keys, values and fetcher are, so
+/// it can live in a `static` and be initialized on first use. Fetchers
+/// written as closures cannot be named in a `static`'s type; use a fetcher
+/// type or a function pointer instead.
+///
+/// ```rust
+/// use std::sync::LazyLock;
+/// use std::time::Duration;
+/// use ttr_cache::TTRCache;
+///
+/// fn lookup_host(name: &String) -> Option<String> {
+///     Some(format!("{name}.internal"))
+/// }
+///
+/// type Fetch = fn(&String) -> Option<String>;
+///
+/// static HOSTS: LazyLock<TTRCache<String, String, Fetch>> =
+///     LazyLock::new(|| TTRCache::new(Duration::from_secs(300), lookup_host));
+///
+/// assert_eq!(HOSTS.get("db").as_deref(), Some("db.internal"));
+/// ```
+///
+/// When the cache is configured at runtime, for example from command-line
+/// arguments, a `OnceLock` set during startup works the same way:
+///
+/// ```rust
+/// use std::sync::OnceLock;
+/// use std::time::Duration;
+/// use ttr_cache::{TTRCache, TTRCacheBuilder};
+///
+/// static PRICES: OnceLock<TTRCache<u32, u64, fn(&u32) -> Option<u64>>> = OnceLock::new();
+///
+/// fn prices() -> &'static TTRCache<u32, u64, fn(&u32) -> Option<u64>> {
+///     PRICES.get().expect("prices cache used before startup")
+/// }
+///
+/// let ttl = Duration::from_secs(30);
+/// PRICES.get_or_init(|| TTRCacheBuilder::new(ttl).build(|id: &u32| Some(u64::from(*id) * 100)));
+/// assert_eq!(prices().get(&3), Some(300));
+/// ```
+pub struct TTRCache<K, V, F, S = RandomState>
+where
+    K: Eq + Hash,
+{
+    shared: Arc<Shared<K, V, F, S>>,
+    workers: Mutex<Workers>,
+    refresh_mode: RefreshMode,
+    timed_fetch: Option<TimedFetch<K, V, F>>,
+}
+
+/// Runs a loader on its own thread, giving up on it after the fetch timeout.
+///
+/// Built where the thread-safety bounds are known, so `get` does not need
+/// them. Returns `None` on timeout.
+type TimedFetch<K, V, F> = Box<
+    dyn Fn(&K, Option<&V>, &FetchContext, Loader<K, V, F>) -> Option<FetchOutcome<V>> + Send + Sync,
+>;
+
+/// A fetch took longer than the fetch timeout.
+struct TimedOut;
+
+/// Background threads and what keeps them running.
+///
+/// Dropping the janitor's, sweeper's or ahead refresher's sender stops it;
+/// the refresh workers and the timer thread are stopped through
+/// [`Shared::stop`].
+struct Workers {
+    /// Whether refresh workers are running.
+    refreshing: bool,
+    janitor: Option<Sender<()>>,
+    sweeper: Option<Sender<()>>,
+    ahead: Option<Sender<()>>,
+    handles: Vec<JoinHandle<()>>,
+}
+
+/// Whether the target can spawn threads; browsers cannot.
+pub(crate) const THREADS: bool = !cfg!(all(target_arch = "wasm32", target_os = "unknown"));
+
+/// How the background workers should fetch a key.
+///
+/// Passed along with each key so the workers can serve both `get` and
+/// `try_get` without knowing which fetcher trait `F` implements. The workers
+/// hand over the cached value, if any, for conditional fetches.
+type Loader<K, V, F> = fn(&F, &K, Option<&V>, &FetchContext) -> FetchOutcome<V>;
+
+/// How the background workers should fetch many keys in one call.
+type BatchLoader<K, V, F> = fn(&F, &[K]) -> HashMap<K, V>;
+
+/// How to fetch a queued key: on its own, and in a batch if the fetcher is
+/// a [`BatchEntityFetcher`].
+struct Load<K, V, F> {
+    single: Loader<K, V, F>,
+    batch: Option<BatchLoader<K, V, F>>,
+}
+
+impl<K, V, F> Load<K, V, F> {
+    fn single(loader: Loader<K, V, F>) -> Self {
+        Load {
+            single: loader,
+            batch: None,
+        }
+    }
+}
+
+impl<K, V, F> Clone for Load<K, V, F> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<K, V, F> Copy for Load<K, V, F> {}
+
+/// Queued background refreshes, with the handles waiting on them.
+type Refreshes<K, V, F> = RefreshQueue<K, Load<K, V, F>, RefreshSender<V>>;
+
+/// A refresh taken off the queue: the key, how to load it and its waiters.
+type Refresh<K, V, F> = (K, Load<K, V, F>, Vec<RefreshSender<V>>);
+
+fn fetch_loader<K, V, F: EntityFetcher<K, V>>(
+    fetcher: &F,
+    key: &K,
+    current: Option<&V>,
+    context: &FetchContext,
+) -> FetchOutcome<V> {
+    fetcher.fetch_with_context(key, current, context)
+}
+
+fn batch_loader<K, V, F: BatchEntityFetcher<K, V>>(
+    fetcher: &F,
+    key: &K,
+    _current: Option<&V>,
+    _context: &FetchContext,
+) -> FetchOutcome<V>
+where
+    K: Eq + Hash + Clone,
+{
+    fetcher
+        .fetch_entities(std::slice::from_ref(key))
+        .remove(key)
+        .into()
+}
+
+fn batch_fetch<K, V, F: BatchEntityFetcher<K, V>>(fetcher: &F, keys: &[K]) -> HashMap<K, V> {
+    fetcher.fetch_entities(keys)
+}
+
+fn fan_out_loader<K, V, F: FanOutFetcher<K, V>>(
+    fetcher: &F,
+    key: &K,
+    _current: Option<&V>,
+    _context: &FetchContext,
+) -> FetchOutcome<V> {
+    fetcher.fetch_entity_multi(key).entity.into()
+}
+
+/// Fetches each key on its own, returning the siblings alongside so the
+/// batch stores them too.
+fn fan_out_batch<K, V, F: FanOutFetcher<K, V>>(fetcher: &F, keys: &[K]) -> HashMap<K, V>
+where
+    K: Eq + Hash + Clone,
+{
+    let mut found = HashMap::new();
+    let mut entities = Vec::with_capacity(keys.len());
+    for key in keys {
+        let FetchResult { entity, siblings } = fetcher.fetch_entity_multi(key);
+        found.extend(siblings);
+        entities.push((key, entity));
+    }
+    // What was asked for wins over what came along.
+    for (key, entity) in entities {
+        match entity {
+            Some(entity) => found.insert(key.clone(), entity),
+            None => found.remove(key),
+        };
+    }
+    found
+}
+
+fn try_fetch_loader<K, V, E, F: TryEntityFetcher<K, V, E>>(
+    fetcher: &F,
+    key: &K,
+    current: Option<&V>,
+    context: &FetchContext,
+) -> FetchOutcome<V> {
+    fetcher
+        .try_fetch_with_context(key, current, context)
+        .unwrap_or(FetchOutcome::NotFound)
+}
+
+impl<K, V, F> TTRCache<K, V, F>
+where
+    K: Eq + Hash + Clone,
+    V: Clone,
+{
+    /// Creates a new cache with given TTL and fetcher.
+    pub fn new(ttl: impl Into<RefreshPolicy>, fetcher: F) -> Self {
+        Self::with_store(TTRCacheBuilder::new(ttl).into_store(), fetcher)
+    }
+
+    /// Creates a new cache whose per-entry TTR is decided by `policy`.
+    ///
+    /// `ttl` is used for entries the policy has no opinion on.
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    /// use ttr_cache::{EntityFetcher, TTRCache};
+    ///
+    /// struct Rates;
+    ///
+    /// impl EntityFetcher<String, f64> for Rates {
+    ///     fn fetch_entity(&self, _key: &String) -> Option<f64> {
+    ///         Some(1.08)
+    ///     }
+    /// }
+    ///
+    /// let cache = TTRCache::with_ttl_policy(
+    ///     Duration::from_secs(300),
+    ///     Rates,
+    ///     |key: &String, _: &f64| key.starts_with("fx:").then(|| Duration::from_secs(5)),
+    /// );
+    /// assert_eq!(cache.get(&"fx:EURUSD".to_string()), Some(1.08));
+    /// ```
+    pub fn with_ttl_policy<P>(ttl: impl Into<RefreshPolicy>, fetcher: F, policy: P) -> Self
+    where
+        P: TtlPolicy<K, V> + Send + Sync + 'static,
+    {
+        let store = TTRCacheBuilder::new(ttl).ttl_policy(policy).into_store();
+        Self::with_store(store, fetcher)
+    }
+
+    /// Creates a new cache holding at most `max_entries`, evicting the least
+    /// recently used.
+    pub fn with_capacity(ttl: impl Into<RefreshPolicy>, fetcher: F, max_entries: usize) -> Self {
+        let store = TTRCacheBuilder::new(ttl)
+            .max_capacity(max_entries)
+            .into_store();
+        Self::with_store(store, fetcher)
+    }
+}
+
+impl<K, V, F, S> TTRCache<K, V, F, S>
+where
+    K: Eq + Hash + Clone,
+    V: Clone,
+    S: BuildHasher,
+{
+    /// Creates a new cache with given TTL and fetcher, hashing keys with
+    /// `hasher`.
+    ///
+    /// See [`TTRCacheBuilder::hasher`].
+    ///
+    /// ```rust
+    /// use std::collections::hash_map::DefaultHasher;
+    /// use std::hash::BuildHasherDefault;
+    /// use std::time::Duration;
+    /// use ttr_cache::{EntityFetcher, TTRCache};
+    ///
+    /// struct Lengths;
+    ///
+    /// impl EntityFetcher<String, usize> for Lengths {
+    ///     fn fetch_entity(&self, key: &String) -> Option<usize> {
+    ///         Some(key.len())
+    ///     }
+    /// }
+    ///
+    /// let cache = TTRCache::with_hasher(
+    ///     Duration::from_secs(60),
+    ///     Lengths,
+    ///     BuildHasherDefault::<DefaultHasher>::default(),
+    /// );
+    /// assert_eq!(cache.get(&"four".to_string()), Some(4));
+    /// ```
+    pub fn with_hasher(ttl: impl Into<RefreshPolicy>, fetcher: F, hasher: S) -> Self
+    where
+        S: Clone,
+    {
+        Self::with_store(
+            TTRCacheBuilder::new(ttl).hasher(hasher).into_store(),
+            fetcher,
+        )
+    }
+
+    pub(crate) fn with_store(store: Store<K, V, S>, fetcher: F) -> Self {
+        TTRCache {
+            shared: Arc::new(Shared {
+                store,
+                fetcher,
+                queue: Mutex::new(RefreshQueue::new()),
+                queued: Condvar::new(),
+                stopping: AtomicBool::new(false),
+                periodic_loader: OnceLock::new(),
+            }),
+            workers: Mutex::new(Workers {
+                refreshing: false,
+                janitor: None,
+                sweeper: None,
+                ahead: None,
+                handles: Vec::new(),
+            }),
+            refresh_mode: RefreshMode::Blocking,
+            timed_fetch: None,
+        }
+    }
+
+    /// Makes `get` give up on fetches that take longer than `timeout`.
+    pub(crate) fn set_fetch_timeout(&mut self, timeout: Duration)
+    where
+        K: Send + Sync + 'static,
+        V: Send + Sync + 'static,
+        F: Send + Sync + 'static,
+        S: Send + Sync + 'static,
+    {
+        let shared = Arc::clone(&self.shared);
+        self.timed_fetch = Some(Box::new(move |key, current, context, loader| {
+            let (sender, receiver) = mpsc::channel();
+            let shared = Arc::clone(&shared);
+            let (key, current, fetching) = (key.clone(), current.cloned(), context.clone());
+            thread::spawn(move || {
+                let _ = sender.send(loader(&shared.fetcher, &key, current.as_ref(), &fetching));
+            });
+            let outcome = receiver.recv_timeout(timeout).ok();
+            if outcome.is_none() {
+                context.cancel();
+            }
+            outcome
+        }));
+    }
+
+    /// Starts `count` worker threads that serve background and early
+    /// refreshes, most requested keys first.
+    ///
+    /// Stale entries are only handed to them in [`RefreshMode::Background`].
+    /// With a `budget`, at most that many refreshes start per interval.
+    pub(crate) fn spawn_refreshers(
+        &mut self,
+        refresh_mode: RefreshMode,
+        count: usize,
+        budget: Option<(usize, Duration)>,
+        batching: Option<(Duration, usize)>,
+    ) where
+        K: Send + Sync + 'static,
+        V: Send + Sync + 'static,
+        F: Send + Sync + 'static,
+        S: Send + Sync + 'static,
+    {
+        let workers = self
+            .workers
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner);
+        if let Some((max, per)) = budget {
+            self.shared.queue().set_budget(max, per);
+        }
+        for _ in 0..count {
+            let shared = Arc::clone(&self.shared);
+            workers.handles.push(thread::spawn(move || {
+                while let Some((key, load, waiters)) = shared.next_refresh() {
+                    match (load.batch, batching) {
+                        (Some(batch), Some(batching)) => {
+                            shared.run_batch(key, waiters, batch, batching);
+                        }
+                        _ => shared.run_refresh(key, load.single, waiters),
+                    }
+                }
+            }));
+        }
+        workers.refreshing = true;
+        self.refresh_mode = refresh_mode;
+    }
+
+    /// Starts the worker thread that purges the cache every `interval`.
+    pub(crate) fn spawn_janitor(&mut self, interval: Duration)
+    where
+        K: Send + Sync + 'static,
+        V: Send + Sync + 'static,
+        F: Send + Sync + 'static,
+        S: Send + Sync + 'static,
+    {
+        let (janitor, stop) = mpsc::channel::<()>();
+
+        let shared = Arc::clone(&self.shared);
+        let handle = thread::spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = stop.recv_timeout(interval) {
+                shared.store.purge();
+            }
+        });
+
+        let workers = self
+            .workers
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner);
+        workers.janitor = Some(janitor);
+        workers.handles.push(handle);
+    }
+
+    /// Starts the thread that refreshes every cached key once per `window`,
+    /// a slice of the keys at a time.
+    pub(crate) fn spawn_sweeper(&mut self, window: Duration, slices: u32)
+    where
+        K: Send + Sync + 'static,
+        V: Send + Sync + 'static,
+        F: Send + Sync + 'static,
+        S: Send + Sync + 'static,
+    {
+        let (sweeper, stop) = mpsc::channel::<()>();
+
+        let shared = Arc::clone(&self.shared);
+        let handle = thread::spawn(move || {
+            let mut slice = 0;
+            while let Err(RecvTimeoutError::Timeout) = stop.recv_timeout(window / slices) {
+                for key in shared.store.keys_in_slice(slice, slices) {
+                    shared.store.expire(&key);
+                    let generation = shared.store.generation(&key);
+                    if generation.is_cached() {
+                        shared.reload(&key, generation);
+                    }
+                }
+                slice = (slice + 1) % slices;
+            }
+        });
+
+        let workers = self
+            .workers
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner);
+        workers.sweeper = Some(sweeper);
+        workers.handles.push(handle);
+    }
+
+    /// Starts the thread that refreshes entries read within `window` as
+    /// they come within `lead` of going stale, checking every half lead.
+    pub(crate) fn spawn_ahead_refresher(&mut self, lead: Duration, window: Duration)
+    where
+        K: Send + Sync + 'static,
+        V: Send + Sync + 'static,
+        F: Send + Sync + 'static,
+        S: Send + Sync + 'static,
+    {
+        let (ahead, stop) = mpsc::channel::<()>();
+
+        let shared = Arc::clone(&self.shared);
+        let handle = thread::spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = stop.recv_timeout(lead / 2) {
+                for key in shared.store.keys_expiring_read_within(lead, window) {
+                    let generation = shared.store.generation(&key);
+                    if generation.is_cached() {
+                        shared.reload(&key, generation);
+                    }
+                }
+            }
+        });
+
+        let workers = self
+            .workers
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner);
+        workers.ahead = Some(ahead);
+        workers.handles.push(handle);
+    }
+
+    /// Subscribes to the invalidation bus, marking keys other instances
+    /// invalidate stale here.
+    pub(crate) fn subscribe_invalidations(&self)
+    where
+        K: Send + Sync + 'static,
+        V: Send + Sync + 'static,
+        F: Send + Sync + 'static,
+        S: Send + Sync + 'static,
+    {
+        if let Some(bus) = self.shared.store.invalidation_bus() {
+            let shared: Arc<dyn Invalidate<K> + Send + Sync> = self.shared.clone();
+            bus.subscribe(InvalidationSink::new(Arc::downgrade(&shared)));
+        }
+    }
+
+    /// Starts the thread that turns the scheduled refresh timers and
+    /// refreshes the keys that come due, sleeping in between.
+    pub(crate) fn spawn_timer(&mut self)
+    where
+        K: Send + Sync + 'static,
+        V: Send + Sync + 'static,
+        F: Send + Sync + 'static,
+        S: Send + Sync + 'static,
+    {
+        let shared = Arc::clone(&self.shared);
+        let handle = thread::spawn(move || {
+            let stopped = || shared.stopping.load(Ordering::Relaxed);
+            while let Some(due) = shared.store.wait_periodic(stopped) {
+                for key in due {
+                    shared.store.expire(&key);
+                    let generation = shared.store.generation(&key);
+                    // Keys removed since they were scheduled are dropped.
+                    if !generation.is_cached() {
+                        continue;
+                    }
+                    // Keys replaced while their timer ran are set a new one.
+                    if !shared.store.is_periodic_due(&key) {
+                        shared.store.schedule_periodic(&key);
+                        continue;
+                    }
+                    shared.reload(&key, generation);
+                    // A successful refresh already set the next timer.
+                    shared.store.schedule_periodic(&key);
+                }
+            }
+        });
+
+        let workers = self
+            .workers
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner);
+        workers.handles.push(handle);
+    }
+
+    /// Hands a stale key to the workers in background refresh mode.
+    ///
+    /// Returns `false` if the cache refreshes on the calling thread instead.
+    fn refresh_in_background(&self, key: &K, load: Load<K, V, F>, priority: Priority) -> bool {
+        self.refresh_mode == RefreshMode::Background && self.schedule(key, load, priority)
+    }
+
+    /// Queues a key for the workers, or moves it up if already queued.
+    ///
+    /// Returns `false` if there are no workers.
+    fn schedule(&self, key: &K, load: Load<K, V, F>, priority: Priority) -> bool {
+        self.enqueue(key, load, None, priority).is_ok()
+    }
+
+    /// Queues a key like [`schedule`](Self::schedule), handing `waiter` the
+    /// result of its refresh.
+    ///
+    /// Gives the waiter back if there are no workers.
+    fn enqueue(
+        &self,
+        key: &K,
+        load: Load<K, V, F>,
+        waiter: Option<RefreshSender<V>>,
+        priority: Priority,
+    ) -> Result<(), Option<RefreshSender<V>>> {
+        let workers = self.workers.lock().unwrap_or_else(PoisonError::into_inner);
+        if !workers.refreshing {
+            return Err(waiter);
+        }
+
+        if self.shared.queue().push(key, load, waiter, priority) {
+            #[cfg(feature = "tracing")]
+            tracing::debug!(key = ?self.shared.store.trace_key(key), "refresh scheduled");
+            self.shared.queued.notify_one();
+        }
+        Ok(())
+    }
+
+    /// Gets a value through a fallible fetcher, refreshing if stale.
+    ///
+    /// Unlike [`get`](Self::get), a failed fetch is reported instead of being
+    /// treated as "not found". The error carries the stale value, if one is
+    /// still cached, so callers can decide whether to serve it.
+    ///
+    /// In background refresh mode stale values are returned immediately, as
+    /// with `get`, and only fetches on the calling thread can report errors.
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    /// use ttr_cache::{TTRCache, TryEntityFetcher};
+    ///
+    /// struct Flaky;
+    ///
+    /// impl TryEntityFetcher<u64, String, &'static str> for Flaky {
+    ///     fn try_fetch_entity(&self, key: &u64) -> Result<Option<String>, &'static str> {
+    ///         match key {
+    ///             0 => Err("connection reset"),
+    ///             _ => Ok(Some(key.to_string())),
+    ///         }
+    ///     }
+    /// }
+    ///
+    /// let cache = TTRCache::new(Duration::from_secs(60), Flaky);
+    /// assert_eq!(cache.try_get(&7), Ok(Some("7".to_string())));
+    ///
+    /// let err = cache.try_get(&0).unwrap_err();
+    /// assert_eq!(err.error, "connection reset");
+    /// assert_eq!(err.stale, None);
+    /// ```
+    pub fn try_get<E>(&self, key: &K) -> Result<Option<V>, FetchError<V, E>>
+    where
+        F: TryEntityFetcher<K, V, E>,
+    {
+        let key = &*self.shared.store.canonical(key);
+        let fetch = |fetcher: &F, key: &K, context: &FetchContext| {
+            fetcher.try_fetch_with_context(key, None, context)
+        };
+        self.shared
+            .periodic_loader
+            .get_or_init(|| try_fetch_loader::<K, V, E, F>);
+        match self.shared.store.lookup(key) {
+            Lookup::Fresh(entity) | Lookup::Retrying(entity) => Ok(Some(entity)),
+            Lookup::Absent => Ok(None),
+            Lookup::Expiring(entity) => {
+                self.schedule(
+                    key,
+                    Load::single(try_fetch_loader::<K, V, E, F>),
+                    Priority::Normal,
+                );
+                Ok(Some(entity))
+            }
+            Lookup::Stale(entity)
+                if self.refresh_in_background(
+                    key,
+                    Load::single(try_fetch_loader::<K, V, E, F>),
+                    Priority::Normal,
+                ) =>
+            {
+                Ok(Some(entity))
+            }
+            Lookup::Stale(entity) => self
+                .shared
+                .load_stale(key, &entity, |fetcher, key, context| {
+                    fetcher.try_fetch_with_context(key, Some(&entity), context)
+                })
+                .map_err(|error| FetchError {
+                    error,
+                    stale: Some(entity),
+                }),
+            Lookup::Revalidate(entity) => self
+                .shared
+                .load_missing(key, |fetcher, key, context| {
+                    fetcher.try_fetch_with_context(key, Some(&entity), context)
+                })
+                .map_err(|error| FetchError {
+                    error,
+                    stale: Some(entity),
+                }),
+            Lookup::Missing if !self.shared.store.fetches_on_miss(key) => Ok(None),
+            Lookup::Missing => self
+                .shared
+                .load_missing(key, fetch)
+                .map_err(|error| FetchError { error, stale: None }),
+        }
+    }
+
+    /// Gets a value through a fallible fetcher like [`try_get`](Self::try_get),
+    /// saying why there is none, so callers can answer a missing key
+    /// differently from an unavailable backend.
+    ///
+    /// A stale value is a [`Hit`](GetResult::Hit) even if refreshing it
+    /// failed, since it is still served; see
+    /// [`entry_state`](Self::entry_state) for the failures.
+    /// [`FetchFailed`](GetResult::FetchFailed) means there was nothing to
+    /// serve.
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    /// use ttr_cache::{GetResult, HitFreshness, MockClock, TTRCache, TTRCacheBuilder, TryEntityFetcher};
+    ///
+    /// struct Accounts;
+    ///
+    /// impl TryEntityFetcher<u64, String, &'static str> for Accounts {
+    ///     fn try_fetch_entity(&self, id: &u64) -> Result<Option<String>, &'static str> {
+    ///         match id {
+    ///             0 => Err("database unavailable"),
+    ///             1 => Ok(Some("ada".to_string())),
+    ///             _ => Ok(None),
+    ///         }
+    ///     }
+    /// }
+    ///
+    /// let clock = MockClock::new();
+    /// let cache: TTRCache<_, _, _> = TTRCacheBuilder::new(Duration::from_secs(60))
+    ///     .max_stale(Duration::from_secs(60))
+    ///     .clock(clock.clone())
+    ///     .build(Accounts);
+    ///
+    /// let hit = GetResult::Hit { value: "ada".to_string(), freshness: HitFreshness::Fresh };
+    /// assert_eq!(cache.get_detailed(&1), hit);
+    /// assert_eq!(cache.get_detailed(&2), GetResult::Missing);
+    /// assert_eq!(cache.get_detailed(&0), GetResult::FetchFailed("database unavailable"));
+    ///
+    /// cache.insert(3, "grace".to_string());
+    /// clock.advance(Duration::from_secs(150));
+    /// assert_eq!(cache.get_detailed(&3), GetResult::Expired);
+    /// ```
+    pub fn get_detailed<E>(&self, key: &K) -> GetResult<V, E>
+    where
+        F: TryEntityFetcher<K, V, E>,
+    {
+        let key = &*self.shared.store.canonical(key);
+        let expired = self.shared.store.has_expired(key);
+        match self.try_get(key) {
+            Ok(Some(value)) => GetResult::Hit {
+                value,
+                freshness: self.shared.store.freshness(key),
+            },
+            Ok(None) if expired => GetResult::Expired,
+            Ok(None) => GetResult::Missing,
+            Err(FetchError {
+                stale: Some(value), ..
+            }) => GetResult::Hit {
+                value,
+                freshness: HitFreshness::Stale,
+            },
+            Err(FetchError { error, stale: None }) => GetResult::FetchFailed(error),
+        }
+    }
+
+    /// Gets many values, refreshing stale and missing keys in one batch.
+    ///
+    /// Keys that were not found are left out of the returned map. Stale
+    /// values are kept when the batch does not return a replacement. In
+    /// background refresh mode stale keys are handed to the workers instead,
+    /// and only missing keys are fetched on the calling thread.
+    ///
+    /// With a [key normalizer](TTRCacheBuilder::key_normalizer) the map is
+    /// keyed by the keys as given, and each logical key is fetched once.
+    ///
+    /// ```rust
+    /// use std::collections::HashMap;
+    /// use std::time::Duration;
+    /// use ttr_cache::{BatchEntityFetcher, TTRCache};
+    ///
+    /// struct Users;
+    ///
+    /// impl BatchEntityFetcher<u32, String> for Users {
+    ///     fn fetch_entities(&self, keys: &[u32]) -> HashMap<u32, String> {
+    ///         keys.iter()
+    ///             .filter(|id| **id != 0)
+    ///             .map(|id| (*id, format!("user-{id}")))
+    ///             .collect()
+    ///     }
+    /// }
+    ///
+    /// let cache = TTRCache::new(Duration::from_secs(60), Users);
+    /// let users = cache.get_many(&[0, 1, 2]);
+    /// assert_eq!(users.len(), 2);
+    /// assert_eq!(users[&2], "user-2");
+    /// ```
+    pub fn get_many(&self, keys: &[K]) -> HashMap<K, V>
+    where
+        F: BatchEntityFetcher<K, V>,
+    {
+        if !self.shared.store.normalizes_keys() {
+            return self.get_many_canonical(keys, batch_loader::<K, V, F>, batch_fetch::<K, V, F>);
+        }
+        let canonical: Vec<K> = keys
+            .iter()
+            .map(|key| self.shared.store.canonical(key).into_owned())
+            .collect();
+        let mut seen = HashSet::with_capacity(canonical.len());
+        let unique: Vec<K> = canonical
+            .iter()
+            .filter(|key| seen.insert(*key))
+            .cloned()
+            .collect();
+        let found =
+            self.get_many_canonical(&unique, batch_loader::<K, V, F>, batch_fetch::<K, V, F>);
+        keys.iter()
+            .zip(&canonical)
+            .filter_map(|(key, canonical)| Some((key.clone(), found.get(canonical)?.clone())))
+            .collect()
+    }
+
+    /// Gets a value, caching the other entities fetched with it.
+    ///
+    /// Looks `key` up like [`get_many`](Self::get_many) would a single key,
+    /// but a stale or missing value is fetched with
+    /// [`fetch_entity_multi`](FanOutFetcher::fetch_entity_multi) and the
+    /// siblings it returns are inserted as if fetched themselves, replacing
+    /// what was cached for them. Background refreshes only bring the
+    /// siblings in when [refresh batching](TTRCacheBuilder::refresh_batching)
+    /// is on.
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    /// use ttr_cache::{FanOutFetcher, FetchResult, TTRCache};
+    ///
+    /// /// Users are listed a team at a time.
+    /// struct Teams;
+    ///
+    /// impl FanOutFetcher<u32, String> for Teams {
+    ///     fn fetch_entity_multi(&self, id: &u32) -> FetchResult<u32, String> {
+    ///         let team = id / 10 * 10;
+    ///         FetchResult {
+    ///             entity: Some(format!("user-{id}")),
+    ///             siblings: (team..team + 10).map(|id| (id, format!("user-{id}"))).collect(),
+    ///         }
+    ///     }
+    /// }
+    ///
+    /// let cache = TTRCache::new(Duration::from_secs(60), Teams);
+    /// assert_eq!(cache.get_with_siblings(&12).as_deref(), Some("user-12"));
+    /// assert_eq!(cache.len(), 10);
+    /// assert!(cache.entry(&17).is_some_and(|entry| !entry.is_stale()));
+    /// ```
+    pub fn get_with_siblings(&self, key: &K) -> Option<V>
+    where
+        F: FanOutFetcher<K, V>,
+    {
+        let key = &*self.shared.store.canonical(key);
+        self.get_many_canonical(
+            std::slice::from_ref(key),
+            fan_out_loader::<K, V, F>,
+            fan_out_batch::<K, V, F>,
+        )
+        .remove(key)
+    }
+
+    /// [`get_many`](Self::get_many) for keys already normalized, loading
+    /// them with `single` and `batch`.
+    fn get_many_canonical(
+        &self,
+        keys: &[K],
+        single: Loader<K, V, F>,
+        batch: BatchLoader<K, V, F>,
+    ) -> HashMap<K, V> {
+        let mut found = HashMap::with_capacity(keys.len());
+        let mut to_fetch = Vec::new();
+        let batch_load = Load {
+            single,
+            batch: Some(batch),
+        };
+
+        for key in keys {
+            match self.shared.store.lookup(key) {
+                Lookup::Fresh(entity) | Lookup::Retrying(entity) => {
+                    found.insert(key.clone(), entity);
+                }
+                Lookup::Expiring(entity) => {
+                    self.schedule(key, batch_load, Priority::Normal);
+                    found.insert(key.clone(), entity);
+                }
+                Lookup::Stale(entity) => {
+                    if !self.refresh_in_background(key, batch_load, Priority::Normal) {
+                        to_fetch.push(key.clone());
+                    }
+                    found.insert(key.clone(), entity);
+                }
+                Lookup::Revalidate(entity) => {
+                    to_fetch.push(key.clone());
+                    found.insert(key.clone(), entity);
+                }
+                Lookup::Absent => {}
+                Lookup::Missing if !self.shared.store.fetches_on_miss(key) => {}
+                Lookup::Missing => to_fetch.push(key.clone()),
+            }
+        }
+
+        to_fetch.retain(|key| self.shared.store.admit_fetch(key));
+        if !to_fetch.is_empty() {
+            for (key, entity) in self.shared.load_batch(&to_fetch, batch) {
+                match entity {
+                    Some(entity) => found.insert(key, entity),
+                    None => found.remove(&key),
+                };
+            }
+        }
+
+        found
+    }
+
+    /// Gets a value, computing it with `init` instead of the fetcher if the
+    /// entry is stale or missing.
+    ///
+    /// A value returned by `init` is stored like a fetched one, so its TTR
+    /// starts now. If `init` returns `None`, the stale value is kept and
+    /// returned.
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    /// use ttr_cache::{EntityFetcher, TTRCache};
+    ///
+    /// struct Nothing;
+    ///
+    /// impl EntityFetcher<&'static str, u32> for Nothing {
+    ///     fn fetch_entity(&self, _key: &&'static str) -> Option<u32> {
+    ///         None
+    ///     }
+    /// }
+    ///
+    /// let cache = TTRCache::new(Duration::from_secs(60), Nothing);
+    /// assert_eq!(cache.get_or_insert_with("one-off", || Some(7)), Some(7));
+    /// assert_eq!(cache.get(&"one-off"), Some(7));
+    /// ```
+    pub fn get_or_insert_with(&self, key: K, init: impl FnOnce() -> Option<V>) -> Option<V> {
+        let key = self.shared.store.canonical_owned(key);
+        let fetch = |_: &F, _: &K, _: &FetchContext| Ok::<_, Infallible>(init().into());
+        let result = match self.shared.store.lookup(&key) {
+            Lookup::Fresh(entity) | Lookup::Expiring(entity) | Lookup::Retrying(entity) => {
+                return Some(entity)
+            }
+            Lookup::Stale(entity) => self.shared.load_stale(&key, &entity, fetch),
+            Lookup::Revalidate(_) | Lookup::Absent | Lookup::Missing => {
+                self.shared.load_missing(&key, fetch)
+            }
+        };
+
+        let Ok(entity) = result;
+        entity
+    }
+
+    /// Returns a cached value with its age and staleness, without fetching.
+    ///
+    /// Does not count as a use for eviction or in the stats.
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    /// use ttr_cache::{EntityFetcher, TTRCache};
+    ///
+    /// struct Prices;
+    ///
+    /// impl EntityFetcher<&'static str, u32> for Prices {
+    ///     fn fetch_entity(&self, _key: &&'static str) -> Option<u32> {
+    ///         Some(100)
+    ///     }
+    /// }
+    ///
+    /// let cache = TTRCache::new(Duration::from_secs(60), Prices);
+    /// assert!(cache.entry(&"widget").is_none());
+    ///
+    /// cache.get(&"widget");
+    /// let entry = cache.entry(&"widget").unwrap();
+    /// assert_eq!(*entry.value(), 100);
+    /// assert!(!entry.is_stale());
+    ///
+    /// cache.invalidate(&"widget");
+    /// assert!(cache.entry(&"widget").unwrap().is_stale());
+    /// ```
+    pub fn entry(&self, key: &K) -> Option<EntryInfo<V>> {
+        self.shared
+            .store
+            .entry_info(&self.shared.store.canonical(key))
+    }
+
+    /// Returns the cached value, stale or not, without ever fetching.
+    ///
+    /// For paths that must not wait on the backend, such as metrics or
+    /// best-effort rendering. Does not count as a use for eviction or in the
+    /// stats; [`entry`](Self::entry) returns the value's metadata too.
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    /// use ttr_cache::TTRCache;
+    ///
+    /// let cache = TTRCache::new(Duration::from_secs(60), |id: &u32| Some(id * 10));
+    /// assert_eq!(cache.peek(&1), None);
+    ///
+    /// cache.get(&1);
+    /// cache.invalidate(&1);
+    /// assert_eq!(cache.peek(&1), Some(10));
+    /// assert_eq!(cache.stats().misses, 1);
+    /// ```
+    pub fn peek(&self, key: &K) -> Option<V> {
+        self.entry(key).map(EntryInfo::into_value)
+    }
+
+    /// Time left before `key` goes stale, zero if it already is, or `None`
+    /// if it is not cached.
+    ///
+    /// Does not fetch, and does not count as a use for eviction or in the
+    /// stats.
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    /// use ttr_cache::{MockClock, TTRCache, TTRCacheBuilder};
+    ///
+    /// let clock = MockClock::new();
+    /// let cache: TTRCache<_, _, ()> = TTRCacheBuilder::new(Duration::from_secs(60))
+    ///     .clock(clock.clone())
+    ///     .build_cache_aside();
+    /// cache.insert("rates", 1.1);
+    ///
+    /// clock.advance(Duration::from_secs(45));
+    /// assert_eq!(cache.time_to_refresh(&"rates"), Some(Duration::from_secs(15)));
+    /// assert_eq!(cache.time_since_refresh(&"rates"), Some(Duration::from_secs(45)));
+    ///
+    /// clock.advance(Duration::from_secs(30));
+    /// assert_eq!(cache.time_to_refresh(&"rates"), Some(Duration::ZERO));
+    /// assert_eq!(cache.time_to_refresh(&"fees"), None);
+    /// ```
+    pub fn time_to_refresh(&self, key: &K) -> Option<Duration> {
+        let (_, left) = self
+            .shared
+            .store
+            .refresh_timing(&self.shared.store.canonical(key))?;
+        Some(left)
+    }
+
+    /// Time since `key` was last fetched or inserted, or `None` if it is not
+    /// cached.
+    ///
+    /// See [`time_to_refresh`](Self::time_to_refresh).
+    pub fn time_since_refresh(&self, key: &K) -> Option<Duration> {
+        let (age, _) = self
+            .shared
+            .store
+            .refresh_timing(&self.shared.store.canonical(key))?;
+        Some(age)
+    }
+
+    /// Returns the last value of `key` that passed validation, even if a
+    /// newer value did not, or `None` unless the cache was built with
+    /// [`keep_last_known_good`](TTRCacheBuilder::keep_last_known_good).
+    ///
+    /// Does not fetch, and is not counted in the stats.
+    pub fn get_last_known_good(&self, key: &K) -> Option<V> {
+        self.shared
+            .store
+            .last_known_good(&self.shared.store.canonical(key))
+    }
+
+    pub(crate) fn store(&self) -> &Store<K, V, S> {
+        &self.shared.store
+    }
+
+    /// Returns the `n` most read keys, most read first, with their hit
+    /// counts and ages.
+    ///
+    /// Hits are reads served from the cache, counted per key since it was
+    /// first cached; refreshes keep the count and removal resets it.
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    /// use ttr_cache::TTRCache;
+    ///
+    /// let cache = TTRCache::new(Duration::from_secs(60), |id: &u64| Some(id * 2));
+    /// for id in [1, 2, 2, 3, 3, 3] {
+    ///     cache.get(&id);
+    /// }
+    ///
+    /// let hot: Vec<_> = cache.top_keys(2).into_iter().map(|hot| (hot.key, hot.hits)).collect();
+    /// assert_eq!(hot, vec![(3, 2), (2, 1)]);
+    /// ```
+    pub fn top_keys(&self, n: usize) -> Vec<HotKey<K>> {
+        self.shared.store.top_keys(n)
+    }
+
+    /// How often `key` was read and how stale, or `None` if it is not
+    /// cached.
+    ///
+    /// For tuning TTRs: a key often read well past its TTR may want a longer
+    /// one, or a refresh ahead. Counted like the [hot keys](Self::top_keys),
+    /// and included in the [dump](Self::dump).
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    /// use ttr_cache::{MockClock, TTRCache, TTRCacheBuilder};
+    ///
+    /// let clock = MockClock::new();
+    /// let cache: TTRCache<_, _, _> = TTRCacheBuilder::new(Duration::from_secs(60))
+    ///     .clock(clock.clone())
+    ///     .build(|_: &&str| Some(101));
+    /// cache.get(&"quote");
+    /// cache.get(&"quote");
+    /// clock.advance(Duration::from_secs(90));
+    /// cache.get(&"quote");
+    ///
+    /// let stats = cache.key_stats(&"quote").unwrap();
+    /// assert_eq!((stats.hits, stats.stale_hits), (2, 1));
+    /// assert_eq!(stats.max_staleness, Duration::from_secs(30));
+    /// ```
+    pub fn key_stats(&self, key: &K) -> Option<KeyStats> {
+        self.shared
+            .store
+            .key_stats(&self.shared.store.canonical(key))
+    }
+
+    /// Where a key is in its refresh cycle: fresh, stale, being refreshed,
+    /// or failing to refresh.
+    ///
+    /// For orchestration around the cache, such as skipping keys that are
+    /// already refreshing or alerting on keys that keep failing. A refresh
+    /// queued for a background worker counts as refreshing. Failures are
+    /// counted until a refresh succeeds or the key is written or
+    /// invalidated. Not a use of the key.
+    ///
+    /// Returns `None` if the key is not cached.
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    /// use ttr_cache::{EntryState, MockClock, TTRCache, TTRCacheBuilder};
+    ///
+    /// let clock = MockClock::new();
+    /// let cache: TTRCache<_, _, _> = TTRCacheBuilder::new(Duration::from_secs(60))
+    ///     .clock(clock.clone())
+    ///     .build(|_: &&str| None::<u32>);
+    /// cache.insert("rate", 3);
+    /// assert_eq!(cache.entry_state(&"rate"), Some(EntryState::Fresh));
+    ///
+    /// clock.advance(Duration::from_secs(90));
+    /// assert_eq!(cache.entry_state(&"rate"), Some(EntryState::Stale));
+    /// cache.get(&"rate");
+    /// cache.get(&"rate");
+    /// assert!(matches!(
+    ///     cache.entry_state(&"rate"),
+    ///     Some(EntryState::Failed { attempts: 2, .. })
+    /// ));
+    /// assert_eq!(cache.entry_state(&"other"), None);
+    /// ```
+    pub fn entry_state(&self, key: &K) -> Option<EntryState> {
+        let key = &*self.shared.store.canonical(key);
+        let queued = self.shared.queue().contains(key);
+        self.shared.store.entry_state(key, queued)
+    }
+
+    /// Looks up a key without ever fetching it.
+    ///
+    /// For cache-aside use, where the caller loads and
+    /// [`insert`](Self::insert)s values itself and only asks the cache
+    /// whether they are still fresh. Counts towards [`stats`](Self::stats)
+    /// like [`get`](Self::get). See
+    /// [`TTRCacheBuilder::build_cache_aside`](crate::TTRCacheBuilder::build_cache_aside)
+    /// for a cache without a fetcher.
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    /// use ttr_cache::{CacheLookup, MockClock, TTRCache, TTRCacheBuilder};
+    ///
+    /// let clock = MockClock::new();
+    /// let cache: TTRCache<_, _, ()> = TTRCacheBuilder::new(Duration::from_secs(60))
+    ///     .clock(clock.clone())
+    ///     .build_cache_aside();
+    ///
+    /// assert_eq!(cache.lookup(&"user:1"), CacheLookup::Missing);
+    /// cache.insert("user:1", "Ada");
+    /// assert_eq!(cache.lookup(&"user:1"), CacheLookup::Fresh("Ada"));
+    ///
+    /// clock.advance(Duration::from_secs(90));
+    /// assert_eq!(cache.lookup(&"user:1"), CacheLookup::Stale("Ada"));
+    /// ```
+    pub fn lookup(&self, key: &K) -> CacheLookup<V> {
+        self.shared
+            .store
+            .lookup(&self.shared.store.canonical(key))
+            .into()
+    }
+
+    /// Subscribes to changes of `key`'s value.
+    ///
+    /// The receiver gets a [`RefreshEvent`] whenever a refresh or insert
+    /// replaces the cached value with one that differs from it. Refreshes
+    /// that return an equal value send nothing. The subscription ends when
+    /// the receiver is dropped.
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    /// use ttr_cache::{EntityFetcher, RefreshEvent, TTRCache};
+    ///
+    /// struct Nothing;
+    ///
+    /// impl EntityFetcher<&'static str, u64> for Nothing {
+    ///     fn fetch_entity(&self, _key: &&'static str) -> Option<u64> {
+    ///         None
+    ///     }
+    /// }
+    ///
+    /// let cache = TTRCache::new(Duration::from_secs(60), Nothing);
+    /// cache.insert("price", 10);
+    /// let changes = cache.subscribe(&"price");
+    ///
+    /// cache.insert("price", 10);
+    /// cache.insert("price", 12);
+    /// assert_eq!(
+    ///     changes.try_recv(),
+    ///     Ok(RefreshEvent { key: "price", previous: 10, value: 12 })
+    /// );
+    /// assert!(changes.try_recv().is_err());
+    /// ```
+    pub fn subscribe(&self, key: &K) -> Receiver<RefreshEvent<K, V>>
+    where
+        V: PartialEq,
+    {
+        self.shared
+            .store
+            .subscribe(self.shared.store.canonical(key).into_owned())
+    }
+
+    /// Watches `key`'s value, yielding the cached value and then every
+    /// value a refresh or insert changes it to.
+    ///
+    /// Unlike [`subscribe`](Self::subscribe), a [`Watch`] is awaited and
+    /// only keeps the latest value, for async consumers of a sync cache. It
+    /// does not fetch: for a key that is not cached yet the first
+    /// [`changed`](Watch::changed) waits for someone to load it.
+    pub fn watch(&self, key: &K) -> Watch<V>
+    where
+        V: PartialEq,
+    {
+        self.shared
+            .store
+            .watch(self.shared.store.canonical(key).into_owned())
+    }
+
+    /// Watches `key`'s value through a `tokio` watch channel, for use with
+    /// `tokio::select!` and the rest of the tokio ecosystem.
+    ///
+    /// Like [`watch`](Self::watch), the channel starts with the cached value,
+    /// if any, and sees every value a refresh or insert changes it to. It
+    /// closes once the cache is dropped. With the `tokio` feature.
+    #[cfg(feature = "tokio")]
+    pub fn watch_channel(&self, key: &K) -> tokio::sync::watch::Receiver<Option<V>>
+    where
+        V: PartialEq,
+    {
+        self.shared
+            .store
+            .watch_channel(self.shared.store.canonical(key).into_owned())
+    }
+
+    /// Iterates over a snapshot of the cached entries.
+    ///
+    /// Stale entries are included as they are; nothing is fetched, and
+    /// entries past the hard expiry are skipped. Iterating does not count as
+    /// a use for eviction or in the stats.
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    /// use ttr_cache::{EntityFetcher, TTRCache};
+    ///
+    /// struct Squares;
+    ///
+    /// impl EntityFetcher<u32, u32> for Squares {
+    ///     fn fetch_entity(&self, key: &u32) -> Option<u32> {
+    ///         Some(key * key)
+    ///     }
+    /// }
+    ///
+    /// let cache = TTRCache::new(Duration::from_secs(60), Squares);
+    /// cache.get(&2);
+    /// cache.get(&3);
+    ///
+    /// let mut entries: Vec<_> = cache.iter().collect();
+    /// entries.sort();
+    /// assert_eq!(entries, vec![(2, 4), (3, 9)]);
+    /// assert_eq!(cache.len(), 2);
+    /// assert_eq!(cache.stats().refreshes, 2);
+    /// ```
+    pub fn iter(&self) -> impl Iterator<Item = (K, V)> {
+        self.shared.store.entries().into_iter()
+    }
+
+    /// Iterates over a snapshot of the cached keys.
+    ///
+    /// See [`iter`](Self::iter).
+    pub fn keys(&self) -> impl Iterator<Item = K> {
+        self.iter().map(|(key, _)| key)
+    }
+
+    /// The cached keys whose value maps to `value` by the index over `I`
+    /// registered with [`secondary_index`](TTRCacheBuilder::secondary_index),
+    /// in no particular order.
+    ///
+    /// Empty if no index over `I` was registered. Does not count as a use of
+    /// the entries, and values past their TTR are not refreshed.
+    pub fn get_by_index<I>(&self, value: &I) -> Vec<K>
+    where
+        K: 'static,
+        V: 'static,
+        I: Eq + Hash + 'static,
+    {
+        self.shared.store.keys_by_index(value)
+    }
+
+    /// Iterates over a snapshot of the entries whose keys are within
+    /// `range`, in key order.
+    ///
+    /// Like [`iter`](Self::iter), this does not count as a use of the
+    /// entries and does not refresh them. The keys are found through the key
+    /// order kept with [`ordered_keys`](TTRCacheBuilder::ordered_keys), or by
+    /// scanning every entry without it.
+    pub fn range<R>(&self, range: R) -> impl Iterator<Item = (K, V)>
+    where
+        K: Ord + 'static,
+        R: RangeBounds<K>,
+    {
+        let store = &self.shared.store;
+        store.entries_of(store.keys_in_range(&range)).into_iter()
+    }
+
+    /// Marks every entry whose key is within `range` stale, like
+    /// [`invalidate`](Self::invalidate). Returns how many were marked.
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    /// use ttr_cache::{TTRCache, TTRCacheBuilder};
+    ///
+    /// let samples: TTRCache<(&str, u32), f64, ()> = TTRCacheBuilder::new(Duration::from_secs(60))
+    ///     .ordered_keys()
+    ///     .build_cache_aside();
+    /// for bucket in 0..4 {
+    ///     samples.insert(("cpu", bucket), f64::from(bucket));
+    ///     samples.insert(("mem", bucket), 0.5);
+    /// }
+    ///
+    /// assert_eq!(samples.invalidate_range(("cpu", 0)..=("cpu", 1)), 2);
+    /// assert!(samples.entry(&("cpu", 1)).unwrap().is_stale());
+    /// assert!(!samples.entry(&("cpu", 2)).unwrap().is_stale());
+    ///
+    /// assert_eq!(samples.remove_range(("mem", 0)..), 4);
+    /// assert_eq!(samples.len(), 4);
+    /// ```
+    pub fn invalidate_range<R>(&self, range: R) -> usize
+    where
+        K: Ord + 'static,
+        R: RangeBounds<K>,
+    {
+        self.shared
+            .store
+            .keys_in_range(&range)
+            .iter()
+            .filter(|key| self.invalidate(key))
+            .count()
+    }
+
+    /// Removes every entry whose key is within `range`, like
+    /// [`remove`](Self::remove). Returns how many were removed.
+    ///
+    /// See [`invalidate_range`](Self::invalidate_range).
+    pub fn remove_range<R>(&self, range: R) -> usize
+    where
+        K: Ord + 'static,
+        R: RangeBounds<K>,
+    {
+        self.shared
+            .store
+            .keys_in_range(&range)
+            .iter()
+            .filter(|key| self.remove(key).is_some())
+            .count()
+    }
+
+    /// Iterates over a snapshot of the cached values.
+    ///
+    /// See [`iter`](Self::iter).
+    pub fn values(&self) -> impl Iterator<Item = V> {
+        self.iter().map(|(_, value)| value)
+    }
+
+    /// Returns the number of cached entries, stale or not, that have not
+    /// expired: as many as [`iter`](Self::iter) yields.
+    pub fn len(&self) -> usize {
+        self.shared.store.len()
+    }
+
+    /// Returns `true` if nothing is cached.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Copies the cached entries and their timestamps, for [`restore`](Self::restore).
+    ///
+    /// Entries past the hard expiry are left out.
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    /// use ttr_cache::{EntityFetcher, TTRCache};
+    ///
+    /// struct Backend;
+    ///
+    /// impl EntityFetcher<u32, String> for Backend {
+    ///     fn fetch_entity(&self, key: &u32) -> Option<String> {
+    ///         Some(key.to_string())
+    ///     }
+    /// }
+    ///
+    /// let cache = TTRCache::new(Duration::from_secs(60), Backend);
+    /// cache.get(&1);
+    ///
+    /// let restarted = TTRCache::new(Duration::from_secs(60), Backend);
+    /// assert_eq!(restarted.restore(cache.snapshot()), 1);
+    /// assert_eq!(restarted.get(&1), Some("1".to_string()));
+    /// assert_eq!(restarted.stats().refreshes, 0);
+    /// ```
+    pub fn snapshot(&self) -> Snapshot<K, V> {
+        self.shared.store.snapshot()
+    }
+
+    /// Copies every cached entry with all its timestamps, for inspecting the
+    /// cache or [`import`](Self::import)ing it into another.
+    ///
+    /// Like [`snapshot`](Self::snapshot), but also records when each key was
+    /// first cached, so a local cache can reproduce what another one held.
+    /// Entries past the hard expiry are left out.
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    /// use ttr_cache::{TTRCache, TTRCacheBuilder};
+    ///
+    /// let production: TTRCache<_, _, ()> =
+    ///     TTRCacheBuilder::new(Duration::from_secs(60)).build_cache_aside();
+    /// production.insert("flag", true);
+    /// production.invalidate(&"flag");
+    /// let dump = production.dump();
+    /// assert!(dump[0].1.invalidated);
+    ///
+    /// let local: TTRCache<_, _, ()> =
+    ///     TTRCacheBuilder::new(Duration::from_secs(60)).build_cache_aside();
+    /// assert_eq!(local.import(dump), 1);
+    /// assert!(local.entry(&"flag").unwrap().is_stale());
+    /// ```
+    pub fn dump(&self) -> Vec<(K, EntrySnapshot<V>)> {
+        self.shared.store.dump()
+    }
+
+    /// Stores dumped entries, keeping their timestamps and TTR.
+    ///
+    /// Entries are not written to the second tier. Entries that went stale
+    /// since the dump was taken are refreshed as usual. Returns the number of
+    /// entries stored.
+    pub fn import(&self, entries: impl IntoIterator<Item = (K, EntrySnapshot<V>)>) -> usize {
+        let store = &self.shared.store;
+        store.import(
+            entries
+                .into_iter()
+                .map(|(key, entry)| (store.canonical_owned(key), entry)),
+        )
+    }
+
+    /// Stores the entries of a snapshot, keeping their age and TTR.
+    ///
+    /// Entries that went stale since the snapshot was taken are refreshed as
+    /// usual. Returns the number of entries stored, which leaves out those
+    /// evicted again to make room.
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    /// use ttr_cache::{TTRCache, TTRCacheBuilder};
+    ///
+    /// let cache = TTRCache::new(Duration::from_secs(60), |id: &u32| Some(*id));
+    /// cache.get(&1);
+    /// cache.get(&2);
+    ///
+    /// let smaller: TTRCache<_, _, _> = TTRCacheBuilder::new(Duration::from_secs(60))
+    ///     .max_capacity(1)
+    ///     .build(|id: &u32| Some(*id));
+    /// assert_eq!(smaller.restore(cache.snapshot()), 1);
+    /// assert_eq!(smaller.len(), 1);
+    /// ```
+    pub fn restore(&self, snapshot: Snapshot<K, V>) -> usize {
+        self.shared.store.restore(snapshot)
+    }
+
+    /// Writes a [`snapshot`](Self::snapshot) to `path` as JSON.
+    ///
+    /// Fails with [`Error::Poisoned`], writing nothing, if a panic poisoned
+    /// one of the cache's locks, such as in a weigher or listener.
+    #[cfg(feature = "serde")]
+    pub fn save_to(&self, path: impl AsRef<std::path::Path>) -> Result<(), Error>
+    where
+        K: serde::Serialize,
+        V: serde::Serialize,
+    {
+        if self.shared.store.is_poisoned() {
+            return Err(Error::Poisoned);
+        }
+        crate::snapshot::save(&self.snapshot(), path.as_ref())
+    }
+
+    /// Restores a snapshot written by [`save_to`](Self::save_to).
+    ///
+    /// Returns the number of entries stored.
+    #[cfg(feature = "serde")]
+    pub fn load_from(&self, path: impl AsRef<std::path::Path>) -> Result<usize, Error>
+    where
+        K: serde::de::DeserializeOwned,
+        V: serde::de::DeserializeOwned,
+    {
+        Ok(self.restore(crate::snapshot::load(path.as_ref())?))
+    }
+
+    /// Returns the namespace `name`, shared, with its counters.
+    pub(crate) fn namespace_stats(&self, name: &str) -> (Arc<str>, Arc<StatsCounter>) {
+        self.shared.store.namespace_stats(name)
+    }
+
+    /// Returns a snapshot of the cache's counters.
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    /// use ttr_cache::{EntityFetcher, TTRCache};
+    ///
+    /// struct Evens;
+    ///
+    /// impl EntityFetcher<u64, u64> for Evens {
+    ///     fn fetch_entity(&self, key: &u64) -> Option<u64> {
+    ///         (key % 2 == 0).then_some(*key)
+    ///     }
+    /// }
+    ///
+    /// let cache = TTRCache::new(Duration::from_secs(60), Evens);
+    /// cache.get(&2);
+    /// cache.get(&2);
+    /// cache.get(&3);
+    ///
+    /// let stats = cache.stats();
+    /// assert_eq!((stats.hits, stats.misses), (1, 2));
+    /// assert_eq!((stats.refreshes, stats.refresh_failures), (2, 1));
+    /// assert_eq!(stats.entry_count, 1);
+    /// ```
+    pub fn stats(&self) -> CacheStats {
+        self.shared.store.stats()
+    }
+
+    /// Checks the cache's bookkeeping, for tests: the entry count and total
+    /// weight match the entries, and the cache is within its capacity and
+    /// weight limits unless only pinned entries are left.
+    ///
+    /// Call it while no other thread uses the cache. With the `test-util`
+    /// feature; see [`test_util`](crate::test_util).
+    ///
+    /// # Panics
+    ///
+    /// Panics if an invariant does not hold.
+    #[cfg(feature = "test-util")]
+    pub fn assert_invariants(&self) {
+        self.shared.store.assert_invariants();
+    }
+
+    /// Estimated bytes held by the cached entries: their total weight with a
+    /// [`weigher`](TTRCacheBuilder::weigher), and otherwise the entry count
+    /// times the [entry size](TTRCacheBuilder::entry_size).
+    ///
+    /// Only counts what entries are estimated at, not the cache's own
+    /// bookkeeping. The highest value reached is `stats().peak_memory_usage`.
+    pub fn memory_usage(&self) -> u64 {
+        self.shared.store.memory_usage()
+    }
+
+    /// Inserts a value as if it had just been fetched.
+    ///
+    /// The entry's TTR comes from the policy, if any, like a fetched value.
+    pub fn insert(&self, key: K, value: V) {
+        self.shared.store.insert(
+            self.shared.store.canonical_owned(key),
+            self.shared.store.now(),
+            value,
+        );
+    }
+
+    /// The age under which `quantile` of the values served by `get` were, or
+    /// `None` before the first read or without
+    /// [`track_served_ages`](TTRCacheBuilder::track_served_ages).
+    ///
+    /// Ages are kept to three significant figures, in milliseconds, and
+    /// rounded up. With the `age-histogram` feature.
+    #[cfg(feature = "age-histogram")]
+    pub fn age_percentile(&self, quantile: f64) -> Option<Duration> {
+        self.shared.store.served_ages()?.percentile(quantile)
+    }
+
+    /// How many values served by `get` were older than the threshold given
+    /// to [`track_served_ages`](TTRCacheBuilder::track_served_ages).
+    ///
+    /// With the `age-histogram` feature.
+    #[cfg(feature = "age-histogram")]
+    pub fn reads_over_age_threshold(&self) -> u64 {
+        self.shared
+            .store
+            .served_ages()
+            .map_or(0, ServedAges::over_threshold)
+    }
+
+    /// Writes a value to the backend with `writer`, then caches it as if just
+    /// fetched.
+    ///
+    /// The cache is only updated if the write succeeds; on error it is left
+    /// as it was and the error returned. Refreshes of `key` that started
+    /// before the value is cached are discarded when they finish, so they
+    /// cannot bring back what was there before. Caches sharing an
+    /// [invalidation bus](TTRCacheBuilder::invalidation_bus) are told to drop
+    /// their copy.
+    ///
+    /// ```rust
+    /// use std::collections::HashMap;
+    /// use std::sync::Mutex;
+    /// use std::time::Duration;
+    /// use ttr_cache::TTRCache;
+    ///
+    /// let db = Mutex::new(HashMap::from([(1, "draft".to_string())]));
+    /// let cache = TTRCache::new(Duration::from_secs(60), |id: &u32| {
+    ///     db.lock().unwrap().get(id).cloned()
+    /// });
+    /// assert_eq!(cache.get(&1).as_deref(), Some("draft"));
+    ///
+    /// let save = |id: &u32, title: &String| {
+    ///     db.lock().unwrap().insert(*id, title.clone());
+    ///     Ok::<_, ()>(())
+    /// };
+    /// cache.write_through(1, "published".to_string(), save).unwrap();
+    /// assert_eq!(cache.get(&1).as_deref(), Some("published"));
+    ///
+    /// let read_only = |_: &u32, _: &String| Err("read-only");
+    /// assert!(cache.write_through(1, "deleted".to_string(), read_only).is_err());
+    /// assert_eq!(cache.get(&1).as_deref(), Some("published"));
+    /// ```
+    pub fn write_through<W>(&self, key: K, value: V, writer: W) -> Result<(), W::Error>
+    where
+        W: EntityWriter<K, V>,
+    {
+        let key = self.shared.store.canonical_owned(key);
+        writer.write_entity(&key, &value)?;
+        self.shared.store.publish_invalidation(&key);
+        self.shared
+            .store
+            .insert(key, self.shared.store.now(), value);
+        Ok(())
+    }
+
+    /// Inserts a value that stays fresh for `ttl`, overriding the cache's TTR.
+    ///
+    /// The override lasts until the entry is next refreshed.
+    pub fn insert_with_ttl(&self, key: K, value: V, ttl: Duration) {
+        let key = self.shared.store.canonical_owned(key);
+        self.shared
+            .store
+            .insert_with_ttl(key, self.shared.store.now(), value, ttl);
+    }
+
+    /// Inserts a value as if it had been fetched at `fetched_at`, keeping its
+    /// age, for replaying recorded data and testing staleness.
+    ///
+    /// `fetched_at` is on the cache's [clock](TTRCacheBuilder::clock), such
+    /// as a [`MockClock`](crate::MockClock) reading; see
+    /// [`populate_at`](Self::populate_at) for wall-clock times. A value older
+    /// than its TTR is stored stale, and times in the future count as now.
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    /// use ttr_cache::{Clock, MockClock, TTRCache, TTRCacheBuilder};
+    ///
+    /// let clock = MockClock::new();
+    /// let cache: TTRCache<_, _, ()> = TTRCacheBuilder::new(Duration::from_secs(60))
+    ///     .clock(clock.clone())
+    ///     .build_cache_aside();
+    ///
+    /// let recorded_at = clock.now();
+    /// clock.advance(Duration::from_secs(90));
+    /// cache.insert_with_timestamp("quote", 101, recorded_at);
+    ///
+    /// let entry = cache.entry(&"quote").unwrap();
+    /// assert_eq!(entry.age(), Duration::from_secs(90));
+    /// assert!(entry.is_stale());
+    /// ```
+    pub fn insert_with_timestamp(&self, key: K, value: V, fetched_at: Instant) {
+        let key = self.shared.store.canonical_owned(key);
+        let now = self.shared.store.now();
+        self.shared.store.insert(key, fetched_at.min(now), value);
+    }
+
+    /// Inserts precomputed values as if they had just been fetched, such as
+    /// a batch job's output, returning how many.
+    ///
+    /// Like calling [`insert`](Self::insert) for each, but reading the clock
+    /// only once. See [`populate_at`](Self::populate_at) for values computed
+    /// earlier.
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    /// use ttr_cache::{CacheLookup, TTRCache, TTRCacheBuilder};
+    ///
+    /// let cache: TTRCache<_, _, ()> =
+    ///     TTRCacheBuilder::new(Duration::from_secs(60)).build_cache_aside();
+    /// assert_eq!(cache.populate((0..100).map(|id| (id, id * 10))), 100);
+    /// assert_eq!(cache.lookup(&7), CacheLookup::Fresh(70));
+    /// ```
+    pub fn populate(&self, entries: impl IntoIterator<Item = (K, V)>) -> usize {
+        let store = &self.shared.store;
+        let entries = entries
+            .into_iter()
+            .map(|(key, value)| (store.canonical_owned(key), value, None));
+        store.populate(entries)
+    }
+
+    /// Inserts precomputed values as if they had been fetched at the
+    /// wall-clock times given, returning how many.
+    ///
+    /// Values older than their TTR are stored stale, and refreshed on their
+    /// next read. Times in the future count as now.
+    ///
+    /// ```rust
+    /// use std::time::{Duration, SystemTime};
+    /// use ttr_cache::{TTRCache, TTRCacheBuilder};
+    ///
+    /// let cache: TTRCache<_, _, ()> =
+    ///     TTRCacheBuilder::new(Duration::from_secs(60)).build_cache_aside();
+    /// let computed_at = SystemTime::now() - Duration::from_secs(120);
+    /// cache.populate_at([("totals", 42, computed_at)]);
+    /// assert!(cache.entry(&"totals").unwrap().is_stale());
+    /// ```
+    pub fn populate_at(&self, entries: impl IntoIterator<Item = (K, V, SystemTime)>) -> usize {
+        let store = &self.shared.store;
+        let entries = entries
+            .into_iter()
+            .map(|(key, value, fetched_at)| (store.canonical_owned(key), value, Some(fetched_at)));
+        store.populate(entries)
+    }
+
+    /// Replaces the cache-wide TTR without rebuilding the cache.
+    ///
+    /// The new TTR applies to values fetched from now on and to cached
+    /// entries using the cache-wide TTR, which go stale once they are that
+    /// old. Entries whose TTR came from a [`TtlPolicy`], a namespace TTR or
+    /// [`insert_with_ttl`](Self::insert_with_ttl) keep theirs. A zero
+    /// `Duration` is rejected like in [`TTRCacheBuilder::new`].
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    /// use ttr_cache::{MockClock, TTRCache, TTRCacheBuilder};
+    ///
+    /// let clock = MockClock::new();
+    /// let cache: TTRCache<_, _, _> = TTRCacheBuilder::new(Duration::from_secs(60))
+    ///     .clock(clock.clone())
+    ///     .build(|id: &u32| Some(id * 10));
+    /// cache.get(&1);
+    ///
+    /// clock.advance(Duration::from_secs(90));
+    /// cache.set_ttl(Duration::from_secs(600)).unwrap();
+    /// assert!(!cache.entry(&1).unwrap().is_stale());
+    /// assert!(cache.set_ttl(Duration::ZERO).is_err());
+    /// ```
+    pub fn set_ttl(&self, ttl: impl Into<RefreshPolicy>) -> Result<(), Error> {
+        self.shared.store.set_ttl(ttl.into().checked_ttl()?);
+        Ok(())
+    }
+
+    /// Stops fetching until [`resume_refresh`](Self::resume_refresh), such as
+    /// during backend maintenance.
+    ///
+    /// Reads keep being served from what is cached, stale or not, without
+    /// calling the fetcher; keys with nothing cached read as `None`.
+    /// Refreshes already running are not interrupted.
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    /// use ttr_cache::{MockClock, TTRCache, TTRCacheBuilder};
+    ///
+    /// let clock = MockClock::new();
+    /// let cache: TTRCache<_, _, _> = TTRCacheBuilder::new(Duration::from_secs(60))
+    ///     .clock(clock.clone())
+    ///     .build(|id: &u32| Some(id * 10));
+    /// cache.insert(1, 1);
+    ///
+    /// cache.pause_refresh();
+    /// clock.advance(Duration::from_secs(90));
+    /// assert_eq!(cache.get(&1), Some(1));
+    /// assert_eq!(cache.get(&2), None);
+    ///
+    /// cache.resume_refresh();
+    /// assert_eq!(cache.get(&1), Some(10));
+    /// assert_eq!(cache.get(&2), Some(20));
+    /// ```
+    pub fn pause_refresh(&self) {
+        self.shared.store.pause_fetches(true);
+    }
+
+    /// Resumes fetching after [`pause_refresh`](Self::pause_refresh). Stale
+    /// entries are refreshed as they are next read.
+    pub fn resume_refresh(&self) {
+        self.shared.store.pause_fetches(false);
+    }
+
+    /// Whether fetching is paused by [`pause_refresh`](Self::pause_refresh).
+    pub fn is_refresh_paused(&self) -> bool {
+        self.shared.store.fetches_paused()
+    }
+
+    /// Marks an entry stale so the next `get` refreshes it.
+    ///
+    /// The stale value keeps being served in the meantime, subject to the
+    /// refresh mode. Returns `false` if the key is not cached. With an
+    /// [invalidation bus](TTRCacheBuilder::invalidation_bus), the other
+    /// instances are told too.
+    ///
+    /// ```rust
+    /// use std::sync::atomic::{AtomicU64, Ordering};
+    /// use std::time::Duration;
+    /// use ttr_cache::{EntityFetcher, TTRCache};
+    ///
+    /// struct Counter(AtomicU64);
+    ///
+    /// impl EntityFetcher<&'static str, u64> for Counter {
+    ///     fn fetch_entity(&self, _key: &&'static str) -> Option<u64> {
+    ///         Some(self.0.fetch_add(1, Ordering::SeqCst))
+    ///     }
+    /// }
+    ///
+    /// let cache = TTRCache::new(Duration::from_secs(300), Counter(AtomicU64::new(1)));
+    /// cache.insert("seeded", 0);
+    /// assert_eq!(cache.get(&"seeded"), Some(0));
+    ///
+    /// assert!(cache.invalidate(&"seeded"));
+    /// assert_eq!(cache.get(&"seeded"), Some(1));
+    ///
+    /// assert_eq!(cache.remove(&"seeded"), Some(1));
+    /// assert_eq!(cache.get(&"seeded"), Some(2));
+    /// ```
+    pub fn invalidate(&self, key: &K) -> bool {
+        let key = &*self.shared.store.canonical(key);
+        let invalidated = self.shared.store.invalidate(key);
+        self.shared.store.publish_invalidation(key);
+        invalidated
+    }
+
+    /// Records that the value of `dependent` is derived from `base`, so
+    /// `dependent` is marked stale whenever `base` is invalidated, refreshed,
+    /// inserted or removed.
+    ///
+    /// Dependencies carry over: a key derived from `dependent` is marked
+    /// stale along with it. They are kept while the keys come and go from the
+    /// cache, until [`remove_dependencies`](Self::remove_dependencies).
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    /// use ttr_cache::{CacheLookup, TTRCache};
+    ///
+    /// let cache = TTRCache::new(Duration::from_secs(300), |key: &&str| Some(format!("{key} v1")));
+    /// cache.add_dependency("user_summary", "user");
+    /// cache.add_dependency("user_summary", "orders");
+    /// cache.get(&"user");
+    /// cache.get(&"user_summary");
+    ///
+    /// cache.insert("orders", "orders v2".to_string());
+    /// assert_eq!(
+    ///     cache.lookup(&"user_summary"),
+    ///     CacheLookup::Stale("user_summary v1".to_string())
+    /// );
+    /// assert_eq!(cache.lookup(&"user"), CacheLookup::Fresh("user v1".to_string()));
+    /// ```
+    pub fn add_dependency(&self, dependent: K, base: K) {
+        let store = &self.shared.store;
+        store.add_dependency(
+            store.canonical_owned(dependent),
+            store.canonical_owned(base),
+        );
+    }
+
+    /// Forgets every key `dependent` was registered as derived from.
+    ///
+    /// Returns `false` if it had no dependencies.
+    pub fn remove_dependencies(&self, dependent: &K) -> bool {
+        self.shared
+            .store
+            .remove_dependencies(&self.shared.store.canonical(dependent))
+    }
+
+    /// Exempts an entry from eviction and hard expiry.
+    ///
+    /// A pinned entry is still refreshed when it goes stale, and can still
+    /// be removed or invalidated explicitly. Pinned entries count towards the
+    /// capacity and weight limits, so unpinned ones are evicted in their
+    /// place. Replacing the value keeps the pin. Returns `false` if the key
+    /// is not cached.
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    /// use ttr_cache::{EntityFetcher, TTRCacheBuilder};
+    ///
+    /// struct Settings;
+    ///
+    /// impl EntityFetcher<&'static str, String> for Settings {
+    ///     fn fetch_entity(&self, key: &&'static str) -> Option<String> {
+    ///         Some(format!("{key} from the backend"))
+    ///     }
+    /// }
+    ///
+    /// let cache = TTRCacheBuilder::new(Duration::from_secs(300))
+    ///     .max_capacity(1)
+    ///     .build(Settings);
+    /// cache.get(&"feature-flags");
+    /// assert!(cache.pin(&"feature-flags"));
+    ///
+    /// cache.get(&"banner");
+    /// assert_eq!(cache.len(), 1);
+    /// assert_eq!(cache.keys().collect::<Vec<_>>(), ["feature-flags"]);
+    ///
+    /// assert!(cache.unpin(&"feature-flags"));
+    /// ```
+    pub fn pin(&self, key: &K) -> bool {
+        self.shared
+            .store
+            .set_pinned(&self.shared.store.canonical(key), true)
+    }
+
+    /// Makes a pinned entry subject to eviction and hard expiry again.
+    ///
+    /// Returns `false` if the key is not cached.
+    pub fn unpin(&self, key: &K) -> bool {
+        self.shared
+            .store
+            .set_pinned(&self.shared.store.canonical(key), false)
+    }
+
+    /// Marks every entry stale at once, keeping the values to serve until
+    /// they are refreshed, and returns the new cache-wide generation.
+    ///
+    /// Unlike [`clear`](Self::clear), nothing is lost, and unlike
+    /// [`invalidate_if`](Self::invalidate_if), no entry is visited: each
+    /// entry records the generation it was fetched in, and entries from an
+    /// older one count as invalidated. Values from refreshes that started
+    /// before the bump are stale too. Invalidation buses are not told.
+    ///
+    /// ```rust
+    /// use std::sync::atomic::{AtomicU32, Ordering};
+    /// use std::time::Duration;
+    /// use ttr_cache::{CacheLookup, TTRCache};
+    ///
+    /// static RELEASE: AtomicU32 = AtomicU32::new(1);
+    ///
+    /// let cache = TTRCache::new(Duration::from_secs(3600), |page: &&str| {
+    ///     Some(format!("{page} r{}", RELEASE.load(Ordering::SeqCst)))
+    /// });
+    /// cache.get(&"home");
+    /// cache.get(&"about");
+    ///
+    /// RELEASE.store(2, Ordering::SeqCst);
+    /// assert_eq!(cache.bump_generation(), 1);
+    /// assert_eq!(cache.lookup(&"about"), CacheLookup::Stale("about r1".to_string()));
+    /// assert_eq!(cache.get(&"home"), Some("home r2".to_string()));
+    /// assert_eq!(cache.lookup(&"home"), CacheLookup::Fresh("home r2".to_string()));
+    /// ```
+    pub fn bump_generation(&self) -> u64 {
+        self.shared.store.bump_generation()
+    }
+
+    /// The cache-wide generation, the number of
+    /// [`bump_generation`](Self::bump_generation) calls so far.
+    pub fn generation(&self) -> u64 {
+        self.shared.store.bulk_generation()
+    }
+
+    /// Marks every entry matching `predicate` stale, in one pass. Returns how
+    /// many were marked.
+    ///
+    /// `predicate` runs while part of the cache is locked, so it must not call
+    /// back into the cache.
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    /// use ttr_cache::{EntityFetcher, TTRCache};
+    ///
+    /// struct Nothing;
+    ///
+    /// impl EntityFetcher<(u32, u32), String> for Nothing {
+    ///     fn fetch_entity(&self, _key: &(u32, u32)) -> Option<String> {
+    ///         None
+    ///     }
+    /// }
+    ///
+    /// let cache = TTRCache::new(Duration::from_secs(60), Nothing);
+    /// cache.insert((1, 10), "a".to_string());
+    /// cache.insert((1, 11), "b".to_string());
+    /// cache.insert((2, 10), "c".to_string());
+    ///
+    /// assert_eq!(cache.invalidate_if(|(tenant, _), _| *tenant == 1), 2);
+    /// assert!(cache.entry(&(1, 10)).unwrap().is_stale());
+    /// assert!(!cache.entry(&(2, 10)).unwrap().is_stale());
+    ///
+    /// assert_eq!(cache.remove_if(|(tenant, _), _| *tenant == 1), 2);
+    /// assert_eq!(cache.len(), 1);
+    /// ```
+    pub fn invalidate_if(&self, predicate: impl FnMut(&K, &V) -> bool) -> usize {
+        self.shared.store.invalidate_if(predicate)
+    }
+
+    /// Marks every entry whose key starts with `prefix` stale. Returns how
+    /// many were marked.
+    pub fn invalidate_prefix(&self, prefix: &str) -> usize
+    where
+        K: AsRef<str>,
+    {
+        self.invalidate_if(|key, _| key.as_ref().starts_with(prefix))
+    }
+
+    /// Removes every entry matching `predicate`, from the second tier too.
+    /// Returns how many were removed.
+    ///
+    /// See [`invalidate_if`](Self::invalidate_if).
+    pub fn remove_if(&self, predicate: impl FnMut(&K, &V) -> bool) -> usize {
+        self.shared.store.remove_if(predicate)
+    }
+
+    /// Removes an entry, returning its value.
+    ///
+    /// A refresh of the entry that is still in flight is discarded when it
+    /// completes, so the entry is not brought back. The same goes for
+    /// refreshes that started before an [`invalidate`](Self::invalidate) or
+    /// [`insert`](Self::insert) of the key.
+    ///
+    /// ```rust
+    /// use std::sync::mpsc::{self, Receiver};
+    /// use std::sync::Mutex;
+    /// use std::time::Duration;
+    /// use ttr_cache::{EntityFetcher, RefreshMode, RefreshPolicy, TTRCacheBuilder};
+    ///
+    /// struct Slow(Mutex<Receiver<u64>>);
+    ///
+    /// impl EntityFetcher<&'static str, u64> for Slow {
+    ///     fn fetch_entity(&self, _key: &&'static str) -> Option<u64> {
+    ///         self.0.lock().unwrap().recv().ok()
+    ///     }
+    /// }
+    ///
+    /// let (release, fetches) = mpsc::channel();
+    /// let cache = TTRCacheBuilder::new(RefreshPolicy::Always)
+    ///     .refresh_mode(RefreshMode::Background)
+    ///     .build_background(Slow(Mutex::new(fetches)));
+    /// cache.insert("session", 1);
+    ///
+    /// // Serves the stale value and starts a refresh, then removes the entry
+    /// // while the refresh waits on the backend.
+    /// assert_eq!(cache.get(&"session"), Some(1));
+    /// assert_eq!(cache.remove(&"session"), Some(1));
+    /// release.send(2).unwrap();
+    ///
+    /// assert!(cache.shutdown(Duration::from_secs(5)));
+    /// assert!(cache.is_empty());
+    /// ```
+    pub fn remove(&self, key: &K) -> Option<V> {
+        self.shared.store.remove(&self.shared.store.canonical(key))
+    }
+
+    /// Removes every entry.
+    pub fn clear(&self) {
+        self.shared.store.clear();
+    }
+
+    /// Stops the background workers, waiting up to `timeout` for them to
+    /// finish, and flushes the second tier.
+    ///
+    /// Fetches already running have their [`FetchContext`] cancelled and
+    /// their results dropped; queued refreshes are dropped too, resolving
+    /// their [`RefreshHandle`]s to `None`. The cache keeps working afterwards,
+    /// but refreshes stale entries on the calling thread and no longer
+    /// refreshes ahead or purges by itself. Returns `false` if a worker was
+    /// still busy at the timeout.
+    ///
+    /// Dropping the cache also stops the workers, without waiting for them.
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    /// use ttr_cache::{EntityFetcher, RefreshPolicy, TTRCache};
+    ///
+    /// struct Backend;
+    ///
+    /// impl EntityFetcher<u32, u32> for Backend {
+    ///     fn fetch_entity(&self, key: &u32) -> Option<u32> {
+    ///         Some(*key)
+    ///     }
+    /// }
+    ///
+    /// let cache = TTRCache::with_background_refresh(RefreshPolicy::Always, Backend);
+    /// cache.insert(1, 1);
+    /// assert!(cache.shutdown(Duration::from_secs(5)));
+    /// // Stale entries are now refreshed on the calling thread.
+    /// assert_eq!(cache.get(&1), Some(1));
+    /// ```
+    pub fn shutdown(&self, timeout: Duration) -> bool {
+        let handles = {
+            let mut workers = self.workers.lock().unwrap_or_else(PoisonError::into_inner);
+            workers.refreshing = false;
+            workers.janitor = None;
+            workers.sweeper = None;
+            workers.ahead = None;
+            std::mem::take(&mut workers.handles)
+        };
+        self.shared.stop();
+        self.shared.queue().drop_waiters();
+        self.shared.store.cancel_fetches(None);
+
+        let deadline = Instant::now() + timeout;
+        while handles.iter().any(|handle| !handle.is_finished()) && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(1));
+        }
+        let finished = handles.iter().all(JoinHandle::is_finished);
+        for handle in handles.into_iter().filter(JoinHandle::is_finished) {
+            let _ = handle.join();
+        }
+
+        self.shared.store.flush();
+        finished
+    }
+
+    /// Removes entries past their hard expiry and, with
+    /// [`max_idle`](TTRCacheBuilder::max_idle), entries that went unused for
+    /// that long. Returns how many were removed.
+    ///
+    /// Expired entries are otherwise only removed when they are next looked
+    /// up. See also [`janitor`](TTRCacheBuilder::janitor).
+    pub fn purge(&self) -> usize {
+        self.shared.store.purge()
+    }
+
+    /// Removes the entries past their hard expiry and yields them, for
+    /// archiving expired data instead of dropping it.
+    ///
+    /// The entries are removed when this is called, not as the iterator is
+    /// advanced, and listeners are told they expired. Idle entries are left
+    /// to [`purge`](Self::purge).
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    /// use ttr_cache::{MockClock, TTRCache, TTRCacheBuilder};
+    ///
+    /// let clock = MockClock::new();
+    /// let cache: TTRCache<_, _, ()> = TTRCacheBuilder::new(Duration::from_secs(60))
+    ///     .clock(clock.clone())
+    ///     .max_stale(Duration::from_secs(240))
+    ///     .build_cache_aside();
+    ///
+    /// cache.insert("order:1", "shipped");
+    /// clock.advance(Duration::from_secs(200));
+    /// cache.insert("order:2", "packed");
+    /// clock.advance(Duration::from_secs(200));
+    ///
+    /// let archived: Vec<_> = cache.drain_expired().collect();
+    /// assert_eq!(archived, [("order:1", "shipped")]);
+    /// assert_eq!(cache.len(), 1);
+    /// ```
+    pub fn drain_expired(&self) -> impl Iterator<Item = (K, V)> {
+        self.shared.store.drain_expired().into_iter()
+    }
+}
+
+impl<K, V, F, S> TTRCache<K, V, F, S>
+where
+    K: Eq + Hash + Clone,
+    V: Clone,
+    F: EntityFetcher<K, V>,
+    S: BuildHasher,
+{
+    /// Gets a value, refreshing if stale.
+    ///
+    /// Only one caller fetches a given key at a time. While a stale key is
+    /// being refreshed, other callers get the stale value; while a missing
+    /// key is being fetched, other callers wait for the result. The fetch
+    /// runs without holding any of the cache's locks, so a slow fetch only
+    /// holds up callers of the same key.
+    ///
+    /// In background refresh mode a stale value is returned immediately and
+    /// replaced once a worker finishes fetching it.
+    ///
+    /// The key can be any borrowed form of `K`, such as `&str` for `String`
+    /// keys. It is only converted to an owned `K` when the value has to be
+    /// refreshed or fetched.
+    ///
+    /// Returns `Some(V)` if found, `None` otherwise.
+    ///
+    /// ```rust
+    /// use std::sync::mpsc::{self, Receiver};
+    /// use std::sync::{Arc, Mutex};
+    /// use std::thread;
+    /// use std::time::Duration;
+    /// use ttr_cache::{EntityFetcher, TTRCache};
+    ///
+    /// struct Slow(Mutex<Receiver<u64>>);
+    ///
+    /// impl EntityFetcher<&'static str, u64> for Slow {
+    ///     fn fetch_entity(&self, _key: &&'static str) -> Option<u64> {
+    ///         self.0.lock().unwrap().recv().ok()
+    ///     }
+    /// }
+    ///
+    /// let (release, backend) = mpsc::channel();
+    /// let cache = Arc::new(TTRCache::new(Duration::from_secs(60), Slow(Mutex::new(backend))));
+    /// cache.insert("b", 2);
+    ///
+    /// let slow = thread::spawn({
+    ///     let cache = Arc::clone(&cache);
+    ///     move || cache.get(&"a")
+    /// });
+    /// // While "a" is being fetched, "b" is still served.
+    /// assert_eq!(cache.get(&"b"), Some(2));
+    ///
+    /// release.send(1).unwrap();
+    /// assert_eq!(slow.join().unwrap(), Some(1));
+    /// ```
+    pub fn get<Q>(&self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    {
+        let sampler = self.shared.store.lookup_sampler();
+        if sampler.is_some() || self.shared.store.tracks_served_ages() {
+            let key = self.shared.store.canonical_owned(key.to_owned());
+            return self.serve_observed(&key, sampler, Priority::Normal);
+        }
+        if self.shared.store.normalizes_keys() {
+            let key = self.shared.store.canonical_owned(key.to_owned());
+            return self.serve(&key, self.shared.store.lookup(&key));
+        }
+        if let Some(entity) = self.shared.store.get_swapped(key) {
+            return Some(entity);
+        }
+        match self.shared.store.lookup_cached(key) {
+            Some(Lookup::Fresh(entity) | Lookup::Retrying(entity)) => Some(entity),
+            Some(lookup) => self.serve(&key.to_owned(), lookup),
+            None => {
+                let key = key.to_owned();
+                self.serve(&key, self.shared.store.lookup(&key))
+            }
+        }
+    }
+
+    /// Gets a value like [`get`](Self::get), queueing a background refresh
+    /// of it at `priority`.
+    ///
+    /// Refreshes queued at a higher priority start first, so latency-critical
+    /// reads can get their keys refreshed ahead of batch jobs' when refresh
+    /// concurrency is limited. A key already queued moves up to the highest
+    /// priority it is read with. Fetches on the calling thread are not
+    /// affected.
+    ///
+    /// ```rust
+    /// use std::sync::mpsc::{self, Receiver, Sender};
+    /// use std::sync::Mutex;
+    /// use std::time::Duration;
+    /// use ttr_cache::{EntityFetcher, MockClock, Priority, RefreshMode, TTRCache, TTRCacheBuilder};
+    ///
+    /// struct Gated {
+    ///     started: Mutex<Sender<&'static str>>,
+    ///     release: Mutex<Receiver<()>>,
+    /// }
+    ///
+    /// impl EntityFetcher<&'static str, u64> for Gated {
+    ///     fn fetch_entity(&self, key: &&'static str) -> Option<u64> {
+    ///         self.started.lock().unwrap().send(key).unwrap();
+    ///         self.release.lock().unwrap().recv().ok().map(|()| 1)
+    ///     }
+    /// }
+    ///
+    /// let (started, fetched) = mpsc::channel();
+    /// let (release, gate) = mpsc::channel();
+    /// let clock = MockClock::new();
+    /// let cache: TTRCache<_, _, _> = TTRCacheBuilder::new(Duration::from_secs(60))
+    ///     .clock(clock.clone())
+    ///     .refresh_mode(RefreshMode::Background)
+    ///     .build_background(Gated {
+    ///         started: Mutex::new(started),
+    ///         release: Mutex::new(gate),
+    ///     });
+    /// for key in ["report", "export", "checkout"] {
+    ///     cache.insert(key, 0);
+    /// }
+    /// clock.advance(Duration::from_secs(90));
+    ///
+    /// // The only worker is busy with the report, so the others queue.
+    /// cache.get_with_priority(&"report", Priority::Low);
+    /// assert_eq!(fetched.recv().unwrap(), "report");
+    /// cache.get_with_priority(&"export", Priority::Low);
+    /// cache.get_with_priority(&"checkout", Priority::High);
+    ///
+    /// for _ in 0..3 {
+    ///     release.send(()).unwrap();
+    /// }
+    /// assert_eq!(fetched.recv().unwrap(), "checkout");
+    /// assert_eq!(fetched.recv().unwrap(), "export");
+    /// ```
+    pub fn get_with_priority(&self, key: &K, priority: Priority) -> Option<V> {
+        let key = &*self.shared.store.canonical(key);
+        let sampler = self.shared.store.lookup_sampler();
+        if sampler.is_some() || self.shared.store.tracks_served_ages() {
+            return self.serve_observed(key, sampler, priority);
+        }
+        if let Some(entity) = self.shared.store.get_swapped(key) {
+            return Some(entity);
+        }
+        self.serve_at(key, self.shared.store.lookup(key), priority)
+    }
+
+    /// Gets a value like [`get`](Self::get), borrowing it from the cache
+    /// instead of cloning it when it is fresh.
+    ///
+    /// For large values read briefly, such as to compute something from
+    /// them. A fresh value is read in place, under a read lock on the shard
+    /// storing the key; any other lookup refreshes or fetches like `get` and
+    /// the guard holds the resulting copy. Values stored encoded or weakly
+    /// are always copied.
+    ///
+    /// While a guard borrows a value, writes to keys in the same shard,
+    /// including refreshes, wait for it, so drop it soon and do not hold it
+    /// across calls that write to the cache.
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    /// use ttr_cache::TTRCache;
+    ///
+    /// let cache = TTRCache::new(Duration::from_secs(60), |id: &u32| {
+    ///     Some(vec![*id; 1 << 20])
+    /// });
+    ///
+    /// let total: u64 = cache.get_guarded(&7).unwrap().iter().map(|&x| u64::from(x)).sum();
+    /// assert_eq!(total, 7 << 20);
+    /// assert_eq!(cache.get_guarded(&7).map(|blob| blob.len()), Some(1 << 20));
+    /// assert_eq!(cache.stats().hits, 1);
+    /// ```
+    pub fn get_guarded<'a, Q>(&'a self, key: &'a Q) -> Option<ValueGuard<'a, V>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    {
+        if let Some(guard) = self.shared.store.get_fresh_guarded(key) {
+            return Some(guard);
+        }
+        self.get(key).map(ValueGuard::owned)
+    }
+
+    /// Gets a value like [`get`](Self::get), but with
+    /// [`MissPolicy::Error`] fails for keys with nothing cached instead of
+    /// returning `None`: with [`Error::Expired`] if the key's entry was
+    /// past its hard expiry, and [`Error::UnexpectedMiss`] otherwise.
+    ///
+    /// Under the other miss policies this is `Ok(get(key))`.
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    /// use ttr_cache::{Error, MissPolicy, MockClock, TTRCache, TTRCacheBuilder};
+    ///
+    /// let clock = MockClock::new();
+    /// let cache: TTRCache<_, _, _> = TTRCacheBuilder::new(Duration::from_secs(60))
+    ///     .max_stale(Duration::from_secs(60))
+    ///     .miss_policy(MissPolicy::Error)
+    ///     .clock(clock.clone())
+    ///     .build(|_: &&str| Some(1.1));
+    /// cache.insert("rates", 1.0);
+    /// assert_eq!(cache.get_strict(&"rates").unwrap(), Some(1.0));
+    ///
+    /// clock.advance(Duration::from_secs(180));
+    /// assert!(matches!(cache.get_strict(&"rates"), Err(Error::Expired)));
+    /// assert!(matches!(cache.get_strict(&"rates"), Err(Error::UnexpectedMiss)));
+    /// ```
+    pub fn get_strict<Q>(&self, key: &Q) -> Result<Option<V>, Error>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    {
+        if self.shared.store.miss_policy() != MissPolicy::Error {
+            return Ok(self.get(key));
+        }
+        let key = self.shared.store.canonical_owned(key.to_owned());
+        if self.shared.store.expire(&key) {
+            return Err(Error::Expired);
+        }
+        match self.shared.store.lookup(&key) {
+            Lookup::Missing => Err(Error::UnexpectedMiss),
+            lookup => Ok(self.serve(&key, lookup)),
+        }
+    }
+
+    /// [`get`](Self::get), also counting the lookup in `stats`.
+    pub(crate) fn get_counted(&self, key: &K, stats: &StatsCounter) -> Option<V> {
+        let key = &*self.shared.store.canonical(key);
+        let lookup = self.shared.store.lookup(key);
+        match &lookup {
+            Lookup::Fresh(_) | Lookup::Expiring(_) => stats.record_hit(),
+            Lookup::Absent => stats.record_absent_hit(),
+            Lookup::Stale(_) | Lookup::Retrying(_) | Lookup::Revalidate(_) => {
+                stats.record_stale_hit()
+            }
+            Lookup::Missing => stats.record_miss(),
+        }
+        self.serve(key, lookup)
+    }
+
+    /// Looks a key up without fetching, counting it in the stats.
+    pub(crate) fn lookup_entry(&self, key: &K) -> Lookup<V> {
+        self.shared.store.lookup(key)
+    }
+
+    /// Returns the value `lookup` found, refreshing or fetching as needed.
+    pub(crate) fn serve(&self, key: &K, lookup: Lookup<V>) -> Option<V> {
+        self.serve_at(key, lookup, Priority::Normal)
+    }
+
+    /// Serves `lookup` like [`serve`](Self::serve), queueing any background
+    /// refresh at `priority`.
+    fn serve_at(&self, key: &K, lookup: Lookup<V>, priority: Priority) -> Option<V> {
+        self.shared
+            .periodic_loader
+            .get_or_init(|| fetch_loader::<K, V, F>);
+        let result = match lookup {
+            Lookup::Fresh(entity) | Lookup::Retrying(entity) => return Some(entity),
+            Lookup::Absent => return None,
+            Lookup::Expiring(entity) => {
+                self.schedule(key, Load::single(fetch_loader::<K, V, F>), priority);
+                return Some(entity);
+            }
+            Lookup::Stale(entity)
+                if self.refresh_in_background(
+                    key,
+                    Load::single(fetch_loader::<K, V, F>),
+                    priority,
+                ) =>
+            {
+                return Some(entity);
+            }
+            Lookup::Stale(entity) => self.shared.load_stale(key, &entity, |_, key, context| {
+                self.fetch(key, Some(&entity), context)
+            }),
+            Lookup::Revalidate(entity) => self.shared.load_missing(key, |_, key, context| {
+                self.fetch(key, Some(&entity), context)
+            }),
+            Lookup::Missing if !self.shared.store.fetches_on_miss(key) => return None,
+            Lookup::Missing => self
+                .shared
+                .load_missing(key, |_, key, context| self.fetch(key, None, context)),
+        };
+
+        // A timed out fetch leaves the stale value, if any, in place.
+        result.unwrap_or_else(|TimedOut| self.shared.store.get(key))
+    }
+
+    /// Looks `key` up and serves it like [`serve`](Self::serve), passing
+    /// the lookup to `sampler`, if any, and recording the served value's age.
+    fn serve_observed(
+        &self,
+        key: &K,
+        sampler: Option<&BoxedLookupSampler<K>>,
+        priority: Priority,
+    ) -> Option<V> {
+        let age = self.shared.store.refresh_timing(key).map(|(age, _)| age);
+        let lookup = self.shared.store.lookup(key);
+        let outcome = lookup.outcome();
+        let started = Instant::now();
+        let entity = self.serve_at(key, lookup, priority);
+        if let Some(sampler) = sampler {
+            sampler(&LookupSample {
+                key,
+                outcome,
+                age,
+                fetch_latency: (outcome != LookupOutcome::Hit).then(|| started.elapsed()),
+            });
+        }
+        if entity.is_some() {
+            self.shared.store.record_served_age(key);
+        }
+        entity
+    }
+
+    /// Fetches `key` through `fetch_if_modified` when a value is cached,
+    /// within the fetch timeout if one is set.
+    fn fetch(
+        &self,
+        key: &K,
+        current: Option<&V>,
+        context: &FetchContext,
+    ) -> Result<FetchOutcome<V>, TimedOut> {
+        let loader = fetch_loader::<K, V, F>;
+        let Some(timed_fetch) = &self.timed_fetch else {
+            return Ok(loader(&self.shared.fetcher, key, current, context));
+        };
+        timed_fetch(key, current, context, loader).ok_or_else(|| {
+            self.shared.store.fetch_timed_out(key);
+            TimedOut
+        })
+    }
+
+    /// Gets a value no older than `freshness` allows.
+    ///
+    /// With [`Freshness::Stale`] this is [`get`](Self::get). Otherwise a
+    /// cached value that is too old is refreshed on the calling thread, even
+    /// in background refresh mode, and callers racing for the same key wait
+    /// for that refresh instead of taking the old value. If the refresh fails,
+    /// `None` is returned rather than a value that is too old.
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    /// use ttr_cache::{EntityFetcher, Freshness, MockClock, TTRCache, TTRCacheBuilder};
+    ///
+    /// struct Down;
+    ///
+    /// impl EntityFetcher<&'static str, u32> for Down {
+    ///     fn fetch_entity(&self, _key: &&'static str) -> Option<u32> {
+    ///         None
+    ///     }
+    /// }
+    ///
+    /// let clock = MockClock::new();
+    /// let cache: TTRCache<_, _, _> = TTRCacheBuilder::new(Duration::from_secs(60))
+    ///     .clock(clock.clone())
+    ///     .build(Down);
+    ///
+    /// cache.insert("balance", 100);
+    /// clock.advance(Duration::from_secs(90));
+    /// assert_eq!(cache.get_with(&"balance", Freshness::Stale), Some(100));
+    /// assert_eq!(cache.get_with(&"balance", Freshness::MaxAge(Duration::from_secs(120))), Some(100));
+    /// assert_eq!(cache.get_with(&"balance", Freshness::Fresh), None);
+    /// ```
+    pub fn get_with(&self, key: &K, freshness: Freshness) -> Option<V> {
+        let key = &*self.shared.store.canonical(key);
+        if freshness == Freshness::Stale {
+            return self.get(key);
+        }
+        let store = &self.shared.store;
+        if let Some(entity) = store.get_if(key, |info| freshness.accepts(info)) {
+            return Some(entity);
+        }
+
+        let current = store.entry_info(key).map(EntryInfo::into_value);
+        let _ = self.shared.load_missing(key, |_, key, context| {
+            self.fetch(key, current.as_ref(), context)
+        });
+        store
+            .entry_info(key)
+            .filter(|info| freshness.accepts(info))
+            .map(EntryInfo::into_value)
+    }
+
+    /// Gets a value younger than `max_age`, refreshing it otherwise.
+    ///
+    /// Lets call sites that need fresher data than the TTR share a cache
+    /// with those that do not. A cached value that is too old is refreshed
+    /// like a stale one: in blocking refresh mode on the calling thread, as
+    /// with [`get_with`](Self::get_with) and [`Freshness::MaxAge`]; in
+    /// background refresh mode by the workers, returning `None` meanwhile.
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    /// use ttr_cache::{EntityFetcher, MockClock, TTRCache, TTRCacheBuilder};
+    ///
+    /// struct Prices;
+    ///
+    /// impl EntityFetcher<&'static str, u32> for Prices {
+    ///     fn fetch_entity(&self, _key: &&'static str) -> Option<u32> {
+    ///         Some(105)
+    ///     }
+    /// }
+    ///
+    /// let clock = MockClock::new();
+    /// let cache: TTRCache<_, _, _> = TTRCacheBuilder::new(Duration::from_secs(300))
+    ///     .clock(clock.clone())
+    ///     .build(Prices);
+    ///
+    /// cache.insert("ticker", 100);
+    /// clock.advance(Duration::from_secs(20));
+    /// assert_eq!(cache.get(&"ticker"), Some(100));
+    /// assert_eq!(cache.get_if_fresher_than(&"ticker", Duration::from_secs(30)), Some(100));
+    /// assert_eq!(cache.get_if_fresher_than(&"ticker", Duration::from_secs(10)), Some(105));
+    /// ```
+    pub fn get_if_fresher_than(&self, key: &K, max_age: Duration) -> Option<V> {
+        let key = &*self.shared.store.canonical(key);
+        let freshness = Freshness::MaxAge(max_age);
+        if self.refresh_mode == RefreshMode::Background {
+            let store = &self.shared.store;
+            if let Some(entity) = store.get_if(key, |info| freshness.accepts(info)) {
+                return Some(entity);
+            }
+            if store.entry_info(key).is_some()
+                && self.schedule(key, Load::single(fetch_loader::<K, V, F>), Priority::Normal)
+            {
+                return None;
+            }
+        }
+        self.get_with(key, freshness)
+    }
+
+    /// Fetches `keys` and stores the results, for preloading the cache before
+    /// traffic arrives.
+    ///
+    /// Every key is fetched, even if it is already cached. Keys are fetched
+    /// one after another; see [`warm_parallel`](Self::warm_parallel) to spread
+    /// them over threads.
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    /// use ttr_cache::{EntityFetcher, TTRCache};
+    ///
+    /// struct Evens;
+    ///
+    /// impl EntityFetcher<u64, u64> for Evens {
+    ///     fn fetch_entity(&self, key: &u64) -> Option<u64> {
+    ///         (key % 2 == 0).then_some(*key)
+    ///     }
+    /// }
+    ///
+    /// let cache = TTRCache::new(Duration::from_secs(60), Evens);
+    /// let report = cache.warm(0..4);
+    /// assert_eq!(report.loaded, [0, 2]);
+    /// assert_eq!(report.missing, [1, 3]);
+    /// assert_eq!(cache.len(), 2);
+    /// ```
+    pub fn warm(&self, keys: impl IntoIterator<Item = K>) -> WarmReport<K> {
+        let mut report = WarmReport::new();
+        for key in keys {
+            let key = self.shared.store.canonical_owned(key);
+            let entity = self
+                .shared
+                .load_missing(&key, |_, key, context| self.fetch(key, None, context))
+                .unwrap_or_else(|TimedOut| self.shared.store.get(&key));
+            report.record(key, entity.is_some());
+        }
+        report
+    }
+
+    /// Like [`warm`](Self::warm), but fetches on up to `threads` threads at
+    /// once.
+    ///
+    /// The keys in the report are not in the order they were given. Where
+    /// threads are unavailable, such as `wasm32-unknown-unknown`, this is
+    /// [`warm`](Self::warm).
+    ///
+    /// # Panics
+    ///
+    /// Panics if `threads` is zero.
+    pub fn warm_parallel(&self, keys: impl IntoIterator<Item = K>, threads: usize) -> WarmReport<K>
+    where
+        K: Send,
+        Self: Sync,
+    {
+        assert!(threads > 0, "warm_parallel needs at least one thread");
+        self.in_parallel(keys, threads, |cache, batch| cache.warm(batch))
+    }
+
+    /// Refreshes a key now, whatever its TTR, and returns the new value.
+    ///
+    /// For when the backend is known to have changed. The key is fetched on
+    /// the calling thread, even in background refresh mode, after any fetch
+    /// of it already in flight. A key that is not cached is fetched and
+    /// cached. If nothing is found, the entry is handled as configured with
+    /// [`on_missing_refresh`](crate::TTRCacheBuilder::on_missing_refresh).
+    ///
+    /// ```rust
+    /// use std::sync::atomic::{AtomicU32, Ordering};
+    /// use std::sync::Arc;
+    /// use std::time::Duration;
+    /// use ttr_cache::TTRCache;
+    ///
+    /// let version = Arc::new(AtomicU32::new(1));
+    /// let backend = Arc::clone(&version);
+    /// let cache = TTRCache::new(Duration::from_secs(3600), move |_: &&str| {
+    ///     Some(backend.load(Ordering::SeqCst))
+    /// });
+    ///
+    /// assert_eq!(cache.get(&"config"), Some(1));
+    /// version.store(2, Ordering::SeqCst);
+    /// assert_eq!(cache.get(&"config"), Some(1));
+    /// assert_eq!(cache.force_refresh(&"config"), Some(2));
+    /// assert_eq!(cache.get(&"config"), Some(2));
+    /// ```
+    pub fn force_refresh(&self, key: &K) -> Option<V> {
+        let key = &*self.shared.store.canonical(key);
+        let current = self.shared.store.entry_info(key).map(EntryInfo::into_value);
+        self.shared
+            .load_now(key, |_, key, context| {
+                self.fetch(key, current.as_ref(), context)
+            })
+            .unwrap_or_else(|TimedOut| self.shared.store.get(key))
+    }
+
+    /// Refreshes a key on the background workers, returning a handle that
+    /// resolves once the refresh is done.
+    ///
+    /// For waiting on a refresh without blocking the caller, such as
+    /// read-after-write flows and tests. The key is refreshed whatever its
+    /// TTR, after any refresh of it already running; a key that is not cached
+    /// is not fetched and resolves to `None`. Without background workers the
+    /// key is refreshed on the calling thread like
+    /// [`force_refresh`](Self::force_refresh) and the handle is already done.
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    /// use ttr_cache::{RefreshMode, TTRCache, TTRCacheBuilder};
+    ///
+    /// let cache: TTRCache<_, _, _> = TTRCacheBuilder::new(Duration::from_secs(60))
+    ///     .refresh_mode(RefreshMode::Background)
+    ///     .build_background(|id: &u32| Some(id * 10));
+    /// cache.insert(1, 0);
+    ///
+    /// let handle = cache.refresh(&1);
+    /// assert_eq!(handle.wait(), Some(10));
+    /// assert_eq!(cache.get(&1), Some(10));
+    /// assert_eq!(cache.refresh(&2).wait(), None);
+    /// ```
+    pub fn refresh(&self, key: &K) -> RefreshHandle<V> {
+        let key = &*self.shared.store.canonical(key);
+        let (handle, sender) = RefreshHandle::new();
+        match self.enqueue(
+            key,
+            Load::single(fetch_loader::<K, V, F>),
+            Some(sender),
+            Priority::Normal,
+        ) {
+            Ok(()) => handle,
+            Err(_) if !self.shared.store.generation(key).is_cached() => RefreshHandle::done(None),
+            Err(_) => RefreshHandle::done(self.force_refresh(key)),
+        }
+    }
+
+    /// Refreshes every cached key now, one after another, like
+    /// [`force_refresh`](Self::force_refresh).
+    ///
+    /// The report lists the keys still cached afterwards as loaded and the
+    /// rest as missing.
+    pub fn refresh_all(&self) -> WarmReport<K> {
+        self.refresh_each(self.keys())
+    }
+
+    /// Like [`refresh_all`](Self::refresh_all), but fetches on up to
+    /// `threads` threads at once.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `threads` is zero.
+    pub fn refresh_all_parallel(&self, threads: usize) -> WarmReport<K>
+    where
+        K: Send,
+        Self: Sync,
+    {
+        assert!(
+            threads > 0,
+            "refresh_all_parallel needs at least one thread"
+        );
+        self.in_parallel(self.keys(), threads, |cache, batch| {
+            cache.refresh_each(batch)
+        })
+    }
+
+    fn refresh_each(&self, keys: impl IntoIterator<Item = K>) -> WarmReport<K> {
+        let mut report = WarmReport::new();
+        for key in keys {
+            let refreshed = self.force_refresh(&key);
+            report.record(key, refreshed.is_some());
+        }
+        report
+    }
+
+    /// Refreshes `keys` now like [`force_refresh`](Self::force_refresh), on up
+    /// to `concurrency` threads at once, and reports how each went.
+    ///
+    /// Unlike [`refresh_all_parallel`](Self::refresh_all_parallel), threads
+    /// take the next key as they finish one, so a few slow keys do not hold
+    /// up the rest, and keys that are not cached are fetched too. Reports
+    /// are in the order the keys finished.
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    /// use ttr_cache::{KeyRefresh, TTRCache};
+    ///
+    /// let cache = TTRCache::new(Duration::from_secs(60), |id: &u32| {
+    ///     (id % 2 == 0).then_some(id * 10)
+    /// });
+    ///
+    /// let mut reports = cache.refresh_keys(1..=4, 2);
+    /// reports.sort_by_key(|report| report.key);
+    /// let outcomes: Vec<_> = reports.iter().map(|report| report.outcome).collect();
+    /// assert_eq!(
+    ///     outcomes,
+    ///     [
+    ///         KeyRefresh::Missing,
+    ///         KeyRefresh::Refreshed,
+    ///         KeyRefresh::Missing,
+    ///         KeyRefresh::Refreshed,
+    ///     ]
+    /// );
+    /// assert_eq!(cache.get(&4), Some(40));
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if `concurrency` is zero.
+    pub fn refresh_keys(
+        &self,
+        keys: impl IntoIterator<Item = K>,
+        concurrency: usize,
+    ) -> Vec<RefreshReport<K>>
+    where
+        K: Send,
+        Self: Sync,
+    {
+        self.refresh_keys_with_progress(keys, concurrency, |_| {})
+    }
+
+    /// Like [`refresh_keys`](Self::refresh_keys), calling `progress` on the
+    /// calling thread with each report as its key finishes.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `concurrency` is zero.
+    pub fn refresh_keys_with_progress(
+        &self,
+        keys: impl IntoIterator<Item = K>,
+        concurrency: usize,
+        mut progress: impl FnMut(&RefreshReport<K>),
+    ) -> Vec<RefreshReport<K>>
+    where
+        K: Send,
+        Self: Sync,
+    {
+        assert!(
+            concurrency > 0,
+            "refresh_keys needs a concurrency of at least one"
+        );
+        let mut reports = Vec::new();
+        let mut record = |report: RefreshReport<K>| {
+            progress(&report);
+            reports.push(report);
+        };
+        let keys: Vec<K> = keys.into_iter().collect();
+        let threads = concurrency.min(keys.len());
+        if !THREADS || threads <= 1 {
+            for key in keys {
+                record(self.refresh_report(key));
+            }
+            return reports;
+        }
+
+        let keys = Mutex::new(keys.into_iter());
+        let (sender, receiver) = mpsc::channel();
+        thread::scope(|scope| {
+            for _ in 0..threads {
+                let sender = sender.clone();
+                let keys = &keys;
+                scope.spawn(move || loop {
+                    let Some(key) = keys.lock().unwrap_or_else(PoisonError::into_inner).next()
+                    else {
+                        return;
+                    };
+                    if sender.send(self.refresh_report(key)).is_err() {
+                        return;
+                    }
+                });
+            }
+            drop(sender);
+            receiver.into_iter().for_each(&mut record);
+        });
+        reports
+    }
+
+    /// Force-refreshes `key`, reporting how the fetch went.
+    fn refresh_report(&self, key: K) -> RefreshReport<K> {
+        let outcome = {
+            let key = &*self.shared.store.canonical(&key);
+            let current = self.shared.store.entry_info(key).map(EntryInfo::into_value);
+            let mut outcome = KeyRefresh::Failed;
+            let _ = self.shared.load_now(key, |_, key, context| {
+                let result = self.fetch(key, current.as_ref(), context);
+                if let Ok(fetched) = &result {
+                    outcome = KeyRefresh::fetched(fetched, |value| {
+                        self.shared.store.validates(key, value)
+                    });
+                }
+                result
+            });
+            outcome
+        };
+        RefreshReport { key, outcome }
+    }
+
+    /// Splits `keys` over up to `threads` threads, running `load` on each
+    /// share and merging the reports.
+    fn in_parallel(
+        &self,
+        keys: impl IntoIterator<Item = K>,
+        threads: usize,
+        load: impl Fn(&Self, Vec<K>) -> WarmReport<K> + Sync,
+    ) -> WarmReport<K>
+    where
+        K: Send,
+        Self: Sync,
+    {
+        if !THREADS {
+            return load(self, keys.into_iter().collect());
+        }
+        let mut batches: Vec<Vec<K>> = (0..threads).map(|_| Vec::new()).collect();
+        for (i, key) in keys.into_iter().enumerate() {
+            batches[i % threads].push(key);
+        }
+
+        thread::scope(|scope| {
+            let handles: Vec<_> = batches
+                .into_iter()
+                .filter(|batch| !batch.is_empty())
+                .map(|batch| scope.spawn(|| load(self, batch)))
+                .collect();
+
+            let mut report = WarmReport::new();
+            for handle in handles {
+                report.merge(
+                    handle
+                        .join()
+                        .unwrap_or_else(|panic| std::panic::resume_unwind(panic)),
+                );
+            }
+            report
+        })
+    }
+}
+
+impl<K, V, F> TTRCache<K, V, F>
+where
+    K: Eq + Hash + Clone + Send + Sync + 'static,
+    V: Clone + Send + Sync + 'static,
+    F: EntityFetcher<K, V> + Send + Sync + 'static,
+{
+    /// Creates a new cache that refreshes stale entries on a worker thread.
+    ///
+    /// Missing keys are still fetched on the calling thread, since there is
+    /// nothing to serve in the meantime. The worker exits when the cache is
+    /// dropped.
+    pub fn with_background_refresh(ttl: impl Into<RefreshPolicy>, fetcher: F) -> Self {
+        TTRCacheBuilder::new(ttl)
+            .refresh_mode(RefreshMode::Background)
+            .build_background(fetcher)
+    }
+}
+
+impl<K, V, F, S> Drop for TTRCache<K, V, F, S>
+where
+    K: Eq + Hash,
+{
+    fn drop(&mut self) {
+        // The janitor's channel closes with the cache.
+        self.shared.stop();
+    }
+}
@@ -0,0 +1,267 @@
+//! A single key's entry, looked up once and then acted on.
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hash};
+
+use crate::cache::TTRCache;
+use crate::fetcher::EntityFetcher;
+use crate::store::Lookup;
+
+impl<K, V, F, S> TTRCache<K, V, F, S>
+where
+    K: Eq + Hash + Clone,
+    V: Clone,
+    F: EntityFetcher<K, V>,
+    S: BuildHasher,
+{
+    /// Looks up `key` once, returning its entry to inspect and act on.
+    ///
+    /// Counts as a lookup like [`get`](Self::get), but never fetches by
+    /// itself. The entry is a snapshot: another thread may change the key
+    /// before it is acted on.
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    /// use ttr_cache::{CacheEntry, MockClock, TTRCache, TTRCacheBuilder};
+    ///
+    /// let clock = MockClock::new();
+    /// let cache: TTRCache<_, _, _> = TTRCacheBuilder::new(Duration::from_secs(60))
+    ///     .clock(clock.clone())
+    ///     .build(|id: &u32| Some(id * 10));
+    ///
+    /// assert!(matches!(cache.cache_entry(1), CacheEntry::Vacant(_)));
+    /// assert_eq!(cache.cache_entry(1).or_fetch(), Some(10));
+    ///
+    /// clock.advance(Duration::from_secs(90));
+    /// match cache.cache_entry(1) {
+    ///     CacheEntry::Stale(entry) if *entry.get() == 10 => entry.insert(11),
+    ///     _ => unreachable!(),
+    /// };
+    /// assert_eq!(cache.cache_entry(1).into_value(), Some(11));
+    /// assert_eq!(cache.cache_entry(2).or_insert_with(|| 0), 0);
+    /// ```
+    pub fn cache_entry(&self, key: K) -> CacheEntry<'_, K, V, F, S> {
+        let key = self.store().canonical_owned(key);
+        let (value, held) = match self.lookup_entry(&key) {
+            Lookup::Fresh(value) => (value, Held::Fresh),
+            Lookup::Expiring(value) => (value, Held::Expiring),
+            Lookup::Stale(value) => (value, Held::Stale),
+            Lookup::Retrying(value) => (value, Held::Retrying),
+            Lookup::Revalidate(value) => (value, Held::Revalidate),
+            Lookup::Absent => {
+                return CacheEntry::Vacant(VacantEntry {
+                    cache: self,
+                    key,
+                    absent: true,
+                })
+            }
+            Lookup::Missing => {
+                return CacheEntry::Vacant(VacantEntry {
+                    cache: self,
+                    key,
+                    absent: false,
+                })
+            }
+        };
+        let entry = OccupiedEntry {
+            cache: self,
+            key,
+            value,
+            held,
+        };
+        match held {
+            Held::Fresh | Held::Expiring => CacheEntry::Fresh(entry),
+            Held::Stale | Held::Retrying | Held::Revalidate => CacheEntry::Stale(entry),
+        }
+    }
+}
+
+/// A key's entry in a [`TTRCache`], returned by
+/// [`TTRCache::cache_entry`].
+pub enum CacheEntry<'a, K, V, F, S = RandomState>
+where
+    K: Eq + Hash,
+{
+    /// The value is younger than its TTR.
+    Fresh(OccupiedEntry<'a, K, V, F, S>),
+    /// The value is due for a refresh, but can still be served.
+    Stale(OccupiedEntry<'a, K, V, F, S>),
+    /// Nothing servable is cached.
+    Vacant(VacantEntry<'a, K, V, F, S>),
+}
+
+impl<'a, K, V, F, S> CacheEntry<'a, K, V, F, S>
+where
+    K: Eq + Hash + Clone,
+    V: Clone,
+    F: EntityFetcher<K, V>,
+    S: BuildHasher,
+{
+    /// The entry's key.
+    pub fn key(&self) -> &K {
+        match self {
+            CacheEntry::Fresh(entry) | CacheEntry::Stale(entry) => entry.key(),
+            CacheEntry::Vacant(entry) => entry.key(),
+        }
+    }
+
+    /// Returns the value, refreshing it if stale and fetching it if vacant,
+    /// exactly like [`get`](TTRCache::get) would have.
+    pub fn or_fetch(self) -> Option<V> {
+        match self {
+            CacheEntry::Fresh(entry) | CacheEntry::Stale(entry) => entry.serve(),
+            CacheEntry::Vacant(entry) => entry.fetch(),
+        }
+    }
+
+    /// Returns a fresh value, or stores and returns the one `init` computes
+    /// instead of refreshing or fetching.
+    pub fn or_insert_with(self, init: impl FnOnce() -> V) -> V {
+        match self {
+            CacheEntry::Fresh(entry) => entry.into_value(),
+            CacheEntry::Stale(entry) => {
+                let value = init();
+                entry.insert(value.clone());
+                value
+            }
+            CacheEntry::Vacant(entry) => entry.insert(init()),
+        }
+    }
+
+    /// Refreshes the key now, whatever its TTR. See
+    /// [`TTRCache::force_refresh`].
+    pub fn refresh_now(self) -> Option<V> {
+        match self {
+            CacheEntry::Fresh(entry) | CacheEntry::Stale(entry) => entry.refresh_now(),
+            CacheEntry::Vacant(entry) => entry.cache.force_refresh(&entry.key),
+        }
+    }
+
+    /// Returns the cached value, fresh or stale, without fetching.
+    pub fn into_value(self) -> Option<V> {
+        match self {
+            CacheEntry::Fresh(entry) | CacheEntry::Stale(entry) => Some(entry.into_value()),
+            CacheEntry::Vacant(_) => None,
+        }
+    }
+}
+
+/// What the lookup behind an [`OccupiedEntry`] found.
+#[derive(Clone, Copy)]
+enum Held {
+    Fresh,
+    Expiring,
+    Stale,
+    Retrying,
+    Revalidate,
+}
+
+/// A key with a cached value. Part of [`CacheEntry`].
+pub struct OccupiedEntry<'a, K, V, F, S = RandomState>
+where
+    K: Eq + Hash,
+{
+    cache: &'a TTRCache<K, V, F, S>,
+    key: K,
+    value: V,
+    held: Held,
+}
+
+impl<'a, K, V, F, S> OccupiedEntry<'a, K, V, F, S>
+where
+    K: Eq + Hash + Clone,
+    V: Clone,
+    F: EntityFetcher<K, V>,
+    S: BuildHasher,
+{
+    /// The entry's key.
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    /// The value as of the lookup.
+    pub fn get(&self) -> &V {
+        &self.value
+    }
+
+    /// Takes the value as of the lookup.
+    pub fn into_value(self) -> V {
+        self.value
+    }
+
+    /// Replaces the value as if it was just fetched, returning the old one.
+    pub fn insert(self, value: V) -> V {
+        self.cache.insert(self.key, value);
+        self.value
+    }
+
+    /// Removes the entry, returning its value if it was still cached.
+    pub fn remove(self) -> Option<V> {
+        self.cache.remove(&self.key)
+    }
+
+    /// Refreshes the key now, whatever its TTR. See
+    /// [`TTRCache::force_refresh`].
+    pub fn refresh_now(self) -> Option<V> {
+        self.cache.force_refresh(&self.key)
+    }
+
+    fn serve(self) -> Option<V> {
+        let lookup = match self.held {
+            Held::Fresh => Lookup::Fresh(self.value),
+            Held::Expiring => Lookup::Expiring(self.value),
+            Held::Stale => Lookup::Stale(self.value),
+            Held::Retrying => Lookup::Retrying(self.value),
+            Held::Revalidate => Lookup::Revalidate(self.value),
+        };
+        self.cache.serve(&self.key, lookup)
+    }
+}
+
+/// A key with nothing servable cached. Part of [`CacheEntry`].
+pub struct VacantEntry<'a, K, V, F, S = RandomState>
+where
+    K: Eq + Hash,
+{
+    cache: &'a TTRCache<K, V, F, S>,
+    key: K,
+    /// The key was recently fetched and not found.
+    absent: bool,
+}
+
+impl<'a, K, V, F, S> VacantEntry<'a, K, V, F, S>
+where
+    K: Eq + Hash + Clone,
+    V: Clone,
+    F: EntityFetcher<K, V>,
+    S: BuildHasher,
+{
+    /// The entry's key.
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    /// Takes the entry's key.
+    pub fn into_key(self) -> K {
+        self.key
+    }
+
+    /// Stores a value as if it was just fetched, and returns it.
+    pub fn insert(self, value: V) -> V {
+        self.cache.insert(self.key, value.clone());
+        value
+    }
+
+    /// Fetches the value like [`get`](TTRCache::get) would have.
+    ///
+    /// A key recently found missing is not fetched again while the negative
+    /// TTR lasts.
+    pub fn fetch(self) -> Option<V> {
+        let lookup = if self.absent {
+            Lookup::Absent
+        } else {
+            Lookup::Missing
+        };
+        self.cache.serve(&self.key, lookup)
+    }
+}
@@ -0,0 +1,192 @@
+//! Adapter to the `cached` crate's store trait.
+
+use std::borrow::Borrow;
+use std::collections::hash_map::RandomState;
+use std::convert::Infallible;
+use std::hash::{BuildHasher, Hash};
+
+use crate::cache::TTRCache;
+use crate::entry::EntryInfo;
+use crate::store::Lookup;
+
+/// A [`TTRCache`] usable wherever the `cached` crate expects a
+/// [`Cached`](::cached::Cached) store, such as its `#[cached]` macros.
+///
+/// `Cached` is a plain store: its callers compute missing values themselves
+/// and store them with `cache_set`. The adapter therefore never fetches, and
+/// reports values past their TTR as missing so they are recomputed. Since
+/// `Cached` hands out references, the adapter keeps a copy of the value read
+/// last; changes made through `cache_get_mut` only affect that copy.
+///
+/// With the `cached` feature.
+///
+/// ```rust
+/// use std::time::Duration;
+/// use cached::Cached;
+/// use ttr_cache::{CachedAdapter, TTRCache, TTRCacheBuilder};
+///
+/// let cache: TTRCache<String, u32, ()> = TTRCacheBuilder::new(Duration::from_secs(60))
+///     .build_cache_aside();
+/// let mut store = CachedAdapter::new(cache);
+///
+/// assert_eq!(store.cache_get("answer"), None);
+/// store.cache_set("answer".to_string(), 42);
+/// assert_eq!(store.cache_get("answer"), Some(&42));
+/// assert_eq!(*store.cache_get_or_set_with("question".to_string(), || 7), 7);
+/// assert_eq!(store.cache_size(), 2);
+/// assert_eq!(store.cache().stats().hits, 1);
+/// ```
+pub struct CachedAdapter<K, V, F, S = RandomState>
+where
+    K: Eq + Hash,
+{
+    cache: TTRCache<K, V, F, S>,
+    /// Copy of the value last handed out by reference.
+    held: Option<V>,
+}
+
+impl<K, V, F, S> CachedAdapter<K, V, F, S>
+where
+    K: Eq + Hash + Clone,
+    V: Clone,
+    S: BuildHasher,
+{
+    /// Wraps `cache`.
+    pub fn new(cache: TTRCache<K, V, F, S>) -> Self {
+        CachedAdapter { cache, held: None }
+    }
+
+    /// The wrapped cache.
+    pub fn cache(&self) -> &TTRCache<K, V, F, S> {
+        &self.cache
+    }
+
+    /// Returns the wrapped cache.
+    pub fn into_inner(self) -> TTRCache<K, V, F, S> {
+        self.cache
+    }
+
+    /// The value of `key` if it is within its TTR.
+    fn fresh<Q>(&self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let store = self.cache.store();
+        let lookup = store
+            .lookup_cached(key)
+            .or_else(|| store.lookup_in_memory(&store.key_of(key)?));
+        match lookup {
+            Some(Lookup::Fresh(value) | Lookup::Expiring(value)) => Some(value),
+            Some(_) => None,
+            None => {
+                store.record_miss();
+                None
+            }
+        }
+    }
+}
+
+impl<K, V, F, S> ::cached::Cached<K, V> for CachedAdapter<K, V, F, S>
+where
+    K: Eq + Hash + Clone,
+    V: Clone,
+    S: BuildHasher,
+{
+    type Error = Infallible;
+
+    fn cache_get<Q>(&mut self, k: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.held = self.fresh(k);
+        self.held.as_ref()
+    }
+
+    fn cache_get_mut<Q>(&mut self, k: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.held = self.fresh(k);
+        self.held.as_mut()
+    }
+
+    fn cache_set(&mut self, k: K, v: V) -> Option<V> {
+        let previous = self.cache.entry(&k).map(EntryInfo::into_value);
+        self.cache.insert(k, v);
+        previous
+    }
+
+    fn cache_get_or_set_with_mut<G: FnOnce() -> V>(&mut self, key: K, f: G) -> &mut V {
+        let value = self.fresh(&key).unwrap_or_else(|| {
+            let value = f();
+            self.cache.insert(key, value.clone());
+            value
+        });
+        self.held.insert(value)
+    }
+
+    fn cache_try_get_or_set_with_mut<G: FnOnce() -> Result<V, E>, E>(
+        &mut self,
+        key: K,
+        f: G,
+    ) -> Result<&mut V, E> {
+        let value = match self.fresh(&key) {
+            Some(value) => value,
+            None => {
+                let value = f()?;
+                self.cache.insert(key, value.clone());
+                value
+            }
+        };
+        Ok(self.held.insert(value))
+    }
+
+    fn cache_remove<Q>(&mut self, k: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let key = self.cache.store().key_of(k)?;
+        self.cache.remove(&key)
+    }
+
+    fn cache_remove_entry<Q>(&mut self, k: &Q) -> Option<(K, V)>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let key = self.cache.store().key_of(k)?;
+        let value = self.cache.remove(&key)?;
+        Some((key, value))
+    }
+
+    fn cache_clear(&mut self) {
+        self.held = None;
+        self.cache.clear();
+    }
+
+    /// Removes every entry. The cache's stats cannot be reset, so its hit
+    /// and miss counts are kept.
+    fn cache_reset(&mut self) {
+        self.cache_clear();
+    }
+
+    fn cache_size(&self) -> usize {
+        self.cache.len()
+    }
+
+    fn cache_hits(&self) -> Option<u64> {
+        Some(self.cache.stats().hits)
+    }
+
+    fn cache_misses(&self) -> Option<u64> {
+        Some(self.cache.stats().misses)
+    }
+
+    fn cache_capacity(&self) -> Option<usize> {
+        self.cache.store().max_capacity()
+    }
+}
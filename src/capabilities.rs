@@ -0,0 +1,99 @@
+//! The features this build of the crate was compiled with.
+
+/// Which optional parts of the crate are compiled in, for logging or
+/// checking a build's configuration at startup.
+///
+/// Each flag but `worker_threads` is the Cargo feature of the same name,
+/// with `async_fetchers` standing for `async`; see the crate documentation
+/// for what they enable.
+///
+#[cfg_attr(feature = "std", doc = "```rust")]
+#[cfg_attr(not(feature = "std"), doc = "```ignore")]
+/// let capabilities = ttr_cache::capabilities();
+/// assert!(capabilities.std);
+/// assert!(capabilities.features().any(|feature| feature == "std"));
+/// println!("ttr-cache features: {:?}", capabilities.features().collect::<Vec<_>>());
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub struct Capabilities {
+    /// The thread-safe caches, builder and everything that needs `std`.
+    pub std: bool,
+    /// `no_std` support with `alloc`.
+    pub alloc: bool,
+    /// Async fetchers and caches, the `async` feature.
+    pub async_fetchers: bool,
+    /// Snapshots and their persistence to files, the `serde` feature.
+    pub serde: bool,
+    /// Spans and events for fetches, refreshes and evictions.
+    pub tracing: bool,
+    /// The adapter to the `cached` crate's traits.
+    pub cached: bool,
+    /// The Redis second tier and invalidation bus.
+    pub redis: bool,
+    /// Test fetchers and invariant checks.
+    pub test_util: bool,
+    /// `tokio` watch channels and configuration caches.
+    pub tokio: bool,
+    /// The HTTP admin endpoint.
+    pub http_admin: bool,
+    /// Percentiles of served-value ages.
+    pub age_histogram: bool,
+    /// Lock-free reads from a swapped-in snapshot.
+    pub snapshot_swap: bool,
+    /// The `ttr-cache` binary.
+    pub cli: bool,
+    /// The `#[ttr_cached]` attribute.
+    pub macros: bool,
+    /// Whether background refreshes and janitors run on threads of their
+    /// own, which they cannot on `wasm32-unknown-unknown`.
+    pub worker_threads: bool,
+}
+
+/// The features this build of the crate was compiled with.
+pub const fn capabilities() -> Capabilities {
+    Capabilities {
+        std: cfg!(feature = "std"),
+        alloc: cfg!(feature = "alloc"),
+        async_fetchers: cfg!(feature = "async"),
+        serde: cfg!(feature = "serde"),
+        tracing: cfg!(feature = "tracing"),
+        cached: cfg!(feature = "cached"),
+        redis: cfg!(feature = "redis"),
+        test_util: cfg!(feature = "test-util"),
+        tokio: cfg!(feature = "tokio"),
+        http_admin: cfg!(feature = "http-admin"),
+        age_histogram: cfg!(feature = "age-histogram"),
+        snapshot_swap: cfg!(feature = "snapshot-swap"),
+        cli: cfg!(feature = "cli"),
+        macros: cfg!(feature = "macros"),
+        worker_threads: cfg!(feature = "std")
+            && !cfg!(all(target_arch = "wasm32", target_os = "unknown")),
+    }
+}
+
+impl Capabilities {
+    /// The names of the enabled Cargo features.
+    pub fn features(&self) -> impl Iterator<Item = &'static str> {
+        [
+            (self.std, "std"),
+            (self.alloc, "alloc"),
+            (self.async_fetchers, "async"),
+            (self.serde, "serde"),
+            (self.tracing, "tracing"),
+            (self.cached, "cached"),
+            (self.redis, "redis"),
+            (self.test_util, "test-util"),
+            (self.tokio, "tokio"),
+            (self.http_admin, "http-admin"),
+            (self.age_histogram, "age-histogram"),
+            (self.snapshot_swap, "snapshot-swap"),
+            (self.cli, "cli"),
+            (self.macros, "macros"),
+        ]
+        .into_iter()
+        .filter(|&(enabled, _)| enabled)
+        .map(|(_, name)| name)
+    }
+}
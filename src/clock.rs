@@ -0,0 +1,193 @@
+//! Time sources, so tests can control how entries age.
+
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+
+use crate::time::{Instant, SystemTime};
+
+/// Source of the current time for TTR bookkeeping.
+///
+/// Fetch latencies in the stats are always measured in real time.
+pub trait Clock {
+    /// Returns the current time.
+    fn now(&self) -> Instant;
+
+    /// Returns the current wall-clock time.
+    ///
+    /// Entries are dated with this when they leave the process, in snapshots
+    /// and the second tier, so their ages survive a restart. It should move
+    /// along with [`now`](Self::now). Defaults to [`SystemTime::now`].
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    /// use ttr_cache::{MockClock, TTRCache, TTRCacheBuilder};
+    ///
+    /// let before: TTRCache<_, _, ()> = TTRCacheBuilder::new(Duration::from_secs(60))
+    ///     .clock(MockClock::new())
+    ///     .build_cache_aside();
+    /// before.insert("config", 1);
+    /// let snapshot = before.snapshot();
+    ///
+    /// // A new process, started 45 seconds later.
+    /// let clock = MockClock::new();
+    /// clock.advance(Duration::from_secs(45));
+    /// let after: TTRCache<_, _, ()> = TTRCacheBuilder::new(Duration::from_secs(60))
+    ///     .clock(clock.clone())
+    ///     .build_cache_aside();
+    /// after.restore(snapshot);
+    /// assert!(after.entry(&"config").unwrap().age() >= Duration::from_secs(45));
+    ///
+    /// clock.advance(Duration::from_secs(15));
+    /// assert!(after.entry(&"config").unwrap().is_stale());
+    /// ```
+    fn wall_time(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// A clock's monotonic and wall-clock time, read together so timestamps can
+/// be converted between the two.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Timeline {
+    now: Instant,
+    wall_now: SystemTime,
+}
+
+impl Timeline {
+    pub(crate) fn read(clock: &(impl Clock + ?Sized)) -> Self {
+        Self::read_at(clock, clock.now())
+    }
+
+    /// Pairs `now`, just read from `clock`, with its wall-clock time.
+    pub(crate) fn read_at(clock: &(impl Clock + ?Sized), now: Instant) -> Self {
+        Timeline {
+            now,
+            wall_now: clock.wall_time(),
+        }
+    }
+
+    /// The monotonic time of the reading.
+    pub(crate) fn now(&self) -> Instant {
+        self.now
+    }
+
+    /// How much further the monotonic clock moved than the wall clock since
+    /// `earlier`.
+    pub(crate) fn drift_since(self, earlier: Timeline) -> Duration {
+        let wall = self
+            .wall_now
+            .duration_since(earlier.wall_now)
+            .unwrap_or_default();
+        self.now
+            .saturating_duration_since(earlier.now)
+            .saturating_sub(wall)
+    }
+
+    /// Converts a monotonic timestamp to wall-clock time.
+    pub(crate) fn to_system_time(self, instant: Instant) -> SystemTime {
+        self.wall_now
+            .checked_sub(self.now.saturating_duration_since(instant))
+            .unwrap_or(self.wall_now)
+    }
+
+    /// Converts a wall-clock timestamp back to monotonic time.
+    ///
+    /// Returns `None` if the time is too far back to represent, which can
+    /// happen when it predates the last reboot.
+    pub(crate) fn to_instant(self, time: SystemTime) -> Option<Instant> {
+        let age = self.wall_now.duration_since(time).unwrap_or_default();
+        self.now.checked_sub(age)
+    }
+}
+
+/// Clock backed by [`Instant::now`], used by default.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// Clock that only moves when told to.
+///
+/// Clones share the same time, so a test can keep one handle and give the
+/// other to the cache. Its wall-clock time starts at the real one and moves
+/// with it, so snapshots taken after [`advance`](Self::advance) date their
+/// entries accordingly; only [`jump`](Self::jump) moves one without the
+/// other.
+///
+/// ```rust
+/// use std::time::Duration;
+/// use ttr_cache::{Clock, EntityFetcher, MockClock, TTRCache, TTRCacheBuilder};
+///
+/// struct Backend;
+///
+/// impl EntityFetcher<u32, u32> for Backend {
+///     fn fetch_entity(&self, key: &u32) -> Option<u32> {
+///         Some(*key)
+///     }
+/// }
+///
+/// let clock = MockClock::new();
+/// let cache: TTRCache<_, _, _> = TTRCacheBuilder::new(Duration::from_secs(60))
+///     .clock(clock.clone())
+///     .build(Backend);
+///
+/// cache.get(&1);
+/// clock.advance(Duration::from_secs(59));
+/// assert!(!cache.entry(&1).unwrap().is_stale());
+/// clock.advance(Duration::from_secs(1));
+/// assert!(cache.entry(&1).unwrap().is_stale());
+/// ```
+#[derive(Debug, Clone)]
+pub struct MockClock {
+    now: Arc<Mutex<Instant>>,
+    /// How far the monotonic time has jumped ahead of the wall-clock time.
+    jumped: Arc<Mutex<Duration>>,
+    started: Instant,
+    wall_started: SystemTime,
+}
+
+impl MockClock {
+    /// Creates a clock stopped at the current time.
+    pub fn new() -> Self {
+        let started = Instant::now();
+        MockClock {
+            now: Arc::new(Mutex::new(started)),
+            jumped: Arc::new(Mutex::new(Duration::ZERO)),
+            started,
+            wall_started: SystemTime::now(),
+        }
+    }
+
+    /// Moves the clock forward by `duration`.
+    pub fn advance(&self, duration: Duration) {
+        *self.now.lock().unwrap_or_else(PoisonError::into_inner) += duration;
+    }
+
+    /// Moves the monotonic time forward by `duration`, leaving the
+    /// wall-clock time where it is, as a clock jump does.
+    pub fn jump(&self, duration: Duration) {
+        *self.jumped.lock().unwrap_or_else(PoisonError::into_inner) += duration;
+        self.advance(duration);
+    }
+}
+
+impl Default for MockClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        *self.now.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn wall_time(&self) -> SystemTime {
+        let jumped = *self.jumped.lock().unwrap_or_else(PoisonError::into_inner);
+        self.wall_started + self.now().duration_since(self.started) - jumped
+    }
+}
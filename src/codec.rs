@@ -0,0 +1,56 @@
+//! Transformations applied to values while they are cached.
+
+/// Converts values to and from the bytes the cache stores.
+///
+/// The cache encodes each value when it is stored and decodes it on every
+/// read, so the public API still deals in `V`. Useful for compressing large
+/// values, trading CPU time on each lookup for memory.
+///
+/// A [`Weigher`](crate::Weigher) still sees the decoded value.
+///
+/// ```rust
+/// use std::time::Duration;
+/// use ttr_cache::{EntityFetcher, TTRCache, TTRCacheBuilder, ValueCodec};
+///
+/// /// Stores runs of repeated bytes as (count, byte) pairs.
+/// struct RunLength;
+///
+/// impl ValueCodec<Vec<u8>> for RunLength {
+///     fn encode(&self, value: &Vec<u8>) -> Vec<u8> {
+///         let mut encoded = Vec::new();
+///         for chunk in value.chunk_by(|a, b| a == b) {
+///             for run in chunk.chunks(255) {
+///                 encoded.extend([run.len() as u8, run[0]]);
+///             }
+///         }
+///         encoded
+///     }
+///
+///     fn decode(&self, bytes: &[u8]) -> Vec<u8> {
+///         bytes
+///             .chunks(2)
+///             .flat_map(|pair| std::iter::repeat(pair[1]).take(pair[0].into()))
+///             .collect()
+///     }
+/// }
+///
+/// struct Blobs;
+///
+/// impl EntityFetcher<u32, Vec<u8>> for Blobs {
+///     fn fetch_entity(&self, _key: &u32) -> Option<Vec<u8>> {
+///         Some(vec![0; 4096])
+///     }
+/// }
+///
+/// let cache: TTRCache<_, _, _> = TTRCacheBuilder::new(Duration::from_secs(60))
+///     .codec(RunLength)
+///     .build(Blobs);
+/// assert_eq!(cache.get(&1), Some(vec![0; 4096]));
+/// ```
+pub trait ValueCodec<V> {
+    /// Encodes a value for storage.
+    fn encode(&self, value: &V) -> Vec<u8>;
+
+    /// Decodes bytes produced by [`encode`](Self::encode).
+    fn decode(&self, bytes: &[u8]) -> V;
+}
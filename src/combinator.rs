@@ -0,0 +1,355 @@
+//! Fetchers built from closures and other fetchers.
+
+use core::marker::PhantomData;
+
+#[cfg(feature = "std")]
+use std::collections::HashMap;
+#[cfg(feature = "std")]
+use std::hash::Hash;
+
+#[cfg(feature = "std")]
+use crate::context::FetchContext;
+#[cfg(feature = "std")]
+use crate::fetcher::BatchEntityFetcher;
+use crate::fetcher::{
+    BorrowingFetcher, EntityFetcher, FetchOutcome, RefreshOutcome, TryEntityFetcher,
+    UpdatingFetcher,
+};
+
+/// A closure used as an [`EntityFetcher`], without a newtype.
+///
+/// Closures implement the trait directly too; the wrapper gives them a type
+/// that can be named.
+///
+#[cfg_attr(feature = "std", doc = "```rust")]
+#[cfg_attr(not(feature = "std"), doc = "```ignore")]
+/// use std::time::Duration;
+/// use ttr_cache::{FnFetcher, TTRCache};
+///
+/// let cache = TTRCache::new(
+///     Duration::from_secs(60),
+///     FnFetcher(|id: &u64| Some(format!("user {id}"))),
+/// );
+/// assert_eq!(cache.get(&7).as_deref(), Some("user 7"));
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct FnFetcher<F>(pub F);
+
+impl<K, V, F> EntityFetcher<K, V> for FnFetcher<F>
+where
+    F: Fn(&K) -> Option<V>,
+{
+    fn fetch_entity(&self, key: &K) -> Option<V> {
+        (self.0)(key)
+    }
+}
+
+/// Tries a second fetcher whenever the first finds nothing or fails.
+///
+/// As a [`TryEntityFetcher`], a failure of the primary is only reported if
+/// the secondary does not find the entity either, and a failure of the
+/// secondary wins over the primary's "not found". Conditional refreshes ask
+/// the secondary again if the primary reports the entity gone.
+///
+#[cfg_attr(feature = "std", doc = "```rust")]
+#[cfg_attr(not(feature = "std"), doc = "```ignore")]
+/// use std::time::Duration;
+/// use ttr_cache::{FallbackFetcher, FnFetcher, TTRCache};
+///
+/// let replica = FnFetcher(|id: &u64| (*id < 10).then(|| format!("replica {id}")));
+/// let primary = FnFetcher(|id: &u64| (*id < 100).then(|| format!("primary {id}")));
+///
+/// let cache = TTRCache::new(Duration::from_secs(60), FallbackFetcher(replica, primary));
+/// assert_eq!(cache.get(&3).as_deref(), Some("replica 3"));
+/// assert_eq!(cache.get(&42).as_deref(), Some("primary 42"));
+/// assert_eq!(cache.get(&500), None);
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct FallbackFetcher<P, S>(pub P, pub S);
+
+impl<K, V, P, S> EntityFetcher<K, V> for FallbackFetcher<P, S>
+where
+    P: EntityFetcher<K, V>,
+    S: EntityFetcher<K, V>,
+{
+    fn fetch_entity(&self, key: &K) -> Option<V> {
+        self.0
+            .fetch_entity(key)
+            .or_else(|| self.1.fetch_entity(key))
+    }
+
+    fn fetch_if_modified(&self, key: &K, current: &V) -> FetchOutcome<V> {
+        match self.0.fetch_if_modified(key, current) {
+            FetchOutcome::NotFound => self.1.fetch_if_modified(key, current),
+            outcome => outcome,
+        }
+    }
+
+    #[cfg(feature = "std")]
+    fn fetch_with_context(
+        &self,
+        key: &K,
+        current: Option<&V>,
+        context: &FetchContext,
+    ) -> FetchOutcome<V> {
+        match self.0.fetch_with_context(key, current, context) {
+            FetchOutcome::NotFound => self.1.fetch_with_context(key, current, context),
+            outcome => outcome,
+        }
+    }
+}
+
+impl<K, V, E, P, S> TryEntityFetcher<K, V, E> for FallbackFetcher<P, S>
+where
+    P: TryEntityFetcher<K, V, E>,
+    S: TryEntityFetcher<K, V, E>,
+{
+    fn try_fetch_entity(&self, key: &K) -> Result<Option<V>, E> {
+        match self.0.try_fetch_entity(key) {
+            Ok(Some(entity)) => Ok(Some(entity)),
+            first => match self.1.try_fetch_entity(key) {
+                Ok(None) => first,
+                second => second,
+            },
+        }
+    }
+
+    fn try_fetch_if_modified(&self, key: &K, current: &V) -> Result<FetchOutcome<V>, E> {
+        match self.0.try_fetch_if_modified(key, current) {
+            Ok(FetchOutcome::NotFound) | Err(_) => {}
+            outcome => return outcome,
+        }
+        self.1.try_fetch_if_modified(key, current)
+    }
+
+    #[cfg(feature = "std")]
+    fn try_fetch_with_context(
+        &self,
+        key: &K,
+        current: Option<&V>,
+        context: &FetchContext,
+    ) -> Result<FetchOutcome<V>, E> {
+        match self.0.try_fetch_with_context(key, current, context) {
+            Ok(FetchOutcome::NotFound) | Err(_) => {}
+            outcome => return outcome,
+        }
+        self.1.try_fetch_with_context(key, current, context)
+    }
+}
+
+/// Asks the secondary only for the keys the primary left out.
+#[cfg(feature = "std")]
+impl<K, V, P, S> BatchEntityFetcher<K, V> for FallbackFetcher<P, S>
+where
+    K: Eq + Hash + Clone,
+    P: BatchEntityFetcher<K, V>,
+    S: BatchEntityFetcher<K, V>,
+{
+    fn fetch_entities(&self, keys: &[K]) -> HashMap<K, V> {
+        let mut found = self.0.fetch_entities(keys);
+        let missing: Vec<K> = keys
+            .iter()
+            .filter(|key| !found.contains_key(*key))
+            .cloned()
+            .collect();
+        if !missing.is_empty() {
+            found.extend(self.1.fetch_entities(&missing));
+        }
+        found
+    }
+}
+
+/// Uses an [`UpdatingFetcher`] as a fetcher, refreshing cached values by
+/// applying changes to them instead of fetching them whole.
+///
+/// As an [`EntityFetcher`], failures are reported as "not found"; use
+/// [`try_get`](crate::TTRCache::try_get) to see them.
+///
+#[cfg_attr(feature = "std", doc = "```rust")]
+#[cfg_attr(not(feature = "std"), doc = "```ignore")]
+/// use std::convert::Infallible;
+/// use std::sync::Mutex;
+/// use ttr_cache::{DeltaFetcher, RefreshOutcome, RefreshPolicy, TTRCache, UpdatingFetcher};
+///
+/// static EVENTS: Mutex<Vec<u32>> = Mutex::new(Vec::new());
+///
+/// /// An append-only log, of which clients fetch the entries they lack.
+/// struct Log;
+///
+/// impl UpdatingFetcher<&'static str, Vec<u32>> for Log {
+///     type Error = Infallible;
+///
+///     fn fetch_entity(&self, _key: &&'static str) -> Result<Option<Vec<u32>>, Infallible> {
+///         Ok(Some(EVENTS.lock().unwrap().clone()))
+///     }
+///
+///     fn apply_update(
+///         &self,
+///         _key: &&'static str,
+///         current: &mut Vec<u32>,
+///     ) -> Result<RefreshOutcome, Infallible> {
+///         let log = EVENTS.lock().unwrap();
+///         if log.len() == current.len() {
+///             return Ok(RefreshOutcome::Unchanged);
+///         }
+///         current.extend_from_slice(&log[current.len()..]);
+///         Ok(RefreshOutcome::Updated)
+///     }
+/// }
+///
+/// EVENTS.lock().unwrap().extend([1, 2]);
+/// let cache = TTRCache::new(RefreshPolicy::Always, DeltaFetcher(Log));
+/// assert_eq!(cache.get(&"events"), Some(vec![1, 2]));
+///
+/// EVENTS.lock().unwrap().push(3);
+/// assert_eq!(cache.get(&"events"), Some(vec![1, 2, 3]));
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct DeltaFetcher<F>(pub F);
+
+impl<K, V, F> TryEntityFetcher<K, V, F::Error> for DeltaFetcher<F>
+where
+    V: Clone,
+    F: UpdatingFetcher<K, V>,
+{
+    fn try_fetch_entity(&self, key: &K) -> Result<Option<V>, F::Error> {
+        self.0.fetch_entity(key)
+    }
+
+    fn try_fetch_if_modified(&self, key: &K, current: &V) -> Result<FetchOutcome<V>, F::Error> {
+        let mut updated = current.clone();
+        Ok(match self.0.apply_update(key, &mut updated)? {
+            RefreshOutcome::Updated => FetchOutcome::Modified(updated),
+            RefreshOutcome::Unchanged => FetchOutcome::NotModified,
+            RefreshOutcome::NotFound => FetchOutcome::NotFound,
+        })
+    }
+}
+
+impl<K, V, F> EntityFetcher<K, V> for DeltaFetcher<F>
+where
+    V: Clone,
+    F: UpdatingFetcher<K, V>,
+{
+    fn fetch_entity(&self, key: &K) -> Option<V> {
+        self.try_fetch_entity(key).ok().flatten()
+    }
+
+    fn fetch_if_modified(&self, key: &K, current: &V) -> FetchOutcome<V> {
+        self.try_fetch_if_modified(key, current)
+            .unwrap_or(FetchOutcome::NotFound)
+    }
+}
+
+/// Uses a [`BorrowingFetcher`] as a fetcher, querying it with a view
+/// borrowed from each key fetched.
+///
+#[cfg_attr(feature = "std", doc = "```rust")]
+#[cfg_attr(not(feature = "std"), doc = "```ignore")]
+/// use std::time::Duration;
+/// use ttr_cache::{BorrowedFetcher, BorrowingFetcher, TTRCache};
+///
+/// #[derive(Clone, PartialEq, Eq, Hash)]
+/// struct OrderKey {
+///     tenant: String,
+///     order: String,
+/// }
+///
+/// struct Orders;
+///
+/// impl BorrowingFetcher<OrderKey, String> for Orders {
+///     type Query<'a> = (&'a str, &'a str);
+///
+///     fn query<'a>(&self, key: &'a OrderKey) -> (&'a str, &'a str) {
+///         (&key.tenant, &key.order)
+///     }
+///
+///     fn fetch_query(&self, (tenant, order): (&str, &str)) -> Option<String> {
+///         Some(format!("{tenant}/{order}"))
+///     }
+/// }
+///
+/// let cache = TTRCache::new(Duration::from_secs(60), BorrowedFetcher(Orders));
+/// let key = OrderKey {
+///     tenant: "acme".to_string(),
+///     order: "17".to_string(),
+/// };
+/// assert_eq!(cache.get(&key).as_deref(), Some("acme/17"));
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct BorrowedFetcher<F>(pub F);
+
+impl<K, V, F> EntityFetcher<K, V> for BorrowedFetcher<F>
+where
+    F: BorrowingFetcher<K, V>,
+{
+    fn fetch_entity(&self, key: &K) -> Option<V> {
+        self.0.fetch_query(self.0.query(key))
+    }
+}
+
+/// Adapts a fetcher to other key and value types.
+///
+/// Keys are converted before fetching and values after. Refreshes are
+/// always unconditional, since cached values cannot be converted back.
+/// `V2` is the value type of the wrapped fetcher.
+///
+#[cfg_attr(feature = "std", doc = "```rust")]
+#[cfg_attr(not(feature = "std"), doc = "```ignore")]
+/// use std::time::Duration;
+/// use ttr_cache::{FnFetcher, MappedFetcher, TTRCache};
+///
+/// let by_id = FnFetcher(|id: &u64| Some(id * 10));
+/// let by_name = MappedFetcher::new(
+///     by_id,
+///     |name: &String| name.len() as u64,
+///     |score: u64| format!("{score} points"),
+/// );
+///
+/// let cache = TTRCache::new(Duration::from_secs(60), by_name);
+/// assert_eq!(cache.get(&"ada".to_string()).as_deref(), Some("30 points"));
+/// ```
+pub struct MappedFetcher<F, MK, MV, V2> {
+    fetcher: F,
+    map_key: MK,
+    map_value: MV,
+    fetched: PhantomData<fn(V2)>,
+}
+
+impl<F, MK, MV, V2> MappedFetcher<F, MK, MV, V2> {
+    /// Wraps `fetcher`, converting keys with `map_key` and values with
+    /// `map_value`.
+    pub fn new(fetcher: F, map_key: MK, map_value: MV) -> Self {
+        MappedFetcher {
+            fetcher,
+            map_key,
+            map_value,
+            fetched: PhantomData,
+        }
+    }
+}
+
+impl<K, V, K2, V2, F, MK, MV> EntityFetcher<K, V> for MappedFetcher<F, MK, MV, V2>
+where
+    F: EntityFetcher<K2, V2>,
+    MK: Fn(&K) -> K2,
+    MV: Fn(V2) -> V,
+{
+    fn fetch_entity(&self, key: &K) -> Option<V> {
+        self.fetcher
+            .fetch_entity(&(self.map_key)(key))
+            .map(&self.map_value)
+    }
+}
+
+impl<K, V, E, K2, V2, F, MK, MV> TryEntityFetcher<K, V, E> for MappedFetcher<F, MK, MV, V2>
+where
+    F: TryEntityFetcher<K2, V2, E>,
+    MK: Fn(&K) -> K2,
+    MV: Fn(V2) -> V,
+{
+    fn try_fetch_entity(&self, key: &K) -> Result<Option<V>, E> {
+        let entity = self.fetcher.try_fetch_entity(&(self.map_key)(key))?;
+        Ok(entity.map(&self.map_value))
+    }
+}
@@ -0,0 +1,131 @@
+//! A single configuration object kept up to date, with change notification.
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hash};
+
+use tokio::sync::watch::Receiver;
+
+use crate::fetcher::EntityFetcher;
+use crate::shared::SharedTTRCache;
+
+/// The latest value of one key of a shared cache, for configuration-style
+/// data that is read often and awaited on for changes.
+///
+/// [`current`](Self::current) reads through the cache, so the value is
+/// refreshed as its TTR says; [`changed`](Self::changed) waits for the next
+/// value, whichever handle or thread caused the refresh or insert. Backed by
+/// a `tokio` watch channel, see [`TTRCache::watch_channel`](crate::TTRCache::watch_channel).
+///
+/// Clones share the cache but track the values they have seen on their own.
+/// With the `tokio` feature.
+///
+/// ```rust
+/// # use std::future::Future;
+/// # use std::pin::pin;
+/// # use std::sync::Arc;
+/// # use std::task::{Context, Poll, Wake, Waker};
+/// # struct Noop;
+/// # impl Wake for Noop { fn wake(self: Arc<Self>) {} }
+/// # fn block_on<F: Future>(future: F) -> F::Output {
+/// #     let waker = Waker::from(Arc::new(Noop));
+/// #     let mut cx = Context::from_waker(&waker);
+/// #     let mut future = pin!(future);
+/// #     loop {
+/// #         if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+/// #             return output;
+/// #         }
+/// #     }
+/// # }
+/// use std::time::Duration;
+/// use ttr_cache::{ConfigCache, SharedTTRCache, TTRCache};
+///
+/// let cache = SharedTTRCache::new(TTRCache::new(Duration::from_secs(30), |_: &&str| {
+///     Some("log_level=info".to_string())
+/// }));
+/// let mut config = ConfigCache::new(cache.clone(), "service");
+/// assert_eq!(config.current().as_deref(), Some("log_level=info"));
+///
+/// cache.insert("service", "log_level=debug".to_string());
+/// assert_eq!(block_on(config.changed()).as_deref(), Some("log_level=debug"));
+/// assert_eq!(config.latest().as_deref(), Some("log_level=debug"));
+/// ```
+pub struct ConfigCache<K, V, F, S = RandomState>
+where
+    K: Eq + Hash,
+{
+    cache: SharedTTRCache<K, V, F, S>,
+    key: K,
+    receiver: Receiver<Option<V>>,
+}
+
+impl<K, V, F, S> ConfigCache<K, V, F, S>
+where
+    K: Eq + Hash + Clone,
+    V: Clone + PartialEq,
+    S: BuildHasher,
+{
+    /// Follows `key` in `cache`, starting from the value already cached, if
+    /// any.
+    pub fn new(cache: SharedTTRCache<K, V, F, S>, key: K) -> Self {
+        let receiver = cache.watch_channel(&key);
+        ConfigCache {
+            cache,
+            key,
+            receiver,
+        }
+    }
+
+    /// The value, refreshed or fetched first as [`TTRCache::get`](crate::TTRCache::get)
+    /// would.
+    ///
+    /// A new value is also published to every handle, this one included,
+    /// waiting on [`changed`](Self::changed).
+    pub fn current(&self) -> Option<V>
+    where
+        F: EntityFetcher<K, V>,
+    {
+        self.cache.get(&self.key)
+    }
+
+    /// The latest value published, without fetching or waiting.
+    pub fn latest(&self) -> Option<V> {
+        self.receiver.borrow().clone()
+    }
+
+    /// Waits for a value this handle has not seen yet.
+    ///
+    /// Resolves to `None` if the cache stops publishing values.
+    pub async fn changed(&mut self) -> Option<V> {
+        self.receiver.changed().await.ok()?;
+        self.receiver.borrow_and_update().clone()
+    }
+
+    /// The underlying watch channel, for `tokio::select!` and other tokio
+    /// utilities.
+    pub fn receiver(&self) -> Receiver<Option<V>> {
+        self.receiver.clone()
+    }
+
+    /// The key followed.
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    /// The cache the value is read from.
+    pub fn cache(&self) -> &SharedTTRCache<K, V, F, S> {
+        &self.cache
+    }
+}
+
+impl<K, V, F, S> Clone for ConfigCache<K, V, F, S>
+where
+    K: Eq + Hash + Clone,
+{
+    fn clone(&self) -> Self {
+        ConfigCache {
+            cache: self.cache.clone(),
+            key: self.key.clone(),
+            receiver: self.receiver.clone(),
+        }
+    }
+}
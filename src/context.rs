@@ -0,0 +1,283 @@
+//! Deadline and cancellation handed to fetchers.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, PoisonError, Weak};
+use std::task::{Context, Poll, Waker};
+use std::time::Duration;
+
+use crate::store::SharedClock;
+use crate::time::Instant;
+
+/// Lets a fetcher stop early once the cache no longer needs its result.
+///
+/// The cache cancels a fetch when it stops waiting for it after the
+/// [fetch timeout](crate::TTRCacheBuilder::fetch_timeout), when the key is
+/// removed or the cache cleared, and when a [`TTRCache`](crate::TTRCache) is
+/// shut down. The deadline is when the fetch timeout runs out; async caches
+/// do not enforce it, so async fetchers should apply it with their runtime's
+/// timer.
+///
+/// Passed to [`EntityFetcher::fetch_with_context`](crate::EntityFetcher::fetch_with_context)
+/// and, with the `async` feature, `AsyncEntityFetcher::fetch_with_context`.
+///
+/// ```rust
+/// use std::sync::atomic::{AtomicBool, Ordering};
+/// use std::sync::Arc;
+/// use std::thread;
+/// use std::time::Duration;
+/// use ttr_cache::{EntityFetcher, FetchContext, FetchOutcome, TTRCache, TTRCacheBuilder};
+///
+/// struct Reports {
+///     gave_up: Arc<AtomicBool>,
+/// }
+///
+/// impl EntityFetcher<u32, String> for Reports {
+///     fn fetch_entity(&self, key: &u32) -> Option<String> {
+///         Some(format!("report {key}"))
+///     }
+///
+///     fn fetch_with_context(
+///         &self,
+///         key: &u32,
+///         _current: Option<&String>,
+///         context: &FetchContext,
+///     ) -> FetchOutcome<String> {
+///         // A slow export, checked on until done.
+///         for _ in 0..1000 {
+///             if context.is_cancelled() {
+///                 self.gave_up.store(true, Ordering::SeqCst);
+///                 return FetchOutcome::NotFound;
+///             }
+///             thread::sleep(Duration::from_millis(1));
+///         }
+///         self.fetch_entity(key).into()
+///     }
+/// }
+///
+/// let gave_up = Arc::new(AtomicBool::new(false));
+/// let cache: TTRCache<_, _, _> = TTRCacheBuilder::new(Duration::from_secs(60))
+///     .fetch_timeout(Duration::from_millis(50))
+///     .build_background(Reports { gave_up: Arc::clone(&gave_up) });
+///
+/// assert_eq!(cache.get(&1), None);
+/// thread::sleep(Duration::from_millis(200));
+/// assert!(gave_up.load(Ordering::SeqCst));
+/// ```
+#[derive(Clone)]
+pub struct FetchContext {
+    deadline: Option<Instant>,
+    /// The cache's clock, which the deadline is measured by; real time
+    /// outside a cache.
+    clock: Option<SharedClock>,
+    token: Arc<Token>,
+}
+
+/// Cancellation state shared by the clones of a context.
+#[derive(Default)]
+struct Token {
+    cancelled: AtomicBool,
+    /// Tasks awaiting [`FetchContext::cancelled`].
+    wakers: Mutex<Wakers>,
+}
+
+/// The wakers of pending [`FetchContext::cancelled`] futures, each under
+/// its own id so a finished future takes its waker out again.
+#[derive(Default)]
+struct Wakers {
+    next_id: u64,
+    waiting: HashMap<u64, Waker>,
+}
+
+impl Token {
+    fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Acquire)
+    }
+
+    fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Release);
+        let wakers = std::mem::take(
+            &mut self
+                .wakers
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .waiting,
+        );
+        wakers.into_values().for_each(Waker::wake);
+    }
+}
+
+impl FetchContext {
+    /// Creates a context that is never cancelled and has no deadline, for
+    /// calling a fetcher outside a cache.
+    pub fn new() -> Self {
+        FetchContext {
+            deadline: None,
+            clock: None,
+            token: Arc::default(),
+        }
+    }
+
+    /// Creates a context whose deadline is `timeout` from now by `clock`.
+    pub(crate) fn timed(clock: SharedClock, timeout: Option<Duration>) -> Self {
+        FetchContext {
+            deadline: timeout.and_then(|timeout| clock.now().checked_add(timeout)),
+            clock: Some(clock),
+            token: Arc::default(),
+        }
+    }
+
+    /// When the cache stops waiting for the fetch, by the cache's
+    /// [clock](crate::TTRCacheBuilder::clock), if it has a timeout.
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    /// use ttr_cache::{EntityFetcher, FetchContext, FetchOutcome, MockClock, TTRCache, TTRCacheBuilder};
+    ///
+    /// struct TimeLeft;
+    ///
+    /// impl EntityFetcher<u32, Duration> for TimeLeft {
+    ///     fn fetch_entity(&self, _key: &u32) -> Option<Duration> {
+    ///         None
+    ///     }
+    ///
+    ///     fn fetch_with_context(
+    ///         &self,
+    ///         _key: &u32,
+    ///         _current: Option<&Duration>,
+    ///         context: &FetchContext,
+    ///     ) -> FetchOutcome<Duration> {
+    ///         context.remaining().into()
+    ///     }
+    /// }
+    ///
+    /// let clock = MockClock::new();
+    /// clock.advance(Duration::from_secs(3600));
+    /// let cache: TTRCache<_, _, _> = TTRCacheBuilder::new(Duration::from_secs(60))
+    ///     .clock(clock)
+    ///     .fetch_timeout(Duration::from_secs(30))
+    ///     .build_background(TimeLeft);
+    /// assert_eq!(cache.get(&1), Some(Duration::from_secs(30)));
+    /// ```
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    /// Time left until the [deadline](Self::deadline), zero once it passed.
+    pub fn remaining(&self) -> Option<Duration> {
+        let deadline = self.deadline?;
+        let now = self
+            .clock
+            .as_ref()
+            .map_or_else(Instant::now, |clock| clock.now());
+        Some(deadline.saturating_duration_since(now))
+    }
+
+    /// Whether the cache gave up on the fetch: it was cancelled, or its
+    /// deadline passed.
+    pub fn is_cancelled(&self) -> bool {
+        self.token.is_cancelled()
+            || self
+                .remaining()
+                .is_some_and(|remaining| remaining.is_zero())
+    }
+
+    /// Resolves once the fetch is cancelled, for racing against the fetch in
+    /// async code. The deadline passing does not wake it.
+    pub fn cancelled(&self) -> impl Future<Output = ()> + Send + 'static {
+        Cancelled {
+            token: Arc::clone(&self.token),
+            id: None,
+        }
+    }
+
+    /// Whether the fetch was cancelled, leaving the deadline aside.
+    pub(crate) fn was_cancelled(&self) -> bool {
+        self.token.is_cancelled()
+    }
+
+    /// Cancels the fetch, for every clone of the context.
+    pub(crate) fn cancel(&self) {
+        self.token.cancel();
+    }
+
+    /// A handle to cancel the fetch with, that does not keep it alive.
+    pub(crate) fn handle(&self) -> CancelHandle {
+        CancelHandle(Arc::downgrade(&self.token))
+    }
+}
+
+impl Default for FetchContext {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Cancels a context while it is still held by a fetch.
+pub(crate) struct CancelHandle(Weak<Token>);
+
+impl CancelHandle {
+    /// Whether this cancels `context`.
+    pub(crate) fn is_for(&self, context: &FetchContext) -> bool {
+        std::ptr::eq(self.0.as_ptr(), Arc::as_ptr(&context.token))
+    }
+
+    pub(crate) fn cancel(&self) {
+        if let Some(token) = self.0.upgrade() {
+            token.cancel();
+        }
+    }
+}
+
+struct Cancelled {
+    token: Arc<Token>,
+    /// The id its waker is kept under, once it waited.
+    id: Option<u64>,
+}
+
+impl Future for Cancelled {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let this = self.get_mut();
+        if this.token.is_cancelled() {
+            return Poll::Ready(());
+        }
+        let mut wakers = this
+            .token
+            .wakers
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        // Checked again under the lock, so a cancel in between still wakes.
+        if this.token.is_cancelled() {
+            return Poll::Ready(());
+        }
+        let id = *this.id.get_or_insert_with(|| {
+            wakers.next_id += 1;
+            wakers.next_id
+        });
+        match wakers.waiting.get_mut(&id) {
+            Some(waker) if waker.will_wake(cx.waker()) => {}
+            Some(waker) => waker.clone_from(cx.waker()),
+            None => {
+                wakers.waiting.insert(id, cx.waker().clone());
+            }
+        }
+        Poll::Pending
+    }
+}
+
+impl Drop for Cancelled {
+    fn drop(&mut self) {
+        if let Some(id) = self.id {
+            self.token
+                .wakers
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .waiting
+                .remove(&id);
+        }
+    }
+}
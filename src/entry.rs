@@ -0,0 +1,237 @@
+//! Metadata about individual cached entries.
+
+use std::fmt;
+use std::ops::Deref;
+use std::time::Duration;
+
+use crate::clock::Timeline;
+use crate::time::Instant;
+
+/// A cached value and its refresh bookkeeping, returned by `entry()`.
+///
+/// This is a snapshot: it is not updated when the entry is refreshed, and
+/// [`age`](Self::age) and [`is_stale`](Self::is_stale) are as of when it was
+/// taken.
+///
+/// With the `serde` feature it serializes its value, age, TTR and staleness,
+/// with the timestamps as wall-clock times. It cannot be deserialized, since
+/// the monotonic timestamps inside cannot be rebuilt.
+#[derive(Debug, Clone)]
+pub struct EntryInfo<V> {
+    pub(crate) observed: Timeline,
+    pub(crate) value: V,
+    pub(crate) inserted_at: Instant,
+    pub(crate) last_refreshed: Instant,
+    pub(crate) ttl: Duration,
+    pub(crate) invalidated: bool,
+}
+
+impl<V> EntryInfo<V> {
+    /// The cached value.
+    pub fn value(&self) -> &V {
+        &self.value
+    }
+
+    /// Takes the cached value.
+    pub fn into_value(self) -> V {
+        self.value
+    }
+
+    /// When the key was first cached.
+    ///
+    /// Refreshes keep this time; removing the entry resets it.
+    pub fn inserted_at(&self) -> Instant {
+        self.inserted_at
+    }
+
+    /// When the value was last fetched or inserted.
+    pub fn last_refreshed(&self) -> Instant {
+        self.last_refreshed
+    }
+
+    /// Time since the value was last fetched or inserted.
+    pub fn age(&self) -> Duration {
+        self.observed
+            .now()
+            .saturating_duration_since(self.last_refreshed)
+    }
+
+    /// The entry's TTR.
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    /// Whether the entry is past its TTR or was invalidated.
+    pub fn is_stale(&self) -> bool {
+        self.invalidated || self.age() >= self.ttl
+    }
+}
+
+/// A frequently read key, returned by `top_keys()`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct HotKey<K> {
+    /// The key.
+    pub key: K,
+    /// Reads served from the cache since the key was first cached.
+    pub hits: u64,
+    /// Time since the value was last fetched or inserted.
+    pub age: Duration,
+    /// Of the hits, how many found the value past its TTR or invalidated.
+    pub stale_hits: u64,
+    /// The furthest past its TTR the value was when read.
+    pub max_staleness: Duration,
+}
+
+/// How often a key was read and how stale, returned by `key_stats()`.
+///
+/// Counted since the key was first cached, across refreshes, like
+/// [`HotKey::hits`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct KeyStats {
+    /// Reads served from the cache, fresh or stale.
+    pub hits: u64,
+    /// Reads that found the value past its TTR or invalidated.
+    pub stale_hits: u64,
+    /// The furthest past its TTR the value was when read.
+    pub max_staleness: Duration,
+}
+
+/// Where a key is in its refresh cycle, returned by `entry_state()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryState {
+    /// The value is younger than its TTR.
+    Fresh,
+    /// The value is past its TTR or invalidated, and no refresh is running
+    /// or queued.
+    Stale,
+    /// A fetch of the key is in flight, or queued for a background worker.
+    Refreshing,
+    /// The last refresh failed, with the value left as it was.
+    Failed {
+        /// Refreshes that failed in a row.
+        attempts: u32,
+        /// When the last of them failed.
+        last_error_at: Instant,
+    },
+}
+
+/// How fresh a value returned by `get_detailed()` is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HitFreshness {
+    /// The value is younger than its TTR, cached or just fetched.
+    Fresh,
+    /// The value is past its TTR or invalidated, and served while it is, or
+    /// after its refresh failed.
+    Stale,
+}
+
+/// Why `get_detailed()` has a value for a key or not.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GetResult<V, E> {
+    /// A value was cached or fetched.
+    Hit {
+        /// The value.
+        value: V,
+        /// Whether it is still within its TTR.
+        freshness: HitFreshness,
+    },
+    /// Nothing was cached, and the fetcher did not find the key.
+    Missing,
+    /// The value cached was past its hard expiry, and fetching the key again
+    /// found nothing.
+    Expired,
+    /// Nothing servable was cached, and the fetch failed.
+    FetchFailed(E),
+}
+
+impl<V, E> GetResult<V, E> {
+    /// The value, if there is one, fresh or stale.
+    pub fn into_value(self) -> Option<V> {
+        match self {
+            GetResult::Hit { value, .. } => Some(value),
+            _ => None,
+        }
+    }
+}
+
+/// What the cache holds for a key, returned by `lookup()`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CacheLookup<V> {
+    /// The value is younger than its TTR.
+    Fresh(V),
+    /// The value is due for a refresh, but can still be served.
+    Stale(V),
+    /// Nothing servable is cached.
+    Missing,
+}
+
+#[cfg(feature = "serde")]
+impl<V: serde::Serialize> serde::Serialize for EntryInfo<V> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+
+        let mut info = serializer.serialize_struct("EntryInfo", 6)?;
+        info.serialize_field("value", &self.value)?;
+        info.serialize_field(
+            "inserted_at",
+            &self.observed.to_system_time(self.inserted_at),
+        )?;
+        info.serialize_field(
+            "last_refreshed",
+            &self.observed.to_system_time(self.last_refreshed),
+        )?;
+        info.serialize_field("age", &self.age())?;
+        info.serialize_field("ttl", &self.ttl)?;
+        info.serialize_field("is_stale", &self.is_stale())?;
+        info.end()
+    }
+}
+
+/// A cached value read in place, returned by
+/// [`TTRCache::get_guarded`](crate::TTRCache::get_guarded).
+///
+/// Dereferences to the value. While it borrows the cached value, it holds a
+/// read lock on the part of the cache storing the key.
+pub struct ValueGuard<'a, V> {
+    value: Guarded<'a, V>,
+}
+
+enum Guarded<'a, V> {
+    Locked(Box<dyn Deref<Target = V> + 'a>),
+    Owned(V),
+}
+
+impl<'a, V> ValueGuard<'a, V> {
+    /// A guard borrowing the value from `locked`.
+    pub(crate) fn locked(locked: impl Deref<Target = V> + 'a) -> Self {
+        ValueGuard {
+            value: Guarded::Locked(Box::new(locked)),
+        }
+    }
+
+    /// A guard holding its own copy of the value.
+    pub(crate) fn owned(value: V) -> Self {
+        ValueGuard {
+            value: Guarded::Owned(value),
+        }
+    }
+}
+
+impl<V> Deref for ValueGuard<'_, V> {
+    type Target = V;
+
+    fn deref(&self) -> &V {
+        match &self.value {
+            Guarded::Locked(locked) => locked,
+            Guarded::Owned(value) => value,
+        }
+    }
+}
+
+impl<V: fmt::Debug> fmt::Debug for ValueGuard<'_, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
@@ -0,0 +1,130 @@
+//! Error types.
+
+use std::error::Error as StdError;
+use std::{fmt, io};
+
+/// A fetch failed while looking up a key.
+///
+/// Carries the stale value that was cached before the failed refresh, if any.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FetchError<V, E> {
+    /// Error returned by the fetcher.
+    pub error: E,
+    /// Previously cached value, still available despite the failure.
+    pub stale: Option<V>,
+}
+
+impl<V, E: fmt::Display> fmt::Display for FetchError<V, E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "failed to fetch entity: {}", self.error)
+    }
+}
+
+impl<V: fmt::Debug, E: StdError + 'static> StdError for FetchError<V, E> {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        Some(&self.error)
+    }
+}
+
+/// Errors from cache operations other than fetching.
+///
+/// Fetch failures are reported as [`FetchError`], which keeps the fetcher's
+/// own error type and the stale value, and converts into
+/// [`FetchFailed`](Error::FetchFailed) for `?`. Reads and writes recover
+/// poisoned locks and carry on; only `save_to` reports them, as
+/// [`Poisoned`](Error::Poisoned), rather than persist contents a panic may
+/// have interrupted.
+///
+/// ```rust
+/// use std::time::Duration;
+/// use ttr_cache::{Error, TTRCache, TryEntityFetcher};
+///
+/// struct Offline;
+///
+/// impl TryEntityFetcher<u64, String, &'static str> for Offline {
+///     fn try_fetch_entity(&self, _key: &u64) -> Result<Option<String>, &'static str> {
+///         Err("connection refused")
+///     }
+/// }
+///
+/// fn name(cache: &TTRCache<u64, String, Offline>, id: u64) -> Result<Option<String>, Error> {
+///     Ok(cache.try_get(&id)?)
+/// }
+///
+/// let cache = TTRCache::new(Duration::from_secs(60), Offline);
+/// let error = name(&cache, 7).unwrap_err();
+/// assert!(matches!(error, Error::FetchFailed(_)));
+/// assert_eq!(error.to_string(), "fetch failed: connection refused");
+/// ```
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// Reading or writing a file failed.
+    Io(io::Error),
+    /// A builder setting is invalid, such as a zero TTR.
+    InvalidConfig(&'static str),
+    /// A key with nothing cached was looked up under
+    /// [`MissPolicy::Error`](crate::MissPolicy::Error).
+    UnexpectedMiss,
+    /// A key's entry was read past its hard expiry under
+    /// [`MissPolicy::Error`](crate::MissPolicy::Error).
+    Expired,
+    /// The fetcher failed, with its error.
+    FetchFailed(Box<dyn StdError + Send + Sync>),
+    /// A lock was poisoned by a panic while it was held.
+    Poisoned,
+    /// A snapshot could not be encoded or decoded.
+    #[cfg(feature = "serde")]
+    Serde(serde_json::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(error) => write!(f, "I/O error: {error}"),
+            Error::InvalidConfig(reason) => write!(f, "invalid configuration: {reason}"),
+            Error::UnexpectedMiss => write!(f, "key not in cache"),
+            Error::Expired => write!(f, "entry expired"),
+            Error::FetchFailed(error) => write!(f, "fetch failed: {error}"),
+            Error::Poisoned => write!(f, "lock poisoned by a panic"),
+            #[cfg(feature = "serde")]
+            Error::Serde(error) => write!(f, "invalid snapshot: {error}"),
+        }
+    }
+}
+
+impl StdError for Error {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            Error::Io(error) => Some(error),
+            Error::FetchFailed(error) => Some(&**error),
+            Error::InvalidConfig(_) | Error::UnexpectedMiss | Error::Expired | Error::Poisoned => {
+                None
+            }
+            #[cfg(feature = "serde")]
+            Error::Serde(error) => Some(error),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Self {
+        Error::Io(error)
+    }
+}
+
+impl<V, E> From<FetchError<V, E>> for Error
+where
+    E: Into<Box<dyn StdError + Send + Sync>>,
+{
+    fn from(error: FetchError<V, E>) -> Self {
+        Error::FetchFailed(error.error.into())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl From<serde_json::Error> for Error {
+    fn from(error: serde_json::Error) -> Self {
+        Error::Serde(error)
+    }
+}
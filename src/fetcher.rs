@@ -0,0 +1,524 @@
+//! Fetcher traits used to load entities into the cache.
+
+use alloc::boxed::Box;
+#[cfg(target_has_atomic = "ptr")]
+use alloc::sync::Arc;
+#[cfg(feature = "std")]
+use std::collections::HashMap;
+#[cfg(feature = "std")]
+use std::sync::{Mutex, PoisonError};
+
+#[cfg(feature = "std")]
+use crate::context::FetchContext;
+
+/// Result of a conditional fetch.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FetchOutcome<V> {
+    /// The entity changed; this is its new value.
+    Modified(V),
+    /// The cached value is still current.
+    NotModified,
+    /// The entity was not found.
+    NotFound,
+}
+
+impl<V> From<Option<V>> for FetchOutcome<V> {
+    fn from(entity: Option<V>) -> Self {
+        entity.map_or(FetchOutcome::NotFound, FetchOutcome::Modified)
+    }
+}
+
+/// Data source interface for fetching entities.
+///
+/// A panicking fetcher fails the fetch without taking the caller down:
+/// `TTRCache` and `AsyncTTRCache` catch the panic, count it in
+/// `CacheStats::fetch_panics` and serve what was cached, if anything.
+/// `LocalTTRCache` lets the panic unwind.
+///
+#[cfg_attr(feature = "std", doc = "```rust")]
+#[cfg_attr(not(feature = "std"), doc = "```ignore")]
+/// use std::time::Duration;
+/// use ttr_cache::{MockClock, TTRCache, TTRCacheBuilder};
+///
+/// let clock = MockClock::new();
+/// let cache: TTRCache<_, _, _> = TTRCacheBuilder::new(Duration::from_secs(60))
+///     .clock(clock.clone())
+///     .build(|_: &u32| -> Option<u32> { panic!("backend bug") });
+///
+/// cache.insert(1, 10);
+/// clock.advance(Duration::from_secs(90));
+/// assert_eq!(cache.get(&1), Some(10));
+/// assert_eq!(cache.get(&2), None);
+/// assert_eq!(cache.stats().fetch_panics, 2);
+/// ```
+pub trait EntityFetcher<K, V> {
+    /// Fetches an entity by key.
+    ///
+    /// Returns `Some(V)` if found, `None` otherwise.
+    fn fetch_entity(&self, key: &K) -> Option<V>;
+
+    /// Refetches an entity the cache already holds as `current`.
+    ///
+    /// Backends that support conditional requests, such as HTTP ETags, can
+    /// override this to return [`FetchOutcome::NotModified`]; the cache then
+    /// keeps `current` and restarts its TTR without storing a new value. The
+    /// version to compare against is typically a field of `current`.
+    ///
+    /// The cache calls this for refreshes and [`fetch_entity`](Self::fetch_entity)
+    /// for first loads, so overriding it also lets a fetcher tell the two
+    /// apart, for example to fetch only what changed since `current` and
+    /// return the merged value as [`FetchOutcome::Modified`].
+    ///
+    /// Defaults to an unconditional [`fetch_entity`](Self::fetch_entity).
+    ///
+    #[cfg_attr(feature = "std", doc = "```rust")]
+    #[cfg_attr(not(feature = "std"), doc = "```ignore")]
+    /// use std::time::Duration;
+    /// use ttr_cache::{EntityFetcher, FetchOutcome, RefreshPolicy, TTRCache};
+    ///
+    /// #[derive(Clone)]
+    /// struct Page {
+    ///     etag: u64,
+    ///     body: String,
+    /// }
+    ///
+    /// struct Server {
+    ///     etag: u64,
+    /// }
+    ///
+    /// impl EntityFetcher<String, Page> for Server {
+    ///     fn fetch_entity(&self, key: &String) -> Option<Page> {
+    ///         Some(Page { etag: self.etag, body: format!("contents of {key}") })
+    ///     }
+    ///
+    ///     fn fetch_if_modified(&self, key: &String, current: &Page) -> FetchOutcome<Page> {
+    ///         if current.etag == self.etag {
+    ///             FetchOutcome::NotModified
+    ///         } else {
+    ///             self.fetch_entity(key).into()
+    ///         }
+    ///     }
+    /// }
+    ///
+    /// let cache = TTRCache::new(RefreshPolicy::Always, Server { etag: 1 });
+    /// let page = Page { etag: 1, body: "kept".to_string() };
+    /// cache.insert("index".to_string(), page);
+    /// assert_eq!(cache.get(&"index".to_string()).unwrap().body, "kept");
+    /// ```
+    fn fetch_if_modified(&self, key: &K, current: &V) -> FetchOutcome<V> {
+        let _ = current;
+        self.fetch_entity(key).into()
+    }
+
+    /// Fetches for a cache: refetches `current` if the key is cached, or
+    /// fetches it anew, with a [`FetchContext`] telling when the cache gives
+    /// up on the result.
+    ///
+    /// Fetchers doing long or many-step work can override this to stop once
+    /// [`is_cancelled`](FetchContext::is_cancelled). The cache calls this
+    /// instead of [`fetch_entity`](Self::fetch_entity) and
+    /// [`fetch_if_modified`](Self::fetch_if_modified), which it defaults to;
+    /// the result of a cancelled fetch is discarded.
+    #[cfg(feature = "std")]
+    fn fetch_with_context(
+        &self,
+        key: &K,
+        current: Option<&V>,
+        context: &FetchContext,
+    ) -> FetchOutcome<V> {
+        let _ = context;
+        match current {
+            Some(current) => self.fetch_if_modified(key, current),
+            None => self.fetch_entity(key).into(),
+        }
+    }
+}
+
+/// Closures fetch by calling them.
+///
+#[cfg_attr(feature = "std", doc = "```rust")]
+#[cfg_attr(not(feature = "std"), doc = "```ignore")]
+/// use std::time::Duration;
+/// use ttr_cache::TTRCache;
+///
+/// let cache = TTRCache::new(Duration::from_secs(60), |id: &u64| Some(id * 2));
+/// assert_eq!(cache.get(&21), Some(42));
+/// ```
+impl<K, V, F> EntityFetcher<K, V> for F
+where
+    F: Fn(&K) -> Option<V>,
+{
+    fn fetch_entity(&self, key: &K) -> Option<V> {
+        self(key)
+    }
+}
+
+/// Shares one fetcher between several caches.
+///
+#[cfg_attr(feature = "std", doc = "```rust")]
+#[cfg_attr(not(feature = "std"), doc = "```ignore")]
+/// use std::sync::Arc;
+/// use std::time::Duration;
+/// use ttr_cache::{EntityFetcher, TTRCache};
+///
+/// struct Db;
+///
+/// impl EntityFetcher<u64, String> for Db {
+///     fn fetch_entity(&self, id: &u64) -> Option<String> {
+///         Some(format!("row {id}"))
+///     }
+/// }
+///
+/// let db = Arc::new(Db);
+/// let short = TTRCache::new(Duration::from_secs(10), Arc::clone(&db));
+/// let long = TTRCache::new(Duration::from_secs(600), db);
+/// assert_eq!(short.get(&1), long.get(&1));
+/// ```
+#[cfg(target_has_atomic = "ptr")]
+impl<K, V, F> EntityFetcher<K, V> for Arc<F>
+where
+    F: EntityFetcher<K, V> + ?Sized,
+{
+    fn fetch_entity(&self, key: &K) -> Option<V> {
+        (**self).fetch_entity(key)
+    }
+
+    fn fetch_if_modified(&self, key: &K, current: &V) -> FetchOutcome<V> {
+        (**self).fetch_if_modified(key, current)
+    }
+
+    #[cfg(feature = "std")]
+    fn fetch_with_context(
+        &self,
+        key: &K,
+        current: Option<&V>,
+        context: &FetchContext,
+    ) -> FetchOutcome<V> {
+        (**self).fetch_with_context(key, current, context)
+    }
+}
+
+/// Lets a cache hold a fetcher chosen at runtime.
+///
+/// `Box<F>` and `&F` are not covered for every fetcher `F`, as they would
+/// overlap with the impl for closures; box or borrow as a trait object, or
+/// use an `Arc`.
+///
+#[cfg_attr(feature = "std", doc = "```rust")]
+#[cfg_attr(not(feature = "std"), doc = "```ignore")]
+/// use std::time::Duration;
+/// use ttr_cache::{EntityFetcher, TTRCache};
+///
+/// let offline = false;
+/// let fetcher: Box<dyn EntityFetcher<u64, u64> + Send + Sync> = if offline {
+///     Box::new(|_: &u64| None)
+/// } else {
+///     Box::new(|id: &u64| Some(id + 1))
+/// };
+/// let cache = TTRCache::new(Duration::from_secs(60), fetcher);
+/// assert_eq!(cache.get(&1), Some(2));
+/// ```
+impl<K, V> EntityFetcher<K, V> for Box<dyn EntityFetcher<K, V> + Send + Sync> {
+    fn fetch_entity(&self, key: &K) -> Option<V> {
+        (**self).fetch_entity(key)
+    }
+
+    fn fetch_if_modified(&self, key: &K, current: &V) -> FetchOutcome<V> {
+        (**self).fetch_if_modified(key, current)
+    }
+
+    #[cfg(feature = "std")]
+    fn fetch_with_context(
+        &self,
+        key: &K,
+        current: Option<&V>,
+        context: &FetchContext,
+    ) -> FetchOutcome<V> {
+        (**self).fetch_with_context(key, current, context)
+    }
+}
+
+impl<K, V> EntityFetcher<K, V> for &(dyn EntityFetcher<K, V> + Send + Sync) {
+    fn fetch_entity(&self, key: &K) -> Option<V> {
+        (**self).fetch_entity(key)
+    }
+
+    fn fetch_if_modified(&self, key: &K, current: &V) -> FetchOutcome<V> {
+        (**self).fetch_if_modified(key, current)
+    }
+
+    #[cfg(feature = "std")]
+    fn fetch_with_context(
+        &self,
+        key: &K,
+        current: Option<&V>,
+        context: &FetchContext,
+    ) -> FetchOutcome<V> {
+        (**self).fetch_with_context(key, current, context)
+    }
+}
+
+/// Data source interface for fetchers that need `&mut self`, such as ones
+/// that rotate tokens or count requests.
+///
+/// Wrap one in a [`Mutex`] to use it as an [`EntityFetcher`]. The cache then
+/// runs one fetch at a time, so prefer atomics or a pool behind `&self` for
+/// fetchers that should fetch concurrently. Closures of the form
+/// `FnMut(&K) -> Option<V>` implement this trait.
+#[cfg(feature = "std")]
+pub trait EntityFetcherMut<K, V> {
+    /// Fetches an entity by key. See [`EntityFetcher::fetch_entity`].
+    fn fetch_entity(&mut self, key: &K) -> Option<V>;
+
+    /// Refetches an entity the cache already holds as `current`. See
+    /// [`EntityFetcher::fetch_if_modified`].
+    fn fetch_if_modified(&mut self, key: &K, current: &V) -> FetchOutcome<V> {
+        let _ = current;
+        self.fetch_entity(key).into()
+    }
+}
+
+#[cfg(feature = "std")]
+impl<K, V, F> EntityFetcherMut<K, V> for F
+where
+    F: FnMut(&K) -> Option<V>,
+{
+    fn fetch_entity(&mut self, key: &K) -> Option<V> {
+        self(key)
+    }
+}
+
+/// Fetches through a fetcher that needs `&mut self`, one fetch at a time.
+///
+/// A fetcher that panicked while fetching is used again as it was left.
+///
+/// ```rust
+/// use std::sync::Mutex;
+/// use std::time::Duration;
+/// use ttr_cache::{EntityFetcherMut, TTRCache};
+///
+/// struct Counting {
+///     requests: u32,
+/// }
+///
+/// impl EntityFetcherMut<u64, String> for Counting {
+///     fn fetch_entity(&mut self, id: &u64) -> Option<String> {
+///         self.requests += 1;
+///         Some(format!("user {id}, request {}", self.requests))
+///     }
+/// }
+///
+/// let cache = TTRCache::new(Duration::from_secs(60), Mutex::new(Counting { requests: 0 }));
+/// assert_eq!(cache.get(&7).as_deref(), Some("user 7, request 1"));
+/// assert_eq!(cache.get(&8).as_deref(), Some("user 8, request 2"));
+/// ```
+#[cfg(feature = "std")]
+impl<K, V, F> EntityFetcher<K, V> for Mutex<F>
+where
+    F: EntityFetcherMut<K, V>,
+{
+    fn fetch_entity(&self, key: &K) -> Option<V> {
+        self.lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .fetch_entity(key)
+    }
+
+    fn fetch_if_modified(&self, key: &K, current: &V) -> FetchOutcome<V> {
+        self.lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .fetch_if_modified(key, current)
+    }
+}
+
+/// Data source interface for fetches that can fail.
+///
+/// Separates "not found" (`Ok(None)`) from "could not ask" (`Err(E)`), so the
+/// cache can keep serving the stale value while reporting the failure.
+pub trait TryEntityFetcher<K, V, E> {
+    /// Fetches an entity by key.
+    ///
+    /// Returns `Ok(Some(V))` if found, `Ok(None)` if the entity does not
+    /// exist, and `Err(E)` if the fetch failed.
+    fn try_fetch_entity(&self, key: &K) -> Result<Option<V>, E>;
+
+    /// Refetches an entity the cache already holds as `current`.
+    ///
+    /// See [`EntityFetcher::fetch_if_modified`]. Defaults to an
+    /// unconditional [`try_fetch_entity`](Self::try_fetch_entity).
+    fn try_fetch_if_modified(&self, key: &K, current: &V) -> Result<FetchOutcome<V>, E> {
+        let _ = current;
+        self.try_fetch_entity(key).map(FetchOutcome::from)
+    }
+
+    /// Fetches for a cache, with a [`FetchContext`] telling when the cache
+    /// gives up on the result.
+    ///
+    /// See [`EntityFetcher::fetch_with_context`]. Defaults to
+    /// [`try_fetch_if_modified`](Self::try_fetch_if_modified) with `current`,
+    /// or [`try_fetch_entity`](Self::try_fetch_entity) without.
+    #[cfg(feature = "std")]
+    fn try_fetch_with_context(
+        &self,
+        key: &K,
+        current: Option<&V>,
+        context: &FetchContext,
+    ) -> Result<FetchOutcome<V>, E> {
+        let _ = context;
+        match current {
+            Some(current) => self.try_fetch_if_modified(key, current),
+            None => self.try_fetch_entity(key).map(FetchOutcome::from),
+        }
+    }
+}
+
+/// Shares one fallible fetcher between several caches.
+#[cfg(target_has_atomic = "ptr")]
+impl<K, V, E, F> TryEntityFetcher<K, V, E> for Arc<F>
+where
+    F: TryEntityFetcher<K, V, E> + ?Sized,
+{
+    fn try_fetch_entity(&self, key: &K) -> Result<Option<V>, E> {
+        (**self).try_fetch_entity(key)
+    }
+
+    fn try_fetch_if_modified(&self, key: &K, current: &V) -> Result<FetchOutcome<V>, E> {
+        (**self).try_fetch_if_modified(key, current)
+    }
+
+    #[cfg(feature = "std")]
+    fn try_fetch_with_context(
+        &self,
+        key: &K,
+        current: Option<&V>,
+        context: &FetchContext,
+    ) -> Result<FetchOutcome<V>, E> {
+        (**self).try_fetch_with_context(key, current, context)
+    }
+}
+
+/// Result of applying a backend's changes to a cached value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RefreshOutcome {
+    /// The value was changed in place.
+    Updated,
+    /// There was nothing to change.
+    Unchanged,
+    /// The entity was not found.
+    NotFound,
+}
+
+/// Data source interface for backends that send the changes to an entity
+/// rather than all of it, such as large collections with delta updates.
+///
+/// Wrap it in a [`DeltaFetcher`](crate::DeltaFetcher) to use it with a
+/// cache. First loads fetch the whole entity; refreshes hand the cached value
+/// to [`apply_update`](Self::apply_update) to be patched. Since readers may
+/// still hold the value being refreshed, the patch is applied to a clone of
+/// it, which then replaces it; for large values, cache an `Arc<T>` and patch
+/// through [`Arc::make_mut`].
+pub trait UpdatingFetcher<K, V> {
+    /// Why a fetch or update failed.
+    type Error;
+
+    /// Fetches an entity by key, like
+    /// [`TryEntityFetcher::try_fetch_entity`].
+    fn fetch_entity(&self, key: &K) -> Result<Option<V>, Self::Error>;
+
+    /// Brings `current` up to date by applying the changes made since it
+    /// was fetched.
+    ///
+    /// A failed update should leave `current` as it was, as should one
+    /// returning [`RefreshOutcome::Unchanged`] or
+    /// [`RefreshOutcome::NotFound`]; the changes are only kept when it
+    /// returns [`RefreshOutcome::Updated`].
+    fn apply_update(&self, key: &K, current: &mut V) -> Result<RefreshOutcome, Self::Error>;
+}
+
+/// Data source interface for backends queried with a borrowed view of the
+/// key, such as a tuple of references into an owned composite key.
+///
+/// Wrap it in a [`BorrowedFetcher`](crate::BorrowedFetcher) to use it with
+/// a cache. The cache keeps owning its keys and, as always, looks them up by
+/// any [`Borrow`](core::borrow::Borrow) form, so no key is built on a hit;
+/// the fetcher only ever sees the [`Query`](Self::Query) borrowed from the
+/// key being fetched.
+pub trait BorrowingFetcher<K, V> {
+    /// The borrowed form the backend is queried with.
+    type Query<'a>
+    where
+        K: 'a;
+
+    /// Borrows the query for `key`.
+    fn query<'a>(&self, key: &'a K) -> Self::Query<'a>;
+
+    /// Fetches the entity for `query`.
+    fn fetch_query(&self, query: Self::Query<'_>) -> Option<V>;
+}
+
+/// Data source interface for fetching many entities in one call.
+#[cfg(feature = "std")]
+pub trait BatchEntityFetcher<K, V> {
+    /// Fetches the entities for `keys`.
+    ///
+    /// Keys that were not found are left out of the returned map.
+    fn fetch_entities(&self, keys: &[K]) -> HashMap<K, V>;
+}
+
+/// What a [`FanOutFetcher`] found: the entity asked for, and others that
+/// came back with it.
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FetchResult<K, V> {
+    /// The entity for the key fetched, `None` if it was not found.
+    pub entity: Option<V>,
+    /// Other entities the same request returned, such as the rest of a
+    /// page or the items of a fetched list.
+    pub siblings: Vec<(K, V)>,
+}
+
+#[cfg(feature = "std")]
+impl<K, V> From<Option<V>> for FetchResult<K, V> {
+    fn from(entity: Option<V>) -> Self {
+        FetchResult {
+            entity,
+            siblings: Vec::new(),
+        }
+    }
+}
+
+/// Data source interface for backends that return more than the entity
+/// asked for.
+///
+/// Read through [`TTRCache::get_with_siblings`](crate::TTRCache::get_with_siblings),
+/// which caches the siblings along with the entity, so later lookups of
+/// them are hits.
+#[cfg(feature = "std")]
+pub trait FanOutFetcher<K, V> {
+    /// Fetches the entity for `key`, along with any others the request
+    /// happened to return.
+    fn fetch_entity_multi(&self, key: &K) -> FetchResult<K, V>;
+}
+
+/// Backend interface for writing entities, used by
+/// [`TTRCache::write_through`](crate::TTRCache::write_through).
+///
+/// Implemented for closures taking a key and a value and returning a
+/// `Result`.
+pub trait EntityWriter<K, V> {
+    /// Why a write failed.
+    type Error;
+
+    /// Writes `value` for `key` to the backend.
+    fn write_entity(&self, key: &K, value: &V) -> Result<(), Self::Error>;
+}
+
+impl<K, V, E, F> EntityWriter<K, V> for F
+where
+    F: Fn(&K, &V) -> Result<(), E>,
+{
+    type Error = E;
+
+    fn write_entity(&self, key: &K, value: &V) -> Result<(), E> {
+        self(key, value)
+    }
+}
@@ -0,0 +1,368 @@
+//! Request coalescing so each key has at most one fetch in flight.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::hash::Hash;
+use std::pin::Pin;
+use std::sync::{Arc, PoisonError};
+use std::task::{Context, Poll, Waker};
+
+use crate::sync::{Condvar, Mutex, MutexGuard};
+
+/// Progress of a single fetch.
+enum State<V> {
+    Running(Vec<Waker>),
+    Done(Option<V>),
+    /// The leader gave up (error, panic or cancellation) without a result.
+    Abandoned,
+}
+
+/// A fetch that other callers can wait on.
+pub(crate) struct Flight<V> {
+    state: Mutex<State<V>>,
+    finished: Condvar,
+    /// Identifies the cache whose caller is fetching.
+    origin: usize,
+}
+
+impl<V> Flight<V> {
+    /// Whether the fetch is for the cache identified by `origin`.
+    pub(crate) fn is_from(&self, origin: usize) -> bool {
+        self.origin == origin
+    }
+
+    fn running(origin: usize) -> Self {
+        Flight {
+            state: Mutex::new(State::Running(Vec::new())),
+            finished: Condvar::new(),
+            origin,
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State<V>> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn finish(&self, state: State<V>) {
+        let previous = std::mem::replace(&mut *self.lock(), state);
+        if let State::Running(wakers) = previous {
+            wakers.into_iter().for_each(Waker::wake);
+        }
+        self.finished.notify_all();
+    }
+}
+
+impl<V: Clone> Flight<V> {
+    /// Blocks until the leader finishes.
+    ///
+    /// Returns `None` if the leader abandoned the fetch, in which case the
+    /// caller should fetch for itself.
+    pub(crate) fn wait(&self) -> Option<Option<V>> {
+        let mut state = self.lock();
+        loop {
+            match &*state {
+                State::Running(_) => {
+                    state = self
+                        .finished
+                        .wait(state)
+                        .unwrap_or_else(PoisonError::into_inner);
+                }
+                State::Done(result) => return Some(result.clone()),
+                State::Abandoned => return None,
+            }
+        }
+    }
+
+    /// Async counterpart of [`wait`](Self::wait).
+    #[cfg_attr(not(feature = "async"), allow(dead_code))]
+    pub(crate) fn wait_async(self: Arc<Self>) -> impl Future<Output = Option<Option<V>>> {
+        WaitFlight { flight: self }
+    }
+}
+
+struct WaitFlight<V> {
+    flight: Arc<Flight<V>>,
+}
+
+impl<V: Clone> Future for WaitFlight<V> {
+    type Output = Option<Option<V>>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match &mut *self.flight.lock() {
+            State::Running(wakers) => {
+                if !wakers.iter().any(|waker| waker.will_wake(cx.waker())) {
+                    wakers.push(cx.waker().clone());
+                }
+                Poll::Pending
+            }
+            State::Done(result) => Poll::Ready(Some(result.clone())),
+            State::Abandoned => Poll::Ready(None),
+        }
+    }
+}
+
+/// Waits for a refresh started with
+/// [`TTRCache::refresh`](crate::TTRCache::refresh).
+///
+/// Resolves to the value cached once the refresh is done, or `None` if the
+/// key is no longer cached. A refresh dropped before it ran, because the
+/// cache was shut down or dropped, also resolves to `None`. Await it in async
+/// code, or block on it with [`wait`](Self::wait).
+pub struct RefreshHandle<V> {
+    flight: Arc<Flight<V>>,
+}
+
+impl<V: Clone> RefreshHandle<V> {
+    /// Creates a handle along with the sender completing it.
+    pub(crate) fn new() -> (Self, RefreshSender<V>) {
+        let flight = Arc::new(Flight::running(0));
+        let sender = RefreshSender {
+            flight: Some(Arc::clone(&flight)),
+        };
+        (RefreshHandle { flight }, sender)
+    }
+
+    /// Creates a handle for a refresh that already finished.
+    pub(crate) fn done(result: Option<V>) -> Self {
+        let (handle, sender) = Self::new();
+        sender.complete(result);
+        handle
+    }
+
+    /// Whether the refresh is done, so waiting would not block.
+    pub fn is_finished(&self) -> bool {
+        !matches!(*self.flight.lock(), State::Running(_))
+    }
+
+    /// Blocks until the refresh is done and returns its result.
+    pub fn wait(self) -> Option<V> {
+        self.flight.wait().flatten()
+    }
+}
+
+impl<V: Clone> Future for RefreshHandle<V> {
+    type Output = Option<V>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<V>> {
+        match &mut *self.flight.lock() {
+            State::Running(wakers) => {
+                if !wakers.iter().any(|waker| waker.will_wake(cx.waker())) {
+                    wakers.push(cx.waker().clone());
+                }
+                Poll::Pending
+            }
+            State::Done(result) => Poll::Ready(result.clone()),
+            State::Abandoned => Poll::Ready(None),
+        }
+    }
+}
+
+/// The refreshing end of a [`RefreshHandle`].
+///
+/// Dropping it without calling [`complete`](Self::complete) resolves the
+/// handle to `None`.
+pub(crate) struct RefreshSender<V> {
+    flight: Option<Arc<Flight<V>>>,
+}
+
+impl<V> RefreshSender<V> {
+    pub(crate) fn complete(mut self, result: Option<V>) {
+        if let Some(flight) = self.flight.take() {
+            flight.finish(State::Done(result));
+        }
+    }
+}
+
+impl<V> Drop for RefreshSender<V> {
+    fn drop(&mut self) {
+        if let Some(flight) = self.flight.take() {
+            flight.finish(State::Abandoned);
+        }
+    }
+}
+
+/// Outcome of trying to start a fetch.
+pub(crate) enum Claim<K: Eq + Hash, V: Clone> {
+    /// The caller must fetch and report through the guard.
+    Leader(FlightGuard<K, V>),
+    /// Another caller is already fetching this key.
+    Follower(Arc<Flight<V>>),
+}
+
+/// Fetches currently in flight, by key, so each key is fetched by at most
+/// one caller at a time.
+///
+/// Every cache has its own group unless given one with
+/// [`TTRCacheBuilder::flight_group`](crate::TTRCacheBuilder::flight_group).
+/// Clones share the same flights, so caches built with clones of one group
+/// coalesce their fetches: a cache missing a key another cache is fetching
+/// waits for that fetch and stores its result.
+///
+/// ```rust
+/// use std::time::Duration;
+/// use ttr_cache::{FlightGroup, TTRCache, TTRCacheBuilder};
+///
+/// let flights = FlightGroup::new();
+/// let build = || -> TTRCache<_, _, _> {
+///     TTRCacheBuilder::new(Duration::from_secs(60))
+///         .flight_group(flights.clone())
+///         .build(|id: &u32| Some(id * 10))
+/// };
+/// let (users, admins) = (build(), build());
+/// assert_eq!(users.get(&1), Some(10));
+/// assert_eq!(admins.get(&1), Some(10));
+/// ```
+pub struct FlightGroup<K, V> {
+    flights: Arc<Mutex<HashMap<K, Arc<Flight<V>>>>>,
+}
+
+impl<K, V> FlightGroup<K, V> {
+    /// Creates a group with nothing in flight.
+    pub fn new() -> Self {
+        FlightGroup {
+            flights: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+}
+
+impl<K, V> Clone for FlightGroup<K, V> {
+    fn clone(&self) -> Self {
+        FlightGroup {
+            flights: Arc::clone(&self.flights),
+        }
+    }
+}
+
+impl<K, V> Default for FlightGroup<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: Eq + Hash, V> FlightGroup<K, V> {
+    /// Whether a fetch of `key` is running.
+    pub(crate) fn is_in_flight(&self, key: &K) -> bool {
+        self.flights
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .contains_key(key)
+    }
+}
+
+impl<K, V> FlightGroup<K, V>
+where
+    K: Eq + Hash + Clone,
+    V: Clone,
+{
+    /// Joins the flight for `key`, starting one for the cache identified by
+    /// `origin` if none is running.
+    pub(crate) fn claim(&self, key: &K, origin: usize) -> Claim<K, V> {
+        let mut flights = self.flights.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(flight) = flights.get(key) {
+            return Claim::Follower(Arc::clone(flight));
+        }
+
+        let flight = Arc::new(Flight::running(origin));
+        flights.insert(key.clone(), Arc::clone(&flight));
+
+        Claim::Leader(FlightGuard {
+            group: self.clone(),
+            key: key.clone(),
+            flight,
+            finished: false,
+        })
+    }
+}
+
+/// Held by the caller performing a fetch.
+///
+/// Dropping the guard without calling [`complete`](Self::complete) abandons
+/// the flight, waking followers so they can fetch for themselves.
+///
+/// The guard holds its group, so it can outlive the cache's borrow, as in a
+/// spawned refresh task.
+pub(crate) struct FlightGuard<K: Eq + Hash, V> {
+    group: FlightGroup<K, V>,
+    key: K,
+    flight: Arc<Flight<V>>,
+    finished: bool,
+}
+
+impl<K: Eq + Hash, V> FlightGuard<K, V> {
+    /// Hands `result` to every follower.
+    pub(crate) fn complete(mut self, result: Option<V>) {
+        self.flight.finish(State::Done(result));
+        self.finished = true;
+    }
+}
+
+impl<K: Eq + Hash, V> Drop for FlightGuard<K, V> {
+    fn drop(&mut self) {
+        self.group
+            .flights
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(&self.key);
+
+        if !self.finished {
+            self.flight.finish(State::Abandoned);
+        }
+    }
+}
+
+#[cfg(all(test, loom))]
+mod loom_tests {
+    use loom::sync::atomic::{AtomicUsize, Ordering};
+    use loom::thread;
+
+    use super::*;
+
+    /// Callers racing for a key get the leader's result, whichever wins.
+    #[test]
+    fn followers_get_the_leaders_result() {
+        loom::model(|| {
+            let group = FlightGroup::<u32, u32>::new();
+            let leaders = Arc::new(AtomicUsize::new(0));
+            let callers: Vec<_> = (0..2)
+                .map(|_| {
+                    let (group, leaders) = (group.clone(), Arc::clone(&leaders));
+                    thread::spawn(move || match group.claim(&1, 0) {
+                        Claim::Leader(guard) => {
+                            leaders.fetch_add(1, Ordering::Relaxed);
+                            guard.complete(Some(10));
+                            Some(10)
+                        }
+                        Claim::Follower(flight) => flight.wait().flatten(),
+                    })
+                })
+                .collect();
+            for caller in callers {
+                assert_eq!(caller.join().unwrap(), Some(10));
+            }
+            assert!((1..=2).contains(&leaders.load(Ordering::Relaxed)));
+            assert!(!group.is_in_flight(&1));
+        });
+    }
+
+    /// A leader that gives up wakes its followers without a result.
+    #[test]
+    fn abandoned_flights_wake_followers() {
+        loom::model(|| {
+            let group = FlightGroup::<u32, u32>::new();
+            let Claim::Leader(guard) = group.claim(&1, 0) else {
+                panic!("first claim must lead");
+            };
+            let follower = {
+                let group = group.clone();
+                thread::spawn(move || match group.claim(&1, 0) {
+                    Claim::Leader(_) => None,
+                    Claim::Follower(flight) => Some(flight.wait()),
+                })
+            };
+            drop(guard);
+            // A follower that joined in time is told to fetch for itself.
+            assert!(matches!(follower.join().unwrap(), None | Some(None)));
+            assert!(!group.is_in_flight(&1));
+        });
+    }
+}
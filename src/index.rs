@@ -0,0 +1,254 @@
+//! Secondary indexes from values back to the keys caching them, and an
+//! ordered index of the keys.
+
+use std::any::Any;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::hash::Hash;
+use std::ops::RangeBounds;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+/// The indexes registered with
+/// [`TTRCacheBuilder::secondary_index`](crate::TTRCacheBuilder::secondary_index),
+/// at most one per index type.
+pub(crate) struct Indexes<K, V> {
+    indexes: Vec<Box<dyn ErasedIndex<K, V>>>,
+    /// The keys in order, if kept with
+    /// [`TTRCacheBuilder::ordered_keys`](crate::TTRCacheBuilder::ordered_keys).
+    order: Option<Box<dyn ErasedOrder<K>>>,
+}
+
+impl<K, V> Indexes<K, V> {
+    pub(crate) fn new() -> Self {
+        Indexes {
+            indexes: Vec::new(),
+            order: None,
+        }
+    }
+
+    /// Starts keeping the keys in order.
+    pub(crate) fn order_keys(&mut self)
+    where
+        K: Ord + Clone + Send + 'static,
+    {
+        self.order = Some(Box::new(KeyOrder {
+            keys: Mutex::new(BTreeSet::new()),
+        }));
+    }
+
+    /// Adds a newly cached key to the key order, if kept.
+    pub(crate) fn insert_key(&self, key: &K) {
+        if let Some(order) = &self.order {
+            order.insert(key);
+        }
+    }
+
+    /// The keys within `range`, in order, if the key order is kept.
+    pub(crate) fn keys_in_range<R>(&self, range: &R) -> Option<Vec<K>>
+    where
+        K: Ord + Clone + 'static,
+        R: RangeBounds<K>,
+    {
+        let order = self
+            .order
+            .as_ref()?
+            .as_any()
+            .downcast_ref::<KeyOrder<K>>()?;
+        Some(order.range(range))
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.indexes.is_empty()
+    }
+
+    /// Adds an index of the values `extract` returns, replacing any built
+    /// over the same type.
+    pub(crate) fn register<I>(&mut self, extract: impl Fn(&V) -> I + Send + Sync + 'static)
+    where
+        K: Eq + Hash + Clone + Send + 'static,
+        V: 'static,
+        I: Eq + Hash + Clone + Send + 'static,
+    {
+        self.indexes
+            .retain(|index| !index.as_any().is::<Index<K, V, I>>());
+        self.indexes.push(Box::new(Index {
+            extract: Box::new(extract),
+            state: Mutex::new(IndexState {
+                keys: HashMap::new(),
+                indexed: HashMap::new(),
+            }),
+        }));
+    }
+
+    /// Indexes `key` under what its new `value` maps to.
+    pub(crate) fn insert(&self, key: &K, value: &V) {
+        for index in &self.indexes {
+            index.insert(key, value);
+        }
+    }
+
+    pub(crate) fn remove(&self, key: &K) {
+        for index in &self.indexes {
+            index.remove(key);
+        }
+        if let Some(order) = &self.order {
+            order.remove(key);
+        }
+    }
+
+    pub(crate) fn clear(&self) {
+        for index in &self.indexes {
+            index.clear();
+        }
+        if let Some(order) = &self.order {
+            order.clear();
+        }
+    }
+
+    /// The keys indexed under `value` by the index over `I`, if there is
+    /// one.
+    pub(crate) fn keys<I>(&self, value: &I) -> Vec<K>
+    where
+        K: Clone + 'static,
+        V: 'static,
+        I: Eq + Hash + 'static,
+    {
+        self.indexes
+            .iter()
+            .find_map(|index| index.as_any().downcast_ref::<Index<K, V, I>>())
+            .map_or_else(Vec::new, |index| index.keys(value))
+    }
+}
+
+/// An [`Index`] with its value type erased.
+trait ErasedIndex<K, V>: Send + Sync {
+    fn insert(&self, key: &K, value: &V);
+    fn remove(&self, key: &K);
+    fn clear(&self);
+    fn as_any(&self) -> &dyn Any;
+}
+
+/// Keys by the value of type `I` their cached value maps to.
+struct Index<K, V, I> {
+    extract: Box<dyn Fn(&V) -> I + Send + Sync>,
+    state: Mutex<IndexState<K, I>>,
+}
+
+struct IndexState<K, I> {
+    keys: HashMap<I, HashSet<K>>,
+    /// What each key is indexed under, to unindex it by key alone.
+    indexed: HashMap<K, I>,
+}
+
+impl<K, I: Eq + Hash> IndexState<K, I> {
+    fn unindex(&mut self, key: &K, value: &I)
+    where
+        K: Eq + Hash,
+    {
+        if let Some(keys) = self.keys.get_mut(value) {
+            keys.remove(key);
+            if keys.is_empty() {
+                self.keys.remove(value);
+            }
+        }
+    }
+}
+
+impl<K, V, I> Index<K, V, I> {
+    fn state(&self) -> MutexGuard<'_, IndexState<K, I>> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn keys(&self, value: &I) -> Vec<K>
+    where
+        K: Clone,
+        I: Eq + Hash,
+    {
+        self.state()
+            .keys
+            .get(value)
+            .map_or_else(Vec::new, |keys| keys.iter().cloned().collect())
+    }
+}
+
+impl<K, V, I> ErasedIndex<K, V> for Index<K, V, I>
+where
+    K: Eq + Hash + Clone + Send + 'static,
+    V: 'static,
+    I: Eq + Hash + Clone + Send + 'static,
+{
+    fn insert(&self, key: &K, value: &V) {
+        let value = (self.extract)(value);
+        let mut state = self.state();
+        if let Some(previous) = state.indexed.insert(key.clone(), value.clone()) {
+            if previous == value {
+                return;
+            }
+            state.unindex(key, &previous);
+        }
+        state.keys.entry(value).or_default().insert(key.clone());
+    }
+
+    fn remove(&self, key: &K) {
+        let mut state = self.state();
+        if let Some(previous) = state.indexed.remove(key) {
+            state.unindex(key, &previous);
+        }
+    }
+
+    fn clear(&self) {
+        let mut state = self.state();
+        state.keys.clear();
+        state.indexed.clear();
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// A [`KeyOrder`] with its key ordering erased.
+trait ErasedOrder<K>: Send + Sync {
+    fn insert(&self, key: &K);
+    fn remove(&self, key: &K);
+    fn clear(&self);
+    fn as_any(&self) -> &dyn Any;
+}
+
+/// The cached keys, in order.
+struct KeyOrder<K> {
+    keys: Mutex<BTreeSet<K>>,
+}
+
+impl<K> KeyOrder<K> {
+    fn keys(&self) -> MutexGuard<'_, BTreeSet<K>> {
+        self.keys.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn range<R: RangeBounds<K>>(&self, range: &R) -> Vec<K>
+    where
+        K: Ord + Clone,
+    {
+        self.keys()
+            .range((range.start_bound(), range.end_bound()))
+            .cloned()
+            .collect()
+    }
+}
+
+impl<K: Ord + Clone + Send + 'static> ErasedOrder<K> for KeyOrder<K> {
+    fn insert(&self, key: &K) {
+        self.keys().insert(key.clone());
+    }
+
+    fn remove(&self, key: &K) {
+        self.keys().remove(key);
+    }
+
+    fn clear(&self) {
+        self.keys().clear();
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
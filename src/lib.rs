@@ -1,6 +1,7 @@
 //! TTR (Time-to-Refresh) cache that serves stale data while refreshing in background.
 //!
-//! ```rust
+#![cfg_attr(feature = "std", doc = "```rust")]
+#![cfg_attr(not(feature = "std"), doc = "```ignore")]
 //! use std::time::Duration;
 //! use ttr_cache::{EntityFetcher, TTRCache};
 //!
@@ -15,78 +16,348 @@
 //! }
 //!
 //! let cache = TTRCache::new(Duration::from_secs(300), MyDataSource);
+//! assert_eq!(cache.get("answer"), Some(42));
 //! ```
 //!
 //! - Generic keys and values
-//! - Configurable TTR
-//! - Thread-safe with sync primitives
+//! - Lookups by borrowed keys, such as `&str` for `String` keys
+//! - Optional key normalization, storing one entry per logical key
+//! - Reads of fresh values in place, without cloning them
+//! - Configurable TTR, globally, per entry or from the value itself, with
+//!   optional jitter, or refreshing always or never; zero TTRs and intervals
+//!   are rejected
+//! - Cache-wide and namespace TTRs changeable at runtime
+//! - Optional TTRs scaled by fetch latency, refreshing costly keys less often
+//! - Optional adaptive TTRs, lengthened while a value stays the same and
+//!   shortened when it changes
+//! - Optional hard expiry for entries that cannot be refreshed
+//! - Optional capacity limit with LRU eviction or TinyLFU admission, by entry
+//!   count or weight
+//! - Optional memory budget shared across caches, each evicting in proportion
+//!   to what it holds
+//! - Pinned entries that are never evicted or hard-expired
+//! - Fallible fetchers that report errors while serving stale data
+//! - Fetcher panics caught and counted as failed fetches
+//! - Optional validation of fetched values, keeping the previous value when
+//!   one is rejected, and an optional last known good value per key
+//! - Fetch timeouts, with a deadline and cancellation passed to fetchers so
+//!   they can stop early
+//! - Optional backoff between failed refreshes
+//! - Optional limit on serving stale data while refreshes fail
+//! - HTTP-style `max-age`, `stale-while-revalidate` and `stale-if-error`
+//!   windows, read from `Cache-Control` headers
+//! - Batch fetchers for refreshing many keys in one call
+//! - Fan-out fetchers whose responses also populate related keys
+//! - Fetcher combinators: closures as fetchers, fallbacks and type adapters
+//! - Fetchers queried with a view borrowed from the key
+//! - Write-through of application writes, cached only once the backend
+//!   accepts them
+//! - Stateful fetchers taking `&mut self`, run behind a `Mutex`
+//! - Conditional refreshes that keep values the backend reports unchanged
+//! - Delta refreshes that patch the cached value with the backend's changes
+//! - Configurable handling of refreshes that find nothing: keep the stale
+//!   value, remove it, leave a tombstone, or restart its TTR
+//! - Optional deduplication of refreshes that return an equal value
+//! - At most one fetch in flight per key, optionally across several caches
+//! - Refreshes that complete after their entry was removed, replaced or
+//!   invalidated are discarded
+//! - Optional limit on concurrent fetches across all keys
+//! - Optional circuit breaker that stops fetching from a failing backend for
+//!   a cool-down, serving stale values meanwhile, with its state in the stats
+//! - Optional negative caching of keys that were not found, and a Bloom filter
+//!   of them for keys probed over and over
+//! - Optional minimum interval between fetches of the same key
+//! - Optional fail-fast on misses, for preloaded caches that should never fetch
+//!   on the request path
+//! - Hit, miss and fetch statistics, with estimated memory usage and its peak
+//! - Per-entry age and staleness metadata, and the time left before a key
+//!   goes stale
+//! - Per-entry refresh states, including whether a refresh is running and
+//!   how often it has failed in a row
+//! - An entry API for acting on one key after a single lookup
+//! - Per-entry hit counts, to find the hottest keys
+//! - Iteration over cached entries without triggering refreshes
+//! - Secondary indexes for finding keys by a field of their value
+//! - Range scans, invalidation and removal over ordered keys, optionally
+//!   kept in a `BTreeSet` beside the entries
+//! - Pluggable storage for each shard's entries, such as a `BTreeMap` in
+//!   place of the default `HashMap`
+//! - Constant-time invalidation of the whole cache by bumping a cache-wide
+//!   generation, serving the old values until they are refreshed
+//! - Dependencies between keys, marking derived values stale when the values
+//!   they are built from change
+//! - Snapshots for warm starts, saved to disk with the `serde` feature
+//! - Dumps of the cache contents for debugging, and importing them elsewhere
+//! - `serde` support for snapshots, stats and entry metadata
+//! - JSON admin endpoints for stats, hot keys, entries and invalidation, on
+//!   `http` types for any framework, behind the `http-admin` feature
+//! - An adapter to the `cached` crate's `Cached` trait, with the `cached`
+//!   feature
+//! - Preloading a known set of hot keys, or seeding precomputed values in bulk
+//! - Forced refreshes of one key or every cached key, regardless of TTR, and
+//!   of large key sets with bounded parallelism and per-key progress
+//! - Background refreshes returning a handle to block on or await
+//! - Optional second tier, such as Redis or disk, behind the in-memory map,
+//!   with a Redis store shared between instances behind the `redis` feature
+//! - Invalidations shared between instances over a pluggable bus, with
+//!   Redis pub/sub behind the `redis` feature
+//! - Write-behind of refreshed values to a durable store, batched and
+//!   retried on a worker thread
+//! - Optional lock files so that processes sharing an on-disk cache do not
+//!   refresh the same key at once, or rewrite the cache file at once
+//! - Pluggable value codecs, such as compression, for stored values
+//! - Optional weak references to `Arc` values, dropped once unused
+//! - Event listeners for the entry lifecycle, told why each value left the
+//!   cache
+//! - Value lifecycle hooks that take ownership of replaced and evicted values,
+//!   to release the resources they hold
+//! - Sampled per-lookup callbacks with the outcome, age and fetch latency,
+//!   for analytics without logging every read
+//! - Percentiles of served-value ages and counts of reads over an age
+//!   threshold, for staleness objectives, behind the `age-histogram` feature
+//! - Re-dating of entries after clock jumps such as suspends, so they do not
+//!   all go stale at once on resume
+//! - Lock-free reads from a swapped-in snapshot for small, read-mostly caches,
+//!   behind the `snapshot-swap` feature
+//! - A `ttr-cache` binary caching command output on disk, behind the `cli`
+//!   feature
+//! - A `#[ttr_cached]` attribute memoizing functions by their arguments,
+//!   behind the `macros` feature
+//! - Per-key subscriptions to value changes, as events or as a watch on the
+//!   latest value
+//! - Thread-safe with sync primitives; `get` takes `&self`
+//! - Usable as a lazily initialized global in a `static`, or shared between
+//!   threads through the cloneable `SharedTTRCache`, or split into a write
+//!   handle and read handles that never fetch
+//! - Optional sharding to spread lock contention across keys
+//! - Pluggable clock, with a mock for deterministic tests
+//! - Invariant checks, a counting fetcher, a scripted fetcher and a fetcher
+//!   injecting failures and latency for tests, behind the `test-util` feature
+//! - A [`prelude`] of the commonly used items, for a single glob import
+//! - A runtime summary of the compiled features, for logging a build's
+//!   configuration at startup
+//! - Per-call choice between serving stale values and waiting for fresh ones,
+//!   or in async code both: the stale value now and the fresh one later
+//! - Lookups that tell a missing key from an expired value and a failed
+//!   fetch, and fresh hits from stale ones
+//! - Cache-aside use without a fetcher, reporting whether values are stale
+//! - Optional background refresh on worker threads, by per-read priority
+//!   and then hottest keys first,
+//!   within an optional budget of refreshes per interval, and optionally
+//!   batched over a short window
+//! - Optional janitor that purges expired and idle entries, and draining of
+//!   expired entries for archiving them
+//! - Optional expiry of entries that stop being read, which are then never
+//!   refreshed again
+//! - Per-key scheduled refreshes, periodic or as soon as a key goes stale, on
+//!   a hierarchical timer wheel, for keys that must stay fresh whether or not
+//!   they are read
+//! - Optional sweep refreshing the whole cache once per window, a slice of the
+//!   keys at a time, for configuration snapshots
+//! - Optional refresh-ahead for entries read shortly before going stale
+//! - A registry holding one cache per entity type, with shared defaults
+//! - Builder presets for name resolution, access tokens and feature flags
+//! - Namespaces sharing one cache, with their own TTRs, stats and clearing
+//! - Async fetchers behind the `async` feature, optionally refreshing in
+//!   tasks handed to the caller's spawner instead of awaiting
+//! - Configuration-style caches of one value with change notification, on
+//!   `tokio` watch channels behind the `tokio` feature
+//! - Builds for `wasm32-unknown-unknown`, with browser time and without worker
+//!   threads
+//! - `tracing` spans and events for fetches, refreshes, evictions and lock
+//!   waits, behind the `tracing` feature
+//! - `no_std` support with the `alloc` feature and without the default `std`
+//!   feature, limited to a single-threaded cache with an injected clock
 
-use std::collections::HashMap;
-use std::hash::Hash;
-use std::time::{Duration, Instant};
+#![cfg_attr(not(feature = "std"), no_std)]
 
-/// Data source interface for fetching entities.
-pub trait EntityFetcher<K, V> {
-    /// Fetches an entity by key.
-    ///
-    /// Returns `Some(V)` if found, `None` otherwise.
-    fn fetch_entity(&self, key: &K) -> Option<V>;
-}
+#[cfg(not(any(feature = "std", feature = "alloc")))]
+compile_error!("ttr-cache needs the `std` or the `alloc` feature");
 
-/// Cache that refreshes stale entries while serving them.
-///
-/// Types:
-/// - `K`: Key type (must be `Eq + Hash`)
-/// - `V`: Value type
-/// - `F`: Fetcher implementing `EntityFetcher<K, V>`
-pub struct TTRCache<K, V, F>
-where
-    K: Eq + Hash,
-    F: EntityFetcher<K, V>,
-{
-    ttl: Duration,
-    cache: HashMap<K, (Instant, V)>,
-    fetcher: F,
-}
+extern crate alloc;
 
-impl<K, V, F> TTRCache<K, V, F>
-where
-    K: Eq + Hash + Clone,
-    F: EntityFetcher<K, V>,
-{
-    /// Creates a new cache with given TTL and fetcher.
-    pub fn new(ttl: Duration, fetcher: F) -> Self {
-        TTRCache {
-            ttl,
-            cache: HashMap::new(),
-            fetcher,
-        }
-    }
+#[cfg(feature = "http-admin")]
+mod admin;
+#[cfg(feature = "age-histogram")]
+mod ages;
+#[cfg(feature = "async")]
+mod async_cache;
+#[cfg(feature = "std")]
+mod breaker;
+#[cfg(feature = "std")]
+mod budget;
+#[cfg(feature = "std")]
+mod builder;
+#[cfg(feature = "std")]
+mod bus;
+#[cfg(feature = "std")]
+mod cache;
+#[cfg(feature = "std")]
+mod cache_entry;
+#[cfg(feature = "cached")]
+mod cached_adapter;
+mod capabilities;
+#[cfg(feature = "std")]
+mod clock;
+#[cfg(feature = "std")]
+mod codec;
+mod combinator;
+#[cfg(feature = "tokio")]
+mod config_cache;
+#[cfg(feature = "std")]
+mod context;
+#[cfg(feature = "std")]
+mod entry;
+#[cfg(feature = "std")]
+mod error;
+mod fetcher;
+#[cfg(feature = "std")]
+mod flight;
+#[cfg(feature = "std")]
+mod index;
+#[cfg(feature = "std")]
+mod limit;
+#[cfg(feature = "std")]
+mod listener;
+#[cfg(any(feature = "std", feature = "alloc"))]
+mod local;
+#[cfg(feature = "std")]
+mod lock_file;
+#[cfg(feature = "std")]
+mod namespace;
+#[cfg(feature = "std")]
+mod policy;
+pub mod prelude;
+#[cfg(feature = "std")]
+mod queue;
+#[cfg(feature = "redis")]
+mod redis_bus;
+#[cfg(feature = "redis")]
+mod redis_store;
+#[cfg(feature = "std")]
+mod registry;
+#[cfg(feature = "std")]
+mod shared;
+#[cfg(feature = "std")]
+mod sketch;
+#[cfg(feature = "std")]
+mod snapshot;
+#[cfg(feature = "async")]
+mod spawn;
+#[cfg(feature = "std")]
+mod stats;
+#[cfg(feature = "std")]
+mod storage;
+#[cfg(feature = "std")]
+mod store;
+#[cfg(feature = "snapshot-swap")]
+mod swap;
+#[cfg(feature = "std")]
+mod sync;
+#[cfg(feature = "test-util")]
+pub mod test_util;
+#[cfg(all(test, feature = "std", not(loom)))]
+mod tests;
+#[cfg(feature = "std")]
+mod tiered;
+#[cfg(feature = "std")]
+pub mod time;
+#[cfg(feature = "tracing")]
+mod trace;
+#[cfg(feature = "std")]
+mod warm;
+#[cfg(feature = "std")]
+mod watch;
+#[cfg(feature = "std")]
+mod wheel;
+#[cfg(feature = "std")]
+mod write_behind;
 
-    fn fetch_entity(&mut self, key: &K) {
-        if let Some(entity) = self.fetcher.fetch_entity(key) {
-            self.cache.insert(key.clone(), (Instant::now(), entity));
-        }
-    }
-
-    fn refresh(&mut self, key: &K) {
-        if !self.cache.contains_key(key) {
-            self.fetch_entity(key);
-            return;
-        }
-
-        if let Some((timestamp, _)) = self.cache.get(key) {
-            if Instant::now().duration_since(*timestamp) >= self.ttl {
-                self.fetch_entity(key);
-            }
-        }
-    }
-
-    /// Gets a value, refreshing if stale.
-    ///
-    /// Returns `Some(&V)` if found, `None` otherwise.
-    pub fn get(&mut self, key: &K) -> Option<&V> {
-        self.refresh(key);
-        self.cache.get(key).map(|(_, entity)| entity)
-    }
-}
+#[cfg(feature = "http-admin")]
+pub use admin::CacheAdmin;
+#[cfg(feature = "async")]
+pub use async_cache::{AsyncEntityFetcher, AsyncTTRCache};
+#[cfg(feature = "std")]
+pub use breaker::BreakerState;
+#[cfg(feature = "std")]
+pub use budget::MemoryBudget;
+#[cfg(feature = "std")]
+pub use builder::{NoWorkers, TTRCacheBuilder, WithWorkers};
+#[cfg(feature = "std")]
+pub use bus::{InvalidationBus, InvalidationSink};
+#[cfg(feature = "std")]
+pub use cache::TTRCache;
+#[cfg(feature = "std")]
+pub use cache_entry::{CacheEntry, OccupiedEntry, VacantEntry};
+#[cfg(feature = "cached")]
+pub use cached_adapter::CachedAdapter;
+pub use capabilities::{capabilities, Capabilities};
+#[cfg(feature = "std")]
+pub use clock::{Clock, MockClock, SystemClock};
+#[cfg(feature = "std")]
+pub use codec::ValueCodec;
+pub use combinator::{BorrowedFetcher, DeltaFetcher, FallbackFetcher, FnFetcher, MappedFetcher};
+#[cfg(feature = "tokio")]
+pub use config_cache::ConfigCache;
+#[cfg(feature = "std")]
+pub use context::FetchContext;
+#[cfg(feature = "std")]
+pub use entry::{
+    CacheLookup, EntryInfo, EntryState, GetResult, HitFreshness, HotKey, KeyStats, ValueGuard,
+};
+#[cfg(feature = "std")]
+pub use error::{Error, FetchError};
+#[cfg(feature = "std")]
+pub use fetcher::{BatchEntityFetcher, EntityFetcherMut, FanOutFetcher, FetchResult};
+pub use fetcher::{
+    BorrowingFetcher, EntityFetcher, EntityWriter, FetchOutcome, RefreshOutcome, TryEntityFetcher,
+    UpdatingFetcher,
+};
+#[cfg(feature = "std")]
+pub use flight::{FlightGroup, RefreshHandle};
+#[cfg(feature = "std")]
+pub use listener::{
+    CacheEventListener, EvictionReason, LookupOutcome, LookupSample, RefreshEvent, ValueLifecycle,
+};
+#[cfg(any(feature = "std", feature = "alloc"))]
+pub use local::{LocalTTRCache, MonotonicClock};
+#[cfg(feature = "std")]
+pub use lock_file::FileLock;
+#[cfg(feature = "std")]
+pub use namespace::{Namespace, NamespacedKey};
+#[cfg(feature = "snapshot-swap")]
+pub use policy::Engine;
+#[cfg(feature = "std")]
+pub use policy::{
+    ClockJump, EvictionPolicy, Freshness, HttpCachePolicy, KeyNormalizer, MissPolicy,
+    OnMissingRefresh, Priority, RefreshMode, RefreshPolicy, RefreshStrategy, RetryPolicy,
+    StaleIfError, TtlFromValue, TtlPolicy, Validator, Weigher,
+};
+#[cfg(feature = "redis")]
+pub use redis_bus::RedisInvalidationBus;
+#[cfg(feature = "redis")]
+pub use redis_store::RedisStore;
+#[cfg(feature = "std")]
+pub use registry::{CacheRegistry, Entity};
+#[cfg(feature = "std")]
+pub use shared::{ReadHandle, SharedTTRCache, WriteHandle};
+#[cfg(feature = "std")]
+pub use snapshot::{EntrySnapshot, PersistedEntry, Snapshot};
+#[cfg(feature = "async")]
+pub use spawn::{RefreshTask, Spawner};
+#[cfg(feature = "std")]
+pub use stats::CacheStats;
+#[cfg(feature = "std")]
+pub use storage::Storage;
+#[cfg(feature = "std")]
+pub use store::Entry as StoredEntry;
+#[cfg(feature = "std")]
+pub use tiered::CacheStore;
+#[cfg(feature = "macros")]
+pub use ttr_cache_macros::ttr_cached;
+#[cfg(feature = "std")]
+pub use warm::{KeyRefresh, RefreshReport, WarmReport};
+#[cfg(feature = "std")]
+pub use watch::Watch;
+#[cfg(feature = "std")]
+pub use write_behind::WriteBehind;
@@ -0,0 +1,94 @@
+//! Limit on how many fetches run at once.
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Condvar, Mutex, MutexGuard, PoisonError};
+use std::task::{Context, Poll, Waker};
+
+struct Permits {
+    available: usize,
+    wakers: Vec<Waker>,
+}
+
+/// Counting semaphore that both threads and futures can wait on.
+pub(crate) struct FetchLimit {
+    permits: Mutex<Permits>,
+    released: Condvar,
+}
+
+/// A taken slot, given back on drop.
+pub(crate) struct Permit<'a> {
+    limit: &'a FetchLimit,
+}
+
+impl FetchLimit {
+    pub(crate) fn new(permits: usize) -> Self {
+        FetchLimit {
+            permits: Mutex::new(Permits {
+                available: permits,
+                wakers: Vec::new(),
+            }),
+            released: Condvar::new(),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Permits> {
+        self.permits.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Blocks until a slot is free and takes it.
+    pub(crate) fn acquire(&self) -> Permit<'_> {
+        let mut permits = self.lock();
+        while permits.available == 0 {
+            permits = self
+                .released
+                .wait(permits)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+        permits.available -= 1;
+        Permit { limit: self }
+    }
+
+    /// Async counterpart of [`acquire`](Self::acquire).
+    #[cfg_attr(not(feature = "async"), allow(dead_code))]
+    pub(crate) fn acquire_async(&self) -> impl Future<Output = Permit<'_>> {
+        Acquire { limit: self }
+    }
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        let wakers = {
+            let mut permits = self.limit.lock();
+            permits.available += 1;
+            std::mem::take(&mut permits.wakers)
+        };
+        self.limit.released.notify_one();
+        // Woken futures race for the slot; the losers register again.
+        wakers.into_iter().for_each(Waker::wake);
+    }
+}
+
+struct Acquire<'a> {
+    limit: &'a FetchLimit,
+}
+
+impl<'a> Future for Acquire<'a> {
+    type Output = Permit<'a>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Permit<'a>> {
+        let mut permits = self.limit.lock();
+        if permits.available == 0 {
+            if !permits
+                .wakers
+                .iter()
+                .any(|waker| waker.will_wake(cx.waker()))
+            {
+                permits.wakers.push(cx.waker().clone());
+            }
+            return Poll::Pending;
+        }
+        permits.available -= 1;
+        Poll::Ready(Permit { limit: self.limit })
+    }
+}
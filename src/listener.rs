@@ -0,0 +1,197 @@
+//! Hooks into the cache's entry lifecycle.
+
+use std::time::Duration;
+
+/// Receives notifications as entries enter, change and leave the cache.
+///
+/// Every method defaults to doing nothing, so implementors only override the
+/// events they care about. Listeners run on the thread that caused the event,
+/// after the cache has released its locks, so they may call back into the
+/// cache.
+pub trait CacheEventListener<K, V> {
+    /// A value was stored for a key that was not cached.
+    fn on_insert(&self, _key: &K, _value: &V) {}
+
+    /// A cached entry was replaced with a new value.
+    fn on_refresh(&self, _key: &K, _value: &V) {}
+
+    /// A fetch returned no value or failed.
+    fn on_refresh_failed(&self, _key: &K) {}
+
+    /// A fetch was given up on after the fetch timeout. It is reported as a
+    /// failed refresh too.
+    fn on_fetch_timeout(&self, _key: &K) {}
+
+    /// An entry was removed to make room under the capacity limit, or by a
+    /// purge after going unused for too long.
+    fn on_evict(&self, _key: &K, _value: &V) {}
+
+    /// An entry was removed after passing its hard expiry.
+    fn on_expire(&self, _key: &K, _value: &V) {}
+
+    /// An entry was removed because its refreshes kept failing past the
+    /// stale-if-error limit.
+    fn on_stale_if_error_exceeded(&self, _key: &K, _value: &V) {}
+
+    /// A value left the cache, for the given reason.
+    ///
+    /// Called for every removal and every replaced value, after the more
+    /// specific method for the event, if any. Marking an entry stale with
+    /// `invalidate` removes nothing and is not reported.
+    ///
+    /// ```rust
+    /// use std::sync::{Arc, Mutex};
+    /// use std::time::Duration;
+    /// use ttr_cache::{CacheEventListener, EvictionReason, TTRCache, TTRCacheBuilder};
+    ///
+    /// #[derive(Clone, Default)]
+    /// struct Removals(Arc<Mutex<Vec<(u32, EvictionReason)>>>);
+    ///
+    /// impl CacheEventListener<u32, u32> for Removals {
+    ///     fn on_removal(&self, key: &u32, _value: &u32, reason: EvictionReason) {
+    ///         self.0.lock().unwrap().push((*key, reason));
+    ///     }
+    /// }
+    ///
+    /// let removals = Removals::default();
+    /// let cache: TTRCache<_, _, _> = TTRCacheBuilder::new(Duration::from_secs(60))
+    ///     .max_capacity(1)
+    ///     .event_listener(removals.clone())
+    ///     .build(|id: &u32| Some(*id));
+    ///
+    /// cache.insert(1, 1);
+    /// cache.insert(1, 10);
+    /// cache.insert(2, 2);
+    /// cache.remove(&2);
+    /// assert_eq!(
+    ///     *removals.0.lock().unwrap(),
+    ///     [
+    ///         (1, EvictionReason::Replaced),
+    ///         (1, EvictionReason::Size),
+    ///         (2, EvictionReason::Explicit),
+    ///     ]
+    /// );
+    /// ```
+    fn on_removal(&self, _key: &K, _value: &V, _reason: EvictionReason) {}
+}
+
+/// Takes ownership of values as the cache lets go of them, to release what
+/// they hold, such as file handles or GPU buffers.
+///
+/// Each value the cache stored is passed on exactly once: to
+/// [`on_replace`](Self::on_replace) when a newer value replaces it, or to
+/// [`on_evict`](Self::on_evict) when its entry is evicted, expires, is
+/// removed or cleared, or the cache is dropped. Values handed back to the
+/// caller, by `remove` or `drain_expired`, are the caller's to release.
+/// Marking an entry stale with `invalidate` keeps its value. Both methods
+/// default to dropping the value, and run on the thread that caused the
+/// event, after the cache has released its locks.
+///
+/// ```rust
+/// use std::sync::{Arc, Mutex};
+/// use std::time::Duration;
+/// use ttr_cache::{TTRCache, TTRCacheBuilder, ValueLifecycle};
+///
+/// #[derive(Clone, Default)]
+/// struct Released(Arc<Mutex<Vec<String>>>);
+///
+/// impl ValueLifecycle<u32> for Released {
+///     fn on_replace(&self, old: u32) {
+///         self.0.lock().unwrap().push(format!("replaced buffer {old}"));
+///     }
+///
+///     fn on_evict(&self, value: u32) {
+///         self.0.lock().unwrap().push(format!("freed buffer {value}"));
+///     }
+/// }
+///
+/// let released = Released::default();
+/// let cache: TTRCache<_, _, _> = TTRCacheBuilder::new(Duration::from_secs(60))
+///     .max_capacity(1)
+///     .value_lifecycle(released.clone())
+///     .build(|id: &u32| Some(*id));
+///
+/// cache.insert(1, 10);
+/// cache.insert(1, 11);
+/// cache.insert(2, 20);
+/// drop(cache);
+/// assert_eq!(
+///     *released.0.lock().unwrap(),
+///     ["replaced buffer 10", "freed buffer 11", "freed buffer 20"]
+/// );
+/// ```
+pub trait ValueLifecycle<V> {
+    /// A newer value replaced `old` for its key.
+    fn on_replace(&self, _old: V) {}
+
+    /// `value` left the cache.
+    fn on_evict(&self, _value: V) {}
+}
+
+/// Why a value left the cache, passed to
+/// [`CacheEventListener::on_removal`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum EvictionReason {
+    /// Removed by `remove`, `remove_if` or `clear`, or by a refresh that
+    /// found nothing under
+    /// [`OnMissingRefresh::Remove`](crate::OnMissingRefresh::Remove) or
+    /// [`Tombstone`](crate::OnMissingRefresh::Tombstone).
+    Explicit,
+    /// Replaced by a new value for the same key.
+    Replaced,
+    /// Evicted to make room under the capacity or weight limit.
+    Size,
+    /// Past its hard expiry, unused for longer than the idle limit, or given
+    /// up on after failed refreshes.
+    Expired,
+}
+
+impl EvictionReason {
+    /// Whether the cache removed the value by itself, rather than the
+    /// application removing or replacing it.
+    pub fn was_evicted(&self) -> bool {
+        matches!(self, EvictionReason::Size | EvictionReason::Expired)
+    }
+}
+
+/// A refresh replaced a cached value with a different one.
+///
+/// Sent to the receivers returned by `subscribe`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RefreshEvent<K, V> {
+    /// Key whose value changed.
+    pub key: K,
+    /// Value that was replaced.
+    pub previous: V,
+    /// Value now cached.
+    pub value: V,
+}
+
+/// How a sampled lookup was served, passed in [`LookupSample`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum LookupOutcome {
+    /// A fresh value was cached.
+    Hit,
+    /// A stale value was cached, and was served or refreshed.
+    StaleHit,
+    /// Nothing was cached, or the key was remembered as not found.
+    Miss,
+}
+
+/// One lookup picked for sampling, passed to the callback given to
+/// [`TTRCacheBuilder::sample_lookups`](crate::TTRCacheBuilder::sample_lookups).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LookupSample<'a, K> {
+    /// The key looked up.
+    pub key: &'a K,
+    /// Whether it was a hit.
+    pub outcome: LookupOutcome,
+    /// Time since the cached value was fetched or inserted, if there was one.
+    pub age: Option<Duration>,
+    /// How long serving a value that was not fresh took, including any fetch
+    /// the lookup waited for; `None` for hits.
+    pub fetch_latency: Option<Duration>,
+}
@@ -0,0 +1,162 @@
+//! Single-threaded cache that only needs `alloc`, for `no_std` targets.
+
+use core::hash::Hash;
+use core::time::Duration;
+
+#[cfg(not(feature = "std"))]
+use hashbrown::HashMap;
+#[cfg(feature = "std")]
+use std::collections::HashMap;
+
+use crate::fetcher::{EntityFetcher, FetchOutcome};
+
+/// Source of monotonic time for [`LocalTTRCache`].
+///
+/// Returns the time elapsed since any fixed point, such as boot, for example
+/// from a hardware timer. It must never go backwards.
+pub trait MonotonicClock {
+    /// Returns the time elapsed since the clock's fixed point.
+    fn now(&self) -> Duration;
+}
+
+impl<F: Fn() -> Duration> MonotonicClock for F {
+    fn now(&self) -> Duration {
+        self()
+    }
+}
+
+struct LocalEntry<V> {
+    value: V,
+    fetched_at: Duration,
+    invalidated: bool,
+}
+
+/// Minimal TTR cache for targets without `std`.
+///
+/// Refreshes on access like `TTRCache`, but takes `&mut self`, has no
+/// capacity limit and no background work, and reads the time from an
+/// injected [`MonotonicClock`]. With the `std` feature off it is the only
+/// cache, and needs the `alloc` feature instead.
+///
+/// ```rust
+/// use std::cell::Cell;
+/// use std::time::Duration;
+/// use ttr_cache::{EntityFetcher, LocalTTRCache};
+///
+/// struct Sensor;
+///
+/// impl EntityFetcher<u8, u16> for Sensor {
+///     fn fetch_entity(&self, channel: &u8) -> Option<u16> {
+///         Some(u16::from(*channel) * 100)
+///     }
+/// }
+///
+/// let ticks = Cell::new(0);
+/// let mut cache = LocalTTRCache::new(Duration::from_secs(1), Sensor, || {
+///     Duration::from_millis(ticks.get())
+/// });
+///
+/// assert_eq!(cache.get(&3), Some(300));
+/// cache.insert(3, 301);
+/// ticks.set(1_500);
+/// assert_eq!(cache.get(&3), Some(300));
+/// ```
+pub struct LocalTTRCache<K, V, F, C> {
+    entries: HashMap<K, LocalEntry<V>>,
+    ttl: Duration,
+    fetcher: F,
+    clock: C,
+}
+
+impl<K, V, F, C> LocalTTRCache<K, V, F, C>
+where
+    K: Eq + Hash + Clone,
+    V: Clone,
+    C: MonotonicClock,
+{
+    /// Creates an empty cache with the given TTR, fetcher and clock.
+    pub fn new(ttl: Duration, fetcher: F, clock: C) -> Self {
+        LocalTTRCache {
+            entries: HashMap::new(),
+            ttl,
+            fetcher,
+            clock,
+        }
+    }
+
+    /// Gets a value, fetching it if it is missing or stale.
+    ///
+    /// A stale value is kept and returned when the refresh finds nothing.
+    pub fn get(&mut self, key: &K) -> Option<V>
+    where
+        F: EntityFetcher<K, V>,
+    {
+        let now = self.clock.now();
+        let outcome = match self.entries.get(key) {
+            Some(entry)
+                if !entry.invalidated && now.saturating_sub(entry.fetched_at) < self.ttl =>
+            {
+                return Some(entry.value.clone());
+            }
+            Some(entry) => self.fetcher.fetch_if_modified(key, &entry.value),
+            None => self.fetcher.fetch_entity(key).into(),
+        };
+
+        match outcome {
+            FetchOutcome::Modified(value) => {
+                self.insert(key.clone(), value.clone());
+                Some(value)
+            }
+            FetchOutcome::NotModified => {
+                let entry = self.entries.get_mut(key)?;
+                entry.fetched_at = self.clock.now();
+                entry.invalidated = false;
+                Some(entry.value.clone())
+            }
+            FetchOutcome::NotFound => self.entries.get(key).map(|entry| entry.value.clone()),
+        }
+    }
+
+    /// Stores a value as if it was just fetched.
+    pub fn insert(&mut self, key: K, value: V) {
+        let entry = LocalEntry {
+            value,
+            fetched_at: self.clock.now(),
+            invalidated: false,
+        };
+        self.entries.insert(key, entry);
+    }
+
+    /// Marks a key as stale, so the next `get` refreshes it.
+    ///
+    /// Returns `true` if the key was cached.
+    pub fn invalidate(&mut self, key: &K) -> bool {
+        match self.entries.get_mut(key) {
+            Some(entry) => {
+                entry.invalidated = true;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Removes a key, returning its value if it was cached.
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        self.entries.remove(key).map(|entry| entry.value)
+    }
+
+    /// Removes every entry.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// Number of cached entries, stale or not.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns `true` if nothing is cached.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
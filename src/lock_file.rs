@@ -0,0 +1,132 @@
+//! Lock files that keep processes sharing a directory from refreshing the
+//! same key at once, or from rewriting the same file at once.
+
+use std::collections::hash_map::DefaultHasher;
+use std::fs::{self, OpenOptions};
+use std::hash::{Hash, Hasher};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+/// How often a wait for a [`FileLock`] checks whether it is free.
+const POLL: Duration = Duration::from_millis(20);
+
+/// A directory holding one lock file per key being refreshed.
+pub(crate) struct LockDir {
+    dir: PathBuf,
+    /// Age after which a lock is taken to be left behind by a process that
+    /// died while refreshing.
+    lease: Duration,
+}
+
+/// Another process holds the lock.
+pub(crate) struct HeldElsewhere;
+
+/// A held lock, released when dropped.
+pub(crate) struct KeyLock {
+    path: PathBuf,
+}
+
+impl LockDir {
+    pub(crate) fn new(dir: PathBuf, lease: Duration) -> Self {
+        LockDir { dir, lease }
+    }
+
+    /// Takes the lock of `key`.
+    ///
+    /// Returns no lock if the lock file cannot be created for another reason
+    /// than being held, so the refresh goes ahead uncoordinated rather than
+    /// not at all.
+    pub(crate) fn lock(&self, key: &impl Hash) -> Result<Option<KeyLock>, HeldElsewhere> {
+        // The default hasher is deterministic, so processes running the same
+        // build agree on the file name.
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        let path = self.dir.join(format!("{:016x}.lock", hasher.finish()));
+        for _ in 0..2 {
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(_) => return Ok(Some(KeyLock { path })),
+                Err(error) if error.kind() == ErrorKind::AlreadyExists => {
+                    if !self.lapsed(&path) {
+                        return Err(HeldElsewhere);
+                    }
+                    let _ = fs::remove_file(&path);
+                }
+                Err(_) => return Ok(None),
+            }
+        }
+        Err(HeldElsewhere)
+    }
+
+    fn lapsed(&self, path: &Path) -> bool {
+        fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .map_or(true, |modified| {
+                SystemTime::now()
+                    .duration_since(modified)
+                    .unwrap_or_default()
+                    >= self.lease
+            })
+    }
+}
+
+/// An exclusive lock on a file shared between processes, held through a
+/// lock file beside it and released when dropped.
+///
+/// For tools that load a cache from a file with
+/// [`load_from`](crate::TTRCache::load_from) and write it back with
+/// [`save_to`](crate::TTRCache::save_to), so that runs sharing the file take
+/// turns instead of overwriting each other's entries.
+///
+/// ```rust
+/// use std::time::Duration;
+/// use ttr_cache::FileLock;
+///
+/// let path = std::env::temp_dir().join("ttr-cache-file-lock.json");
+/// let lock = FileLock::acquire(&path, Duration::from_secs(60));
+/// assert!(FileLock::try_acquire(&path, Duration::from_secs(60)).is_none());
+/// drop(lock);
+/// assert!(FileLock::try_acquire(&path, Duration::from_secs(60)).is_some());
+/// ```
+pub struct FileLock {
+    /// `None` if the lock file could not be created, so the file is used
+    /// unlocked rather than not at all.
+    _lock: Option<KeyLock>,
+}
+
+impl FileLock {
+    /// Waits for the lock on `path`, taking over a lock held for longer than
+    /// `lease` as left behind by a process that died holding it.
+    pub fn acquire(path: &Path, lease: Duration) -> FileLock {
+        loop {
+            if let Some(lock) = Self::try_acquire(path, lease) {
+                return lock;
+            }
+            thread::sleep(POLL);
+        }
+    }
+
+    /// Takes the lock on `path`, or returns `None` if another holder has had
+    /// it for less than `lease`.
+    ///
+    /// If the lock file cannot be created, such as in a read-only
+    /// directory, the lock is granted without one.
+    pub fn try_acquire(path: &Path, lease: Duration) -> Option<FileLock> {
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+            _ => PathBuf::from("."),
+        };
+        let name = path.file_name().unwrap_or(path.as_os_str());
+        match LockDir::new(dir, lease).lock(&name) {
+            Ok(lock) => Some(FileLock { _lock: lock }),
+            Err(HeldElsewhere) => None,
+        }
+    }
+}
+
+impl Drop for KeyLock {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
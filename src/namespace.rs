@@ -0,0 +1,241 @@
+//! Logical caches sharing one physical cache.
+
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hash};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::cache::TTRCache;
+use crate::error::Error;
+use crate::fetcher::EntityFetcher;
+use crate::policy::RefreshPolicy;
+use crate::stats::{CacheStats, StatsCounter};
+
+/// TTRs set per namespace with `TTRCacheBuilder::namespace_ttl`.
+pub(crate) struct NamespaceTtls<K> {
+    /// [`NamespacedKey::namespace`], captured so the store does not need to
+    /// know its key type.
+    pub(crate) namespace_of: fn(&K) -> &str,
+    pub(crate) ttls: HashMap<Arc<str>, Duration>,
+}
+
+impl<K> NamespaceTtls<K> {
+    pub(crate) fn ttl_for(&self, key: &K) -> Option<Duration> {
+        self.ttls.get((self.namespace_of)(key)).copied()
+    }
+}
+
+/// Key of a cache shared between namespaces: the namespace and the key
+/// within it.
+///
+/// Fetchers for such a cache implement `EntityFetcher<NamespacedKey<K>, V>`
+/// and can tell the namespaces apart with [`namespace`](Self::namespace).
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct NamespacedKey<K> {
+    namespace: Arc<str>,
+    key: K,
+}
+
+impl<K> NamespacedKey<K> {
+    /// Creates the key for `key` within `namespace`.
+    pub fn new(namespace: impl Into<Arc<str>>, key: K) -> Self {
+        NamespacedKey {
+            namespace: namespace.into(),
+            key,
+        }
+    }
+
+    /// The namespace the key belongs to.
+    pub fn namespace(&self) -> &str {
+        &self.namespace
+    }
+
+    /// The key within its namespace.
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    /// Returns the key within its namespace.
+    pub fn into_key(self) -> K {
+        self.key
+    }
+}
+
+impl<K, V, F, S> TTRCache<NamespacedKey<K>, V, F, S>
+where
+    K: Eq + Hash + Clone,
+    V: Clone,
+    S: BuildHasher,
+{
+    /// Returns a handle on the namespace `name`, scoping keys to it.
+    ///
+    /// Handles are cheap, and all handles on a namespace share its
+    /// entries and stats.
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    /// use ttr_cache::{NamespacedKey, TTRCache, TTRCacheBuilder};
+    ///
+    /// let cache: TTRCache<_, _, _> = TTRCacheBuilder::new(Duration::from_secs(300))
+    ///     .namespace_ttl("sessions", Duration::from_secs(30))
+    ///     .build(|key: &NamespacedKey<u64>| {
+    ///         Some(format!("{} {}", key.namespace(), key.key()))
+    ///     });
+    ///
+    /// let users = cache.namespace("users");
+    /// let sessions = cache.namespace("sessions");
+    /// assert_eq!(users.get(&1).as_deref(), Some("users 1"));
+    /// assert_eq!(sessions.get(&1).as_deref(), Some("sessions 1"));
+    /// assert_eq!(users.stats().misses, 1);
+    ///
+    /// let session = cache.entry(&NamespacedKey::new("sessions", 1)).unwrap();
+    /// assert_eq!(session.ttl(), Duration::from_secs(30));
+    ///
+    /// assert_eq!(cache.clear_namespace("sessions"), 1);
+    /// assert_eq!(users.len(), 1);
+    /// assert!(sessions.is_empty());
+    /// ```
+    pub fn namespace(&self, name: &str) -> Namespace<'_, K, V, F, S> {
+        let (name, stats) = self.namespace_stats(name);
+        Namespace {
+            cache: self,
+            name,
+            stats,
+        }
+    }
+
+    /// Replaces the TTR of the namespace `name` without rebuilding the cache,
+    /// like [`TTRCache::set_ttl`] does for the cache-wide TTR.
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    /// use ttr_cache::{MockClock, NamespacedKey, TTRCache, TTRCacheBuilder};
+    ///
+    /// let clock = MockClock::new();
+    /// let cache: TTRCache<_, _, _> = TTRCacheBuilder::new(Duration::from_secs(60))
+    ///     .clock(clock.clone())
+    ///     .build(|key: &NamespacedKey<u32>| Some(*key.key()));
+    /// let reports = cache.namespace("reports");
+    /// reports.get(&1);
+    ///
+    /// clock.advance(Duration::from_secs(90));
+    /// reports.set_ttl(Duration::from_secs(3600)).unwrap();
+    /// let report = cache.entry(&NamespacedKey::new("reports", 1)).unwrap();
+    /// assert_eq!(report.ttl(), Duration::from_secs(3600));
+    /// ```
+    pub fn set_namespace_ttl(
+        &self,
+        name: &str,
+        ttl: impl Into<RefreshPolicy>,
+    ) -> Result<(), Error> {
+        let ttl = ttl.into().checked_ttl()?;
+        self.store()
+            .set_namespace_ttl(NamespacedKey::namespace, name.into(), ttl);
+        Ok(())
+    }
+
+    /// Removes every entry in the namespace `name`, returning how many.
+    pub fn clear_namespace(&self, name: &str) -> usize {
+        self.remove_if(|key, _| key.namespace() == name)
+    }
+}
+
+/// A cache's view of one namespace, returned by
+/// [`TTRCache::namespace`].
+///
+/// Keys are scoped to the namespace, so namespaces can use the same keys
+/// without seeing each other's values. Everything else, including the
+/// capacity and the background workers, is shared with the cache.
+pub struct Namespace<'a, K, V, F, S = RandomState>
+where
+    K: Eq + Hash,
+{
+    cache: &'a TTRCache<NamespacedKey<K>, V, F, S>,
+    name: Arc<str>,
+    stats: Arc<StatsCounter>,
+}
+
+impl<'a, K, V, F, S> Namespace<'a, K, V, F, S>
+where
+    K: Eq + Hash + Clone,
+    V: Clone,
+    S: BuildHasher,
+{
+    fn scoped(&self, key: &K) -> NamespacedKey<K> {
+        NamespacedKey {
+            namespace: Arc::clone(&self.name),
+            key: key.clone(),
+        }
+    }
+
+    /// The namespace's name.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Gets a value, refreshing it if stale. See [`TTRCache::get`].
+    pub fn get(&self, key: &K) -> Option<V>
+    where
+        F: EntityFetcher<NamespacedKey<K>, V>,
+    {
+        self.cache.get_counted(&self.scoped(key), &self.stats)
+    }
+
+    /// Stores a value as if it was just fetched. See [`TTRCache::insert`].
+    pub fn insert(&self, key: K, value: V) {
+        let key = NamespacedKey {
+            namespace: Arc::clone(&self.name),
+            key,
+        };
+        self.cache.insert(key, value);
+    }
+
+    /// Marks an entry stale. See [`TTRCache::invalidate`].
+    pub fn invalidate(&self, key: &K) -> bool {
+        self.cache.invalidate(&self.scoped(key))
+    }
+
+    /// Removes an entry, returning its value.
+    pub fn remove(&self, key: &K) -> Option<V> {
+        self.cache.remove(&self.scoped(key))
+    }
+
+    /// Replaces the namespace's TTR. See [`TTRCache::set_namespace_ttl`].
+    pub fn set_ttl(&self, ttl: impl Into<RefreshPolicy>) -> Result<(), Error> {
+        self.cache.set_namespace_ttl(&self.name, ttl)
+    }
+
+    /// Marks every entry in the namespace stale, returning how many.
+    pub fn invalidate_all(&self) -> usize {
+        self.cache
+            .invalidate_if(|key, _| key.namespace == self.name)
+    }
+
+    /// Removes every entry in the namespace, returning how many.
+    pub fn clear(&self) -> usize {
+        self.cache.clear_namespace(&self.name)
+    }
+
+    /// Number of entries cached in the namespace.
+    pub fn len(&self) -> usize {
+        self.cache
+            .keys()
+            .filter(|key| key.namespace == self.name)
+            .count()
+    }
+
+    /// Returns `true` if nothing is cached in the namespace.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns a snapshot of the namespace's counters.
+    ///
+    /// Only lookups made through namespace handles are counted, as hits,
+    /// stale hits and misses; fetches are only counted in the cache's own
+    /// [`stats`](TTRCache::stats).
+    pub fn stats(&self) -> CacheStats {
+        self.stats.snapshot(self.len())
+    }
+}
@@ -0,0 +1,557 @@
+//! Policies controlling how long entries stay fresh and how they are refreshed.
+
+use std::time::Duration;
+
+use crate::entry::EntryInfo;
+use crate::error::Error;
+use crate::time::Instant;
+
+/// Decides the TTR of an entry when it is fetched.
+///
+/// Closures of the form `Fn(&K, &V) -> Option<Duration>` implement this trait.
+pub trait TtlPolicy<K, V> {
+    /// Returns the TTR for a freshly fetched value.
+    ///
+    /// Returns `None` to fall back to the cache-wide TTR.
+    fn ttl_for(&self, key: &K, value: &V) -> Option<Duration>;
+}
+
+impl<K, V, P> TtlPolicy<K, V> for P
+where
+    P: Fn(&K, &V) -> Option<Duration>,
+{
+    fn ttl_for(&self, key: &K, value: &V) -> Option<Duration> {
+        self(key, value)
+    }
+}
+
+/// A value that knows how long it stays fresh, such as a token with an
+/// expiry.
+///
+/// Used with [`TTRCacheBuilder::ttl_from_value`](crate::TTRCacheBuilder::ttl_from_value).
+pub trait TtlFromValue {
+    /// Returns the TTR of this value.
+    ///
+    /// Returns `None` to fall back to the cache-wide TTR.
+    fn ttl(&self) -> Option<Duration>;
+}
+
+/// The [`TtlPolicy`] of values implementing [`TtlFromValue`].
+pub(crate) fn value_ttl<K, V: TtlFromValue>(_key: &K, value: &V) -> Option<Duration> {
+    value.ttl()
+}
+
+/// Measures how much of the cache's weight budget an entry uses.
+///
+/// Closures of the form `Fn(&K, &V) -> u64` implement this trait.
+pub trait Weigher<K, V> {
+    /// Returns the weight of an entry, such as its size in bytes.
+    fn weigh(&self, key: &K, value: &V) -> u64;
+}
+
+impl<K, V, W> Weigher<K, V> for W
+where
+    W: Fn(&K, &V) -> u64,
+{
+    fn weigh(&self, key: &K, value: &V) -> u64 {
+        self(key, value)
+    }
+}
+
+/// Maps the forms of a logical key to the one the cache stores it under,
+/// such as lowercasing or trimming a trailing slash.
+///
+/// Closures of the form `Fn(&K) -> K` implement this trait.
+pub trait KeyNormalizer<K> {
+    /// Returns the canonical form of `key`.
+    ///
+    /// Should return canonical keys unchanged.
+    fn normalize(&self, key: &K) -> K;
+}
+
+impl<K, N> KeyNormalizer<K> for N
+where
+    N: Fn(&K) -> K,
+{
+    fn normalize(&self, key: &K) -> K {
+        self(key)
+    }
+}
+
+/// Checks fetched values before they are cached.
+///
+/// Closures of the form `Fn(&K, &V) -> bool` implement this trait.
+pub trait Validator<K, V> {
+    /// Returns whether `value` may be cached for `key`.
+    fn validate(&self, key: &K, value: &V) -> bool;
+}
+
+impl<K, V, F> Validator<K, V> for F
+where
+    F: Fn(&K, &V) -> bool,
+{
+    fn validate(&self, key: &K, value: &V) -> bool {
+        self(key, value)
+    }
+}
+
+/// How long to wait before refreshing an entry again after its refresh
+/// failed.
+///
+/// The wait starts at the initial backoff and is multiplied after every
+/// consecutive failure, up to the maximum. The stale value is served in the
+/// meantime, without calling the fetcher.
+///
+/// ```rust
+/// use std::time::Duration;
+/// use ttr_cache::RetryPolicy;
+///
+/// let policy = RetryPolicy::exponential(Duration::from_millis(100))
+///     .max_backoff(Duration::from_secs(10))
+///     .max_attempts(8)
+///     .jitter(0.2);
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    pub(crate) initial_backoff: Duration,
+    pub(crate) max_backoff: Duration,
+    pub(crate) multiplier: f64,
+    pub(crate) max_attempts: Option<u32>,
+    pub(crate) jitter: Option<f64>,
+}
+
+impl RetryPolicy {
+    /// Waits `initial_backoff` after the first failure, doubling after each
+    /// further one, up to a minute.
+    pub fn exponential(initial_backoff: Duration) -> Self {
+        RetryPolicy {
+            initial_backoff,
+            max_backoff: Duration::from_secs(60),
+            multiplier: 2.0,
+            max_attempts: None,
+            jitter: None,
+        }
+    }
+
+    /// Caps the wait between attempts.
+    pub fn max_backoff(mut self, max_backoff: Duration) -> Self {
+        self.max_backoff = max_backoff;
+        self
+    }
+
+    /// Replaces the factor the wait grows by after each failure.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `multiplier` is less than `1.0`.
+    pub fn multiplier(mut self, multiplier: f64) -> Self {
+        assert!(
+            multiplier >= 1.0,
+            "backoff multiplier must be at least 1.0, got {multiplier}"
+        );
+        self.multiplier = multiplier;
+        self
+    }
+
+    /// Stops refreshing an entry on reads after `max_attempts` consecutive
+    /// failures.
+    ///
+    /// The stale value is then served until the entry expires or is
+    /// inserted, invalidated or removed. By default, entries are retried
+    /// indefinitely.
+    pub fn max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = Some(max_attempts);
+        self
+    }
+
+    /// Varies each wait randomly by up to `fraction` of itself.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `fraction` is not between `0.0` and `1.0`.
+    pub fn jitter(mut self, fraction: f64) -> Self {
+        assert!(
+            (0.0..=1.0).contains(&fraction),
+            "backoff jitter must be between 0.0 and 1.0, got {fraction}"
+        );
+        self.jitter = Some(fraction);
+        self
+    }
+
+    /// Returns the wait after `failures` consecutive failures, before jitter.
+    pub(crate) fn backoff(&self, failures: u32) -> Duration {
+        let exponent = failures.saturating_sub(1).min(i32::MAX as u32) as i32;
+        let backoff = self.initial_backoff.as_secs_f64() * self.multiplier.powi(exponent);
+        Duration::try_from_secs_f64(backoff)
+            .unwrap_or(self.max_backoff)
+            .min(self.max_backoff)
+    }
+
+    /// Whether an entry that failed `failures` times in a row is given up on.
+    pub(crate) fn exhausted(&self, failures: u32) -> bool {
+        self.max_attempts
+            .is_some_and(|max_attempts| failures >= max_attempts)
+    }
+}
+
+/// Limits how long a stale entry is served once its refreshes start failing.
+///
+/// When the limit is reached the entry is dropped, so the next `get` fetches
+/// on the calling thread and returns `None` if that fails too. Listeners are
+/// told through
+/// [`on_stale_if_error_exceeded`](crate::CacheEventListener::on_stale_if_error_exceeded).
+///
+/// ```rust
+/// use std::time::Duration;
+/// use ttr_cache::StaleIfError;
+///
+/// // Serve stale data for up to ten minutes of failures, or five failed
+/// // refreshes, whichever comes first.
+/// let policy = StaleIfError::new(Duration::from_secs(600)).max_failures(5);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StaleIfError {
+    pub(crate) window: Duration,
+    pub(crate) max_failures: Option<u32>,
+}
+
+impl StaleIfError {
+    /// Serves a stale entry for at most `window` after its first failed
+    /// refresh.
+    pub fn new(window: Duration) -> Self {
+        StaleIfError {
+            window,
+            max_failures: None,
+        }
+    }
+
+    /// Also stops serving the entry after `max_failures` consecutive failed
+    /// refreshes.
+    pub fn max_failures(mut self, max_failures: u32) -> Self {
+        self.max_failures = Some(max_failures);
+        self
+    }
+
+    /// Whether an entry failing since `failing_since` has used up its limit.
+    pub(crate) fn exceeded(
+        &self,
+        failures: u32,
+        failing_since: Option<Instant>,
+        now: Instant,
+    ) -> bool {
+        let Some(failing_since) = failing_since else {
+            return false;
+        };
+
+        now.saturating_duration_since(failing_since) >= self.window
+            || self
+                .max_failures
+                .is_some_and(|max_failures| failures >= max_failures)
+    }
+}
+
+/// Freshness windows of an HTTP response, as in its `Cache-Control` header.
+///
+/// Applied with
+/// [`TTRCacheBuilder::http_policy`](crate::TTRCacheBuilder::http_policy):
+/// values are fresh for `max-age`, then served while being refreshed for
+/// `stale-while-revalidate`, and served when refreshes fail until
+/// `stale-if-error` has passed, as described in RFC 5861. Both windows
+/// start when the value goes stale and default to zero.
+///
+/// ```rust
+/// use std::time::Duration;
+/// use ttr_cache::HttpCachePolicy;
+///
+/// let parsed = HttpCachePolicy::from_cache_control(
+///     "public, max-age=60, stale-while-revalidate=30, stale-if-error=86400",
+/// );
+/// let built = HttpCachePolicy::new(Duration::from_secs(60))
+///     .stale_while_revalidate(Duration::from_secs(30))
+///     .stale_if_error(Duration::from_secs(86400));
+/// assert_eq!(parsed, Some(built));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HttpCachePolicy {
+    pub(crate) max_age: Duration,
+    pub(crate) stale_while_revalidate: Duration,
+    pub(crate) stale_if_error: Duration,
+}
+
+impl HttpCachePolicy {
+    /// Keeps values fresh for `max_age`, with no stale windows.
+    pub fn new(max_age: Duration) -> Self {
+        HttpCachePolicy {
+            max_age,
+            stale_while_revalidate: Duration::ZERO,
+            stale_if_error: Duration::ZERO,
+        }
+    }
+
+    /// Serves stale values while they are refreshed for `window`.
+    pub fn stale_while_revalidate(mut self, window: Duration) -> Self {
+        self.stale_while_revalidate = window;
+        self
+    }
+
+    /// Serves stale values when refreshes fail for `window`.
+    pub fn stale_if_error(mut self, window: Duration) -> Self {
+        self.stale_if_error = window;
+        self
+    }
+
+    /// Reads the windows from a `Cache-Control` header value.
+    ///
+    /// `s-maxage` takes precedence over `max-age`, as for a shared cache.
+    /// Returns `None` if the header sets neither or has `no-store`.
+    pub fn from_cache_control(header: &str) -> Option<Self> {
+        let mut max_age = None;
+        let mut shared_max_age = None;
+        let mut policy = HttpCachePolicy::new(Duration::ZERO);
+        for directive in header.split(',') {
+            let (name, value) = match directive.split_once('=') {
+                Some((name, value)) => (name.trim(), Some(value.trim().trim_matches('"'))),
+                None => (directive.trim(), None),
+            };
+            let seconds = value
+                .and_then(|value| value.parse().ok())
+                .map(Duration::from_secs);
+            match name.to_ascii_lowercase().as_str() {
+                "no-store" => return None,
+                "max-age" => max_age = seconds,
+                "s-maxage" => shared_max_age = seconds,
+                "stale-while-revalidate" => {
+                    policy.stale_while_revalidate = seconds.unwrap_or_default();
+                }
+                "stale-if-error" => policy.stale_if_error = seconds.unwrap_or_default(),
+                _ => {}
+            }
+        }
+        policy.max_age = shared_max_age.or(max_age)?;
+        Some(policy)
+    }
+}
+
+/// How old a value `get_with` may return.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Freshness {
+    /// Serve stale values, exactly like `get`.
+    #[default]
+    Stale,
+    /// Only serve values within their TTR, refreshing on the calling thread
+    /// otherwise.
+    Fresh,
+    /// Only serve values fetched at most this long ago, even past their TTR,
+    /// refreshing on the calling thread otherwise.
+    MaxAge(Duration),
+}
+
+impl Freshness {
+    pub(crate) fn accepts<V>(&self, info: &EntryInfo<V>) -> bool {
+        match *self {
+            Freshness::Stale => true,
+            Freshness::Fresh => !info.is_stale(),
+            Freshness::MaxAge(max_age) => !info.invalidated && info.age() <= max_age,
+        }
+    }
+}
+
+/// How urgently a key read with
+/// [`get_with_priority`](crate::TTRCache::get_with_priority) should be
+/// refreshed in the background.
+///
+/// Queued refreshes start in priority order, so when
+/// [concurrency](crate::TTRCacheBuilder::max_concurrent_refreshes) or the
+/// [refresh budget](crate::TTRCacheBuilder::refresh_budget) is limited, the keys of
+/// latency-critical reads are refreshed before those of batch jobs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub enum Priority {
+    /// Refreshed once nothing else is queued.
+    Low,
+    /// The priority of every other read.
+    #[default]
+    Normal,
+    /// Refreshed ahead of everything else queued.
+    High,
+}
+
+/// Which entry makes room when the cache is over its capacity or weight.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EvictionPolicy {
+    /// Evicts the least recently used entry.
+    #[default]
+    Lru,
+    /// Evicts the least recently used entry only if the new key was accessed
+    /// more often recently, by TinyLFU's frequency estimate, and otherwise
+    /// drops the new key instead.
+    ///
+    /// A scan over keys that are read once then cannot push out the working
+    /// set. Keys are counted on every lookup, including misses.
+    TinyLfu,
+}
+
+/// How a cache stores its entries, chosen with
+/// [`TTRCacheBuilder::engine`](crate::TTRCacheBuilder::engine).
+///
+/// With the `snapshot-swap` feature.
+#[cfg(feature = "snapshot-swap")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Engine {
+    /// Entries behind read-write locks, one per shard.
+    #[default]
+    Sharded,
+    /// The sharded entries, plus an immutable copy of the fresh ones that
+    /// `get` reads without locking, swapped for a new copy after writes.
+    ///
+    /// For small caches read far more often than written, such as
+    /// configuration. Every write makes the next read copy the whole cache.
+    /// Reads served from the copy are counted in the copy, and added to each
+    /// key's recency, idle time and [hits](crate::TTRCache::key_stats) once
+    /// the copy is replaced or those are read.
+    SnapshotSwap,
+}
+
+/// How entries are re-dated after the clock jumps forward, as on resuming
+/// from a suspend, chosen with
+/// [`TTRCacheBuilder::on_clock_jump`](crate::TTRCacheBuilder::on_clock_jump).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClockJump {
+    /// Moves every entry's fetch time forward by the jump, so each picks up
+    /// with the TTR it had left before it.
+    Rearm,
+    /// Entries whose TTR ran out during the jump go stale at random points
+    /// over this window instead of all at once; the rest are left as they
+    /// are.
+    Spread(Duration),
+}
+
+/// When a key is refreshed, as chosen per key by
+/// [`TTRCacheBuilder::refresh_strategy`](crate::TTRCacheBuilder::refresh_strategy).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RefreshStrategy {
+    /// Refreshes the key when it is read stale, as configured for the cache.
+    #[default]
+    OnRead,
+    /// Also refreshes the key in the background every interval while it is
+    /// cached, whether or not it is read.
+    Periodic(Duration),
+    /// Also refreshes the key in the background as soon as it goes stale,
+    /// whether or not it is read, so reads keep finding it fresh.
+    WhenStale,
+}
+
+/// What a lookup does for a key with nothing cached.
+///
+/// Only reads on the request path follow it: `get`, `try_get`, `get_many`
+/// and the entry API. Warming, forced refreshes and `get_or_insert_with`
+/// always load the key. A key evicted or removed counts as missing again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MissPolicy {
+    /// Fetches the key.
+    #[default]
+    Fetch,
+    /// Returns `None` without fetching, for caches that are preloaded.
+    ReturnNone,
+    /// Returns `None` without fetching, and reports the miss as an error: as
+    /// [`Error::UnexpectedMiss`](crate::Error::UnexpectedMiss), or
+    /// [`Error::Expired`](crate::Error::Expired) for an entry past its hard
+    /// expiry, from [`get_strict`](crate::TTRCache::get_strict), and as an
+    /// error event with the `tracing` feature.
+    Error,
+}
+
+/// What a refresh that finds nothing does to the cached entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OnMissingRefresh {
+    /// Keeps serving the stale value, trying again on the next lookup.
+    #[default]
+    KeepStale,
+    /// Removes the entry, so deletions upstream reach the cache.
+    Remove,
+    /// Keeps the value and restarts its TTR, as if it was unchanged.
+    KeepAndResetTimer,
+    /// Removes the entry and leaves a tombstone for the duration, during
+    /// which lookups return `None` without fetching the key again.
+    /// Inserting, removing or invalidating the key clears the tombstone.
+    Tombstone(Duration),
+}
+
+/// When entries are due for a refresh.
+///
+/// Anything taking a TTR accepts a `Duration`, which converts to
+/// [`After`](Self::After).
+///
+/// ```rust
+/// use std::time::Duration;
+/// use ttr_cache::{RefreshPolicy, TTRCache, TTRCacheBuilder};
+///
+/// let cache: TTRCache<_, _, _> = TTRCacheBuilder::new(RefreshPolicy::Always)
+///     .build(|id: &u32| Some(id * 10));
+/// cache.get(&1);
+/// cache.get(&1);
+/// assert_eq!(cache.stats().refreshes, 2);
+///
+/// let result = TTRCacheBuilder::<u32, u32>::new(Duration::ZERO).try_build(|id: &u32| Some(*id));
+/// assert!(result.is_err());
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RefreshPolicy {
+    /// Entries go stale this long after they are fetched.
+    ///
+    /// A zero TTR is rejected when the cache is built, since it is more
+    /// often a configuration mistake than a wish to refetch on every read;
+    /// use [`Always`](Self::Always) for that.
+    After(Duration),
+    /// Entries are stale as soon as they are fetched, so every read
+    /// refreshes them.
+    Always,
+    /// Entries never go stale with age, only when invalidated.
+    Never,
+}
+
+impl RefreshPolicy {
+    /// The TTR the policy amounts to.
+    pub(crate) fn ttl(self) -> Duration {
+        match self {
+            RefreshPolicy::After(ttl) => ttl,
+            RefreshPolicy::Always => Duration::ZERO,
+            RefreshPolicy::Never => Duration::MAX,
+        }
+    }
+
+    /// Whether this is a zero TTR that was not asked for as
+    /// [`Always`](Self::Always).
+    pub(crate) fn is_ambiguous(self) -> bool {
+        self == RefreshPolicy::After(Duration::ZERO)
+    }
+
+    /// The TTR the policy amounts to, unless it is ambiguous.
+    pub(crate) fn checked_ttl(self) -> Result<Duration, Error> {
+        if self.is_ambiguous() {
+            return Err(Error::InvalidConfig(ZERO_TTL));
+        }
+        Ok(self.ttl())
+    }
+}
+
+/// Why a zero TTR is rejected.
+pub(crate) const ZERO_TTL: &str =
+    "the TTR must not be zero; use RefreshPolicy::Always to refresh on every read";
+
+impl From<Duration> for RefreshPolicy {
+    fn from(ttl: Duration) -> Self {
+        RefreshPolicy::After(ttl)
+    }
+}
+
+/// Where stale entries are refreshed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RefreshMode {
+    /// `get` fetches the new value before returning.
+    #[default]
+    Blocking,
+    /// `get` returns the stale value and a worker thread fetches the new one.
+    ///
+    /// See [`TTRCacheBuilder::max_concurrent_refreshes`](crate::TTRCacheBuilder::max_concurrent_refreshes).
+    Background,
+}
@@ -0,0 +1,32 @@
+//! The items most code using the cache needs, for a single glob import.
+//!
+#![cfg_attr(feature = "std", doc = "```rust")]
+#![cfg_attr(not(feature = "std"), doc = "```ignore")]
+//! use std::time::Duration;
+//! use ttr_cache::prelude::*;
+//!
+//! struct Users;
+//!
+//! impl EntityFetcher<u64, String> for Users {
+//!     fn fetch_entity(&self, id: &u64) -> Option<String> {
+//!         Some(format!("user {id}"))
+//!     }
+//! }
+//!
+//! let cache: TTRCache<_, _, _> = TTRCacheBuilder::new(Duration::from_secs(60))
+//!     .refresh_mode(RefreshMode::Background)
+//!     .build_background(Users);
+//! assert_eq!(cache.get_with(&1, Freshness::Fresh).as_deref(), Some("user 1"));
+//! assert_eq!(cache.stats().misses, 1);
+//! ```
+
+#[cfg(any(feature = "std", feature = "alloc"))]
+pub use crate::LocalTTRCache;
+#[cfg(feature = "async")]
+pub use crate::{AsyncEntityFetcher, AsyncTTRCache};
+#[cfg(feature = "std")]
+pub use crate::{
+    BatchEntityFetcher, CacheStats, Error, FetchContext, FetchError, Freshness, RefreshMode,
+    RefreshPolicy, SharedTTRCache, TTRCache, TTRCacheBuilder, TtlPolicy,
+};
+pub use crate::{EntityFetcher, FetchOutcome, TryEntityFetcher};
@@ -0,0 +1,170 @@
+//! Pending background refreshes, served by priority, then most requested
+//! first.
+
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::time::Duration;
+
+use crate::policy::Priority;
+use crate::time::Instant;
+
+/// A queued refresh and how often its key was asked for while waiting.
+struct Request<T, W> {
+    payload: T,
+    /// Waiting for the next refresh of the key to finish.
+    waiters: Vec<W>,
+    /// The highest priority the key was asked for with while waiting.
+    priority: Priority,
+    requests: u64,
+    /// When the key was last asked for, in pushes; breaks ties in favour of
+    /// recently read keys.
+    last_requested: u64,
+    started: bool,
+}
+
+/// How many refreshes may start per interval.
+struct Budget {
+    max: usize,
+    per: Duration,
+    window_start: Instant,
+    started: usize,
+}
+
+/// Keys waiting for a background refresh, at most one request per key.
+///
+/// Higher priority keys go first. Within a priority, keys that are asked
+/// for again while queued move ahead of colder ones.
+/// Picking the next key scans the queue, which stays short as long as the
+/// workers keep up.
+pub(crate) struct RefreshQueue<K, T, W> {
+    requests: HashMap<K, Request<T, W>>,
+    pushes: u64,
+    budget: Option<Budget>,
+}
+
+impl<K: Eq + Hash + Clone, T: Copy, W> RefreshQueue<K, T, W> {
+    pub(crate) fn new() -> Self {
+        RefreshQueue {
+            requests: HashMap::new(),
+            pushes: 0,
+            budget: None,
+        }
+    }
+
+    /// Lets at most `max` refreshes start every `per`.
+    pub(crate) fn set_budget(&mut self, max: usize, per: Duration) {
+        self.budget = Some(Budget {
+            max,
+            per,
+            window_start: Instant::now(),
+            started: 0,
+        });
+    }
+
+    /// How long until another refresh may start, if the budget is used up.
+    pub(crate) fn exhausted(&mut self) -> Option<Duration> {
+        let budget = self.budget.as_mut()?;
+        let elapsed = budget.window_start.elapsed();
+        if elapsed >= budget.per {
+            budget.window_start = Instant::now();
+            budget.started = 0;
+            return None;
+        }
+        (budget.started >= budget.max).then(|| budget.per - elapsed)
+    }
+
+    /// Queues a refresh of `key`, or counts another request for it if it is
+    /// already queued or running.
+    ///
+    /// A `waiter` is handed back with the next refresh of the key to start,
+    /// so one already running when it is pushed does not count.
+    ///
+    /// A key asked for with a higher priority than it was queued with moves
+    /// up to that priority.
+    ///
+    /// Returns `true` if the key was newly queued.
+    pub(crate) fn push(
+        &mut self,
+        key: &K,
+        payload: T,
+        waiter: Option<W>,
+        priority: Priority,
+    ) -> bool {
+        self.pushes += 1;
+        if let Some(request) = self.requests.get_mut(key) {
+            request.priority = request.priority.max(priority);
+            request.requests += 1;
+            request.last_requested = self.pushes;
+            request.waiters.extend(waiter);
+            return false;
+        }
+        self.requests.insert(
+            key.clone(),
+            Request {
+                payload,
+                waiters: waiter.into_iter().collect(),
+                priority,
+                requests: 1,
+                last_requested: self.pushes,
+                started: false,
+            },
+        );
+        true
+    }
+
+    /// Takes the highest priority, most requested key that is not already
+    /// being refreshed, with its waiters, counting it against the budget.
+    pub(crate) fn pop(&mut self) -> Option<(K, T, Vec<W>)> {
+        self.pop_if(|_| true)
+    }
+
+    /// Takes the next key like [`pop`](Self::pop), among those
+    /// whose payload is accepted.
+    pub(crate) fn pop_if(&mut self, accept: impl Fn(&T) -> bool) -> Option<(K, T, Vec<W>)> {
+        let (key, request) = self
+            .requests
+            .iter_mut()
+            .filter(|(_, request)| !request.started && accept(&request.payload))
+            .max_by_key(|(_, request)| {
+                (request.priority, request.requests, request.last_requested)
+            })?;
+        request.started = true;
+        if let Some(budget) = &mut self.budget {
+            budget.started += 1;
+        }
+        Some((
+            key.clone(),
+            request.payload,
+            std::mem::take(&mut request.waiters),
+        ))
+    }
+
+    /// Forgets a key once its refresh is done, or queues it again if waiters
+    /// arrived while it ran.
+    ///
+    /// Returns `true` if the key was queued again.
+    pub(crate) fn finish(&mut self, key: &K) -> bool {
+        match self.requests.get_mut(key) {
+            Some(request) if !request.waiters.is_empty() => {
+                request.started = false;
+                true
+            }
+            _ => {
+                self.requests.remove(key);
+                false
+            }
+        }
+    }
+
+    /// Whether `key` is queued or being refreshed.
+    pub(crate) fn contains(&self, key: &K) -> bool {
+        self.requests.contains_key(key)
+    }
+
+    /// Drops every waiter, for when queued refreshes will not run.
+    pub(crate) fn drop_waiters(&mut self) {
+        for request in self.requests.values_mut() {
+            request.waiters.clear();
+        }
+    }
+}
@@ -0,0 +1,154 @@
+//! Redis pub/sub as the invalidation bus.
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::marker::PhantomData;
+use std::sync::{Mutex, PoisonError};
+use std::thread;
+use std::time::Duration;
+
+use redis::{Client, Commands, Connection};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::bus::{InvalidationBus, InvalidationSink};
+
+/// How long the subscriber waits for a message before checking whether the
+/// cache is still there.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Wait before reconnecting after the subscription fails.
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+/// An [`InvalidationBus`] over a Redis pub/sub channel, for instances that
+/// share a Redis server.
+///
+/// Invalidations are published on the channel as JSON, tagged with the
+/// publishing instance so it ignores its own. Each subscribed cache listens
+/// on a thread of its own, reconnecting after errors, and stops once the
+/// cache is dropped. Invalidations published while an instance is
+/// disconnected are missed, as Redis pub/sub does not keep them.
+///
+/// With the `redis` feature.
+///
+/// ```rust,no_run
+/// use std::time::Duration;
+/// use ttr_cache::{RedisInvalidationBus, TTRCache, TTRCacheBuilder};
+///
+/// let client = redis::Client::open("redis://127.0.0.1/").unwrap();
+/// let cache: TTRCache<_, _, _> = TTRCacheBuilder::new(Duration::from_secs(60))
+///     .invalidation_bus(RedisInvalidationBus::new(client).channel("users:invalidate"))
+///     .build_background(|id: &u64| Some(format!("user {id}")));
+///
+/// cache.get(&1);
+/// // Also marks key 1 stale in every other instance on the channel.
+/// cache.invalidate(&1);
+/// ```
+pub struct RedisInvalidationBus<K> {
+    client: Client,
+    connection: Mutex<Option<Connection>>,
+    channel: String,
+    /// Tags this instance's messages.
+    origin: u64,
+    keys: PhantomData<fn() -> K>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Message<K> {
+    origin: u64,
+    key: K,
+}
+
+impl<K> RedisInvalidationBus<K> {
+    /// Creates a bus using `client`, on the channel `ttr-cache:invalidate`.
+    pub fn new(client: Client) -> Self {
+        RedisInvalidationBus {
+            client,
+            connection: Mutex::new(None),
+            channel: "ttr-cache:invalidate".to_string(),
+            origin: RandomState::new().build_hasher().finish(),
+            keys: PhantomData,
+        }
+    }
+
+    /// Sets the channel, so caches of different entities can share a Redis
+    /// server.
+    pub fn channel(mut self, channel: impl Into<String>) -> Self {
+        self.channel = channel.into();
+        self
+    }
+}
+
+impl<K> InvalidationBus<K> for RedisInvalidationBus<K>
+where
+    K: Serialize + DeserializeOwned + 'static,
+{
+    fn publish(&self, key: &K) {
+        let Ok(json) = serde_json::to_string(&Message {
+            origin: self.origin,
+            key,
+        }) else {
+            return;
+        };
+        let mut connection = self
+            .connection
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let result = match &mut *connection {
+            Some(connection) => connection.publish::<_, _, ()>(&self.channel, &json),
+            None => self.client.get_connection().and_then(|opened| {
+                connection
+                    .insert(opened)
+                    .publish::<_, _, ()>(&self.channel, &json)
+            }),
+        };
+        if let Err(_error) = result {
+            #[cfg(feature = "tracing")]
+            tracing::warn!(error = %_error, "publishing invalidation failed");
+            *connection = None;
+        }
+    }
+
+    fn subscribe(&self, sink: InvalidationSink<K>) {
+        let client = self.client.clone();
+        let channel = self.channel.clone();
+        let origin = self.origin;
+        thread::spawn(move || {
+            while !sink.is_closed() {
+                if let Err(_error) = listen(&client, &channel, origin, &sink) {
+                    #[cfg(feature = "tracing")]
+                    tracing::warn!(error = %_error, "invalidation subscription failed");
+                    thread::sleep(RECONNECT_DELAY);
+                }
+            }
+        });
+    }
+}
+
+/// Passes the keys other instances publish on `channel` to `sink` until the
+/// cache is dropped.
+fn listen<K: DeserializeOwned>(
+    client: &Client,
+    channel: &str,
+    origin: u64,
+    sink: &InvalidationSink<K>,
+) -> redis::RedisResult<()> {
+    let mut connection = client.get_connection()?;
+    let mut pubsub = connection.as_pubsub();
+    pubsub.set_read_timeout(Some(POLL_INTERVAL))?;
+    pubsub.subscribe(channel)?;
+    while !sink.is_closed() {
+        let message = match pubsub.get_message() {
+            Ok(message) => message,
+            Err(error) if error.is_timeout() => continue,
+            Err(error) => return Err(error),
+        };
+        let Ok(message) = serde_json::from_slice::<Message<K>>(message.get_payload_bytes()) else {
+            continue;
+        };
+        if message.origin != origin {
+            sink.invalidate(&message.key);
+        }
+    }
+    Ok(())
+}
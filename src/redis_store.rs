@@ -0,0 +1,154 @@
+//! Redis as the second storage tier.
+
+use std::marker::PhantomData;
+use std::sync::{Mutex, PoisonError};
+use std::time::Duration;
+
+use redis::{Client, Commands, Connection, RedisResult};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::snapshot::PersistedEntry;
+use crate::tiered::CacheStore;
+use crate::time::SystemTime;
+
+/// A [`CacheStore`] in Redis, letting instances sharing a Redis server reuse
+/// each other's fetches.
+///
+/// Keys are stored under a prefix, as the prefix followed by the key's JSON,
+/// and entries as JSON. Each entry is set to expire in Redis once it goes
+/// stale, plus a [retention](Self::stale_retention) period for serving it
+/// stale while refreshing.
+///
+/// Redis errors are treated as misses, after which the connection is
+/// reopened on next use.
+///
+/// With the `redis` feature.
+///
+/// ```rust,no_run
+/// use std::time::Duration;
+/// use ttr_cache::{RedisStore, TTRCache, TTRCacheBuilder};
+///
+/// let client = redis::Client::open("redis://127.0.0.1/").unwrap();
+/// let store = RedisStore::new(client)
+///     .prefix("users:")
+///     .stale_retention(Duration::from_secs(600));
+///
+/// let cache: TTRCache<_, _, _> = TTRCacheBuilder::new(Duration::from_secs(60))
+///     .second_tier(store)
+///     .build(|id: &u64| Some(format!("user {id}")));
+/// assert_eq!(cache.get(&1).as_deref(), Some("user 1"));
+/// ```
+pub struct RedisStore<K, V> {
+    client: Client,
+    connection: Mutex<Option<Connection>>,
+    prefix: String,
+    stale_retention: Duration,
+    entries: PhantomData<fn() -> (K, V)>,
+}
+
+impl<K, V> RedisStore<K, V> {
+    /// Creates a store using `client`, under the prefix `ttr-cache:` and
+    /// without retention.
+    pub fn new(client: Client) -> Self {
+        RedisStore {
+            client,
+            connection: Mutex::new(None),
+            prefix: "ttr-cache:".to_string(),
+            stale_retention: Duration::ZERO,
+            entries: PhantomData,
+        }
+    }
+
+    /// Sets the prefix of every key, so caches can share a Redis database.
+    pub fn prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefix = prefix.into();
+        self
+    }
+
+    /// Keeps entries in Redis for `retention` after they go stale, so they
+    /// can still be served while refreshing.
+    pub fn stale_retention(mut self, retention: Duration) -> Self {
+        self.stale_retention = retention;
+        self
+    }
+
+    /// Runs `command` on the connection, opening it if needed.
+    fn run<T>(&self, command: impl FnOnce(&mut Connection) -> RedisResult<T>) -> Option<T> {
+        let mut connection = self
+            .connection
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let result = match &mut *connection {
+            Some(connection) => command(connection),
+            None => self
+                .client
+                .get_connection()
+                .and_then(|opened| command(connection.insert(opened))),
+        };
+        match result {
+            Ok(value) => Some(value),
+            Err(_error) => {
+                #[cfg(feature = "tracing")]
+                tracing::warn!(error = %_error, "redis command failed");
+                *connection = None;
+                None
+            }
+        }
+    }
+}
+
+impl<K: Serialize, V> RedisStore<K, V> {
+    fn redis_key(&self, key: &K) -> Option<String> {
+        let key = serde_json::to_string(key).ok()?;
+        Some(format!("{}{}", self.prefix, key))
+    }
+}
+
+impl<K, V> CacheStore<K, V> for RedisStore<K, V>
+where
+    K: Serialize + DeserializeOwned,
+    V: Serialize + DeserializeOwned,
+{
+    fn load(&self, key: &K) -> Option<PersistedEntry<K, V>> {
+        let redis_key = self.redis_key(key)?;
+        let json: Option<String> = self.run(|connection| connection.get(&redis_key))?;
+        serde_json::from_str(&json?).ok()
+    }
+
+    fn store(&self, entry: PersistedEntry<K, V>) {
+        let Some(redis_key) = self.redis_key(&entry.key) else {
+            return;
+        };
+        let expires_at = entry
+            .fetched_at
+            .checked_add(entry.ttl)
+            .and_then(|stale_at| stale_at.checked_add(self.stale_retention));
+        let millis = expires_at.map(|expires_at| {
+            let remaining = expires_at
+                .duration_since(SystemTime::now())
+                .unwrap_or_default();
+            u64::try_from(remaining.as_millis()).unwrap_or(u64::MAX)
+        });
+        if millis == Some(0) {
+            self.run(|connection| connection.del::<_, ()>(&redis_key));
+            return;
+        }
+        let Ok(json) = serde_json::to_string(&entry) else {
+            return;
+        };
+        // Entries that never go stale are kept until evicted by Redis.
+        match millis {
+            Some(millis) => {
+                self.run(|connection| connection.pset_ex::<_, _, ()>(&redis_key, json, millis))
+            }
+            None => self.run(|connection| connection.set::<_, _, ()>(&redis_key, json)),
+        };
+    }
+
+    fn remove(&self, key: &K) {
+        if let Some(redis_key) = self.redis_key(key) {
+            self.run(|connection| connection.del::<_, ()>(&redis_key));
+        }
+    }
+}
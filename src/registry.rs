@@ -0,0 +1,187 @@
+//! Registry holding one cache per entity type.
+
+use std::any::{Any, TypeId};
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::time::Duration;
+
+use crate::builder::{TTRCacheBuilder, WithWorkers};
+use crate::cache::TTRCache;
+use crate::policy::{RefreshMode, RefreshPolicy};
+use crate::stats::CacheStats;
+use crate::time::Instant;
+
+/// An entity type with its own cache in a [`CacheRegistry`].
+///
+/// Ties the type to the key and fetcher of its cache, so the registry can
+/// hand out the cache by entity type alone.
+pub trait Entity: Clone + Send + Sync + 'static {
+    /// Key the entity is cached under.
+    type Key: Eq + Hash + Clone + Send + Sync + 'static;
+    /// Fetcher that loads the entity.
+    type Fetcher: Send + Sync + 'static;
+}
+
+/// What the registry needs from a cache without knowing its types.
+trait RegisteredCache: Send + Sync {
+    fn as_any(&self) -> &dyn Any;
+    fn stats(&self) -> CacheStats;
+    fn shutdown(&self, timeout: Duration) -> bool;
+}
+
+impl<V: Entity> RegisteredCache for TTRCache<V::Key, V, V::Fetcher> {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn stats(&self) -> CacheStats {
+        TTRCache::stats(self)
+    }
+
+    fn shutdown(&self, timeout: Duration) -> bool {
+        TTRCache::shutdown(self, timeout)
+    }
+}
+
+/// Holds one [`TTRCache`] per [`Entity`] type, with shared defaults for
+/// building them.
+///
+/// ```rust
+/// use std::time::Duration;
+/// use ttr_cache::{CacheRegistry, Entity, EntityFetcher};
+///
+/// #[derive(Clone)]
+/// struct User(String);
+///
+/// struct UserDb;
+///
+/// impl EntityFetcher<u64, User> for UserDb {
+///     fn fetch_entity(&self, id: &u64) -> Option<User> {
+///         Some(User(format!("user {id}")))
+///     }
+/// }
+///
+/// impl Entity for User {
+///     type Key = u64;
+///     type Fetcher = UserDb;
+/// }
+///
+/// let mut registry = CacheRegistry::new(Duration::from_secs(300));
+/// let users = registry
+///     .builder::<User>()
+///     .max_capacity(1_000)
+///     .build_background(UserDb);
+/// registry.register(users);
+///
+/// let users = registry.cache::<User>().unwrap();
+/// assert_eq!(users.get(&7).unwrap().0, "user 7");
+/// assert_eq!(registry.stats().misses, 1);
+/// assert!(registry.shutdown(Duration::from_secs(1)));
+/// ```
+pub struct CacheRegistry {
+    caches: HashMap<TypeId, Box<dyn RegisteredCache>>,
+    ttl: RefreshPolicy,
+    max_stale: Option<Duration>,
+    max_idle: Option<Duration>,
+    refresh_mode: RefreshMode,
+    janitor: Option<Duration>,
+    fetch_timeout: Option<Duration>,
+}
+
+impl CacheRegistry {
+    /// Creates an empty registry whose caches default to the given TTR.
+    pub fn new(ttl: impl Into<RefreshPolicy>) -> Self {
+        CacheRegistry {
+            caches: HashMap::new(),
+            ttl: ttl.into(),
+            max_stale: None,
+            max_idle: None,
+            refresh_mode: RefreshMode::Blocking,
+            janitor: None,
+            fetch_timeout: None,
+        }
+    }
+
+    /// Defaults caches to [`TTRCacheBuilder::max_stale`].
+    pub fn max_stale(mut self, max_stale: Duration) -> Self {
+        self.max_stale = Some(max_stale);
+        self
+    }
+
+    /// Defaults caches to [`TTRCacheBuilder::max_idle`].
+    pub fn max_idle(mut self, max_idle: Duration) -> Self {
+        self.max_idle = Some(max_idle);
+        self
+    }
+
+    /// Defaults caches to [`TTRCacheBuilder::refresh_mode`].
+    pub fn refresh_mode(mut self, refresh_mode: RefreshMode) -> Self {
+        self.refresh_mode = refresh_mode;
+        self
+    }
+
+    /// Defaults caches to [`TTRCacheBuilder::janitor`].
+    pub fn janitor(mut self, interval: Duration) -> Self {
+        self.janitor = Some(interval);
+        self
+    }
+
+    /// Defaults caches to [`TTRCacheBuilder::fetch_timeout`].
+    pub fn fetch_timeout(mut self, timeout: Duration) -> Self {
+        self.fetch_timeout = Some(timeout);
+        self
+    }
+
+    /// Returns a builder for `V`'s cache with the registry's defaults set,
+    /// to build with [`build_background`](TTRCacheBuilder::build_background).
+    pub fn builder<V: Entity>(&self) -> TTRCacheBuilder<V::Key, V, RandomState, WithWorkers> {
+        let mut builder = TTRCacheBuilder::new(self.ttl).refresh_mode(self.refresh_mode);
+        if let Some(max_stale) = self.max_stale {
+            builder = builder.max_stale(max_stale);
+        }
+        if let Some(max_idle) = self.max_idle {
+            builder = builder.max_idle(max_idle);
+        }
+        if let Some(interval) = self.janitor {
+            builder = builder.janitor(interval);
+        }
+        if let Some(timeout) = self.fetch_timeout {
+            builder = builder.fetch_timeout(timeout);
+        }
+        builder
+    }
+
+    /// Adds the cache for `V`, replacing any it already holds.
+    pub fn register<V: Entity>(&mut self, cache: TTRCache<V::Key, V, V::Fetcher>) {
+        self.caches.insert(TypeId::of::<V>(), Box::new(cache));
+    }
+
+    /// Returns the cache for `V`, if one is registered.
+    pub fn cache<V: Entity>(&self) -> Option<&TTRCache<V::Key, V, V::Fetcher>> {
+        self.caches
+            .get(&TypeId::of::<V>())
+            .and_then(|cache| cache.as_any().downcast_ref())
+    }
+
+    /// Returns the counters of all registered caches added together.
+    pub fn stats(&self) -> CacheStats {
+        let mut stats = CacheStats::default();
+        for cache in self.caches.values() {
+            stats.merge(&cache.stats());
+        }
+        stats
+    }
+
+    /// Shuts down every registered cache, waiting up to `timeout` in total.
+    ///
+    /// Returns `true` if all their workers exited in time. See
+    /// [`TTRCache::shutdown`].
+    pub fn shutdown(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        self.caches.values().fold(true, |done, cache| {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            cache.shutdown(remaining) && done
+        })
+    }
+}
@@ -0,0 +1,301 @@
+//! Cheaply cloneable handles to a cache shared between threads.
+
+use std::borrow::Borrow;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hash};
+use std::ops::Deref;
+use std::sync::Arc;
+
+use crate::cache::TTRCache;
+use crate::entry::{CacheLookup, EntryInfo};
+use crate::fetcher::EntityFetcher;
+use crate::stats::CacheStats;
+use crate::store::Lookup;
+
+/// A [`TTRCache`] behind an `Arc`, cloned into every thread that uses it.
+///
+/// The cache already takes `&self` everywhere and locks internally, so no
+/// outer `RwLock` or `Mutex` is needed: clones share one cache and call it
+/// concurrently. Every method of the cache is available through `Deref`.
+///
+/// ```rust
+/// use std::thread;
+/// use std::time::Duration;
+/// use ttr_cache::{SharedTTRCache, TTRCache};
+///
+/// let cache = SharedTTRCache::new(TTRCache::new(Duration::from_secs(60), |id: &u32| {
+///     Some(id * 10)
+/// }));
+///
+/// let worker = cache.clone();
+/// thread::spawn(move || worker.insert(1, 1)).join().unwrap();
+///
+/// assert_eq!(cache.get_cloned(&1), Some(1));
+/// assert!(cache.invalidate(&1));
+/// assert_eq!(cache.get_cloned(&1), Some(10));
+/// assert_eq!(cache.stats().entry_count, 1);
+/// ```
+pub struct SharedTTRCache<K, V, F, S = RandomState>
+where
+    K: Eq + Hash,
+{
+    cache: Arc<TTRCache<K, V, F, S>>,
+}
+
+impl<K, V, F, S> SharedTTRCache<K, V, F, S>
+where
+    K: Eq + Hash + Clone,
+    V: Clone,
+    S: BuildHasher,
+{
+    /// Shares `cache`.
+    pub fn new(cache: TTRCache<K, V, F, S>) -> Self {
+        SharedTTRCache {
+            cache: Arc::new(cache),
+        }
+    }
+
+    /// Gets a clone of the value, refreshing if stale, like
+    /// [`TTRCache::get`].
+    pub fn get_cloned<Q>(&self, key: &Q) -> Option<V>
+    where
+        F: EntityFetcher<K, V>,
+        K: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    {
+        self.cache.get(key)
+    }
+
+    /// Inserts a value as if it had just been fetched, like
+    /// [`TTRCache::insert`].
+    pub fn insert(&self, key: K, value: V) {
+        self.cache.insert(key, value);
+    }
+
+    /// Marks a key stale, like [`TTRCache::invalidate`].
+    pub fn invalidate(&self, key: &K) -> bool {
+        self.cache.invalidate(key)
+    }
+
+    /// A handle to the same cache that can only read it.
+    pub fn read_handle(&self) -> ReadHandle<K, V, F, S> {
+        ReadHandle {
+            cache: Arc::clone(&self.cache),
+        }
+    }
+}
+
+impl<K, V, F, S> Clone for SharedTTRCache<K, V, F, S>
+where
+    K: Eq + Hash,
+{
+    fn clone(&self) -> Self {
+        SharedTTRCache {
+            cache: Arc::clone(&self.cache),
+        }
+    }
+}
+
+impl<K, V, F, S> Deref for SharedTTRCache<K, V, F, S>
+where
+    K: Eq + Hash,
+{
+    type Target = TTRCache<K, V, F, S>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.cache
+    }
+}
+
+/// The one handle that fetches, refreshes and writes a cache whose reads are
+/// served through [`ReadHandle`]s.
+///
+/// Like the write half of `evmap` or `left-right`: the component that owns
+/// the cache's contents keeps this, with every method of the cache available
+/// through `Deref`, and hands read handles to the components that only serve
+/// reads. Stale entries are refreshed when this handle reads them, or by
+/// the worker threads of a cache built with
+/// [`build_background`](crate::TTRCacheBuilder::build_background).
+pub struct WriteHandle<K, V, F, S = RandomState>
+where
+    K: Eq + Hash,
+{
+    cache: Arc<TTRCache<K, V, F, S>>,
+}
+
+impl<K, V, F, S> WriteHandle<K, V, F, S>
+where
+    K: Eq + Hash + Clone,
+    V: Clone,
+    S: BuildHasher,
+{
+    /// Takes ownership of `cache`'s writes.
+    pub fn new(cache: TTRCache<K, V, F, S>) -> Self {
+        WriteHandle {
+            cache: Arc::new(cache),
+        }
+    }
+
+    /// A handle that reads the same cache.
+    pub fn read_handle(&self) -> ReadHandle<K, V, F, S> {
+        ReadHandle {
+            cache: Arc::clone(&self.cache),
+        }
+    }
+}
+
+impl<K, V, F, S> Deref for WriteHandle<K, V, F, S>
+where
+    K: Eq + Hash,
+{
+    type Target = TTRCache<K, V, F, S>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.cache
+    }
+}
+
+impl<K, V, F, S> From<TTRCache<K, V, F, S>> for WriteHandle<K, V, F, S>
+where
+    K: Eq + Hash + Clone,
+    V: Clone,
+    S: BuildHasher,
+{
+    fn from(cache: TTRCache<K, V, F, S>) -> Self {
+        Self::new(cache)
+    }
+}
+
+/// A cheaply cloneable handle that only reads a cache: it never fetches,
+/// refreshes, writes or invalidates.
+///
+/// Reads serve what is cached, stale or not, and leave refreshing to the
+/// [`WriteHandle`] or [`SharedTTRCache`] it came from, so they never wait on
+/// a fetch or claim one. With the `SnapshotSwap` engine, fresh values are
+/// read from the cache's immutable copy without taking a lock, so readers do
+/// not wait on writers; the first read after a write rebuilds the copy, and
+/// other reads take the shards' read locks.
+///
+/// ```rust
+/// use std::thread;
+/// use std::time::Duration;
+/// use ttr_cache::{CacheLookup, TTRCache, WriteHandle};
+///
+/// let writer = WriteHandle::new(TTRCache::new(Duration::from_secs(60), |id: &u32| {
+///     Some(id * 10)
+/// }));
+/// let reader = writer.read_handle();
+///
+/// assert_eq!(reader.get(&1), None);
+/// assert_eq!(writer.get(&1), Some(10));
+/// assert_eq!(reader.get(&1), Some(10));
+///
+/// writer.insert(2, 7);
+/// let worker = reader.clone();
+/// assert_eq!(thread::spawn(move || worker.lookup(&2)).join().unwrap(), CacheLookup::Fresh(7));
+/// assert_eq!(reader.stats().refreshes, 1);
+/// ```
+pub struct ReadHandle<K, V, F, S = RandomState>
+where
+    K: Eq + Hash,
+{
+    cache: Arc<TTRCache<K, V, F, S>>,
+}
+
+impl<K, V, F, S> ReadHandle<K, V, F, S>
+where
+    K: Eq + Hash + Clone,
+    V: Clone,
+    S: BuildHasher,
+{
+    /// The cached value, stale or not, without fetching or refreshing it.
+    ///
+    /// Counts as a use for eviction and in the stats, like [`TTRCache::get`].
+    pub fn get<Q>(&self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    {
+        let store = self.cache.store();
+        if !store.normalizes_keys() {
+            if let Some(entity) = store.get_swapped(key) {
+                return Some(entity);
+            }
+        }
+        match self.read(key)? {
+            Lookup::Fresh(entity)
+            | Lookup::Expiring(entity)
+            | Lookup::Stale(entity)
+            | Lookup::Retrying(entity) => Some(entity),
+            Lookup::Revalidate(_) | Lookup::Absent | Lookup::Missing => None,
+        }
+    }
+
+    /// Looks a key up without fetching, like [`TTRCache::lookup`].
+    pub fn lookup(&self, key: &K) -> CacheLookup<V> {
+        self.read(key).map_or(CacheLookup::Missing, Into::into)
+    }
+
+    /// The entry and its metadata, like [`TTRCache::entry`].
+    pub fn entry(&self, key: &K) -> Option<EntryInfo<V>> {
+        self.cache.entry(key)
+    }
+
+    /// The cached value without fetching, like [`TTRCache::peek`].
+    pub fn peek(&self, key: &K) -> Option<V> {
+        self.cache.peek(key)
+    }
+
+    /// The number of entries, like [`TTRCache::len`].
+    pub fn len(&self) -> usize {
+        self.cache.len()
+    }
+
+    /// Returns `true` if nothing is cached.
+    pub fn is_empty(&self) -> bool {
+        self.cache.is_empty()
+    }
+
+    /// The cache's statistics, like [`TTRCache::stats`].
+    pub fn stats(&self) -> CacheStats {
+        self.cache.stats()
+    }
+
+    /// Looks `key` up in memory under its shard's read lock, without
+    /// expiring, fetching or consulting a second tier.
+    fn read<Q>(&self, key: &Q) -> Option<Lookup<V>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    {
+        let store = self.cache.store();
+        if !store.normalizes_keys() {
+            if let Some(lookup) = store.lookup_cached(key) {
+                return Some(lookup);
+            }
+        }
+        store.lookup_in_memory(&store.canonical_owned(key.to_owned()))
+    }
+}
+
+impl<K, V, F, S> Clone for ReadHandle<K, V, F, S>
+where
+    K: Eq + Hash,
+{
+    fn clone(&self) -> Self {
+        ReadHandle {
+            cache: Arc::clone(&self.cache),
+        }
+    }
+}
+
+impl<K, V, F, S> From<TTRCache<K, V, F, S>> for SharedTTRCache<K, V, F, S>
+where
+    K: Eq + Hash + Clone,
+    V: Clone,
+    S: BuildHasher,
+{
+    fn from(cache: TTRCache<K, V, F, S>) -> Self {
+        Self::new(cache)
+    }
+}
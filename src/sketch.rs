@@ -0,0 +1,164 @@
+//! Approximate access counts for TinyLFU admission, and an approximate set
+//! of keys found missing.
+
+use std::sync::atomic::{AtomicU64, AtomicU8, AtomicUsize, Ordering};
+use std::sync::{Mutex, PoisonError};
+use std::time::Duration;
+
+use crate::time::Instant;
+
+const ROWS: usize = 4;
+const MAX_COUNT: u8 = 15;
+/// Odd multipliers that spread one key hash over a different slot per row.
+const SEEDS: [u64; ROWS] = [
+    0x9E37_79B9_7F4A_7C15,
+    0xC2B2_AE3D_27D4_EB4F,
+    0x1656_67B1_9E37_79F9,
+    0xFF51_AFD7_ED55_8CCD,
+];
+
+/// Count-min sketch of how often keys were accessed recently.
+///
+/// Counts saturate at 15 and are halved once the sketch has seen ten times
+/// as many accesses as it has slots per row, so keys that were hot long ago
+/// lose out to keys that are hot now.
+pub(crate) struct FrequencySketch {
+    counters: Box<[AtomicU8]>,
+    width: usize,
+    accesses: AtomicUsize,
+    sample_size: usize,
+}
+
+impl FrequencySketch {
+    /// Creates a sketch sized for about `capacity` keys.
+    pub(crate) fn new(capacity: usize) -> Self {
+        let width = capacity.clamp(16, 1 << 24).next_power_of_two();
+        FrequencySketch {
+            counters: (0..ROWS * width).map(|_| AtomicU8::new(0)).collect(),
+            width,
+            accesses: AtomicUsize::new(0),
+            sample_size: 10 * width,
+        }
+    }
+
+    fn slots(&self, hash: u64) -> impl Iterator<Item = usize> + '_ {
+        SEEDS.iter().enumerate().map(move |(row, seed)| {
+            let spread = (hash.wrapping_mul(*seed) >> 32) as usize;
+            row * self.width + (spread & (self.width - 1))
+        })
+    }
+
+    /// Counts an access to the key with `hash`.
+    pub(crate) fn increment(&self, hash: u64) {
+        for slot in self.slots(hash) {
+            let _ =
+                self.counters[slot].fetch_update(Ordering::Relaxed, Ordering::Relaxed, |count| {
+                    (count < MAX_COUNT).then_some(count + 1)
+                });
+        }
+        if self.accesses.fetch_add(1, Ordering::Relaxed) + 1 == self.sample_size {
+            self.accesses.fetch_sub(self.sample_size, Ordering::Relaxed);
+            self.age();
+        }
+    }
+
+    /// Estimates how often the key with `hash` was accessed recently.
+    pub(crate) fn frequency(&self, hash: u64) -> u8 {
+        self.slots(hash)
+            .map(|slot| self.counters[slot].load(Ordering::Relaxed))
+            .min()
+            .unwrap_or(0)
+    }
+
+    fn age(&self) {
+        for counter in self.counters.iter() {
+            counter.store(counter.load(Ordering::Relaxed) / 2, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Bits set per key in a [`MissFilter`].
+const MISS_HASHES: u64 = 7;
+
+/// Bloom filter of keys the fetcher recently found nothing for.
+///
+/// Sized at ten bits per expected key, for about 1% false positives once
+/// that many are recorded. Keys cannot be taken out one by one, so the
+/// filter is cleared every reset period, and whenever a key it holds is
+/// cached or removed.
+pub(crate) struct MissFilter {
+    words: Box<[AtomicU64]>,
+    /// Bits in the filter, a power of two.
+    bits: u64,
+    reset_every: Duration,
+    /// When it is next cleared; never if that is out of range.
+    reset_at: Mutex<Option<Instant>>,
+}
+
+impl MissFilter {
+    /// Creates a filter for about `expected_keys` keys, cleared every
+    /// `reset_every` from `now`.
+    pub(crate) fn new(expected_keys: usize, reset_every: Duration, now: Instant) -> Self {
+        let bits = expected_keys
+            .saturating_mul(10)
+            .clamp(64, 1 << 32)
+            .next_power_of_two() as u64;
+        MissFilter {
+            words: (0..bits / 64).map(|_| AtomicU64::new(0)).collect(),
+            bits,
+            reset_every,
+            reset_at: Mutex::new(now.checked_add(reset_every)),
+        }
+    }
+
+    /// The bits of the key with `hash`, by double hashing.
+    fn bits(&self, hash: u64) -> impl Iterator<Item = u64> + '_ {
+        let step = hash.wrapping_mul(SEEDS[0]).rotate_left(32) | 1;
+        (0..MISS_HASHES).map(move |i| hash.wrapping_add(i.wrapping_mul(step)) & (self.bits - 1))
+    }
+
+    /// Records the key with `hash` as missing.
+    pub(crate) fn insert(&self, hash: u64, now: Instant) {
+        self.reset_if_due(now);
+        for bit in self.bits(hash) {
+            self.words[(bit / 64) as usize].fetch_or(1 << (bit % 64), Ordering::Relaxed);
+        }
+    }
+
+    /// Whether the key with `hash` was probably recorded since the last
+    /// reset.
+    pub(crate) fn contains(&self, hash: u64, now: Instant) -> bool {
+        self.reset_if_due(now);
+        self.holds(hash)
+    }
+
+    /// Clears the filter if it holds the key with `hash`, returning whether
+    /// it did.
+    pub(crate) fn forget(&self, hash: u64) -> bool {
+        let held = self.holds(hash);
+        if held {
+            self.clear();
+        }
+        held
+    }
+
+    pub(crate) fn clear(&self) {
+        for word in self.words.iter() {
+            word.store(0, Ordering::Relaxed);
+        }
+    }
+
+    fn holds(&self, hash: u64) -> bool {
+        self.bits(hash).all(|bit| {
+            self.words[(bit / 64) as usize].load(Ordering::Relaxed) & (1 << (bit % 64)) != 0
+        })
+    }
+
+    fn reset_if_due(&self, now: Instant) {
+        let mut reset_at = self.reset_at.lock().unwrap_or_else(PoisonError::into_inner);
+        if reset_at.is_some_and(|reset_at| now >= reset_at) {
+            self.clear();
+            *reset_at = now.checked_add(self.reset_every);
+        }
+    }
+}
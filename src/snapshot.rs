@@ -0,0 +1,84 @@
+//! Copies of the cache contents that outlive the process.
+
+use std::time::Duration;
+
+use crate::entry::KeyStats;
+#[cfg(feature = "serde")]
+use crate::error::Error;
+use crate::time::SystemTime;
+
+/// Cached entries with their timestamps, for warm-starting another cache.
+///
+/// Returned by `snapshot()` and accepted by `restore()`. With the `serde`
+/// feature it can be serialized in any format, and `save_to` / `load_from`
+/// store it as JSON.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Snapshot<K, V> {
+    /// Entries in no particular order.
+    pub entries: Vec<PersistedEntry<K, V>>,
+}
+
+/// One entry of a [`Snapshot`].
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PersistedEntry<K, V> {
+    /// Key of the entry.
+    pub key: K,
+    /// Cached value.
+    pub value: V,
+    /// Wall-clock time the value was fetched, according to the cache's
+    /// [`Clock::wall_time`](crate::Clock::wall_time).
+    pub fetched_at: SystemTime,
+    /// TTR of the entry.
+    pub ttl: Duration,
+    /// Whether the entry was invalidated, making it stale regardless of age.
+    pub invalidated: bool,
+}
+
+/// An entry with all of its timestamps, returned by `dump()` and accepted
+/// by `import()`.
+///
+/// Meant for debugging: it shows when the key was first cached as well as
+/// when it was last fetched, and importing it reproduces both.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct EntrySnapshot<V> {
+    /// Cached value.
+    pub value: V,
+    /// Wall-clock time the key was first cached.
+    pub inserted_at: SystemTime,
+    /// Wall-clock time the value was last fetched or inserted.
+    pub fetched_at: SystemTime,
+    /// TTR of the entry.
+    pub ttl: Duration,
+    /// Whether the entry was invalidated, making it stale regardless of age.
+    pub invalidated: bool,
+    /// How often the key was read, and how stale.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub stats: KeyStats,
+}
+
+#[cfg(feature = "serde")]
+pub(crate) fn save<K, V>(snapshot: &Snapshot<K, V>, path: &std::path::Path) -> Result<(), Error>
+where
+    K: serde::Serialize,
+    V: serde::Serialize,
+{
+    use std::io::Write;
+
+    let mut file = std::io::BufWriter::new(std::fs::File::create(path)?);
+    serde_json::to_writer(&mut file, snapshot)?;
+    file.flush()?;
+    Ok(())
+}
+
+#[cfg(feature = "serde")]
+pub(crate) fn load<K, V>(path: &std::path::Path) -> Result<Snapshot<K, V>, Error>
+where
+    K: serde::de::DeserializeOwned,
+    V: serde::de::DeserializeOwned,
+{
+    let file = std::io::BufReader::new(std::fs::File::open(path)?);
+    Ok(serde_json::from_reader(file)?)
+}
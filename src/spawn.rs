@@ -0,0 +1,139 @@
+//! Refresh tasks handed to the caller's runtime.
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use crate::context::FetchContext;
+
+/// Runs the background refreshes of an [`AsyncTTRCache`](crate::AsyncTTRCache)
+/// as tasks the caller owns.
+///
+/// A cache built with [`TTRCacheBuilder::build_async_with_spawner`](crate::TTRCacheBuilder::build_async_with_spawner)
+/// serves stale values at once and hands their refreshes to the spawner,
+/// which puts them on a runtime, a `JoinSet` or any other task group, and
+/// never spawns anything by itself. Closures taking a [`RefreshTask`] are
+/// spawners, so a tokio `JoinSet` behind a mutex is one with
+/// `move |task| { set.lock().unwrap().spawn(task); }`.
+pub trait Spawner: Send + Sync {
+    /// Runs `task` to completion, or drops it to skip the refresh.
+    fn spawn(&self, task: RefreshTask);
+}
+
+impl<T> Spawner for T
+where
+    T: Fn(RefreshTask) + Send + Sync,
+{
+    fn spawn(&self, task: RefreshTask) {
+        self(task);
+    }
+}
+
+/// A background refresh of one key, handed to a [`Spawner`].
+///
+/// The task finishes early, without refreshing, once its cache is dropped,
+/// so a task group holding it can be awaited after the cache is gone.
+pub struct RefreshTask {
+    /// The refresh and the cache's shutdown, until either is done.
+    running: Option<Running>,
+}
+
+struct Running {
+    refresh: Pin<Box<dyn Future<Output = ()> + Send>>,
+    shutdown: Pin<Box<dyn Future<Output = ()> + Send>>,
+    /// The cache's count of unfinished tasks.
+    count: Arc<AtomicUsize>,
+}
+
+impl RefreshTask {
+    /// Drops the refresh and the wait for shutdown, and counts the task as
+    /// finished.
+    fn finish(&mut self) {
+        if let Some(running) = self.running.take() {
+            running.count.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+}
+
+impl Future for RefreshTask {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let Some(running) = &mut self.running else {
+            return Poll::Ready(());
+        };
+        if running.shutdown.as_mut().poll(cx).is_ready()
+            || running.refresh.as_mut().poll(cx).is_ready()
+        {
+            self.finish();
+            return Poll::Ready(());
+        }
+        Poll::Pending
+    }
+}
+
+impl Drop for RefreshTask {
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
+
+/// The spawner of a cache, cancelling its tasks when dropped.
+///
+/// Each task is made from a `T` holding what its refresh needs.
+pub(crate) struct Spawning<T> {
+    spawn: Box<dyn Fn(T) -> RefreshTask + Send + Sync>,
+    spawner: Box<dyn Spawner>,
+    shutdown: FetchContext,
+    running: Arc<AtomicUsize>,
+}
+
+impl<T> Spawning<T> {
+    /// Spawns with `spawner` the refreshes made by `refresh`.
+    pub(crate) fn new<R, Fut>(spawner: impl Spawner + 'static, refresh: R) -> Self
+    where
+        R: Fn(T) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let shutdown = FetchContext::new();
+        let running = Arc::new(AtomicUsize::new(0));
+        let spawn = {
+            let shutdown = shutdown.clone();
+            let running = Arc::clone(&running);
+            move |job| {
+                running.fetch_add(1, Ordering::Relaxed);
+                RefreshTask {
+                    running: Some(Running {
+                        refresh: Box::pin(refresh(job)),
+                        shutdown: Box::pin(shutdown.cancelled()),
+                        count: Arc::clone(&running),
+                    }),
+                }
+            }
+        };
+        Spawning {
+            spawn: Box::new(spawn),
+            spawner: Box::new(spawner),
+            shutdown,
+            running,
+        }
+    }
+
+    /// Hands the refresh made from `job` to the spawner.
+    pub(crate) fn spawn(&self, job: T) {
+        self.spawner.spawn((self.spawn)(job));
+    }
+
+    /// Tasks spawned and not yet finished or dropped.
+    pub(crate) fn running(&self) -> usize {
+        self.running.load(Ordering::Relaxed)
+    }
+}
+
+impl<T> Drop for Spawning<T> {
+    fn drop(&mut self) {
+        self.shutdown.cancel();
+    }
+}
@@ -0,0 +1,168 @@
+//! Cache statistics.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use crate::breaker::BreakerState;
+
+/// Snapshot of a cache's counters, returned by `stats()`.
+///
+/// Counters are cumulative since the cache was created.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CacheStats {
+    /// Lookups served from a fresh entry.
+    pub hits: u64,
+    /// Lookups served from a stale entry.
+    pub stale_hits: u64,
+    /// Lookups of keys remembered as missing, by negative caching, a
+    /// tombstone or the miss filter. Neither hits nor misses, but counted
+    /// in the lookups [`hit_rate`](Self::hit_rate) is a fraction of.
+    pub absent_hits: u64,
+    /// Lookups that found nothing servable.
+    pub misses: u64,
+    /// Fetcher calls, for missing and stale keys alike.
+    pub refreshes: u64,
+    /// Fetcher calls that returned no value or an error.
+    pub refresh_failures: u64,
+    /// Fetcher calls given up on after the fetch timeout. Also counted as
+    /// refresh failures.
+    pub fetch_timeouts: u64,
+    /// Fetcher calls that panicked. Also counted as refresh failures.
+    pub fetch_panics: u64,
+    /// Time spent in fetcher calls.
+    pub total_fetch_time: Duration,
+    /// Entries currently cached.
+    pub entry_count: usize,
+    /// Estimated bytes held by the cached entries; see
+    /// [`TTRCache::memory_usage`](crate::TTRCache::memory_usage). Zero for
+    /// namespaces.
+    pub memory_usage: u64,
+    /// The highest [`memory_usage`](Self::memory_usage) reached.
+    pub peak_memory_usage: u64,
+    /// State of the [circuit breaker](crate::TTRCacheBuilder::circuit_breaker),
+    /// closed without one and for namespaces.
+    pub breaker_state: BreakerState,
+}
+
+impl CacheStats {
+    fn lookups(&self) -> u64 {
+        self.hits + self.stale_hits + self.absent_hits + self.misses
+    }
+
+    pub(crate) fn merge(&mut self, other: &CacheStats) {
+        self.hits += other.hits;
+        self.stale_hits += other.stale_hits;
+        self.absent_hits += other.absent_hits;
+        self.misses += other.misses;
+        self.refreshes += other.refreshes;
+        self.refresh_failures += other.refresh_failures;
+        self.fetch_timeouts += other.fetch_timeouts;
+        self.fetch_panics += other.fetch_panics;
+        self.total_fetch_time += other.total_fetch_time;
+        self.entry_count += other.entry_count;
+        self.memory_usage += other.memory_usage;
+        self.peak_memory_usage += other.peak_memory_usage;
+        self.breaker_state = self.breaker_state.worst(other.breaker_state);
+    }
+
+    /// Fraction of lookups served from the cache, fresh or stale.
+    ///
+    /// Returns `0.0` before the first lookup.
+    pub fn hit_rate(&self) -> f64 {
+        match self.lookups() {
+            0 => 0.0,
+            lookups => (self.hits + self.stale_hits) as f64 / lookups as f64,
+        }
+    }
+
+    /// Fraction of lookups that found nothing servable.
+    ///
+    /// Returns `0.0` before the first lookup.
+    pub fn miss_rate(&self) -> f64 {
+        match self.lookups() {
+            0 => 0.0,
+            lookups => self.misses as f64 / lookups as f64,
+        }
+    }
+
+    /// Mean duration of a fetcher call.
+    ///
+    /// Returns `Duration::ZERO` before the first fetch.
+    pub fn average_fetch_latency(&self) -> Duration {
+        match u32::try_from(self.refreshes) {
+            Ok(0) => Duration::ZERO,
+            Ok(refreshes) => self.total_fetch_time / refreshes,
+            Err(_) => {
+                Duration::from_secs_f64(self.total_fetch_time.as_secs_f64() / self.refreshes as f64)
+            }
+        }
+    }
+}
+
+/// Atomic counters behind [`CacheStats`].
+#[derive(Default)]
+pub(crate) struct StatsCounter {
+    hits: AtomicU64,
+    stale_hits: AtomicU64,
+    absent_hits: AtomicU64,
+    misses: AtomicU64,
+    refreshes: AtomicU64,
+    refresh_failures: AtomicU64,
+    fetch_timeouts: AtomicU64,
+    fetch_panics: AtomicU64,
+    fetch_nanos: AtomicU64,
+}
+
+impl StatsCounter {
+    pub(crate) fn record_hit(&self) {
+        self.hits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_stale_hit(&self) {
+        self.stale_hits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_absent_hit(&self) {
+        self.absent_hits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_miss(&self) {
+        self.misses.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_fetch(&self, elapsed: Duration, succeeded: bool) {
+        self.refreshes.fetch_add(1, Ordering::Relaxed);
+        if !succeeded {
+            self.refresh_failures.fetch_add(1, Ordering::Relaxed);
+        }
+        let nanos = u64::try_from(elapsed.as_nanos()).unwrap_or(u64::MAX);
+        self.fetch_nanos.fetch_add(nanos, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_timeout(&self) {
+        self.fetch_timeouts.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_panic(&self) {
+        self.fetch_panics.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn snapshot(&self, entry_count: usize) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            stale_hits: self.stale_hits.load(Ordering::Relaxed),
+            absent_hits: self.absent_hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            refreshes: self.refreshes.load(Ordering::Relaxed),
+            refresh_failures: self.refresh_failures.load(Ordering::Relaxed),
+            fetch_timeouts: self.fetch_timeouts.load(Ordering::Relaxed),
+            fetch_panics: self.fetch_panics.load(Ordering::Relaxed),
+            total_fetch_time: Duration::from_nanos(self.fetch_nanos.load(Ordering::Relaxed)),
+            entry_count,
+            memory_usage: 0,
+            peak_memory_usage: 0,
+            breaker_state: BreakerState::Closed,
+        }
+    }
+}
@@ -0,0 +1,295 @@
+//! Maps holding the entries of a cache's shards.
+
+use std::borrow::Borrow;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::{BuildHasher, Hash};
+use std::ops::Bound;
+
+/// A map of a shard's entries, for storing them other than in the default
+/// `HashMap`.
+///
+/// `E` is the cache's own [`StoredEntry`](crate::StoredEntry), which
+/// implementations store as is; they are written for any `E`, and picked with
+/// [`TTRCacheBuilder::storage`](crate::TTRCacheBuilder::storage). `HashMap`
+/// and `BTreeMap` implement it; a map that implements [`range`](Self::range),
+/// as a `BTreeMap` does, lets [`TTRCache::range`](crate::TTRCache::range) and
+/// the range invalidations skip the keys outside the range.
+///
+/// A cache with custom storage looks keys up by their owned form, so reads
+/// by a borrowed form of the key take an owned copy first.
+///
+/// ```rust
+/// use std::collections::BTreeMap;
+/// use std::time::Duration;
+/// use ttr_cache::{TTRCache, TTRCacheBuilder};
+///
+/// let cache: TTRCache<_, _, _> = TTRCacheBuilder::new(Duration::from_secs(60))
+///     .storage(BTreeMap::new)
+///     .build(|id: &u32| Some(id * 10));
+///
+/// for id in [4, 1, 3, 2] {
+///     cache.get(&id);
+/// }
+/// let ids: Vec<_> = cache.range(2..).map(|(id, _)| id).collect();
+/// assert_eq!(ids, [2, 3, 4]);
+/// ```
+pub trait Storage<K, E> {
+    /// The entry of `key`.
+    fn get(&self, key: &K) -> Option<&E>;
+
+    /// The entry of `key`, to update in place.
+    fn get_mut(&mut self, key: &K) -> Option<&mut E>;
+
+    /// Stores the entry of `key`, returning the one it replaces.
+    fn insert(&mut self, key: K, entry: E) -> Option<E>;
+
+    /// Removes the entry of `key`, returning it with the stored key.
+    fn remove_entry(&mut self, key: &K) -> Option<(K, E)>;
+
+    /// How many entries are stored.
+    fn len(&self) -> usize;
+
+    /// Whether no entries are stored.
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Every entry, in any order.
+    fn iter(&self) -> Box<dyn Iterator<Item = (&K, &E)> + '_>;
+
+    /// Every entry, to update in place.
+    fn iter_mut(&mut self) -> Box<dyn Iterator<Item = (&K, &mut E)> + '_>;
+
+    /// Removes every entry, yielding them.
+    fn drain(&mut self) -> Box<dyn Iterator<Item = (K, E)> + '_>;
+
+    /// The entries with keys between `from` and `to`, in key order, or
+    /// `None` if the map does not keep its keys in order.
+    fn range(
+        &self,
+        from: Bound<&K>,
+        to: Bound<&K>,
+    ) -> Option<Box<dyn Iterator<Item = (&K, &E)> + '_>> {
+        let _ = (from, to);
+        None
+    }
+}
+
+impl<K: Eq + Hash, E, S: BuildHasher> Storage<K, E> for HashMap<K, E, S> {
+    fn get(&self, key: &K) -> Option<&E> {
+        HashMap::get(self, key)
+    }
+
+    fn get_mut(&mut self, key: &K) -> Option<&mut E> {
+        HashMap::get_mut(self, key)
+    }
+
+    fn insert(&mut self, key: K, entry: E) -> Option<E> {
+        HashMap::insert(self, key, entry)
+    }
+
+    fn remove_entry(&mut self, key: &K) -> Option<(K, E)> {
+        HashMap::remove_entry(self, key)
+    }
+
+    fn len(&self) -> usize {
+        HashMap::len(self)
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = (&K, &E)> + '_> {
+        Box::new(HashMap::iter(self))
+    }
+
+    fn iter_mut(&mut self) -> Box<dyn Iterator<Item = (&K, &mut E)> + '_> {
+        Box::new(HashMap::iter_mut(self))
+    }
+
+    fn drain(&mut self) -> Box<dyn Iterator<Item = (K, E)> + '_> {
+        Box::new(HashMap::drain(self))
+    }
+}
+
+impl<K: Ord, E> Storage<K, E> for BTreeMap<K, E> {
+    fn get(&self, key: &K) -> Option<&E> {
+        BTreeMap::get(self, key)
+    }
+
+    fn get_mut(&mut self, key: &K) -> Option<&mut E> {
+        BTreeMap::get_mut(self, key)
+    }
+
+    fn insert(&mut self, key: K, entry: E) -> Option<E> {
+        BTreeMap::insert(self, key, entry)
+    }
+
+    fn remove_entry(&mut self, key: &K) -> Option<(K, E)> {
+        BTreeMap::remove_entry(self, key)
+    }
+
+    fn len(&self) -> usize {
+        BTreeMap::len(self)
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = (&K, &E)> + '_> {
+        Box::new(BTreeMap::iter(self))
+    }
+
+    fn iter_mut(&mut self) -> Box<dyn Iterator<Item = (&K, &mut E)> + '_> {
+        Box::new(BTreeMap::iter_mut(self))
+    }
+
+    fn drain(&mut self) -> Box<dyn Iterator<Item = (K, E)> + '_> {
+        Box::new(std::mem::take(self).into_iter())
+    }
+
+    fn range(
+        &self,
+        from: Bound<&K>,
+        to: Bound<&K>,
+    ) -> Option<Box<dyn Iterator<Item = (&K, &E)> + '_>> {
+        Some(Box::new(BTreeMap::range::<K, _>(self, (from, to))))
+    }
+}
+
+/// Boxed storage shared by a cache's threads.
+pub(crate) type BoxedStorage<K, E> = Box<dyn Storage<K, E> + Send + Sync>;
+
+/// The entries of one shard: in a `HashMap`, or in storage from the builder.
+pub(crate) enum Entries<K, E, S> {
+    Hashed(HashMap<K, E, S>),
+    Custom(BoxedStorage<K, E>),
+}
+
+/// Either map's iterator.
+pub(crate) enum Iter<H, C> {
+    Hashed(H),
+    Custom(C),
+}
+
+impl<T, H: Iterator<Item = T>, C: Iterator<Item = T>> Iterator for Iter<H, C> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        match self {
+            Iter::Hashed(iter) => iter.next(),
+            Iter::Custom(iter) => iter.next(),
+        }
+    }
+}
+
+impl<K, E, S> Entries<K, E, S> {
+    pub(crate) fn drain(&mut self) -> impl Iterator<Item = (K, E)> + '_ {
+        match self {
+            Entries::Hashed(map) => Iter::Hashed(map.drain()),
+            Entries::Custom(storage) => Iter::Custom(storage.drain()),
+        }
+    }
+}
+
+impl<K: Eq + Hash, E, S: BuildHasher> Entries<K, E, S> {
+    pub(crate) fn get(&self, key: &K) -> Option<&E> {
+        match self {
+            Entries::Hashed(map) => map.get(key),
+            Entries::Custom(storage) => storage.get(key),
+        }
+    }
+
+    /// The entry of a borrowed form of a key, or `None` if the storage can
+    /// only look up owned keys.
+    pub(crate) fn get_borrowed<Q>(&self, key: &Q) -> Option<Option<&E>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        match self {
+            Entries::Hashed(map) => Some(map.get(key)),
+            Entries::Custom(_) => None,
+        }
+    }
+
+    /// The stored key and entry of a borrowed form of a key, searching
+    /// storage that can only look up owned keys.
+    #[cfg(feature = "cached")]
+    pub(crate) fn find<Q>(&self, key: &Q) -> Option<(&K, &E)>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        match self {
+            Entries::Hashed(map) => map.get_key_value(key),
+            Entries::Custom(storage) => {
+                storage.iter().find(|(stored, _)| (*stored).borrow() == key)
+            }
+        }
+    }
+
+    pub(crate) fn get_mut(&mut self, key: &K) -> Option<&mut E> {
+        match self {
+            Entries::Hashed(map) => map.get_mut(key),
+            Entries::Custom(storage) => storage.get_mut(key),
+        }
+    }
+
+    pub(crate) fn insert(&mut self, key: K, entry: E) -> Option<E> {
+        match self {
+            Entries::Hashed(map) => map.insert(key, entry),
+            Entries::Custom(storage) => storage.insert(key, entry),
+        }
+    }
+
+    pub(crate) fn remove_entry(&mut self, key: &K) -> Option<(K, E)> {
+        match self {
+            Entries::Hashed(map) => map.remove_entry(key),
+            Entries::Custom(storage) => storage.remove_entry(key),
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        match self {
+            Entries::Hashed(map) => map.len(),
+            Entries::Custom(storage) => storage.len(),
+        }
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (&K, &E)> {
+        match self {
+            Entries::Hashed(map) => Iter::Hashed(map.iter()),
+            Entries::Custom(storage) => Iter::Custom(storage.iter()),
+        }
+    }
+
+    pub(crate) fn iter_mut(&mut self) -> impl Iterator<Item = (&K, &mut E)> {
+        match self {
+            Entries::Hashed(map) => Iter::Hashed(map.iter_mut()),
+            Entries::Custom(storage) => Iter::Custom(storage.iter_mut()),
+        }
+    }
+
+    pub(crate) fn values(&self) -> impl Iterator<Item = &E> {
+        self.iter().map(|(_, entry)| entry)
+    }
+
+    pub(crate) fn values_mut(&mut self) -> impl Iterator<Item = &mut E> {
+        self.iter_mut().map(|(_, entry)| entry)
+    }
+
+    pub(crate) fn clear(&mut self) {
+        match self {
+            Entries::Hashed(map) => map.clear(),
+            Entries::Custom(storage) => storage.drain().for_each(drop),
+        }
+    }
+
+    /// The entries with keys in a range, in order, if the storage keeps
+    /// them ordered.
+    pub(crate) fn range(
+        &self,
+        from: Bound<&K>,
+        to: Bound<&K>,
+    ) -> Option<impl Iterator<Item = (&K, &E)>> {
+        match self {
+            Entries::Hashed(_) => None,
+            Entries::Custom(storage) => storage.range(from, to),
+        }
+    }
+}
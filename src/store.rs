@@ -0,0 +1,3428 @@
+//! Shared entry storage used by the sync and async caches.
+
+use std::any::Any;
+use std::borrow::{Borrow, Cow};
+use std::cmp::Reverse;
+use std::collections::hash_map::RandomState;
+use std::collections::{HashMap, HashSet};
+use std::hash::{BuildHasher, Hash, Hasher};
+#[cfg(feature = "snapshot-swap")]
+use std::ops::DerefMut;
+use std::ops::{Deref, RangeBounds};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, PoisonError, TryLockError};
+use std::time::Duration;
+
+#[cfg(feature = "age-histogram")]
+use crate::ages::ServedAges;
+use crate::breaker::{Breaker, BreakerState};
+use crate::budget::{Account, MemoryBudget};
+use crate::bus::InvalidationBus;
+use crate::clock::{Clock, Timeline};
+use crate::codec::ValueCodec;
+use crate::context::{CancelHandle, FetchContext};
+use crate::entry::{
+    CacheLookup, EntryInfo, EntryState, HitFreshness, HotKey, KeyStats, ValueGuard,
+};
+use crate::flight::{Claim, Flight, FlightGroup};
+use crate::index::Indexes;
+use crate::limit::{FetchLimit, Permit};
+use crate::listener::{
+    CacheEventListener, EvictionReason, LookupOutcome, LookupSample, RefreshEvent, ValueLifecycle,
+};
+use crate::lock_file::{HeldElsewhere, KeyLock, LockDir};
+use crate::namespace::NamespaceTtls;
+#[cfg(feature = "snapshot-swap")]
+use crate::policy::Engine;
+use crate::policy::{
+    ClockJump, EvictionPolicy, KeyNormalizer, MissPolicy, OnMissingRefresh, RefreshStrategy,
+    RetryPolicy, StaleIfError, TtlPolicy, Validator, Weigher,
+};
+use crate::sketch::{FrequencySketch, MissFilter};
+use crate::snapshot::{EntrySnapshot, PersistedEntry, Snapshot};
+use crate::stats::{CacheStats, StatsCounter};
+use crate::storage::{BoxedStorage, Entries};
+#[cfg(feature = "snapshot-swap")]
+use crate::swap::{SwapMap, SwapReads};
+use crate::sync::{Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use crate::tiered::CacheStore;
+use crate::time::{Instant, SystemTime};
+#[cfg(feature = "tracing")]
+use crate::trace::{KeyFormatter, TraceKey};
+use crate::watch::{Watch, WatchSlot};
+use crate::wheel::Timers;
+use crate::write_behind::WriteBehindQueue;
+
+/// Boxed policy as stored by the cache.
+pub(crate) type BoxedTtlPolicy<K, V> = Box<dyn TtlPolicy<K, V> + Send + Sync>;
+
+/// Scales a TTR by the latency of the fetch that produced the value.
+pub(crate) type BoxedFetchCostTtl = Box<dyn Fn(Duration, Duration) -> Duration + Send + Sync>;
+
+/// Boxed weigher as stored by the cache.
+pub(crate) type BoxedWeigher<K, V> = Box<dyn Weigher<K, V> + Send + Sync>;
+
+/// Boxed validator as stored by the cache.
+pub(crate) type BoxedValidator<K, V> = Box<dyn Validator<K, V> + Send + Sync>;
+
+/// Boxed key normalizer as stored by the cache.
+pub(crate) type BoxedKeyNormalizer<K> = Box<dyn KeyNormalizer<K> + Send + Sync>;
+
+/// Boxed bus for invalidations shared with other instances.
+pub(crate) type BoxedInvalidationBus<K> = Box<dyn InvalidationBus<K> + Send + Sync>;
+
+/// Boxed callback for sampled lookups.
+pub(crate) type BoxedLookupSampler<K> = Box<dyn Fn(&LookupSample<'_, K>) + Send + Sync>;
+
+/// Chooses each key's refresh strategy.
+pub(crate) type BoxedRefreshStrategy<K> = Box<dyn Fn(&K) -> RefreshStrategy + Send + Sync>;
+
+/// Boxed second tier as stored by the cache.
+pub(crate) type BoxedCacheStore<K, V> = Box<dyn CacheStore<K, V> + Send + Sync>;
+
+/// Boxed clock as stored by the cache.
+pub(crate) type SharedClock = Arc<dyn Clock + Send + Sync>;
+
+/// Boxed codec as stored by the cache.
+pub(crate) type BoxedCodec<V> = Box<dyn ValueCodec<V> + Send + Sync>;
+
+/// A `Weak<T>`, for a store whose values are `Arc<T>`.
+pub(crate) type BoxedWeak = Box<dyn Any + Send + Sync>;
+
+/// Conversions between a store's `Arc<T>` values and their weak references.
+///
+/// Captured where `V` is known to be an `Arc`, so the store itself does not
+/// need to be.
+pub(crate) struct WeakValues<V> {
+    pub(crate) downgrade: fn(&V) -> BoxedWeak,
+    pub(crate) upgrade: fn(&BoxedWeak) -> Option<V>,
+}
+
+impl<V> WeakValues<V> {
+    fn upgrade(&self, weak: &BoxedWeak) -> Option<V> {
+        (self.upgrade)(weak)
+    }
+
+    fn is_alive(&self, weak: &BoxedWeak) -> bool {
+        self.upgrade(weak).is_some()
+    }
+}
+
+/// Bounds of a TTR adapted to how often the value changes.
+///
+/// Captured where `V` is known to be `PartialEq`, like [`WeakValues`].
+pub(crate) struct AdaptiveTtl<V> {
+    pub(crate) eq: fn(&V, &V) -> bool,
+    pub(crate) min: Duration,
+    pub(crate) max: Duration,
+}
+
+impl<V> AdaptiveTtl<V> {
+    /// The TTR following `ttl` after a refresh that left the value
+    /// `unchanged` or not: doubled or halved, within the bounds.
+    fn next(&self, ttl: Duration, unchanged: bool) -> Duration {
+        let ttl = if unchanged {
+            ttl.saturating_mul(2)
+        } else {
+            ttl / 2
+        };
+        ttl.clamp(self.min, self.max)
+    }
+}
+
+/// Boxed listener as stored by the cache.
+pub(crate) type BoxedListener<K, V> = Box<dyn CacheEventListener<K, V> + Send + Sync>;
+
+pub(crate) type BoxedLifecycle<V> = Box<dyn ValueLifecycle<V> + Send + Sync>;
+
+/// Where a subscriber to one key's changes wants them.
+enum Sink<K, V> {
+    /// Every change, as a [`RefreshEvent`].
+    Events(Sender<RefreshEvent<K, V>>),
+    /// The latest value, including the first one cached.
+    Watch(Arc<WatchSlot<V>>),
+    /// The latest value, in a `tokio` watch channel.
+    #[cfg(feature = "tokio")]
+    Channel(tokio::sync::watch::Sender<Option<V>>),
+}
+
+/// Subscriber to the changes of one key's value.
+struct Subscriber<K, V> {
+    sink: Sink<K, V>,
+    /// `PartialEq::eq` for `V`, captured when subscribing so the store itself
+    /// does not need the bound.
+    eq: fn(&V, &V) -> bool,
+}
+
+/// Result of looking up a key without fetching.
+pub(crate) enum Lookup<V> {
+    /// Entry is younger than its TTR.
+    Fresh(V),
+    /// Entry is fresh but within the refresh-ahead window.
+    Expiring(V),
+    /// Entry is due for a refresh.
+    Stale(V),
+    /// Entry is stale, but is backing off after failed refreshes.
+    Retrying(V),
+    /// Entry is past its stale-while-revalidate window, so it may only be
+    /// served if refreshing it fails.
+    Revalidate(V),
+    /// Key was recently fetched and not found.
+    Absent,
+    /// Key is not cached.
+    Missing,
+}
+
+impl<V> Lookup<V> {
+    pub(crate) fn outcome(&self) -> LookupOutcome {
+        match self {
+            Lookup::Fresh(_) | Lookup::Expiring(_) => LookupOutcome::Hit,
+            Lookup::Stale(_) | Lookup::Retrying(_) | Lookup::Revalidate(_) => {
+                LookupOutcome::StaleHit
+            }
+            Lookup::Absent | Lookup::Missing => LookupOutcome::Miss,
+        }
+    }
+}
+
+impl<V> From<Lookup<V>> for CacheLookup<V> {
+    fn from(lookup: Lookup<V>) -> Self {
+        match lookup {
+            Lookup::Fresh(value) | Lookup::Expiring(value) => CacheLookup::Fresh(value),
+            Lookup::Stale(value) | Lookup::Retrying(value) | Lookup::Revalidate(value) => {
+                CacheLookup::Stale(value)
+            }
+            Lookup::Absent | Lookup::Missing => CacheLookup::Missing,
+        }
+    }
+}
+
+/// Settings shared by every entry in a store.
+pub(crate) struct Config<K, V> {
+    pub(crate) ttl: Duration,
+    pub(crate) ttl_policy: Option<BoxedTtlPolicy<K, V>>,
+    pub(crate) fetch_cost_ttl: Option<BoxedFetchCostTtl>,
+    pub(crate) max_stale: Option<Duration>,
+    pub(crate) max_idle: Option<Duration>,
+    pub(crate) expire_after_idle: Option<Duration>,
+    pub(crate) max_concurrent_fetches: Option<usize>,
+    /// Consecutive failures that open the breaker, and its cool-down.
+    pub(crate) circuit_breaker: Option<(u32, Duration)>,
+    pub(crate) max_capacity: Option<usize>,
+    pub(crate) eviction_policy: EvictionPolicy,
+    pub(crate) weigher: Option<BoxedWeigher<K, V>>,
+    pub(crate) max_weight: Option<u64>,
+    pub(crate) memory_budget: Option<MemoryBudget>,
+    pub(crate) validator: Option<BoxedValidator<K, V>>,
+    pub(crate) key_normalizer: Option<BoxedKeyNormalizer<K>>,
+    /// Estimated bytes per entry, for memory usage without a weigher.
+    pub(crate) entry_size: u64,
+    pub(crate) refresh_ahead: Option<Duration>,
+    pub(crate) refresh_strategy: Option<BoxedRefreshStrategy<K>>,
+    pub(crate) ttl_jitter: Option<f64>,
+    pub(crate) retry_policy: Option<RetryPolicy>,
+    pub(crate) stale_if_error: Option<StaleIfError>,
+    pub(crate) negative_ttl: Option<Duration>,
+    /// Expected missing keys and the reset period of the miss filter.
+    pub(crate) miss_filter: Option<(usize, Duration)>,
+    pub(crate) min_fetch_interval: Option<Duration>,
+    pub(crate) stale_while_revalidate: Option<Duration>,
+    pub(crate) on_missing_refresh: OnMissingRefresh,
+    pub(crate) miss_policy: MissPolicy,
+    pub(crate) namespace_ttls: Option<NamespaceTtls<K>>,
+    pub(crate) listener: Option<BoxedListener<K, V>>,
+    pub(crate) lifecycle: Option<BoxedLifecycle<V>>,
+    pub(crate) codec: Option<BoxedCodec<V>>,
+    pub(crate) weak_values: Option<WeakValues<V>>,
+    /// `PartialEq::eq` for `V`, if refreshes to an equal value only renew
+    /// the entry.
+    pub(crate) dedupe_refreshes: Option<fn(&V, &V) -> bool>,
+    pub(crate) adaptive_ttl: Option<AdaptiveTtl<V>>,
+    /// Keeps each key's last value that passed validation.
+    pub(crate) keep_last_known_good: bool,
+    pub(crate) shards: usize,
+    pub(crate) storage: Option<StorageFactory<K, V>>,
+    pub(crate) second_tier: Option<BoxedCacheStore<K, V>>,
+    pub(crate) write_behind: Option<WriteBehindQueue<K, V>>,
+    pub(crate) refresh_locks: Option<LockDir>,
+    pub(crate) invalidation_bus: Option<BoxedInvalidationBus<K>>,
+    /// The share of lookups sampled, and the callback they are passed to.
+    pub(crate) lookup_sampler: Option<(f64, BoxedLookupSampler<K>)>,
+    #[cfg(feature = "age-histogram")]
+    pub(crate) served_ages: Option<ServedAges>,
+    #[cfg(feature = "snapshot-swap")]
+    pub(crate) engine: Engine,
+    /// The smallest gap between clock readings taken as a jump, and how to
+    /// re-date entries after one.
+    pub(crate) clock_jump: Option<(Duration, ClockJump)>,
+    pub(crate) flights: Option<FlightGroup<K, V>>,
+    pub(crate) fetch_timeout: Option<Duration>,
+    pub(crate) indexes: Indexes<K, V>,
+    pub(crate) clock: SharedClock,
+    #[cfg(feature = "tracing")]
+    pub(crate) trace_keys: Option<KeyFormatter<K>>,
+}
+
+/// A cached value, encoded if the store has a codec.
+enum Slot<V> {
+    Plain(V),
+    Encoded(Box<[u8]>),
+    Weak(BoxedWeak),
+}
+
+/// A cached value, borrowed as stored or decoded into a temporary.
+enum Decoded<'a, V> {
+    Borrowed(&'a V),
+    Owned(V),
+}
+
+impl<V> Deref for Decoded<'_, V> {
+    type Target = V;
+
+    fn deref(&self) -> &V {
+        match self {
+            Decoded::Borrowed(value) => value,
+            Decoded::Owned(value) => value,
+        }
+    }
+}
+
+/// A plain value borrowed from under its shard's read lock.
+struct Locked<'a, K, V, S, Q: ?Sized> {
+    entries: RwLockReadGuard<'a, Entries<K, Entry<V>, S>>,
+    key: &'a Q,
+}
+
+impl<K, V, S, Q> Deref for Locked<'_, K, V, S, Q>
+where
+    K: Eq + Hash + Borrow<Q>,
+    S: BuildHasher,
+    Q: Hash + Eq + ?Sized,
+{
+    type Target = V;
+
+    fn deref(&self) -> &V {
+        match self
+            .entries
+            .get_borrowed(self.key)
+            .flatten()
+            .map(|entry| &entry.value)
+        {
+            Some(Slot::Plain(value)) => value,
+            // The read lock keeps the entry as it was when checked.
+            _ => unreachable!("guarded entry changed under its read lock"),
+        }
+    }
+}
+
+/// Cached value and its refresh bookkeeping.
+pub struct Entry<V> {
+    value: Slot<V>,
+    inserted_at: Instant,
+    fetched_at: Instant,
+    ttl: Duration,
+    weight: u64,
+    last_access: AtomicU64,
+    /// Nanoseconds from the store's epoch to the last read or write.
+    last_used: AtomicU64,
+    /// Nanoseconds from the store's epoch to the last read or explicit
+    /// write; refreshes leave it alone.
+    last_read: AtomicU64,
+    /// Reads served since the key was first cached.
+    hits: AtomicU64,
+    /// Of those, reads that found the entry stale.
+    stale_hits: AtomicU64,
+    /// Nanoseconds past its TTR the entry was at most when read stale.
+    max_staleness: AtomicU64,
+    invalidated: bool,
+    /// The cache-wide generation the value was fetched in; entries from
+    /// before the current one are stale.
+    bulk_generation: u64,
+    /// Exempt from eviction and hard expiry.
+    pinned: bool,
+    /// Changes on every write and invalidation; see [`Generation`].
+    generation: u64,
+    /// The generation the refresh that wrote the entry started from, if a
+    /// refresh did.
+    refreshed_from: Option<Generation>,
+    /// Consecutive failed refreshes.
+    failures: u32,
+    /// When the current run of failed refreshes started.
+    failing_since: Option<Instant>,
+    /// When the last of those failed.
+    failed_at: Option<Instant>,
+    /// No refresh should be attempted before this time.
+    retry_at: Option<Instant>,
+    /// The TTR came from the cache-wide or namespace TTR, and changes with
+    /// it.
+    follows_default: bool,
+}
+
+impl<V> Entry<V> {
+    fn age(&self, now: Instant) -> Duration {
+        now.saturating_duration_since(self.fetched_at)
+    }
+
+    fn is_fresh(&self, now: Instant) -> bool {
+        !self.invalidated && self.age(now) < self.ttl
+    }
+
+    /// Counts a read that found the entry stale at `now`.
+    fn record_stale_hit(&self, now: Instant) {
+        let staleness = self.age(now).saturating_sub(self.ttl);
+        self.stale_hits.fetch_add(1, Ordering::Relaxed);
+        self.max_staleness.fetch_max(
+            u64::try_from(staleness.as_nanos()).unwrap_or(u64::MAX),
+            Ordering::Relaxed,
+        );
+    }
+
+    /// Counts reads served from the swap copy as reads of the entry.
+    #[cfg(feature = "snapshot-swap")]
+    fn add_swap_reads(&self, reads: SwapReads) {
+        self.hits.fetch_add(reads.hits, Ordering::Relaxed);
+        self.last_access
+            .fetch_max(reads.last_access, Ordering::Relaxed);
+        self.last_used.fetch_max(reads.last_read, Ordering::Relaxed);
+        self.last_read.fetch_max(reads.last_read, Ordering::Relaxed);
+    }
+
+    fn stats(&self) -> KeyStats {
+        KeyStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            stale_hits: self.stale_hits.load(Ordering::Relaxed),
+            max_staleness: Duration::from_nanos(self.max_staleness.load(Ordering::Relaxed)),
+        }
+    }
+
+    /// Sets the read counters, kept across writes to the key.
+    fn keep_stats(&mut self, stats: KeyStats) {
+        self.hits = AtomicU64::new(stats.hits);
+        self.stale_hits = AtomicU64::new(stats.stale_hits);
+        self.max_staleness =
+            AtomicU64::new(u64::try_from(stats.max_staleness.as_nanos()).unwrap_or(u64::MAX));
+    }
+
+    /// Whether the entry is more than `window` past its TTR, so it should
+    /// not be served without trying to refresh it first.
+    fn must_revalidate(&self, window: Option<Duration>, now: Instant) -> bool {
+        window.is_some_and(|window| self.age(now) >= self.ttl.saturating_add(window))
+    }
+
+    /// Whether a fresh entry is within `lead` of going stale.
+    fn is_expiring(&self, lead: Duration, now: Instant) -> bool {
+        self.age(now) >= self.ttl.saturating_sub(lead)
+    }
+
+    /// Whether refreshes are on hold after failing.
+    fn is_backing_off(&self, retry_policy: &RetryPolicy, now: Instant) -> bool {
+        retry_policy.exhausted(self.failures)
+            || self.retry_at.is_some_and(|retry_at| now < retry_at)
+    }
+
+    /// Makes the entry stale and clears its failed refreshes, moving it to
+    /// `generation`.
+    fn invalidate(&mut self, generation: u64) {
+        self.invalidated = true;
+        self.generation = generation;
+        self.refreshed_from = None;
+        self.failures = 0;
+        self.failing_since = None;
+        self.failed_at = None;
+        self.retry_at = None;
+    }
+
+    /// Whether the entry went unused for at least `max_idle`.
+    fn is_idle(&self, max_idle: Option<Duration>, epoch: Instant, now: Instant) -> bool {
+        let last_used = Duration::from_nanos(self.last_used.load(Ordering::Relaxed));
+        max_idle.is_some_and(|max_idle| {
+            now.saturating_duration_since(epoch)
+                .saturating_sub(last_used)
+                >= max_idle
+        })
+    }
+
+    /// Whether the entry went unread for at least `idle`.
+    fn is_unread(&self, idle: Option<Duration>, epoch: Instant, now: Instant) -> bool {
+        let last_read = Duration::from_nanos(self.last_read.load(Ordering::Relaxed));
+        idle.is_some_and(|idle| {
+            now.saturating_duration_since(epoch)
+                .saturating_sub(last_read)
+                >= idle
+        })
+    }
+
+    /// Whether the entry is too old to be served at all.
+    fn is_expired(&self, max_stale: Option<Duration>, now: Instant) -> bool {
+        max_stale.is_some_and(|max_stale| self.age(now) >= self.ttl.saturating_add(max_stale))
+    }
+}
+
+/// Version of a key's entry, as seen before a refresh started.
+///
+/// Writing or invalidating an entry gives it a new generation, so a refresh
+/// that finishes after either can tell that its result is out of date. Of
+/// two refreshes started from the same generation, the one started last
+/// wins, whichever finishes first.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Generation {
+    /// `None` if the key was not cached.
+    entry: Option<u64>,
+    /// The cache-wide generation when the refresh started.
+    bulk: u64,
+    /// Orders refreshes by when they started.
+    started: u64,
+}
+
+impl Generation {
+    /// Whether the key was cached when the refresh started.
+    pub(crate) fn is_cached(self) -> bool {
+        self.entry.is_some()
+    }
+
+    /// Whether `entry` is still at this generation.
+    fn matches<V>(self, entry: &Entry<V>) -> bool {
+        self.entry == Some(entry.generation)
+    }
+
+    /// Whether a refresh started at this generation is newer than what
+    /// wrote `entry`: the entry is unchanged since, or was only written by
+    /// a refresh from the same generation that started earlier.
+    fn supersedes<V>(self, entry: Option<&Entry<V>>) -> bool {
+        let Some(entry) = entry else {
+            return self.entry.is_none();
+        };
+        self.matches(entry)
+            || entry.refreshed_from.is_some_and(|previous| {
+                previous.entry == self.entry && previous.started < self.started
+            })
+    }
+}
+
+/// Map of entries by key.
+type Shard<K, V, S> = RwLock<Entries<K, Entry<V>, S>>;
+
+/// Makes the storage of each shard, instead of a `HashMap`.
+pub(crate) type StorageFactory<K, V> = Box<dyn Fn() -> BoxedStorage<K, Entry<V>> + Send + Sync>;
+
+/// The contexts of the fetches in flight for the keys of one shard.
+type ContextShard<K, S> = Mutex<HashMap<K, Vec<CancelHandle>, S>>;
+
+fn lock_contexts<K, S>(
+    registry: &ContextShard<K, S>,
+) -> MutexGuard<'_, HashMap<K, Vec<CancelHandle>, S>> {
+    registry.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// The context of a fetch in flight, which can be cancelled through its key
+/// until this is dropped.
+pub(crate) struct ActiveContext<'a, K: Eq + Hash, S: BuildHasher> {
+    registry: &'a ContextShard<K, S>,
+    key: K,
+    context: FetchContext,
+}
+
+impl<K: Eq + Hash, S: BuildHasher> Deref for ActiveContext<'_, K, S> {
+    type Target = FetchContext;
+
+    fn deref(&self) -> &FetchContext {
+        &self.context
+    }
+}
+
+impl<K: Eq + Hash, S: BuildHasher> Drop for ActiveContext<'_, K, S> {
+    fn drop(&mut self) {
+        let mut contexts = lock_contexts(self.registry);
+        if let Some(handles) = contexts.get_mut(&self.key) {
+            handles.retain(|handle| !handle.is_for(&self.context));
+            if handles.is_empty() {
+                contexts.remove(&self.key);
+            }
+        }
+    }
+}
+
+/// A write lock on a shard, outdating the snapshot copy once written.
+#[cfg(feature = "snapshot-swap")]
+struct ShardWrite<'a, K, V, S> {
+    entries: RwLockWriteGuard<'a, Entries<K, Entry<V>, S>>,
+    swap: Option<&'a SwapMap<K, V>>,
+}
+
+#[cfg(not(feature = "snapshot-swap"))]
+type ShardWrite<'a, K, V, S> = RwLockWriteGuard<'a, Entries<K, Entry<V>, S>>;
+
+#[cfg(feature = "snapshot-swap")]
+impl<K, V, S> Deref for ShardWrite<'_, K, V, S> {
+    type Target = Entries<K, Entry<V>, S>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.entries
+    }
+}
+
+#[cfg(feature = "snapshot-swap")]
+impl<K, V, S> DerefMut for ShardWrite<'_, K, V, S> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.entries
+    }
+}
+
+#[cfg(feature = "snapshot-swap")]
+impl<K, V, S> Drop for ShardWrite<'_, K, V, S> {
+    fn drop(&mut self) {
+        // Still under the lock, so a rebuild waiting on it sees the write.
+        if let Some(swap) = self.swap {
+            swap.outdate();
+        }
+    }
+}
+
+type Subscribers<K, V, S> = HashMap<K, Vec<Subscriber<K, V>>, S>;
+
+/// How many keys remembered as missing are held before lapsed ones are
+/// first dropped.
+const MIN_ABSENT_PRUNE: usize = 64;
+
+/// Timestamped entries behind read-write locks, sharded by key hash.
+///
+/// The entry count and total weight are only changed while a shard's write
+/// lock is held.
+///
+/// Fetching always happens outside the locks, so readers only ever wait for
+/// short map operations. Reads record recency with an atomic tick instead of
+/// reordering a list, so they never need a write lock; the price is that
+/// evicting scans every shard for the oldest tick.
+pub(crate) struct Store<K, V, S = RandomState> {
+    config: Config<K, V>,
+    /// Cache-wide TTR, initially [`Config::ttl`].
+    ttl: RwLock<Duration>,
+    /// Per-namespace TTRs, initially [`Config::namespace_ttls`].
+    namespace_ttls: RwLock<Option<NamespaceTtls<K>>>,
+    shards: Box<[Shard<K, V, S>]>,
+    hasher: S,
+    /// Time the `last_used` offsets of entries are measured from.
+    epoch: Instant,
+    /// When the clock was last read for a lookup or purge, with
+    /// `clock_jump`.
+    last_observed: Mutex<Timeline>,
+    ticks: AtomicU64,
+    len: AtomicUsize,
+    weight: AtomicU64,
+    /// The highest `weight` reached.
+    peak_weight: AtomicU64,
+    /// Bumped to mark every entry stale; see [`Entry::bulk_generation`].
+    bulk_generation: AtomicU64,
+    /// This cache's usage of a shared memory budget.
+    budget: Option<Arc<Account>>,
+    /// Keys the fetcher found nothing for, and until when that is trusted.
+    absent: Mutex<HashMap<K, Instant, S>>,
+    /// The size `absent` may grow to before lapsed keys are dropped.
+    absent_prune_at: AtomicUsize,
+    /// Keys fetched within the minimum fetch interval, and when.
+    fetch_attempts: Mutex<HashMap<K, Instant, S>>,
+    /// Lookup counters of the namespaces handed out, by name.
+    namespaces: Mutex<HashMap<Arc<str>, Arc<StatsCounter>, S>>,
+    subscribers: Mutex<Subscribers<K, V, S>>,
+    /// Keys derived from each key, marked stale when it changes.
+    dependents: Mutex<HashMap<K, HashSet<K>, S>>,
+    /// The last value of each key that passed validation, with
+    /// `keep_last_known_good`.
+    last_known_good: Mutex<HashMap<K, V, S>>,
+    fetch_limit: Option<FetchLimit>,
+    breaker: Option<Breaker>,
+    flights: FlightGroup<K, V>,
+    /// Contexts of the fetches in flight, by key, sharded like `shards`.
+    fetch_contexts: Box<[ContextShard<K, S>]>,
+    /// Set while fetching is paused.
+    paused: AtomicBool,
+    /// Access counts for TinyLFU admission, if enabled.
+    sketch: Option<FrequencySketch>,
+    /// Keys recently found missing, checked before `absent`.
+    miss_filter: Option<MissFilter>,
+    /// Timers of the keys refreshed periodically, if any can be.
+    periodic: Option<Timers<K>>,
+    /// The copy of fresh entries read without locking, with
+    /// [`Engine::SnapshotSwap`].
+    #[cfg(feature = "snapshot-swap")]
+    swap: Option<SwapMap<K, V>>,
+    stats: StatsCounter,
+}
+
+impl<K, V, S> Store<K, V, S>
+where
+    K: Eq + Hash,
+    S: BuildHasher,
+{
+    pub(crate) fn new(mut config: Config<K, V>, hasher: S) -> Self
+    where
+        S: Clone,
+    {
+        let shards = (0..config.shards)
+            .map(|_| {
+                RwLock::new(match &config.storage {
+                    Some(storage) => Entries::Custom(storage()),
+                    None => Entries::Hashed(HashMap::with_hasher(hasher.clone())),
+                })
+            })
+            .collect();
+        let fetch_contexts = (0..config.shards)
+            .map(|_| Mutex::new(HashMap::with_hasher(hasher.clone())))
+            .collect();
+
+        Store {
+            epoch: config.clock.now(),
+            last_observed: Mutex::new(Timeline::read(&*config.clock)),
+            fetch_limit: config.max_concurrent_fetches.map(FetchLimit::new),
+            breaker: config
+                .circuit_breaker
+                .map(|(failures, cool_down)| Breaker::new(failures, cool_down)),
+            sketch: (config.eviction_policy == EvictionPolicy::TinyLfu
+                && (config.max_capacity.is_some()
+                    || config.max_weight.is_some()
+                    || config.memory_budget.is_some()))
+            .then(|| FrequencySketch::new(config.max_capacity.unwrap_or(1024))),
+            miss_filter: config.miss_filter.map(|(expected_keys, reset_every)| {
+                MissFilter::new(expected_keys, reset_every, config.clock.now())
+            }),
+            periodic: config.refresh_strategy.is_some().then(Timers::new),
+            ttl: RwLock::new(config.ttl),
+            namespace_ttls: RwLock::new(config.namespace_ttls.take()),
+            flights: config.flights.take().unwrap_or_default(),
+            budget: config.memory_budget.as_ref().map(MemoryBudget::register),
+            #[cfg(feature = "snapshot-swap")]
+            swap: (config.engine == Engine::SnapshotSwap).then(SwapMap::new),
+            config,
+            shards,
+            ticks: AtomicU64::new(0),
+            paused: AtomicBool::new(false),
+            len: AtomicUsize::new(0),
+            weight: AtomicU64::new(0),
+            peak_weight: AtomicU64::new(0),
+            bulk_generation: AtomicU64::new(0),
+            absent: Mutex::new(HashMap::with_hasher(hasher.clone())),
+            absent_prune_at: AtomicUsize::new(MIN_ABSENT_PRUNE),
+            fetch_attempts: Mutex::new(HashMap::with_hasher(hasher.clone())),
+            fetch_contexts,
+            namespaces: Mutex::new(HashMap::with_hasher(hasher.clone())),
+            subscribers: Mutex::new(HashMap::with_hasher(hasher.clone())),
+            dependents: Mutex::new(HashMap::with_hasher(hasher.clone())),
+            last_known_good: Mutex::new(HashMap::with_hasher(hasher.clone())),
+            hasher,
+            stats: StatsCounter::default(),
+        }
+    }
+
+    /// Current time according to the configured clock.
+    pub(crate) fn now(&self) -> Instant {
+        self.config.clock.now()
+    }
+
+    /// `key` in the form it is stored under.
+    pub(crate) fn canonical<'k>(&self, key: &'k K) -> Cow<'k, K>
+    where
+        K: Clone,
+    {
+        match &self.config.key_normalizer {
+            Some(normalizer) => Cow::Owned(normalizer.normalize(key)),
+            None => Cow::Borrowed(key),
+        }
+    }
+
+    /// Owned `key` in the form it is stored under.
+    pub(crate) fn canonical_owned(&self, key: K) -> K {
+        match &self.config.key_normalizer {
+            Some(normalizer) => normalizer.normalize(&key),
+            None => key,
+        }
+    }
+
+    /// Whether keys are normalized before they are used.
+    pub(crate) fn normalizes_keys(&self) -> bool {
+        self.config.key_normalizer.is_some()
+    }
+
+    /// Waits for a free fetch slot if fetches are limited.
+    pub(crate) fn fetch_permit(&self) -> Option<Permit<'_>> {
+        let limit = self.fetch_limit.as_ref()?;
+        #[cfg(feature = "tracing")]
+        if tracing::enabled!(tracing::Level::TRACE) {
+            let started = Instant::now();
+            let permit = limit.acquire();
+            tracing::trace!(wait = ?started.elapsed(), "fetch slot acquired");
+            return Some(permit);
+        }
+        Some(limit.acquire())
+    }
+
+    /// Wraps a key for a trace field.
+    #[cfg(feature = "tracing")]
+    pub(crate) fn trace_key<'a>(&self, key: &'a K) -> TraceKey<'a, K> {
+        TraceKey {
+            key,
+            format: self.config.trace_keys,
+        }
+    }
+
+    /// Async counterpart of [`fetch_permit`](Self::fetch_permit).
+    #[cfg(feature = "async")]
+    pub(crate) async fn fetch_permit_async(&self) -> Option<Permit<'_>> {
+        match &self.fetch_limit {
+            Some(limit) => Some(limit.acquire_async().await),
+            None => None,
+        }
+    }
+
+    /// Current time, monotonic and wall-clock, for dating entries that
+    /// leave the process.
+    fn timeline(&self) -> Timeline {
+        Timeline::read(&*self.config.clock)
+    }
+
+    fn tick(&self) -> u64 {
+        self.ticks.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// A random number in `[0, 1)`.
+    fn random_unit(&self) -> f64 {
+        // RandomState is randomly keyed, so hashing a counter gives cheap,
+        // well-spread noise without a dependency on a random number crate.
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u64(self.tick());
+        (hasher.finish() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// The callback for sampled lookups, if this lookup is picked.
+    pub(crate) fn lookup_sampler(&self) -> Option<&BoxedLookupSampler<K>> {
+        let (rate, sampler) = self.config.lookup_sampler.as_ref()?;
+        (self.random_unit() < *rate).then_some(sampler)
+    }
+
+    /// Whether the ages of values served are recorded.
+    pub(crate) fn tracks_served_ages(&self) -> bool {
+        #[cfg(feature = "age-histogram")]
+        return self.config.served_ages.is_some();
+        #[cfg(not(feature = "age-histogram"))]
+        false
+    }
+
+    /// Records the age of `key`'s cached value, which was just served.
+    #[cfg(feature = "age-histogram")]
+    pub(crate) fn record_served_age(&self, key: &K) {
+        if let Some(ages) = &self.config.served_ages {
+            ages.record(
+                self.refresh_timing(key)
+                    .map_or(Duration::ZERO, |(age, _)| age),
+            );
+        }
+    }
+
+    #[cfg(not(feature = "age-histogram"))]
+    pub(crate) fn record_served_age(&self, _key: &K) {}
+
+    #[cfg(feature = "age-histogram")]
+    pub(crate) fn served_ages(&self) -> Option<&ServedAges> {
+        self.config.served_ages.as_ref()
+    }
+
+    fn since_epoch(&self) -> u64 {
+        let elapsed = self.now().saturating_duration_since(self.epoch);
+        u64::try_from(elapsed.as_nanos()).unwrap_or(u64::MAX)
+    }
+
+    fn touch<Q: Hash + ?Sized>(&self, key: &Q, entry: &Entry<V>) {
+        entry.last_access.store(self.tick(), Ordering::Relaxed);
+        let since_epoch = self.since_epoch();
+        entry.last_used.store(since_epoch, Ordering::Relaxed);
+        entry.last_read.store(since_epoch, Ordering::Relaxed);
+        entry.hits.fetch_add(1, Ordering::Relaxed);
+        self.record_access(key);
+    }
+
+    /// Counts an access to `key` for TinyLFU admission.
+    fn record_access<Q: Hash + ?Sized>(&self, key: &Q) {
+        if let Some(sketch) = &self.sketch {
+            sketch.increment(self.hasher.hash_one(key));
+        }
+    }
+
+    /// Whether `candidate` may take the place of `victim`, which is next in
+    /// line for eviction.
+    fn admits(&self, candidate: &K, victim: &K) -> bool {
+        match &self.sketch {
+            Some(sketch) => {
+                sketch.frequency(self.hasher.hash_one(candidate))
+                    > sketch.frequency(self.hasher.hash_one(victim))
+            }
+            None => true,
+        }
+    }
+
+    fn shard<Q: Hash + ?Sized>(&self, key: &Q) -> &Shard<K, V, S> {
+        &self.shards[self.shard_index(key)]
+    }
+
+    fn shard_index<Q: Hash + ?Sized>(&self, key: &Q) -> usize {
+        match self.shards.len() {
+            1 => 0,
+            shards => self.hasher.hash_one(key) as usize % shards,
+        }
+    }
+
+    fn read<Q: Hash + ?Sized>(&self, key: &Q) -> RwLockReadGuard<'_, Entries<K, Entry<V>, S>> {
+        read_shard(self.shard(key))
+    }
+
+    fn write(&self, key: &K) -> ShardWrite<'_, K, V, S> {
+        self.write_shard(self.shard(key))
+    }
+
+    #[cfg(feature = "snapshot-swap")]
+    fn write_shard<'a>(&'a self, shard: &'a Shard<K, V, S>) -> ShardWrite<'a, K, V, S> {
+        ShardWrite {
+            entries: write_shard(shard),
+            swap: self.swap.as_ref(),
+        }
+    }
+
+    #[cfg(not(feature = "snapshot-swap"))]
+    fn write_shard<'a>(&self, shard: &'a Shard<K, V, S>) -> ShardWrite<'a, K, V, S> {
+        write_shard(shard)
+    }
+
+    /// Applies `f` to every entry that has not expired, one shard at a time.
+    fn collect<T>(&self, f: impl Fn(&K, &Entry<V>) -> T) -> Vec<T> {
+        self.settle_swap_reads();
+        let now = self.now();
+        let mut collected = Vec::new();
+        for shard in self.shards.iter() {
+            let entries = read_shard(shard);
+            collected.extend(
+                entries
+                    .iter()
+                    .filter(|(_, entry)| !self.is_expired(entry, now))
+                    .map(|(key, entry)| f(key, entry)),
+            );
+        }
+        collected
+    }
+
+    fn absent(&self) -> MutexGuard<'_, HashMap<K, Instant, S>> {
+        self.absent.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn last_known_good_values(&self) -> MutexGuard<'_, HashMap<K, V, S>> {
+        self.last_known_good
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn subscribers(&self) -> MutexGuard<'_, Subscribers<K, V, S>> {
+        self.subscribers
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Returns a receiver for the changes to `key`'s value.
+    pub(crate) fn subscribe(&self, key: K) -> Receiver<RefreshEvent<K, V>>
+    where
+        V: PartialEq,
+    {
+        let (sender, receiver) = mpsc::channel();
+        self.subscribers().entry(key).or_default().push(Subscriber {
+            sink: Sink::Events(sender),
+            eq: V::eq,
+        });
+        receiver
+    }
+
+    /// Returns a watch on `key`'s value, starting with the cached one.
+    pub(crate) fn watch(&self, key: K) -> Watch<V>
+    where
+        V: PartialEq + Clone,
+    {
+        let current = self.entry_info(&key).map(EntryInfo::into_value);
+        let (watch, slot) = Watch::new(current);
+        self.subscribers().entry(key).or_default().push(Subscriber {
+            sink: Sink::Watch(slot),
+            eq: V::eq,
+        });
+        watch
+    }
+
+    /// Returns a `tokio` watch channel of `key`'s value, starting with the
+    /// cached one.
+    #[cfg(feature = "tokio")]
+    pub(crate) fn watch_channel(&self, key: K) -> tokio::sync::watch::Receiver<Option<V>>
+    where
+        V: PartialEq + Clone,
+    {
+        let current = self.entry_info(&key).map(EntryInfo::into_value);
+        let (sender, receiver) = tokio::sync::watch::channel(current);
+        self.subscribers().entry(key).or_default().push(Subscriber {
+            sink: Sink::Channel(sender),
+            eq: V::eq,
+        });
+        receiver
+    }
+
+    fn is_subscribed(&self, key: &K) -> bool {
+        self.subscribers().contains_key(key)
+    }
+
+    /// Tells `key`'s subscribers about its new value if it changed, dropping
+    /// those whose receiving end is gone.
+    ///
+    /// Events are only sent when a value is replaced; watches also see the
+    /// first value cached.
+    fn notify(&self, key: &K, previous: Option<&V>, value: &V)
+    where
+        K: Clone,
+        V: Clone,
+    {
+        let mut subscribers = self.subscribers();
+        let Some(receivers) = subscribers.get_mut(key) else {
+            return;
+        };
+        receivers.retain(|subscriber| {
+            let changed = previous.is_none_or(|previous| !(subscriber.eq)(previous, value));
+            match &subscriber.sink {
+                Sink::Events(sender) => match previous {
+                    Some(previous) if changed => sender
+                        .send(RefreshEvent {
+                            key: key.clone(),
+                            previous: previous.clone(),
+                            value: value.clone(),
+                        })
+                        .is_ok(),
+                    _ => true,
+                },
+                Sink::Watch(slot) if Arc::strong_count(slot) == 1 => false,
+                Sink::Watch(slot) => {
+                    if changed {
+                        slot.publish(value.clone());
+                    }
+                    true
+                }
+                #[cfg(feature = "tokio")]
+                Sink::Channel(sender) if sender.is_closed() => false,
+                #[cfg(feature = "tokio")]
+                Sink::Channel(sender) => {
+                    if changed {
+                        sender.send_replace(Some(value.clone()));
+                    }
+                    true
+                }
+            }
+        });
+        if receivers.is_empty() {
+            subscribers.remove(key);
+        }
+    }
+
+    /// Looks up a key and classifies it against its TTR.
+    ///
+    /// Entries past the hard expiry, or whose weak value was reclaimed, are
+    /// removed and reported as missing. Keys missing from memory are looked
+    /// up in the second tier, if any.
+    pub(crate) fn lookup(&self, key: &K) -> Lookup<V>
+    where
+        K: Clone,
+        V: Clone,
+    {
+        if let Some(lookup) = self.lookup_in_memory(key) {
+            return lookup;
+        }
+        self.expire(key);
+
+        if self.is_filtered_miss(key) || self.is_absent(key) {
+            self.stats.record_absent_hit();
+            return Lookup::Absent;
+        }
+
+        if let Some(lookup) = self.lookup_second_tier(key) {
+            return lookup;
+        }
+
+        self.record_access(key);
+        self.stats.record_miss();
+        Lookup::Missing
+    }
+
+    /// Looks a key up in memory only, by any borrowed form of it.
+    ///
+    /// Returns `None`, without counting the lookup, if the key is not cached,
+    /// its entry has expired or the storage only looks up owned keys.
+    pub(crate) fn lookup_cached<Q>(&self, key: &Q) -> Option<Lookup<V>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+        V: Clone,
+    {
+        self.observe_clock();
+        let entries = self.read(key);
+        self.classify_cached(key, entries.get_borrowed(key).flatten())
+    }
+
+    /// Looks a key up in memory only, like [`lookup_cached`](Self::lookup_cached)
+    /// but in any storage.
+    pub(crate) fn lookup_in_memory(&self, key: &K) -> Option<Lookup<V>>
+    where
+        V: Clone,
+    {
+        self.observe_clock();
+        let entries = self.read(key);
+        self.classify_cached(key, entries.get(key))
+    }
+
+    /// Classifies the entry found for `key`, unless it has expired.
+    fn classify_cached<Q>(&self, key: &Q, entry: Option<&Entry<V>>) -> Option<Lookup<V>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+        V: Clone,
+    {
+        let now = self.now();
+        let entry = entry?;
+        self.settle_swap_reads_of(key, entry);
+        let entry = Some(entry).filter(|entry| !self.is_expired(entry, now))?;
+        self.touch(key, entry);
+        self.classify(entry, now)
+    }
+
+    /// Notes the time, re-dating entries as `clock_jump` says if the
+    /// monotonic clock moved on by at least its threshold more than the wall
+    /// clock did since last noted.
+    ///
+    /// Skipped while another thread is noting it.
+    fn observe_clock(&self) {
+        let Some((threshold, policy)) = self.config.clock_jump else {
+            return;
+        };
+        let mut last = match self.last_observed.try_lock() {
+            Ok(last) => last,
+            Err(TryLockError::Poisoned(poisoned)) => poisoned.into_inner(),
+            Err(TryLockError::WouldBlock) => return,
+        };
+        let now = self.timeline();
+        let gap = now.drift_since(*last);
+        *last = now;
+        drop(last);
+        if gap >= threshold {
+            self.redate(gap, policy);
+        }
+    }
+
+    /// Re-dates every entry after the clock jumped forward by `gap`.
+    fn redate(&self, gap: Duration, policy: ClockJump) {
+        #[cfg(feature = "tracing")]
+        tracing::warn!(?gap, ?policy, "clock jumped, re-dating entries");
+        let now = self.now();
+        for shard in self.shards.iter() {
+            for entry in self.write_shard(shard).values_mut() {
+                match policy {
+                    ClockJump::Rearm => {
+                        entry.fetched_at = entry
+                            .fetched_at
+                            .checked_add(gap)
+                            .map_or(now, |fetched_at| fetched_at.min(now));
+                    }
+                    ClockJump::Spread(window) => {
+                        let age = entry.age(now);
+                        if self.is_invalidated(entry)
+                            || age < entry.ttl
+                            || age.saturating_sub(gap) >= entry.ttl
+                        {
+                            continue;
+                        }
+                        let stale_in = window.mul_f64(self.random_unit());
+                        if let Some(fetched_at) = (now + stale_in).checked_sub(entry.ttl) {
+                            entry.fetched_at = fetched_at;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Reads a fresh value from the snapshot copy without locking, counting
+    /// the hit.
+    ///
+    /// Returns `None`, without counting the lookup, unless the copy is
+    /// current and has a fresh value for the key. An out-of-date copy is
+    /// rebuilt first.
+    #[cfg(feature = "snapshot-swap")]
+    pub(crate) fn get_swapped<Q>(&self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q> + Clone,
+        Q: Hash + Eq + ?Sized,
+        V: Clone,
+    {
+        let swap = self.swap.as_ref()?;
+        self.observe_clock();
+        let now = self.now();
+        let stamp = || (self.tick(), self.since_epoch());
+        let value = match swap.get(key, now, stamp) {
+            Some(value) => value,
+            None => {
+                swap.rebuild(
+                    || self.swappable(now),
+                    |key, reads| self.settle_swap_read(key, reads),
+                );
+                swap.get(key, now, stamp)?
+            }
+        };
+        self.record_access(key);
+        self.stats.record_hit();
+        Some(value)
+    }
+
+    #[cfg(not(feature = "snapshot-swap"))]
+    pub(crate) fn get_swapped<Q: ?Sized>(&self, _key: &Q) -> Option<V> {
+        None
+    }
+
+    /// Adds the reads served from the swap copy to the entries' stats and
+    /// recency, before anything reads those.
+    #[cfg(feature = "snapshot-swap")]
+    fn settle_swap_reads(&self) {
+        if let Some(swap) = &self.swap {
+            swap.drain_reads(|key, reads| self.settle_swap_read(key, reads));
+        }
+    }
+
+    #[cfg(not(feature = "snapshot-swap"))]
+    fn settle_swap_reads(&self) {}
+
+    #[cfg(feature = "snapshot-swap")]
+    fn settle_swap_read(&self, key: &K, reads: SwapReads) {
+        if let Some(entry) = self.read(key).get(key) {
+            entry.add_swap_reads(reads);
+        }
+    }
+
+    /// Adds the reads of `key` served from the swap copy to `entry`.
+    #[cfg(feature = "snapshot-swap")]
+    fn settle_swap_reads_of<Q>(&self, key: &Q, entry: &Entry<V>)
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        if let Some(reads) = self.swap.as_ref().and_then(|swap| swap.take_reads(key)) {
+            entry.add_swap_reads(reads);
+        }
+    }
+
+    #[cfg(not(feature = "snapshot-swap"))]
+    fn settle_swap_reads_of<Q: ?Sized>(&self, _key: &Q, _entry: &Entry<V>) {}
+
+    /// The fresh plain values, with when each is due for a refresh, for the
+    /// snapshot copy.
+    #[cfg(feature = "snapshot-swap")]
+    fn swappable(&self, now: Instant) -> HashMap<K, (V, Instant)>
+    where
+        K: Clone,
+        V: Clone,
+    {
+        let lead = self.config.refresh_ahead.unwrap_or_default();
+        let mut fresh = HashMap::new();
+        for shard in self.shards.iter() {
+            for (key, entry) in read_shard(shard).iter() {
+                let Slot::Plain(value) = &entry.value else {
+                    continue;
+                };
+                let due = entry.fetched_at.checked_add(entry.ttl.saturating_sub(lead));
+                match due {
+                    Some(due) if self.is_fresh(entry, now) && !self.is_expired(entry, now) => {
+                        fresh.insert(key.clone(), (value.clone(), due));
+                    }
+                    _ => {}
+                }
+            }
+        }
+        fresh
+    }
+
+    /// Reads a fresh value in place, counting the hit.
+    ///
+    /// Returns `None`, without counting the lookup, unless the key has a
+    /// fresh entry in memory that is not due for a refresh ahead.
+    pub(crate) fn get_fresh_guarded<'a, Q>(&'a self, key: &'a Q) -> Option<ValueGuard<'a, V>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+        V: Clone,
+    {
+        // A borrowed key cannot be normalized without an owned copy.
+        if self.normalizes_keys() {
+            return None;
+        }
+        self.observe_clock();
+        let now = self.now();
+        let entries = self.read(key);
+        let entry = entries.get_borrowed(key).flatten().filter(|entry| {
+            !self.is_expired(entry, now)
+                && self.is_fresh(entry, now)
+                && !self
+                    .config
+                    .refresh_ahead
+                    .is_some_and(|lead| entry.is_expiring(lead, now))
+        })?;
+        // Only plain values can be borrowed; others are decoded as usual.
+        let owned = match &entry.value {
+            Slot::Plain(_) => None,
+            slot => Some(self.decoded(slot)?),
+        };
+        self.touch(key, entry);
+        self.stats.record_hit();
+        match owned {
+            Some(value) => Some(ValueGuard::owned(value)),
+            None => Some(ValueGuard::locked(Locked { entries, key })),
+        }
+    }
+
+    /// The cached key equal to `key`, which may be a borrowed form of it.
+    #[cfg(feature = "cached")]
+    pub(crate) fn key_of<Q>(&self, key: &Q) -> Option<K>
+    where
+        K: Borrow<Q> + Clone,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.read(key).find(key).map(|(key, _)| key.clone())
+    }
+
+    #[cfg(feature = "cached")]
+    pub(crate) fn max_capacity(&self) -> Option<usize> {
+        self.config.max_capacity
+    }
+
+    /// Copies an entry from the second tier into memory and classifies it.
+    ///
+    /// Returns `None` if the second tier has nothing servable.
+    fn lookup_second_tier(&self, key: &K) -> Option<Lookup<V>>
+    where
+        K: Clone,
+        V: Clone,
+    {
+        let entry = self.config.second_tier.as_ref()?.load(key)?;
+        self.put_persisted(entry);
+
+        let now = self.now();
+        let entries = self.read(key);
+        let entry = entries
+            .get(key)
+            .filter(|entry| !self.is_expired(entry, now))?;
+        self.touch(key, entry);
+        self.classify(entry, now)
+    }
+
+    /// Stores a persisted entry, keeping its age and TTR, without writing it
+    /// through to the second tier.
+    ///
+    /// Entries too old to date are stored as stale.
+    fn put_persisted(&self, entry: PersistedEntry<K, V>)
+    where
+        K: Clone,
+        V: Clone,
+    {
+        let timeline = self.timeline();
+        let (fetched_at, invalidated) = match timeline.to_instant(entry.fetched_at) {
+            Some(fetched_at) => (fetched_at, entry.invalidated),
+            None => (timeline.now(), true),
+        };
+        let invalidated = invalidated.then(|| entry.key.clone());
+        self.put(entry.key, fetched_at, entry.value, entry.ttl);
+        if let Some(key) = invalidated {
+            self.invalidate(&key);
+        }
+    }
+
+    /// Classifies an entry against its TTR, counting the hit.
+    ///
+    /// Returns `None`, without counting it, if the weak value was reclaimed
+    /// since the entry was checked, so the lookup goes on as for an expired
+    /// entry.
+    fn classify(&self, entry: &Entry<V>, now: Instant) -> Option<Lookup<V>>
+    where
+        V: Clone,
+    {
+        let Some(value) = self.decoded(&entry.value) else {
+            #[cfg(feature = "tracing")]
+            tracing::warn!("cached value reclaimed while being read, looking it up again");
+            return None;
+        };
+        if !self.is_fresh(entry, now) {
+            self.stats.record_stale_hit();
+            entry.record_stale_hit(now);
+            return Some(match &self.config.retry_policy {
+                Some(policy) if entry.is_backing_off(policy, now) => Lookup::Retrying(value),
+                _ if entry.must_revalidate(self.config.stale_while_revalidate, now) => {
+                    Lookup::Revalidate(value)
+                }
+                _ => Lookup::Stale(value),
+            });
+        }
+
+        self.stats.record_hit();
+        Some(match self.config.refresh_ahead {
+            Some(lead) if entry.is_expiring(lead, now) => Lookup::Expiring(value),
+            _ => Lookup::Fresh(value),
+        })
+    }
+
+    /// Whether an entry can no longer be served, because it is past its hard
+    /// expiry, its refreshes failed for too long or it went unread for too
+    /// long.
+    ///
+    /// Pinned entries only expire once their weak value is reclaimed.
+    fn is_expired(&self, entry: &Entry<V>, now: Instant) -> bool {
+        let expired = !entry.pinned
+            && (entry.is_expired(self.config.max_stale, now)
+                || self.is_given_up(entry, now)
+                || entry.is_unread(self.config.expire_after_idle, self.epoch, now));
+        expired || self.is_reclaimed(entry)
+    }
+
+    fn is_given_up(&self, entry: &Entry<V>, now: Instant) -> bool {
+        self.config
+            .stale_if_error
+            .as_ref()
+            .is_some_and(|policy| policy.exceeded(entry.failures, entry.failing_since, now))
+    }
+
+    /// Removes an entry if it still cannot be served, returning whether it
+    /// did.
+    ///
+    /// Checked under the read lock first, so the write lock is only taken
+    /// when there is an expired entry to remove.
+    pub(crate) fn expire(&self, key: &K) -> bool {
+        let now = self.now();
+        if !self
+            .read(key)
+            .get(key)
+            .is_some_and(|entry| self.is_expired(entry, now))
+        {
+            return false;
+        }
+        let mut entries = self.write(key);
+        let expired = match entries.get(key) {
+            Some(entry) if self.is_expired(entry, now) => {
+                let given_up = self.is_given_up(entry, now);
+                self.take(&mut entries, key)
+                    .map(|expired| (expired, given_up))
+            }
+            _ => None,
+        };
+        drop(entries);
+
+        #[cfg(feature = "tracing")]
+        if let Some(((key, _), _)) = &expired {
+            tracing::debug!(key = ?self.trace_key(key), "expired");
+        }
+        let Some(((key, entry), given_up)) = expired else {
+            return false;
+        };
+        self.notify_expired(&key, &entry, given_up);
+        self.release(entry);
+        true
+    }
+
+    /// Whether a panic poisoned a shard's lock while it was held.
+    #[cfg(feature = "serde")]
+    pub(crate) fn is_poisoned(&self) -> bool {
+        self.shards.iter().any(|shard| shard.read().is_err())
+    }
+
+    /// Tells the listener an entry was removed past its hard expiry.
+    fn notify_expired(&self, key: &K, entry: &Entry<V>, given_up: bool) {
+        let Some(listener) = &self.config.listener else {
+            return;
+        };
+        let Some(value) = self.decode(&entry.value) else {
+            return;
+        };
+        if given_up {
+            listener.on_stale_if_error_exceeded(key, &value);
+        } else {
+            listener.on_expire(key, &value);
+        }
+        listener.on_removal(key, &value, EvictionReason::Expired);
+    }
+
+    /// Tells the listener an entry was evicted for `reason`.
+    fn notify_evicted(&self, key: &K, entry: &Entry<V>, reason: EvictionReason) {
+        let Some(listener) = &self.config.listener else {
+            return;
+        };
+        if let Some(value) = self.decode(&entry.value) {
+            listener.on_evict(key, &value);
+            listener.on_removal(key, &value, reason);
+        }
+    }
+
+    /// Tells the listener an entry was removed on request.
+    fn notify_removed(&self, key: &K, entry: &Entry<V>) {
+        let Some(listener) = &self.config.listener else {
+            return;
+        };
+        if let Some(value) = self.decode(&entry.value) {
+            listener.on_removal(key, &value, EvictionReason::Explicit);
+        }
+    }
+
+    /// Whether keys found missing are remembered, by negative caching or as
+    /// tombstones.
+    fn remembers_absent(&self) -> bool {
+        self.config.negative_ttl.is_some()
+            || matches!(
+                self.config.on_missing_refresh,
+                OnMissingRefresh::Tombstone(_)
+            )
+    }
+
+    /// Whether `key` was found missing within the negative TTL, or has a
+    /// tombstone.
+    fn is_absent(&self, key: &K) -> bool {
+        if !self.remembers_absent() {
+            return false;
+        }
+
+        let now = self.now();
+        let mut absent = self.absent();
+        match absent.get(key) {
+            Some(until) if now < *until => true,
+            Some(_) => {
+                absent.remove(key);
+                false
+            }
+            None => false,
+        }
+    }
+
+    /// Whether the miss filter holds `key`, so it probably was found missing
+    /// since the filter was last reset.
+    fn is_filtered_miss(&self, key: &K) -> bool {
+        self.miss_filter
+            .as_ref()
+            .is_some_and(|filter| filter.contains(self.hasher.hash_one(key), self.now()))
+    }
+
+    /// Remembers that the fetcher found nothing for `key`, if negative
+    /// caching is enabled.
+    pub(crate) fn record_absent(&self, key: K) {
+        if let Some(filter) = &self.miss_filter {
+            filter.insert(self.hasher.hash_one(&key), self.now());
+        }
+        if let Some(negative_ttl) = self.config.negative_ttl {
+            self.remember_absent(key, negative_ttl);
+        }
+    }
+
+    /// Remembers `key` as missing for `period`.
+    ///
+    /// Lapsed keys are dropped when looked up, by the janitor, and here once
+    /// the set has doubled since they were last dropped, so each insert
+    /// pays for the scan only once.
+    fn remember_absent(&self, key: K, period: Duration) {
+        let now = self.now();
+        let mut absent = self.absent();
+        if absent.len() >= self.absent_prune_at.load(Ordering::Relaxed) {
+            self.prune_absent(&mut absent, now);
+        }
+        if let Some(until) = now.checked_add(period) {
+            absent.insert(key, until);
+        }
+    }
+
+    /// Drops the keys in `absent` that lapsed by `now`.
+    fn prune_absent(&self, absent: &mut HashMap<K, Instant, S>, now: Instant) {
+        absent.retain(|_, until| now < *until);
+        let prune_at = (absent.len() * 2).max(MIN_ABSENT_PRUNE);
+        self.absent_prune_at.store(prune_at, Ordering::Relaxed);
+    }
+
+    /// Creates the context of a fetch of `key`, cancelled if the key is
+    /// removed before the fetch is done.
+    ///
+    /// The context is registered with the key until the returned guard is
+    /// dropped, so create it only once a fetch starts.
+    pub(crate) fn fetch_context(&self, key: &K) -> ActiveContext<'_, K, S>
+    where
+        K: Clone,
+    {
+        let context =
+            FetchContext::timed(Arc::clone(&self.config.clock), self.config.fetch_timeout);
+        let registry = &self.fetch_contexts[self.shard_index(key)];
+        lock_contexts(registry)
+            .entry(key.clone())
+            .or_default()
+            .push(context.handle());
+        ActiveContext {
+            registry,
+            key: key.clone(),
+            context,
+        }
+    }
+
+    /// Cancels the fetches of `key` in flight, or of every key.
+    pub(crate) fn cancel_fetches(&self, key: Option<&K>) {
+        let cancelled: Vec<CancelHandle> = match key {
+            Some(key) => lock_contexts(&self.fetch_contexts[self.shard_index(key)])
+                .remove(key)
+                .unwrap_or_default(),
+            None => self
+                .fetch_contexts
+                .iter()
+                .flat_map(|registry| {
+                    let handles: Vec<CancelHandle> = lock_contexts(registry)
+                        .drain()
+                        .flat_map(|(_, handles)| handles)
+                        .collect();
+                    handles
+                })
+                .collect(),
+        };
+        cancelled.iter().for_each(CancelHandle::cancel);
+    }
+
+    /// Joins the fetch of `key` in flight, or starts one for this store.
+    pub(crate) fn claim(&self, key: &K) -> Claim<K, V>
+    where
+        K: Clone,
+        V: Clone,
+    {
+        self.flights.claim(key, self.origin())
+    }
+
+    /// Stores `entity`, the result of a joined `flight`, as a refresh from
+    /// `generation` if the fetch was for another store sharing the flight
+    /// group; a fetch for this store already stored it.
+    pub(crate) fn adopt(&self, key: &K, generation: Generation, flight: &Flight<V>, entity: &V)
+    where
+        K: Clone,
+        V: Clone,
+    {
+        if !flight.is_from(self.origin()) {
+            self.refresh(key.clone(), self.now(), generation, entity.clone(), None);
+        }
+    }
+
+    /// Identifies this store in its flight group. Flights only last while
+    /// the store is borrowed, so its address does not change under them.
+    fn origin(&self) -> usize {
+        self as *const Self as usize
+    }
+
+    pub(crate) fn miss_policy(&self) -> MissPolicy {
+        self.config.miss_policy
+    }
+
+    /// Whether a lookup of `key`, which has nothing cached, should fetch it,
+    /// reporting the miss under [`MissPolicy::Error`].
+    pub(crate) fn fetches_on_miss(&self, _key: &K) -> bool {
+        match self.config.miss_policy {
+            MissPolicy::Fetch => true,
+            MissPolicy::ReturnNone => false,
+            MissPolicy::Error => {
+                #[cfg(feature = "tracing")]
+                tracing::error!(key = ?self.trace_key(_key), "unexpected cache miss");
+                false
+            }
+        }
+    }
+
+    /// Stops or resumes fetching; see [`admit_fetch`](Self::admit_fetch).
+    pub(crate) fn pause_fetches(&self, paused: bool) {
+        self.paused.store(paused, Ordering::Relaxed);
+    }
+
+    pub(crate) fn fetches_paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+
+    /// Takes the lock shared with other processes for fetching `key`, if
+    /// there is one.
+    ///
+    /// Fails only if another process holds it and a value is cached to serve
+    /// in the meantime; keys with nothing cached are fetched regardless.
+    pub(crate) fn lock_fetch(&self, key: &K) -> Result<Option<KeyLock>, HeldElsewhere> {
+        let Some(locks) = &self.config.refresh_locks else {
+            return Ok(None);
+        };
+        match locks.lock(key) {
+            Err(HeldElsewhere) if !self.generation(key).is_cached() => Ok(None),
+            lock => lock,
+        }
+    }
+
+    /// Records a fetch attempt for `key`, unless fetching is paused or there
+    /// was one within the minimum fetch interval, in which case it returns
+    /// `false` and the fetch should be skipped.
+    ///
+    /// Lapsed keys are dropped at the same time, like in
+    /// [`remember_absent`](Self::remember_absent).
+    pub(crate) fn admit_fetch(&self, key: &K) -> bool
+    where
+        K: Clone,
+    {
+        if self.fetches_paused() {
+            return false;
+        }
+        let now = self.now();
+        if let Some(interval) = self.config.min_fetch_interval {
+            let mut attempts = self
+                .fetch_attempts
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+            attempts.retain(|_, at| now.saturating_duration_since(*at) < interval);
+            if attempts.contains_key(key) {
+                return false;
+            }
+            attempts.insert(key.clone(), now);
+        }
+        // Last, so a trial fetch is only started if it goes ahead.
+        self.breaker
+            .as_ref()
+            .is_none_or(|breaker| breaker.admit(now))
+    }
+
+    /// Forgets that `key` was found missing.
+    ///
+    /// Returns `false` if it was not remembered.
+    fn forget_absent(&self, key: &K) -> bool {
+        let filtered = self
+            .miss_filter
+            .as_ref()
+            .is_some_and(|filter| filter.forget(self.hasher.hash_one(key)));
+        (self.remembers_absent() && self.absent().remove(key).is_some()) || filtered
+    }
+
+    /// The last value of `key` that passed validation, with
+    /// `keep_last_known_good`.
+    pub(crate) fn last_known_good(&self, key: &K) -> Option<V>
+    where
+        V: Clone,
+    {
+        if !self.config.keep_last_known_good {
+            return None;
+        }
+        self.last_known_good_values().get(key).cloned()
+    }
+
+    /// Forgets the last known good value of `key`.
+    fn forget_last_known_good(&self, key: &K) {
+        if self.config.keep_last_known_good {
+            self.last_known_good_values().remove(key);
+        }
+    }
+
+    fn encode(&self, value: V) -> Slot<V> {
+        if let Some(weak_values) = &self.config.weak_values {
+            return Slot::Weak((weak_values.downgrade)(&value));
+        }
+        match &self.config.codec {
+            Some(codec) => Slot::Encoded(codec.encode(&value).into()),
+            None => Slot::Plain(value),
+        }
+    }
+
+    /// Borrows a cached value, decoding it if needed.
+    ///
+    /// Returns `None` if the value was weakly held and has been dropped.
+    fn decode<'a>(&self, slot: &'a Slot<V>) -> Option<Decoded<'a, V>> {
+        match slot {
+            Slot::Plain(value) => Some(Decoded::Borrowed(value)),
+            Slot::Encoded(bytes) => Some(Decoded::Owned(self.codec().decode(bytes))),
+            Slot::Weak(weak) => self.weak_values().upgrade(weak).map(Decoded::Owned),
+        }
+    }
+
+    /// Copies a cached value out, decoding it if needed.
+    fn decoded(&self, slot: &Slot<V>) -> Option<V>
+    where
+        V: Clone,
+    {
+        match slot {
+            Slot::Plain(value) => Some(value.clone()),
+            Slot::Encoded(bytes) => Some(self.codec().decode(bytes)),
+            Slot::Weak(weak) => self.weak_values().upgrade(weak),
+        }
+    }
+
+    /// Whether the entry was marked stale, by itself or by a bulk
+    /// invalidation since it was fetched.
+    fn is_invalidated(&self, entry: &Entry<V>) -> bool {
+        entry.invalidated || entry.bulk_generation < self.bulk_generation.load(Ordering::Relaxed)
+    }
+
+    fn is_fresh(&self, entry: &Entry<V>, now: Instant) -> bool {
+        entry.is_fresh(now) && !self.is_invalidated(entry)
+    }
+
+    /// Marks every entry stale at once, by moving to a new cache-wide
+    /// generation, and returns it.
+    pub(crate) fn bump_generation(&self) -> u64 {
+        let generation = self.bulk_generation.fetch_add(1, Ordering::Relaxed) + 1;
+        #[cfg(feature = "snapshot-swap")]
+        if let Some(swap) = &self.swap {
+            swap.outdate();
+        }
+        generation
+    }
+
+    /// The current cache-wide generation.
+    pub(crate) fn bulk_generation(&self) -> u64 {
+        self.bulk_generation.load(Ordering::Relaxed)
+    }
+
+    /// Whether the value was weakly held and has been dropped.
+    fn is_reclaimed(&self, entry: &Entry<V>) -> bool {
+        match &entry.value {
+            Slot::Weak(weak) => !self.weak_values().is_alive(weak),
+            _ => false,
+        }
+    }
+
+    fn info(&self, entry: &Entry<V>, now: Instant) -> Option<EntryInfo<V>>
+    where
+        V: Clone,
+    {
+        Some(EntryInfo {
+            observed: Timeline::read_at(&*self.config.clock, now),
+            value: self.decoded(&entry.value)?,
+            inserted_at: entry.inserted_at,
+            last_refreshed: entry.fetched_at,
+            ttl: entry.ttl,
+            invalidated: self.is_invalidated(entry),
+        })
+    }
+
+    /// Returns the cached value if it has not expired, regardless of staleness.
+    pub(crate) fn get(&self, key: &K) -> Option<V>
+    where
+        V: Clone,
+    {
+        let now = self.now();
+        let entries = self.read(key);
+        let entry = entries
+            .get(key)
+            .filter(|entry| !self.is_expired(entry, now))?;
+
+        self.touch(key, entry);
+        self.decoded(&entry.value)
+    }
+
+    /// Returns the cached value with its refresh bookkeeping.
+    ///
+    /// Like [`get`](Self::get), but without counting as a use.
+    pub(crate) fn key_stats(&self, key: &K) -> Option<KeyStats> {
+        let now = self.now();
+        let entries = self.read(key);
+        let entry = entries.get(key)?;
+        self.settle_swap_reads_of(key, entry);
+        Some(entry)
+            .filter(|entry| !self.is_expired(entry, now))
+            .map(Entry::stats)
+    }
+
+    /// Where `key` is in its refresh cycle, given whether a background
+    /// refresh of it is `queued`.
+    pub(crate) fn entry_state(&self, key: &K, queued: bool) -> Option<EntryState> {
+        let refreshing = queued || self.flights.is_in_flight(key);
+        let now = self.now();
+        let entries = self.read(key);
+        let entry = entries
+            .get(key)
+            .filter(|entry| !self.is_expired(entry, now))?;
+        Some(match entry.failed_at {
+            _ if refreshing => EntryState::Refreshing,
+            Some(last_error_at) if entry.failures > 0 => EntryState::Failed {
+                attempts: entry.failures,
+                last_error_at,
+            },
+            _ if self.is_fresh(entry, now) => EntryState::Fresh,
+            _ => EntryState::Stale,
+        })
+    }
+
+    /// Whether `key` has an entry past its hard expiry, not purged yet.
+    pub(crate) fn has_expired(&self, key: &K) -> bool {
+        let now = self.now();
+        self.read(key)
+            .get(key)
+            .is_some_and(|entry| self.is_expired(entry, now))
+    }
+
+    /// How fresh the value cached for `key` is; values that were not kept,
+    /// such as ones the cache did not admit, count as fresh.
+    pub(crate) fn freshness(&self, key: &K) -> HitFreshness {
+        let now = self.now();
+        match self.read(key).get(key) {
+            Some(entry) if !self.is_fresh(entry, now) => HitFreshness::Stale,
+            _ => HitFreshness::Fresh,
+        }
+    }
+
+    pub(crate) fn entry_info(&self, key: &K) -> Option<EntryInfo<V>>
+    where
+        V: Clone,
+    {
+        let now = self.now();
+        let entries = self.read(key);
+        let entry = entries.get(key)?;
+        self.settle_swap_reads_of(key, entry);
+        let entry = Some(entry).filter(|entry| !self.is_expired(entry, now))?;
+
+        self.info(entry, now)
+    }
+
+    /// Time since `key` was last fetched or inserted, and time left before
+    /// it goes stale, zero if it already has.
+    ///
+    /// Not a use, like [`entry_info`](Self::entry_info), and the value is
+    /// not copied.
+    pub(crate) fn refresh_timing(&self, key: &K) -> Option<(Duration, Duration)> {
+        let now = self.now();
+        let entries = self.read(key);
+        let entry = entries
+            .get(key)
+            .filter(|entry| !self.is_expired(entry, now))?;
+        let age = entry.age(now);
+        let left = if self.is_invalidated(entry) {
+            Duration::ZERO
+        } else {
+            entry.ttl.saturating_sub(age)
+        };
+        Some((age, left))
+    }
+
+    /// Returns the cached value if `accepts` approves of its bookkeeping.
+    ///
+    /// Counts as a use and a lookup either way; a rejected entry is a miss.
+    pub(crate) fn get_if(&self, key: &K, accepts: impl FnOnce(&EntryInfo<V>) -> bool) -> Option<V>
+    where
+        V: Clone,
+    {
+        let now = self.now();
+        let entries = self.read(key);
+        let accepted = entries
+            .get(key)
+            .filter(|entry| !self.is_expired(entry, now))
+            .and_then(|entry| self.info(entry, now))
+            .filter(|info| accepts(info));
+
+        let Some(info) = accepted else {
+            self.stats.record_miss();
+            return None;
+        };
+        if let Some(entry) = entries.get(key) {
+            self.touch(key, entry);
+        }
+        if info.is_stale() {
+            self.stats.record_stale_hit();
+        } else {
+            self.stats.record_hit();
+        }
+        Some(info.into_value())
+    }
+
+    /// Copies out every entry that has not expired, stale or not.
+    ///
+    /// Like [`get`](Self::get), but without counting as a use.
+    pub(crate) fn entries(&self) -> Vec<(K, V)>
+    where
+        K: Clone,
+        V: Clone,
+    {
+        self.collect(|key, entry| Some((key.clone(), self.decoded(&entry.value)?)))
+            .into_iter()
+            .flatten()
+            .collect()
+    }
+
+    /// The fresh keys within `lead` of going stale that were read within
+    /// `window`.
+    pub(crate) fn keys_expiring_read_within(&self, lead: Duration, window: Duration) -> Vec<K>
+    where
+        K: Clone,
+    {
+        let now = self.now();
+        self.collect(|key, entry| {
+            (self.is_fresh(entry, now)
+                && entry.is_expiring(lead, now)
+                && !entry.is_unread(Some(window), self.epoch, now))
+            .then(|| key.clone())
+        })
+        .into_iter()
+        .flatten()
+        .collect()
+    }
+
+    /// The `n` most read keys, most read first.
+    pub(crate) fn top_keys(&self, n: usize) -> Vec<HotKey<K>>
+    where
+        K: Clone,
+    {
+        let now = self.now();
+        let mut keys = self.collect(|key, entry| HotKey {
+            key: key.clone(),
+            hits: entry.hits.load(Ordering::Relaxed),
+            age: entry.age(now),
+            stale_hits: entry.stale_hits.load(Ordering::Relaxed),
+            max_staleness: entry.stats().max_staleness,
+        });
+        keys.sort_unstable_by_key(|hot| Reverse(hot.hits));
+        keys.truncate(n);
+        keys
+    }
+
+    /// Copies out every entry that has not expired, with all its timestamps.
+    pub(crate) fn dump(&self) -> Vec<(K, EntrySnapshot<V>)>
+    where
+        K: Clone,
+        V: Clone,
+    {
+        let timeline = self.timeline();
+        self.collect(|key, entry| {
+            let snapshot = EntrySnapshot {
+                value: self.decoded(&entry.value)?,
+                inserted_at: timeline.to_system_time(entry.inserted_at),
+                fetched_at: timeline.to_system_time(entry.fetched_at),
+                ttl: entry.ttl,
+                invalidated: self.is_invalidated(entry),
+                stats: entry.stats(),
+            };
+            Some((key.clone(), snapshot))
+        })
+        .into_iter()
+        .flatten()
+        .collect()
+    }
+
+    /// Stores dumped entries, keeping their timestamps and TTR, without
+    /// writing them through to the second tier.
+    ///
+    /// Entries too old to date are stored as stale. Returns the number of
+    /// entries stored.
+    pub(crate) fn import(&self, entries: impl IntoIterator<Item = (K, EntrySnapshot<V>)>) -> usize
+    where
+        K: Clone,
+        V: Clone,
+    {
+        let timeline = self.timeline();
+        let mut imported = 0;
+        for (key, snapshot) in entries {
+            let (fetched_at, invalidated) = match timeline.to_instant(snapshot.fetched_at) {
+                Some(fetched_at) => (fetched_at, snapshot.invalidated),
+                None => (timeline.now(), true),
+            };
+            let inserted_at = timeline
+                .to_instant(snapshot.inserted_at)
+                .map_or(fetched_at, |inserted_at| inserted_at.min(fetched_at));
+            self.put(key.clone(), fetched_at, snapshot.value, snapshot.ttl);
+            if let Some(entry) = self.write(&key).get_mut(&key) {
+                entry.inserted_at = inserted_at;
+                entry.keep_stats(snapshot.stats);
+                if invalidated {
+                    entry.invalidate(self.tick());
+                }
+            }
+            imported += 1;
+        }
+        imported
+    }
+
+    /// Copies out every entry that has not expired, with its timestamps.
+    pub(crate) fn snapshot(&self) -> Snapshot<K, V>
+    where
+        K: Clone,
+        V: Clone,
+    {
+        let timeline = self.timeline();
+        let entries = self
+            .collect(|key, entry| {
+                Some(PersistedEntry {
+                    key: key.clone(),
+                    value: self.decoded(&entry.value)?,
+                    fetched_at: timeline.to_system_time(entry.fetched_at),
+                    ttl: entry.ttl,
+                    invalidated: self.is_invalidated(entry),
+                })
+            })
+            .into_iter()
+            .flatten()
+            .collect();
+
+        Snapshot { entries }
+    }
+
+    /// Stores the entries of a snapshot, keeping their age and TTR.
+    ///
+    /// Entries too old to date are restored as stale. Returns the number of
+    /// keys still stored once all are, leaving out those evicted to make
+    /// room.
+    pub(crate) fn restore(&self, snapshot: Snapshot<K, V>) -> usize
+    where
+        K: Clone,
+        V: Clone,
+    {
+        let keys: HashSet<K> = snapshot
+            .entries
+            .iter()
+            .map(|entry| entry.key.clone())
+            .collect();
+        for entry in snapshot.entries {
+            self.put_persisted(entry);
+        }
+        keys.iter()
+            .filter(|key| self.read(*key).get(key).is_some())
+            .count()
+    }
+
+    /// Counts the entries that have not expired.
+    ///
+    /// One by one, as [`entries`](Self::entries) skips them, if entries can
+    /// expire before they are removed.
+    pub(crate) fn len(&self) -> usize {
+        if self.expires_in_place() {
+            self.collect(|_, _| ()).len()
+        } else {
+            self.len.load(Ordering::Relaxed)
+        }
+    }
+
+    /// Whether a setting lets entries expire while they are still stored,
+    /// until a lookup or purge finds them.
+    fn expires_in_place(&self) -> bool {
+        self.config.max_stale.is_some()
+            || self.config.expire_after_idle.is_some()
+            || self.config.stale_if_error.is_some()
+            || self.config.weak_values.is_some()
+    }
+
+    /// Inserts precomputed values, each fetched now or at the wall-clock
+    /// time given, returning how many.
+    ///
+    /// The clock is read once for all of them. Times too far back to
+    /// represent store the value stale.
+    pub(crate) fn populate(
+        &self,
+        entries: impl IntoIterator<Item = (K, V, Option<SystemTime>)>,
+    ) -> usize
+    where
+        K: Clone,
+        V: Clone,
+    {
+        let timeline = self.timeline();
+        let mut populated = 0;
+        for (key, value, fetched_at) in entries {
+            match fetched_at.map_or(Some(timeline.now()), |time| timeline.to_instant(time)) {
+                Some(fetched_at) => self.insert(key, fetched_at, value),
+                None => {
+                    self.insert(key.clone(), timeline.now(), value);
+                    self.invalidate(&key);
+                }
+            }
+            populated += 1;
+        }
+        populated
+    }
+
+    /// Stores a value fetched at `fetched_at`, with its TTR taken from the
+    /// policy and jittered.
+    pub(crate) fn insert(&self, key: K, fetched_at: Instant, value: V)
+    where
+        K: Clone,
+        V: Clone,
+    {
+        let (ttl, follows_default) = self.ttl_for(&key, &value, None);
+        self.write_through(key, fetched_at, value, ttl, follows_default);
+    }
+
+    /// The generation of `key`'s entry, to pass to [`refresh`](Self::refresh)
+    /// once a fetch for it completes.
+    pub(crate) fn generation(&self, key: &K) -> Generation {
+        let entry = self.read(key).get(key).map(|entry| entry.generation);
+        Generation {
+            entry,
+            bulk: self.bulk_generation.load(Ordering::Relaxed),
+            started: self.tick(),
+        }
+    }
+
+    /// Stores a refreshed value like [`insert`](Self::insert), unless the
+    /// entry was written, invalidated or removed since it was at `generation`
+    /// by anything but an earlier-started refresh.
+    ///
+    /// With `dedupe_refreshes`, a value equal to the cached one only renews
+    /// the entry. With `adaptive_ttl`, the TTR is lengthened or shortened
+    /// from the entry's current one. `latency` is how long the fetch took, if
+    /// it was made for this store.
+    ///
+    /// Returns the value now cached, or `None` if `value` was discarded as
+    /// out of date.
+    pub(crate) fn refresh(
+        &self,
+        key: K,
+        fetched_at: Instant,
+        generation: Generation,
+        value: V,
+        latency: Option<Duration>,
+    ) -> Option<V>
+    where
+        K: Clone,
+        V: Clone,
+    {
+        let current = (self.config.dedupe_refreshes.is_some()
+            || self.config.adaptive_ttl.is_some())
+        .then(|| {
+            let entries = self.read(&key);
+            let entry = entries
+                .get(&key)
+                .filter(|entry| generation.matches(entry))?;
+            Some((self.decoded(&entry.value)?, entry.ttl))
+        })
+        .flatten();
+        if let (Some(eq), Some((current, _))) = (self.config.dedupe_refreshes, &current) {
+            if eq(current, &value) {
+                return self.renew(&key, generation);
+            }
+        }
+        let (ttl, follows_default) = match (&self.config.adaptive_ttl, current) {
+            (Some(adaptive), Some((current, ttl))) => {
+                (adaptive.next(ttl, (adaptive.eq)(&current, &value)), false)
+            }
+            (Some(adaptive), None) => {
+                let (ttl, _) = self.ttl_for(&key, &value, latency);
+                (ttl.clamp(adaptive.min, adaptive.max), false)
+            }
+            (None, _) => self.ttl_for(&key, &value, latency),
+        };
+        let written = self.config.write_behind.as_ref().map(|_| key.clone());
+        let persisted = self.config.second_tier.as_ref().map(|_| PersistedEntry {
+            key: key.clone(),
+            value: value.clone(),
+            fetched_at: self.timeline().to_system_time(fetched_at),
+            ttl,
+            invalidated: false,
+        });
+        if !self.put_if(
+            key,
+            fetched_at,
+            value.clone(),
+            ttl,
+            follows_default,
+            Some(generation),
+        ) {
+            return None;
+        }
+        if let (Some(second_tier), Some(persisted)) = (&self.config.second_tier, persisted) {
+            second_tier.store(persisted);
+        }
+        if let (Some(write_behind), Some(key)) = (&self.config.write_behind, written) {
+            write_behind.push(key, value.clone());
+        }
+        Some(value)
+    }
+
+    /// TTR for a new value: from the policy or the default, scaled by the
+    /// fetch `latency`, jittered, and whether it is the default.
+    fn ttl_for(&self, key: &K, value: &V, latency: Option<Duration>) -> (Duration, bool) {
+        let policy_ttl = self
+            .config
+            .ttl_policy
+            .as_ref()
+            .and_then(|policy| policy.ttl_for(key, value));
+        let (ttl, follows_default) = match policy_ttl {
+            Some(ttl) => (ttl, false),
+            None => (self.default_ttl(key), true),
+        };
+        match (&self.config.fetch_cost_ttl, latency) {
+            (Some(scale), Some(latency)) => (self.with_jitter(scale(ttl, latency)), false),
+            _ => (self.with_jitter(ttl), follows_default),
+        }
+    }
+
+    /// The namespace or cache-wide TTR for `key`.
+    fn default_ttl(&self, key: &K) -> Duration {
+        let namespace_ttl = self
+            .namespace_ttls
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .as_ref()
+            .and_then(|ttls| ttls.ttl_for(key));
+        namespace_ttl.unwrap_or_else(|| *self.ttl.read().unwrap_or_else(PoisonError::into_inner))
+    }
+
+    fn with_jitter(&self, ttl: Duration) -> Duration {
+        match self.config.ttl_jitter {
+            Some(jitter) => self.jittered(ttl, jitter),
+            None => ttl,
+        }
+    }
+
+    /// Replaces the cache-wide TTR, for new values and for cached entries
+    /// that use it.
+    pub(crate) fn set_ttl(&self, ttl: Duration) {
+        *self.ttl.write().unwrap_or_else(PoisonError::into_inner) = ttl;
+        self.retune();
+    }
+
+    /// Replaces the TTR of the namespace `namespace`, like
+    /// [`set_ttl`](Self::set_ttl).
+    pub(crate) fn set_namespace_ttl(
+        &self,
+        namespace_of: fn(&K) -> &str,
+        namespace: Arc<str>,
+        ttl: Duration,
+    ) {
+        self.namespace_ttls
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get_or_insert_with(|| NamespaceTtls {
+                namespace_of,
+                ttls: HashMap::new(),
+            })
+            .ttls
+            .insert(namespace, ttl);
+        self.retune();
+    }
+
+    /// Gives entries that use the namespace or cache-wide TTR its current
+    /// value, jittered anew.
+    fn retune(&self) {
+        for shard in self.shards.iter() {
+            for (key, entry) in self.write_shard(shard).iter_mut() {
+                if entry.follows_default {
+                    entry.ttl = self.with_jitter(self.default_ttl(key));
+                }
+            }
+        }
+    }
+
+    /// Scales `ttl` by a random factor within `1 ± jitter`.
+    fn jittered(&self, ttl: Duration, jitter: f64) -> Duration {
+        let unit = self.random_unit();
+        // Saturates rather than overflowing for `RefreshPolicy::Never`.
+        Duration::try_from_secs_f64(ttl.as_secs_f64() * (1.0 + jitter * (2.0 * unit - 1.0)))
+            .unwrap_or(Duration::MAX)
+    }
+
+    /// Stores a value fetched at `fetched_at` with an explicit TTR, writing
+    /// it through to the second tier.
+    pub(crate) fn insert_with_ttl(&self, key: K, fetched_at: Instant, value: V, ttl: Duration)
+    where
+        K: Clone,
+        V: Clone,
+    {
+        self.write_through(key, fetched_at, value, ttl, false);
+    }
+
+    fn write_through(
+        &self,
+        key: K,
+        fetched_at: Instant,
+        value: V,
+        ttl: Duration,
+        follows_default: bool,
+    ) where
+        K: Clone,
+        V: Clone,
+    {
+        if let Some(second_tier) = &self.config.second_tier {
+            second_tier.store(PersistedEntry {
+                key: key.clone(),
+                value: value.clone(),
+                fetched_at: self.timeline().to_system_time(fetched_at),
+                ttl,
+                invalidated: false,
+            });
+        }
+        self.put_if(key, fetched_at, value, ttl, follows_default, None);
+    }
+
+    /// Restarts the TTR of a cached entry whose value was confirmed
+    /// unchanged, returning that value.
+    ///
+    /// Does nothing and returns `None` if the entry is no longer at
+    /// `generation`; see [`refresh`](Self::refresh). With `adaptive_ttl`,
+    /// the TTR is lengthened.
+    pub(crate) fn renew(&self, key: &K, generation: Generation) -> Option<V>
+    where
+        K: Clone,
+        V: Clone,
+    {
+        self.restart(key, generation, true)
+    }
+
+    /// Restarts the TTR of a cached entry, lengthening it with
+    /// `adaptive_ttl` if the value was confirmed `unchanged`.
+    fn restart(&self, key: &K, generation: Generation, unchanged: bool) -> Option<V>
+    where
+        K: Clone,
+        V: Clone,
+    {
+        let now = self.now();
+        let (value, ttl) = {
+            let mut entries = self.write(key);
+            let entry = entries
+                .get_mut(key)
+                .filter(|entry| generation.matches(entry))?;
+            entry.fetched_at = now;
+            entry.invalidated = false;
+            entry.bulk_generation = generation.bulk;
+            entry.failures = 0;
+            entry.failing_since = None;
+            entry.failed_at = None;
+            entry.retry_at = None;
+            if let Some(adaptive) = self.config.adaptive_ttl.as_ref().filter(|_| unchanged) {
+                entry.ttl = adaptive.next(entry.ttl, true);
+                entry.follows_default = false;
+            }
+            (self.decoded(&entry.value)?, entry.ttl)
+        };
+
+        if let Some(second_tier) = &self.config.second_tier {
+            second_tier.store(PersistedEntry {
+                key: key.clone(),
+                value: value.clone(),
+                fetched_at: self.timeline().to_system_time(now),
+                ttl,
+                invalidated: false,
+            });
+        }
+        Some(value)
+    }
+
+    /// Handles a fetch of `key` that found nothing, as configured with
+    /// `on_missing_refresh`, returning what is left to serve.
+    ///
+    /// An entry written, invalidated or removed since `generation` is left
+    /// alone. A key with nothing left to serve is remembered as missing, for
+    /// the tombstone's duration if there is one.
+    pub(crate) fn refresh_missing(&self, key: &K, generation: Generation) -> Option<V>
+    where
+        K: Clone,
+        V: Clone,
+    {
+        let cached = match self.config.on_missing_refresh {
+            OnMissingRefresh::KeepStale => self.get(key),
+            OnMissingRefresh::Remove | OnMissingRefresh::Tombstone(_) => {
+                self.remove_at(key, generation);
+                self.get(key)
+            }
+            OnMissingRefresh::KeepAndResetTimer => self
+                .restart(key, generation, false)
+                .or_else(|| self.get(key)),
+        };
+        match self.config.on_missing_refresh {
+            _ if cached.is_some() => {}
+            OnMissingRefresh::Tombstone(period) => self.remember_absent(key.clone(), period),
+            _ => self.record_absent(key.clone()),
+        }
+        cached
+    }
+
+    /// Removes an entry, from both tiers, if it is still at `generation`.
+    fn remove_at(&self, key: &K, generation: Generation)
+    where
+        K: Clone,
+    {
+        let mut entries = self.write(key);
+        if !entries
+            .get(key)
+            .is_some_and(|entry| generation.matches(entry))
+        {
+            return;
+        }
+        let removed = self.take(&mut entries, key);
+        drop(entries);
+        self.invalidate_dependents(key);
+        if let Some(second_tier) = &self.config.second_tier {
+            second_tier.remove(key);
+        }
+        if let Some((key, entry)) = removed {
+            self.notify_removed(&key, &entry);
+            self.release(entry);
+        }
+    }
+
+    /// Stores a value in memory only.
+    fn put(&self, key: K, fetched_at: Instant, value: V, ttl: Duration)
+    where
+        K: Clone,
+        V: Clone,
+    {
+        self.put_if(key, fetched_at, value, ttl, false, None);
+    }
+
+    /// Stores a value in memory only, if a refresh from `expected` supersedes
+    /// the entry when one is given. Returns whether it was stored.
+    fn put_if(
+        &self,
+        key: K,
+        fetched_at: Instant,
+        value: V,
+        ttl: Duration,
+        follows_default: bool,
+        expected: Option<Generation>,
+    ) -> bool
+    where
+        K: Clone,
+        V: Clone,
+    {
+        let inserted = (self.config.listener.is_some() || self.is_subscribed(&key))
+            .then(|| (key.clone(), value.clone()));
+
+        let weight = self
+            .config
+            .weigher
+            .as_ref()
+            .map_or(1, |weigher| weigher.weigh(&key, &value));
+        self.forget_absent(&key);
+        let candidate = self.sketch.is_some().then(|| key.clone());
+        let indexed = (!self.config.indexes.is_empty()).then(|| value.clone());
+        let periodic = self.periodic.is_some().then(|| key.clone());
+        let base = self.has_dependents().then(|| key.clone());
+        let good = (self.config.keep_last_known_good
+            && self
+                .config
+                .validator
+                .as_ref()
+                .is_none_or(|validator| validator.validate(&key, &value)))
+        .then(|| (key.clone(), value.clone()));
+        let mut entry = Entry {
+            value: self.encode(value),
+            inserted_at: fetched_at,
+            fetched_at,
+            ttl,
+            weight,
+            last_access: AtomicU64::new(self.tick()),
+            last_used: AtomicU64::new(self.since_epoch()),
+            last_read: AtomicU64::new(self.since_epoch()),
+            hits: AtomicU64::new(0),
+            stale_hits: AtomicU64::new(0),
+            max_staleness: AtomicU64::new(0),
+            invalidated: false,
+            bulk_generation: expected.map_or_else(
+                || self.bulk_generation.load(Ordering::Relaxed),
+                |generation| generation.bulk,
+            ),
+            pinned: false,
+            generation: self.tick(),
+            refreshed_from: expected,
+            failures: 0,
+            failing_since: None,
+            failed_at: None,
+            retry_at: None,
+            follows_default,
+        };
+
+        let mut entries = self.write(&key);
+        if expected.is_some_and(|expected| !expected.supersedes(entries.get(&key))) {
+            return false;
+        }
+        if let Some(previous) = entries.get(&key) {
+            entry.inserted_at = previous.inserted_at;
+            entry.pinned = previous.pinned;
+            entry.keep_stats(previous.stats());
+            if expected.is_some() {
+                entry.last_read = AtomicU64::new(previous.last_read.load(Ordering::Relaxed));
+            }
+        } else {
+            self.config.indexes.insert_key(&key);
+        }
+        if let Some(value) = &indexed {
+            self.config.indexes.insert(&key, value);
+        }
+        self.add_weight(weight);
+        let replaced = entries.insert(key, entry);
+        if let Some(replaced) = &replaced {
+            self.sub_weight(replaced.weight);
+        } else {
+            self.len.fetch_add(1, Ordering::Relaxed);
+        }
+        self.peak_weight
+            .fetch_max(self.weight.load(Ordering::Relaxed), Ordering::Relaxed);
+        drop(entries);
+
+        if let Some((key, value)) = good {
+            self.last_known_good_values().insert(key, value);
+        }
+        if let Some(key) = &periodic {
+            self.schedule_periodic(key);
+        }
+        if let Some(key) = &base {
+            self.invalidate_dependents(key);
+        }
+        let evicted = self.evict(candidate.as_ref().filter(|_| replaced.is_none()));
+        for (key, _) in &evicted {
+            self.forget_last_known_good(key);
+        }
+
+        if let Some((key, value)) = &inserted {
+            let previous = replaced
+                .as_ref()
+                .and_then(|replaced| self.decode(&replaced.value));
+            self.notify(key, previous.as_deref(), value);
+            if let Some(listener) = &self.config.listener {
+                if replaced.is_some() {
+                    listener.on_refresh(key, value);
+                } else {
+                    listener.on_insert(key, value);
+                }
+                if let Some(previous) = &previous {
+                    listener.on_removal(key, previous, EvictionReason::Replaced);
+                }
+                for (key, entry) in &evicted {
+                    self.notify_evicted(key, entry, EvictionReason::Size);
+                }
+            }
+        }
+        if let Some(replaced) = replaced {
+            self.release_replaced(replaced);
+        }
+        for (_, entry) in evicted {
+            self.release(entry);
+        }
+        true
+    }
+
+    /// Counts a lookup that found nothing, for the stats.
+    #[cfg(feature = "cached")]
+    pub(crate) fn record_miss(&self) {
+        self.stats.record_miss();
+    }
+
+    /// Records a fetcher call for the stats.
+    pub(crate) fn record_fetch(&self, elapsed: Duration, succeeded: bool) {
+        self.stats.record_fetch(elapsed, succeeded);
+        if let Some(breaker) = &self.breaker {
+            breaker.record(succeeded, self.now());
+        }
+    }
+
+    /// Whether `value` passes the validator, if one is set, without logging.
+    pub(crate) fn validates(&self, key: &K, value: &V) -> bool {
+        self.config
+            .validator
+            .as_ref()
+            .is_none_or(|validator| validator.validate(key, value))
+    }
+
+    /// Whether the validator rejects a fetched `value` for `key`.
+    pub(crate) fn rejects(&self, key: &K, value: &V) -> bool {
+        let rejected = !self.validates(key, value);
+        #[cfg(feature = "tracing")]
+        if rejected {
+            tracing::warn!(key = ?self.trace_key(key), "fetched value rejected");
+        }
+        rejected
+    }
+
+    /// Sets a timer for `key` if its strategy refreshes it on a schedule and
+    /// it has none yet.
+    pub(crate) fn schedule_periodic(&self, key: &K)
+    where
+        K: Clone,
+    {
+        let (Some(periodic), Some(strategy)) = (&self.periodic, &self.config.refresh_strategy)
+        else {
+            return;
+        };
+        let after = match strategy(key) {
+            RefreshStrategy::OnRead => return,
+            RefreshStrategy::Periodic(interval) => interval,
+            RefreshStrategy::WhenStale => {
+                let now = self.now();
+                // A key already stale, whose refresh failed, is tried again
+                // a TTR later.
+                let after = self
+                    .read(key)
+                    .get(key)
+                    .map(|entry| match self.is_fresh(entry, now) {
+                        true => entry.ttl.saturating_sub(entry.age(now)),
+                        false => entry.ttl,
+                    });
+                let Some(after) = after else {
+                    return;
+                };
+                after
+            }
+        };
+        periodic.schedule(key.clone(), after);
+    }
+
+    /// Whether a key whose timer fired is due for a refresh: always if it
+    /// is refreshed periodically, and once it went stale otherwise.
+    pub(crate) fn is_periodic_due(&self, key: &K) -> bool {
+        match self
+            .config
+            .refresh_strategy
+            .as_ref()
+            .map(|strategy| strategy(key))
+        {
+            Some(RefreshStrategy::WhenStale) => self.freshness(key) == HitFreshness::Stale,
+            _ => true,
+        }
+    }
+
+    /// Waits until scheduled refreshes are due, returning their keys, or
+    /// `None` once `stopped` is true after [`wake_timers`](Self::wake_timers).
+    pub(crate) fn wait_periodic(&self, stopped: impl Fn() -> bool) -> Option<Vec<K>>
+    where
+        K: Clone,
+    {
+        self.periodic.as_ref()?.wait_due(stopped)
+    }
+
+    /// Records that fetching `key` was given up on after the fetch timeout.
+    pub(crate) fn fetch_timed_out(&self, key: &K) {
+        self.stats.record_timeout();
+        if let Some(listener) = &self.config.listener {
+            listener.on_fetch_timeout(key);
+        }
+    }
+
+    /// Records that fetching `key` panicked, then handles it like any other
+    /// failed fetch.
+    pub(crate) fn fetch_panicked(&self, key: &K) {
+        #[cfg(feature = "tracing")]
+        tracing::warn!(key = ?self.trace_key(key), "fetch panicked");
+        self.stats.record_panic();
+        self.fetch_failed(key);
+    }
+
+    /// Notifies the listener that fetching `key` produced no value, and
+    /// records the failure for the entry state and the retry and
+    /// stale-if-error policies.
+    pub(crate) fn fetch_failed(&self, key: &K) {
+        let now = self.now();
+        if let Some(entry) = self.write(key).get_mut(key) {
+            entry.failures = entry.failures.saturating_add(1);
+            entry.failing_since.get_or_insert(now);
+            entry.failed_at = Some(now);
+            if let Some(policy) = &self.config.retry_policy {
+                let backoff = match policy.jitter {
+                    Some(jitter) => self.jittered(policy.backoff(entry.failures), jitter),
+                    None => policy.backoff(entry.failures),
+                };
+                entry.retry_at = now.checked_add(backoff);
+            }
+        }
+
+        if let Some(listener) = &self.config.listener {
+            listener.on_refresh_failed(key);
+        }
+        if self.config.stale_if_error.is_some() {
+            self.expire(key);
+        }
+    }
+
+    pub(crate) fn stats(&self) -> CacheStats {
+        CacheStats {
+            memory_usage: self.memory_usage(),
+            peak_memory_usage: self.bytes(self.peak_weight.load(Ordering::Relaxed)),
+            breaker_state: self
+                .breaker
+                .as_ref()
+                .map_or_else(BreakerState::default, |breaker| breaker.state(self.now())),
+            ..self.stats.snapshot(self.len.load(Ordering::Relaxed))
+        }
+    }
+
+    /// Panics if the entry count or total weight is out of step with the
+    /// entries, or the cache is over its limits with unpinned entries left.
+    #[cfg(feature = "test-util")]
+    pub(crate) fn assert_invariants(&self) {
+        let (mut len, mut weight, mut pinned) = (0, 0, 0);
+        for shard in self.shards.iter() {
+            for entry in read_shard(shard).values() {
+                len += 1;
+                weight += entry.weight;
+                pinned += usize::from(entry.pinned);
+            }
+        }
+        assert_eq!(len, self.len.load(Ordering::Relaxed), "entry count drifted");
+        assert_eq!(
+            weight,
+            self.weight.load(Ordering::Relaxed),
+            "total weight drifted"
+        );
+        assert!(
+            weight <= self.peak_weight.load(Ordering::Relaxed),
+            "peak weight below the current weight"
+        );
+        if let Some(max_capacity) = self.config.max_capacity {
+            assert!(
+                len <= max_capacity || len == pinned,
+                "{len} entries over the capacity of {max_capacity}"
+            );
+        }
+        if let Some(max_weight) = self.config.max_weight {
+            assert!(
+                weight <= max_weight || len == pinned,
+                "weight {weight} over the limit of {max_weight}"
+            );
+        }
+    }
+
+    /// Estimated bytes held by the cached entries.
+    pub(crate) fn memory_usage(&self) -> u64 {
+        self.bytes(self.weight.load(Ordering::Relaxed))
+    }
+
+    fn add_weight(&self, weight: u64) {
+        self.weight.fetch_add(weight, Ordering::Relaxed);
+        if let Some(budget) = &self.budget {
+            budget.add(self.bytes(weight));
+        }
+    }
+
+    fn sub_weight(&self, weight: u64) {
+        self.weight.fetch_sub(weight, Ordering::Relaxed);
+        if let Some(budget) = &self.budget {
+            budget.sub(self.bytes(weight));
+        }
+    }
+
+    /// Weights are bytes with a weigher, and entry counts without one.
+    fn bytes(&self, weight: u64) -> u64 {
+        match self.config.weigher {
+            Some(_) => weight,
+            None => weight.saturating_mul(self.config.entry_size),
+        }
+    }
+
+    /// Returns the namespace `name` and its counters, registering it on
+    /// first use.
+    pub(crate) fn namespace_stats(&self, name: &str) -> (Arc<str>, Arc<StatsCounter>) {
+        let mut namespaces = self
+            .namespaces
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if let Some((name, stats)) = namespaces.get_key_value(name) {
+            return (Arc::clone(name), Arc::clone(stats));
+        }
+        let (name, stats): (Arc<str>, Arc<StatsCounter>) = (name.into(), Arc::default());
+        namespaces.insert(Arc::clone(&name), Arc::clone(&stats));
+        (name, stats)
+    }
+
+    /// Marks an entry stale so the next lookup refreshes it, even if it is
+    /// backing off.
+    ///
+    /// A key remembered as missing is forgotten instead. Returns `false` if
+    /// the key is neither.
+    pub(crate) fn invalidate(&self, key: &K) -> bool
+    where
+        K: Clone,
+    {
+        let invalidated = match self.write(key).get_mut(key) {
+            Some(entry) => {
+                entry.invalidate(self.tick());
+                true
+            }
+            None => self.forget_absent(key),
+        };
+        self.invalidate_dependents(key);
+        invalidated
+    }
+
+    /// Tells the other instances on the invalidation bus, if any, that `key`
+    /// was invalidated.
+    pub(crate) fn publish_invalidation(&self, key: &K) {
+        if let Some(bus) = &self.config.invalidation_bus {
+            bus.publish(key);
+        }
+    }
+
+    pub(crate) fn invalidation_bus(&self) -> Option<&BoxedInvalidationBus<K>> {
+        self.config.invalidation_bus.as_ref()
+    }
+
+    fn dependents(&self) -> MutexGuard<'_, HashMap<K, HashSet<K>, S>> {
+        self.dependents
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn has_dependents(&self) -> bool {
+        !self.dependents().is_empty()
+    }
+
+    /// Records that `dependent` is derived from `base`.
+    pub(crate) fn add_dependency(&self, dependent: K, base: K) {
+        self.dependents().entry(base).or_default().insert(dependent);
+    }
+
+    /// Forgets what `dependent` is derived from, returning `false` if it was
+    /// not registered.
+    pub(crate) fn remove_dependencies(&self, dependent: &K) -> bool {
+        let mut removed = false;
+        self.dependents().retain(|_, dependents| {
+            removed |= dependents.remove(dependent);
+            !dependents.is_empty()
+        });
+        removed
+    }
+
+    /// Marks every key derived from `key`, directly or not, stale.
+    fn invalidate_dependents(&self, key: &K)
+    where
+        K: Clone,
+    {
+        let stale: Vec<K> = {
+            let graph = self.dependents();
+            let mut seen = HashSet::from([key]);
+            let mut bases = vec![key];
+            while let Some(base) = bases.pop() {
+                for dependent in graph.get(base).into_iter().flatten() {
+                    if seen.insert(dependent) {
+                        bases.push(dependent);
+                    }
+                }
+            }
+            seen.into_iter()
+                .filter(|dependent| *dependent != key)
+                .cloned()
+                .collect()
+        };
+        for dependent in &stale {
+            if let Some(entry) = self.write(dependent).get_mut(dependent) {
+                entry.invalidate(self.tick());
+            }
+        }
+    }
+
+    /// Sets whether an entry is exempt from eviction and hard expiry.
+    ///
+    /// Returns `false` if the key is not cached.
+    pub(crate) fn set_pinned(&self, key: &K, pinned: bool) -> bool {
+        match self.write(key).get_mut(key) {
+            Some(entry) => {
+                entry.pinned = pinned;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Marks every entry matching `predicate` stale, returning how many.
+    pub(crate) fn invalidate_if(&self, mut predicate: impl FnMut(&K, &V) -> bool) -> usize {
+        let mut invalidated = 0;
+        for shard in self.shards.iter() {
+            for (key, entry) in self.write_shard(shard).iter_mut() {
+                if self
+                    .decode(&entry.value)
+                    .is_some_and(|value| predicate(key, &value))
+                {
+                    entry.invalidate(self.tick());
+                    invalidated += 1;
+                }
+            }
+        }
+        invalidated
+    }
+
+    /// Removes every entry matching `predicate` from memory and the second
+    /// tier, returning how many.
+    pub(crate) fn remove_if(&self, mut predicate: impl FnMut(&K, &V) -> bool) -> usize
+    where
+        K: Clone,
+    {
+        let mut removed = Vec::new();
+        for shard in self.shards.iter() {
+            let mut entries = self.write_shard(shard);
+            let matching: Vec<K> = entries
+                .iter()
+                .filter(|(key, entry)| {
+                    self.decode(&entry.value)
+                        .is_some_and(|value| predicate(key, &value))
+                })
+                .map(|(key, _)| key.clone())
+                .collect();
+            for key in matching {
+                removed.extend(self.take(&mut entries, &key));
+            }
+        }
+
+        for (key, entry) in &removed {
+            self.forget_absent(key);
+            self.forget_last_known_good(key);
+            if let Some(second_tier) = &self.config.second_tier {
+                second_tier.remove(key);
+            }
+            self.notify_removed(key, entry);
+        }
+        let count = removed.len();
+        for (_, entry) in removed {
+            self.release(entry);
+        }
+        count
+    }
+
+    /// Removes an entry from memory and the second tier, returning its value.
+    pub(crate) fn remove(&self, key: &K) -> Option<V>
+    where
+        K: Clone,
+    {
+        self.forget_absent(key);
+        self.forget_last_known_good(key);
+        self.cancel_fetches(Some(key));
+        if let Some(second_tier) = &self.config.second_tier {
+            second_tier.remove(key);
+        }
+        let (key, entry) = self.take(&mut self.write(key), key)?;
+        self.invalidate_dependents(&key);
+        self.notify_removed(&key, &entry);
+        self.take_value(entry.value)
+    }
+
+    /// Flushes the second tier, if any.
+    pub(crate) fn flush(&self) {
+        if let Some(second_tier) = &self.config.second_tier {
+            second_tier.flush();
+        }
+        if let Some(write_behind) = &self.config.write_behind {
+            write_behind.flush();
+        }
+    }
+
+    /// The cached keys in `slice` of `slices` even parts, split by key hash,
+    /// for going over every key a slice at a time.
+    pub(crate) fn keys_in_slice(&self, slice: u32, slices: u32) -> Vec<K>
+    where
+        K: Clone,
+    {
+        self.collect(|key, _| {
+            (self.hasher.hash_one(key) % u64::from(slices) == u64::from(slice)).then(|| key.clone())
+        })
+        .into_iter()
+        .flatten()
+        .collect()
+    }
+
+    /// The cached keys whose value maps to `value`, by the index over `I`.
+    pub(crate) fn keys_by_index<I>(&self, value: &I) -> Vec<K>
+    where
+        K: Clone + 'static,
+        V: 'static,
+        I: Eq + Hash + 'static,
+    {
+        self.config.indexes.keys(value)
+    }
+
+    /// The cached keys within `range`, in order, by the key order if it is
+    /// kept or the storage is ordered, and by a scan of every entry otherwise.
+    pub(crate) fn keys_in_range<R>(&self, range: &R) -> Vec<K>
+    where
+        K: Ord + Clone + 'static,
+        R: RangeBounds<K>,
+    {
+        if let Some(keys) = self.config.indexes.keys_in_range(range) {
+            return keys;
+        }
+        let mut keys: Vec<K> = match self.ordered_keys_in(range) {
+            Some(keys) => keys,
+            None => self
+                .collect(|key, _| range.contains(key).then(|| key.clone()))
+                .into_iter()
+                .flatten()
+                .collect(),
+        };
+        keys.sort_unstable();
+        keys
+    }
+
+    /// The unexpired keys within `range` of every shard, unsorted, if the
+    /// storage keeps them in order.
+    fn ordered_keys_in<R>(&self, range: &R) -> Option<Vec<K>>
+    where
+        K: Clone,
+        R: RangeBounds<K>,
+    {
+        let now = self.now();
+        let mut keys = Vec::new();
+        for shard in self.shards.iter() {
+            let entries = read_shard(shard);
+            let in_range = entries.range(range.start_bound(), range.end_bound())?;
+            keys.extend(
+                in_range
+                    .filter(|(_, entry)| !self.is_expired(entry, now))
+                    .map(|(key, _)| key.clone()),
+            );
+        }
+        Some(keys)
+    }
+
+    /// The values of `keys` that are cached, without counting as uses.
+    pub(crate) fn entries_of(&self, keys: Vec<K>) -> Vec<(K, V)>
+    where
+        V: Clone,
+    {
+        let now = self.now();
+        keys.into_iter()
+            .filter_map(|key| {
+                let value = self
+                    .read(&key)
+                    .get(&key)
+                    .filter(|entry| !self.is_expired(entry, now))
+                    .and_then(|entry| self.decoded(&entry.value))?;
+                Some((key, value))
+            })
+            .collect()
+    }
+
+    /// Removes every entry from memory, leaving the second tier alone.
+    pub(crate) fn clear(&self) {
+        self.absent().clear();
+        if let Some(filter) = &self.miss_filter {
+            filter.clear();
+        }
+        self.last_known_good_values().clear();
+        self.cancel_fetches(None);
+        for shard in self.shards.iter() {
+            let mut entries = self.write_shard(shard);
+            let weight: u64 = entries.values().map(|entry| entry.weight).sum();
+            self.len.fetch_sub(entries.len(), Ordering::Relaxed);
+            self.sub_weight(weight);
+            if self.config.listener.is_none() && self.config.lifecycle.is_none() {
+                entries.clear();
+                continue;
+            }
+            let removed: Vec<_> = entries.drain().collect();
+            drop(entries);
+            for (key, entry) in removed {
+                self.notify_removed(&key, &entry);
+                self.release(entry);
+            }
+        }
+        self.config.indexes.clear();
+    }
+
+    /// Removes an entry, keeping the entry count and total weight in step.
+    fn take(&self, entries: &mut Entries<K, Entry<V>, S>, key: &K) -> Option<(K, Entry<V>)> {
+        let (key, entry) = entries.remove_entry(key)?;
+        self.config.indexes.remove(&key);
+        self.len.fetch_sub(1, Ordering::Relaxed);
+        self.sub_weight(entry.weight);
+        Some((key, entry))
+    }
+
+    /// Removes entries that are past their hard expiry or went unused for
+    /// longer than the configured idle limit, returning how many.
+    pub(crate) fn purge(&self) -> usize
+    where
+        K: Clone,
+    {
+        self.observe_clock();
+        self.settle_swap_reads();
+        let now = self.now();
+        let mut idle = Vec::new();
+        let mut expired = Vec::new();
+        for shard in self.shards.iter() {
+            let mut entries = self.write_shard(shard);
+            let purgeable: Vec<K> = entries
+                .iter()
+                .filter(|(_, entry)| {
+                    self.is_expired(entry, now)
+                        || (!entry.pinned && entry.is_idle(self.config.max_idle, self.epoch, now))
+                })
+                .map(|(key, _)| key.clone())
+                .collect();
+            for key in purgeable {
+                let Some((key, entry)) = self.take(&mut entries, &key) else {
+                    continue;
+                };
+                if self.is_expired(&entry, now) {
+                    let given_up = self.is_given_up(&entry, now);
+                    expired.push((key, entry, given_up));
+                } else {
+                    idle.push((key, entry));
+                }
+            }
+        }
+
+        if self.remembers_absent() {
+            self.prune_absent(&mut self.absent(), now);
+        }
+
+        let purged = idle.len() + expired.len();
+        #[cfg(feature = "tracing")]
+        {
+            for (key, _) in &idle {
+                tracing::debug!(key = ?self.trace_key(key), reason = "idle", "evicted");
+            }
+            for (key, _, _) in &expired {
+                tracing::debug!(key = ?self.trace_key(key), "expired");
+            }
+        }
+        for (key, entry) in idle {
+            self.notify_evicted(&key, &entry, EvictionReason::Expired);
+            self.release(entry);
+        }
+        for (key, entry, given_up) in expired {
+            self.notify_expired(&key, &entry, given_up);
+            self.release(entry);
+        }
+        // A cache holding more than its share of a shared budget sheds it
+        // here when it is not being written to.
+        if self.budget.is_some() {
+            for (key, entry) in self.evict(None) {
+                self.forget_last_known_good(&key);
+                self.notify_evicted(&key, &entry, EvictionReason::Size);
+                self.release(entry);
+            }
+        }
+        purged
+    }
+
+    /// Removes the entries past their hard expiry, returning their values.
+    pub(crate) fn drain_expired(&self) -> Vec<(K, V)>
+    where
+        K: Clone,
+    {
+        let now = self.now();
+        let mut expired = Vec::new();
+        for shard in self.shards.iter() {
+            let mut entries = self.write_shard(shard);
+            let keys: Vec<K> = entries
+                .iter()
+                .filter(|(_, entry)| self.is_expired(entry, now))
+                .map(|(key, _)| key.clone())
+                .collect();
+            for key in keys {
+                if let Some((key, entry)) = self.take(&mut entries, &key) {
+                    let given_up = self.is_given_up(&entry, now);
+                    expired.push((key, entry, given_up));
+                }
+            }
+        }
+
+        let mut drained = Vec::with_capacity(expired.len());
+        for (key, entry, given_up) in expired {
+            #[cfg(feature = "tracing")]
+            tracing::debug!(key = ?self.trace_key(&key), "expired");
+            self.notify_expired(&key, &entry, given_up);
+            // Weak values already dropped have nothing left to archive.
+            if let Some(value) = self.take_value(entry.value) {
+                drained.push((key, value));
+            }
+        }
+        drained
+    }
+
+    /// Evicts least recently used entries until the cache is within its
+    /// capacity, weight and budget limits, or only pinned entries are left.
+    ///
+    /// Only one shard is locked at a time, so concurrent inserts may briefly
+    /// overshoot the limits.
+    fn evict(&self, mut candidate: Option<&K>) -> Vec<(K, Entry<V>)>
+    where
+        K: Clone,
+    {
+        let mut evicted = Vec::new();
+        while self.over_limits() {
+            let Some((shard, oldest)) = self.find_lru() else {
+                break;
+            };
+            let (shard, victim) = match candidate.take() {
+                Some(candidate) if !self.admits(candidate, &oldest) => {
+                    (self.shard(candidate), candidate.clone())
+                }
+                _ => (shard, oldest),
+            };
+            // Another thread may have removed it since; if so, look again.
+            let taken = self.take(&mut self.write_shard(shard), &victim);
+            #[cfg(feature = "tracing")]
+            if let Some((key, _)) = &taken {
+                tracing::debug!(key = ?self.trace_key(key), reason = "capacity", "evicted");
+            }
+            evicted.extend(taken);
+        }
+        evicted
+    }
+
+    fn over_limits(&self) -> bool {
+        let over_capacity = self
+            .config
+            .max_capacity
+            .is_some_and(|max_capacity| self.len.load(Ordering::Relaxed) > max_capacity);
+        let over_weight = self
+            .config
+            .max_weight
+            .is_some_and(|max_weight| self.weight.load(Ordering::Relaxed) > max_weight);
+        over_capacity || over_weight || self.budget.as_ref().is_some_and(|budget| budget.is_over())
+    }
+
+    /// Finds the least recently used unpinned key and the shard holding it.
+    fn find_lru(&self) -> Option<(&Shard<K, V, S>, K)>
+    where
+        K: Clone,
+    {
+        self.settle_swap_reads();
+        self.shards
+            .iter()
+            .filter_map(|shard| {
+                let entries = read_shard(shard);
+                let (key, entry) = entries
+                    .iter()
+                    .filter(|(_, entry)| !entry.pinned)
+                    .min_by_key(|(_, entry)| entry.last_access.load(Ordering::Relaxed))?;
+                Some((
+                    entry.last_access.load(Ordering::Relaxed),
+                    shard,
+                    key.clone(),
+                ))
+            })
+            .min_by_key(|(last_access, _, _)| *last_access)
+            .map(|(_, shard, key)| (shard, key))
+    }
+}
+
+impl<K, V, S> Store<K, V, S> {
+    /// Wakes the thread waiting for scheduled refreshes.
+    pub(crate) fn wake_timers(&self) {
+        if let Some(periodic) = &self.periodic {
+            periodic.wake();
+        }
+    }
+
+    /// Hands a value replaced by a newer one to the lifecycle hook, if any.
+    fn release_replaced(&self, entry: Entry<V>) {
+        if let Some(lifecycle) = &self.config.lifecycle {
+            if let Some(value) = self.take_value(entry.value) {
+                lifecycle.on_replace(value);
+            }
+        }
+    }
+
+    /// Hands a value that left the cache to the lifecycle hook, if any.
+    fn release(&self, entry: Entry<V>) {
+        if let Some(lifecycle) = &self.config.lifecycle {
+            if let Some(value) = self.take_value(entry.value) {
+                lifecycle.on_evict(value);
+            }
+        }
+    }
+
+    fn take_value(&self, slot: Slot<V>) -> Option<V> {
+        match slot {
+            Slot::Plain(value) => Some(value),
+            Slot::Encoded(bytes) => Some(self.codec().decode(&bytes)),
+            Slot::Weak(weak) => self.weak_values().upgrade(&weak),
+        }
+    }
+
+    fn codec(&self) -> &BoxedCodec<V> {
+        // Values are only encoded when there is a codec.
+        self.config
+            .codec
+            .as_ref()
+            .expect("encoded value without a codec")
+    }
+
+    fn weak_values(&self) -> &WeakValues<V> {
+        // Values are only held weakly when configured to.
+        self.config
+            .weak_values
+            .as_ref()
+            .expect("weak value without weak references configured")
+    }
+}
+
+impl<K, V, S> Drop for Store<K, V, S> {
+    fn drop(&mut self) {
+        if self.config.lifecycle.is_some() {
+            for shard in std::mem::take(&mut self.shards).iter_mut() {
+                let entries = shard.get_mut().unwrap_or_else(PoisonError::into_inner);
+                for (_, entry) in entries.drain() {
+                    self.release(entry);
+                }
+            }
+        }
+        let subscribers = self
+            .subscribers
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner);
+        for subscriber in subscribers.values().flatten() {
+            if let Sink::Watch(slot) = &subscriber.sink {
+                slot.close();
+            }
+        }
+    }
+}
+
+// Writers never leave a map half-updated, so a poisoned lock is safe to reuse.
+fn read_shard<K, V, S>(shard: &Shard<K, V, S>) -> RwLockReadGuard<'_, Entries<K, Entry<V>, S>> {
+    #[cfg(feature = "tracing")]
+    if tracing::enabled!(tracing::Level::TRACE) {
+        let started = Instant::now();
+        let entries = shard.read().unwrap_or_else(PoisonError::into_inner);
+        tracing::trace!(wait = ?started.elapsed(), "shard read lock acquired");
+        return entries;
+    }
+    shard.read().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn write_shard<K, V, S>(shard: &Shard<K, V, S>) -> RwLockWriteGuard<'_, Entries<K, Entry<V>, S>> {
+    #[cfg(feature = "tracing")]
+    if tracing::enabled!(tracing::Level::TRACE) {
+        let started = Instant::now();
+        let entries = shard.write().unwrap_or_else(PoisonError::into_inner);
+        tracing::trace!(wait = ?started.elapsed(), "shard write lock acquired");
+        return entries;
+    }
+    shard.write().unwrap_or_else(PoisonError::into_inner)
+}
+
+#[cfg(all(test, loom))]
+mod loom_tests {
+    use loom::sync::atomic::AtomicU32;
+    use loom::thread;
+
+    use crate::{TTRCache, TTRCacheBuilder};
+
+    use super::*;
+
+    /// A fetch racing a write-through of the same key never leaves the
+    /// value it read from before the write cached.
+    #[test]
+    fn fetches_racing_writes_do_not_win() {
+        loom::model(|| {
+            let backend = Arc::new(AtomicU32::new(10));
+            let cache: Arc<TTRCache<u32, u32, _>> = Arc::new({
+                let backend = Arc::clone(&backend);
+                TTRCacheBuilder::new(Duration::from_secs(60))
+                    .build(move |_: &u32| Some(backend.load(Ordering::SeqCst)))
+            });
+            let reader = {
+                let cache = Arc::clone(&cache);
+                thread::spawn(move || cache.get(&1))
+            };
+            backend.store(20, Ordering::SeqCst);
+            cache.insert(1, 20);
+            assert!(matches!(reader.join().unwrap(), Some(10 | 20)));
+            assert_eq!(cache.peek(&1), Some(20));
+        });
+    }
+
+    /// Writers to keys sharing a shard both land.
+    #[test]
+    fn writes_to_one_shard_are_not_lost() {
+        loom::model(|| {
+            let cache: Arc<TTRCache<u32, u32, ()>> =
+                Arc::new(TTRCacheBuilder::new(Duration::from_secs(60)).build_cache_aside());
+            let writer = {
+                let cache = Arc::clone(&cache);
+                thread::spawn(move || cache.insert(1, 10))
+            };
+            cache.insert(2, 20);
+            writer.join().unwrap();
+            assert_eq!((cache.peek(&1), cache.peek(&2)), (Some(10), Some(20)));
+            assert_eq!(cache.len(), 2);
+        });
+    }
+}
@@ -0,0 +1,237 @@
+//! Lock-free reads from a copy of the fresh entries.
+
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+use arc_swap::ArcSwap;
+
+use crate::sync::{AtomicBool, AtomicU64};
+use crate::time::Instant;
+
+/// The fresh entries as of one version of the store.
+struct Published<K, V> {
+    version: u64,
+    entries: HashMap<K, Swapped<V>>,
+}
+
+/// A value in the copy, with when it stops being served from here.
+struct Swapped<V> {
+    value: V,
+    fresh_until: Instant,
+    reads: Reads,
+}
+
+/// Reads served from the copy, until added to the store's entry.
+///
+/// Plain `std` atomics even under loom: they are published through the
+/// `ArcSwap`, which loom cannot see.
+struct Reads {
+    hits: std::sync::atomic::AtomicU64,
+    /// The store's tick at the last read.
+    last_access: std::sync::atomic::AtomicU64,
+    /// Nanoseconds from the store's epoch to the last read.
+    last_read: std::sync::atomic::AtomicU64,
+}
+
+impl Reads {
+    fn take(&self) -> Option<SwapReads> {
+        let hits = self.hits.swap(0, Ordering::AcqRel);
+        (hits > 0).then(|| SwapReads {
+            hits,
+            last_access: self.last_access.load(Ordering::Relaxed),
+            last_read: self.last_read.load(Ordering::Relaxed),
+        })
+    }
+}
+
+/// Reads of one key served from the copy since last taken, for the store
+/// to add to its entry's stats and recency.
+#[derive(Clone, Copy)]
+pub(crate) struct SwapReads {
+    pub(crate) hits: u64,
+    pub(crate) last_access: u64,
+    pub(crate) last_read: u64,
+}
+
+/// A copy of a store's fresh entries, published whole and swapped in.
+///
+/// Every write to the store moves its version on, so a copy from an older
+/// version is never read; the lookup goes through the locks instead and
+/// rebuilds the copy.
+pub(crate) struct SwapMap<K, V> {
+    published: ArcSwap<Published<K, V>>,
+    version: AtomicU64,
+    /// Set while a copy is being built, so only one reader builds it.
+    rebuilding: AtomicBool,
+}
+
+impl<K, V> SwapMap<K, V> {
+    pub(crate) fn new() -> Self {
+        SwapMap {
+            published: ArcSwap::from_pointee(Published {
+                version: 0,
+                entries: HashMap::new(),
+            }),
+            version: AtomicU64::new(1),
+            rebuilding: AtomicBool::new(false),
+        }
+    }
+
+    /// Records a write, making the copy out of date.
+    pub(crate) fn outdate(&self) {
+        self.version.fetch_add(1, Ordering::Release);
+    }
+
+    /// Publishes the entries `collect` returns unless the copy is current
+    /// or another thread is already building it, passing the reads served
+    /// from the copy it replaces to `settle`.
+    pub(crate) fn rebuild(
+        &self,
+        collect: impl FnOnce() -> HashMap<K, (V, Instant)>,
+        settle: impl FnMut(&K, SwapReads),
+    ) where
+        K: Eq + Hash,
+    {
+        // Read first: a write during the rebuild outdates the copy again.
+        let version = self.version.load(Ordering::Acquire);
+        if self.published.load().version == version || self.rebuilding.swap(true, Ordering::Acquire)
+        {
+            return;
+        }
+        // Cleared even if `collect` panics, so later reads can rebuild.
+        let _rebuilding = Rebuilding(&self.rebuilding);
+        let entries = collect()
+            .into_iter()
+            .map(|(key, (value, fresh_until))| {
+                let reads = Reads {
+                    hits: 0.into(),
+                    last_access: 0.into(),
+                    last_read: 0.into(),
+                };
+                let swapped = Swapped {
+                    value,
+                    fresh_until,
+                    reads,
+                };
+                (key, swapped)
+            })
+            .collect();
+        let replaced = self
+            .published
+            .swap(Arc::new(Published { version, entries }));
+        drain(&replaced, settle);
+    }
+
+    /// Passes the reads served from the copy since last taken to `settle`.
+    pub(crate) fn drain_reads(&self, settle: impl FnMut(&K, SwapReads)) {
+        drain(&self.published.load(), settle);
+    }
+}
+
+fn drain<K, V>(published: &Published<K, V>, mut settle: impl FnMut(&K, SwapReads)) {
+    for (key, swapped) in &published.entries {
+        if let Some(reads) = swapped.reads.take() {
+            settle(key, reads);
+        }
+    }
+}
+
+/// Clears the rebuilding flag when dropped.
+struct Rebuilding<'a>(&'a AtomicBool);
+
+impl Drop for Rebuilding<'_> {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::Release);
+    }
+}
+
+impl<K, V> SwapMap<K, V>
+where
+    K: Eq + Hash,
+{
+    /// The value for `key`, if the copy is current and it is fresh at `now`.
+    ///
+    /// A read served is recorded at the store's tick and time since its
+    /// epoch that `stamp` returns.
+    pub(crate) fn get<Q>(
+        &self,
+        key: &Q,
+        now: Instant,
+        stamp: impl FnOnce() -> (u64, u64),
+    ) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+        V: Clone,
+    {
+        let published = self.published.load();
+        if published.version != self.version.load(Ordering::Acquire) {
+            return None;
+        }
+        let swapped = published
+            .entries
+            .get(key)
+            .filter(|swapped| now < swapped.fresh_until)?;
+        let (tick, since_epoch) = stamp();
+        swapped.reads.last_access.fetch_max(tick, Ordering::Relaxed);
+        swapped
+            .reads
+            .last_read
+            .fetch_max(since_epoch, Ordering::Relaxed);
+        swapped.reads.hits.fetch_add(1, Ordering::AcqRel);
+        Some(swapped.value.clone())
+    }
+
+    /// Takes the reads of `key` served from the copy since last taken.
+    pub(crate) fn take_reads<Q>(&self, key: &Q) -> Option<SwapReads>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.published.load().entries.get(key)?.reads.take()
+    }
+}
+
+#[cfg(all(test, loom))]
+mod loom_tests {
+    use std::time::Duration;
+
+    use loom::sync::Mutex;
+    use loom::thread;
+
+    use super::*;
+
+    /// A copy built while a write lands is never served after the write.
+    #[test]
+    fn copies_from_before_a_write_are_not_served() {
+        loom::model(|| {
+            let value = Arc::new(Mutex::new(1));
+            let swap = Arc::new(SwapMap::<u32, u32>::new());
+            let until = Instant::now() + Duration::from_secs(60);
+            let stamp = || (0, 0);
+            let copy =
+                move |value: &Mutex<u32>| HashMap::from([(0, (*value.lock().unwrap(), until))]);
+            let rebuilder = {
+                let (value, swap) = (Arc::clone(&value), Arc::clone(&swap));
+                thread::spawn(move || swap.rebuild(|| copy(&value), |_, _| {}))
+            };
+            let writer = {
+                let (value, swap) = (Arc::clone(&value), Arc::clone(&swap));
+                thread::spawn(move || {
+                    *value.lock().unwrap() = 2;
+                    swap.outdate();
+                })
+            };
+            writer.join().unwrap();
+            assert_ne!(swap.get(&0, Instant::now(), stamp), Some(1));
+            rebuilder.join().unwrap();
+            assert_ne!(swap.get(&0, Instant::now(), stamp), Some(1));
+
+            swap.rebuild(|| copy(&value), |_, _| {});
+            assert_eq!(swap.get(&0, Instant::now(), stamp), Some(2));
+        });
+    }
+}
@@ -0,0 +1,18 @@
+//! The locks behind the shards and flights, swapped for `loom`'s when
+//! model checking with `--cfg loom`:
+//!
+//! ```text
+//! RUSTFLAGS="--cfg loom" cargo test --lib --release --features snapshot-swap loom_tests
+//! ```
+
+#[cfg(loom)]
+pub(crate) use loom::sync::{
+    Condvar, Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard,
+};
+#[cfg(not(loom))]
+pub(crate) use std::sync::{Condvar, Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+#[cfg(all(feature = "snapshot-swap", loom))]
+pub(crate) use loom::sync::atomic::{AtomicBool, AtomicU64};
+#[cfg(all(feature = "snapshot-swap", not(loom)))]
+pub(crate) use std::sync::atomic::{AtomicBool, AtomicU64};
@@ -0,0 +1,575 @@
+//! Helpers for testing code that uses the cache, and the cache itself.
+//!
+//! With the `test-util` feature. [`TTRCache::assert_invariants`] checks the
+//! cache's bookkeeping after each step of a test, [`CountingFetcher`]
+//! records how the cache called the backend, [`ScriptedFetcher`] plays
+//! back a fixed sequence of responses per key, and [`ChaosFetcher`] injects
+//! random failures and latency. Together with a
+//! [`MockClock`](crate::MockClock) they let randomized sequences of
+//! operations check properties such as never serving a value past its hard
+//! expiry:
+//!
+//! ```rust
+//! use std::sync::atomic::{AtomicU64, Ordering};
+//! use std::sync::Arc;
+//! use std::time::Duration;
+//! use ttr_cache::test_util::CountingFetcher;
+//! use ttr_cache::{MockClock, TTRCache, TTRCacheBuilder};
+//!
+//! let clock = MockClock::new();
+//! let now = Arc::new(AtomicU64::new(0));
+//! let backend = Arc::clone(&now);
+//! // Values are the second they were fetched at; odd keys are never found.
+//! let fetcher = Arc::new(CountingFetcher::new(move |key: &u64| {
+//!     (key % 2 == 0).then(|| backend.load(Ordering::SeqCst))
+//! }));
+//! let cache: TTRCache<_, _, _> = TTRCacheBuilder::new(Duration::from_secs(10))
+//!     .max_stale(Duration::from_secs(5))
+//!     .max_capacity(8)
+//!     .clock(clock.clone())
+//!     .build(Arc::clone(&fetcher));
+//!
+//! let mut seed = 0x2545_f491_4f6c_dd1d_u64;
+//! for _ in 0..2000 {
+//!     seed ^= seed << 13;
+//!     seed ^= seed >> 7;
+//!     seed ^= seed << 17;
+//!     let key = seed % 16;
+//!     match (seed >> 8) % 6 {
+//!         0 => cache.insert(key, now.load(Ordering::SeqCst)),
+//!         1 => drop(cache.remove(&key)),
+//!         2 => drop(cache.invalidate(&key)),
+//!         3 => {
+//!             clock.advance(Duration::from_secs(3));
+//!             now.fetch_add(3, Ordering::SeqCst);
+//!         }
+//!         _ => {
+//!             if let Some(fetched_at) = cache.get(&key) {
+//!                 assert!(now.load(Ordering::SeqCst) - fetched_at <= 15);
+//!             }
+//!         }
+//!     }
+//!     cache.assert_invariants();
+//! }
+//! assert!(fetcher.calls() > 0);
+//! assert_eq!(fetcher.max_concurrent(), 1);
+//! ```
+
+use std::collections::hash_map::RandomState;
+use std::collections::{HashMap, VecDeque};
+use std::error::Error as StdError;
+use std::fmt;
+use std::hash::{BuildHasher, Hash, Hasher};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::thread;
+use std::time::Duration;
+
+use crate::context::FetchContext;
+use crate::fetcher::{EntityFetcher, FetchOutcome, TryEntityFetcher};
+
+/// Wraps a fetcher, counting its calls and how many ran at once.
+///
+/// A key fetched by many threads at once should still reach the backend
+/// once, so tests of request coalescing can check
+/// [`max_concurrent`](Self::max_concurrent) and [`calls`](Self::calls).
+pub struct CountingFetcher<F> {
+    inner: F,
+    calls: AtomicUsize,
+    running: AtomicUsize,
+    max_running: AtomicUsize,
+}
+
+impl<F> CountingFetcher<F> {
+    /// Wraps `inner`, with nothing counted yet.
+    pub fn new(inner: F) -> Self {
+        CountingFetcher {
+            inner,
+            calls: AtomicUsize::new(0),
+            running: AtomicUsize::new(0),
+            max_running: AtomicUsize::new(0),
+        }
+    }
+
+    /// The wrapped fetcher.
+    pub fn inner(&self) -> &F {
+        &self.inner
+    }
+
+    /// How many fetches were made.
+    pub fn calls(&self) -> usize {
+        self.calls.load(Ordering::SeqCst)
+    }
+
+    /// The most fetches that ran at the same time.
+    pub fn max_concurrent(&self) -> usize {
+        self.max_running.load(Ordering::SeqCst)
+    }
+
+    fn count<T>(&self, fetch: impl FnOnce() -> T) -> T {
+        self.calls.fetch_add(1, Ordering::SeqCst);
+        let running = self.running.fetch_add(1, Ordering::SeqCst) + 1;
+        self.max_running.fetch_max(running, Ordering::SeqCst);
+        let _running = Running(&self.running);
+        fetch()
+    }
+}
+
+/// Counts a fetch as done when dropped, even if it panics.
+struct Running<'a>(&'a AtomicUsize);
+
+impl Drop for Running<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl<K, V, F: EntityFetcher<K, V>> EntityFetcher<K, V> for CountingFetcher<F> {
+    fn fetch_entity(&self, key: &K) -> Option<V> {
+        self.count(|| self.inner.fetch_entity(key))
+    }
+
+    fn fetch_if_modified(&self, key: &K, current: &V) -> FetchOutcome<V> {
+        self.count(|| self.inner.fetch_if_modified(key, current))
+    }
+
+    fn fetch_with_context(
+        &self,
+        key: &K,
+        current: Option<&V>,
+        context: &FetchContext,
+    ) -> FetchOutcome<V> {
+        self.count(|| self.inner.fetch_with_context(key, current, context))
+    }
+}
+
+/// One step of a [`ScriptedFetcher`]'s script for a key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScriptedResponse<V, E = ()> {
+    /// The entity was found.
+    Value(V),
+    /// The entity was not found.
+    NotFound,
+    /// The fetch failed.
+    Error(E),
+    /// Sleeps, in wall time, then goes on to the next step in the same
+    /// fetch.
+    Delay(Duration),
+    /// The fetcher panics.
+    Panic,
+}
+
+/// A fetcher that plays back pre-programmed responses per key and counts
+/// the fetches of each.
+///
+/// Each fetch of a key takes the next step of its script. Once a script is
+/// down to its last response, that response is repeated; keys without a
+/// script are not found. As an [`EntityFetcher`] a scripted error fails the
+/// fetch the only way that trait can, by panicking; use it as a
+/// [`TryEntityFetcher`], such as through [`TTRCache::try_get`](crate::TTRCache::try_get),
+/// to see the error itself.
+///
+/// ```rust
+/// use std::sync::Arc;
+/// use std::time::Duration;
+/// use ttr_cache::test_util::{ScriptedFetcher, ScriptedResponse};
+/// use ttr_cache::{MockClock, TTRCache, TTRCacheBuilder};
+///
+/// let fetcher = Arc::new(ScriptedFetcher::new().script(
+///     "config",
+///     [
+///         ScriptedResponse::Value(1),
+///         ScriptedResponse::Error("backend down"),
+///         ScriptedResponse::Value(2),
+///     ],
+/// ));
+/// let clock = MockClock::new();
+/// let cache: TTRCache<_, _, _> = TTRCacheBuilder::new(Duration::from_secs(60))
+///     .clock(clock.clone())
+///     .build(Arc::clone(&fetcher));
+///
+/// assert_eq!(cache.try_get(&"config"), Ok(Some(1)));
+/// clock.advance(Duration::from_secs(61));
+/// let error = cache.try_get(&"config").unwrap_err();
+/// assert_eq!((error.error, error.stale), ("backend down", Some(1)));
+/// assert_eq!(cache.try_get(&"config"), Ok(Some(2)));
+/// assert_eq!(fetcher.calls(&"config"), 3);
+/// assert_eq!(cache.try_get(&"other"), Ok(None));
+/// ```
+pub struct ScriptedFetcher<K, V, E = ()> {
+    scripts: Mutex<HashMap<K, VecDeque<ScriptedResponse<V, E>>>>,
+    calls: Mutex<HashMap<K, usize>>,
+}
+
+impl<K, V, E> ScriptedFetcher<K, V, E>
+where
+    K: Eq + Hash + Clone,
+    V: Clone,
+    E: Clone,
+{
+    /// Creates a fetcher with no scripts, finding nothing.
+    pub fn new() -> Self {
+        ScriptedFetcher {
+            scripts: Mutex::new(HashMap::new()),
+            calls: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Appends `responses` to the script of `key`.
+    pub fn script(
+        self,
+        key: K,
+        responses: impl IntoIterator<Item = ScriptedResponse<V, E>>,
+    ) -> Self {
+        self.push(key, responses);
+        self
+    }
+
+    /// Appends `responses` to the script of `key`, once the fetcher is in
+    /// use.
+    pub fn push(&self, key: K, responses: impl IntoIterator<Item = ScriptedResponse<V, E>>) {
+        self.scripts().entry(key).or_default().extend(responses);
+    }
+
+    /// How many times `key` was fetched.
+    pub fn calls(&self, key: &K) -> usize {
+        self.calls
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(key)
+            .copied()
+            .unwrap_or(0)
+    }
+
+    /// How many fetches were made, of any key.
+    pub fn total_calls(&self) -> usize {
+        self.calls
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .values()
+            .sum()
+    }
+
+    fn scripts(&self) -> MutexGuard<'_, HashMap<K, VecDeque<ScriptedResponse<V, E>>>> {
+        self.scripts.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Plays the next steps of `key`'s script.
+    fn respond(&self, key: &K) -> Result<Option<V>, E> {
+        *self
+            .calls
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .entry(key.clone())
+            .or_default() += 1;
+        loop {
+            let step = match self.scripts().get_mut(key) {
+                Some(script)
+                    if script.len() > 1
+                        || matches!(script.front(), Some(ScriptedResponse::Delay(_))) =>
+                {
+                    script.pop_front()
+                }
+                Some(script) => script.front().cloned(),
+                None => None,
+            };
+            match step {
+                Some(ScriptedResponse::Value(value)) => return Ok(Some(value)),
+                Some(ScriptedResponse::NotFound) | None => return Ok(None),
+                Some(ScriptedResponse::Error(error)) => return Err(error),
+                Some(ScriptedResponse::Delay(delay)) => thread::sleep(delay),
+                Some(ScriptedResponse::Panic) => panic!("scripted fetcher panic"),
+            }
+        }
+    }
+}
+
+impl<K, V, E> Default for ScriptedFetcher<K, V, E>
+where
+    K: Eq + Hash + Clone,
+    V: Clone,
+    E: Clone,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K, V, E> EntityFetcher<K, V> for ScriptedFetcher<K, V, E>
+where
+    K: Eq + Hash + Clone,
+    V: Clone,
+    E: Clone,
+{
+    fn fetch_entity(&self, key: &K) -> Option<V> {
+        match self.respond(key) {
+            Ok(entity) => entity,
+            Err(_) => panic!("scripted fetch error"),
+        }
+    }
+}
+
+impl<K, V, E> TryEntityFetcher<K, V, E> for ScriptedFetcher<K, V, E>
+where
+    K: Eq + Hash + Clone,
+    V: Clone,
+    E: Clone,
+{
+    fn try_fetch_entity(&self, key: &K) -> Result<Option<V>, E> {
+        self.respond(key)
+    }
+}
+
+/// How long a [`ChaosFetcher`] holds up each fetch, in wall time.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Latency {
+    /// Every fetch takes this long.
+    Fixed(Duration),
+    /// Each fetch takes a random time between the two, uniformly.
+    Uniform(Duration, Duration),
+    /// Each fetch takes `base`, or `spike` for a random share of them given
+    /// by `rate`, between 0 and 1.
+    Spikes {
+        /// The usual latency.
+        base: Duration,
+        /// The latency of a spike.
+        spike: Duration,
+        /// The share of fetches that spike.
+        rate: f64,
+    },
+}
+
+/// The error a [`ChaosFetcher`] fails a fallible fetch with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InjectedFault;
+
+impl fmt::Display for InjectedFault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("injected fetch failure")
+    }
+}
+
+impl StdError for InjectedFault {}
+
+/// Wraps a fetcher, failing a random share of its fetches and delaying
+/// them, to check that services degrade gracefully when the backend
+/// misbehaves.
+///
+/// A failed fetch does not reach the wrapped fetcher. As an
+/// [`EntityFetcher`] it finds nothing, which the cache treats as a failed
+/// refresh; as a [`TryEntityFetcher`] it returns the error converted from
+/// [`InjectedFault`]. Latency is added before each fetch, failed or not.
+///
+/// Faults can be changed while the cache uses the fetcher, by sharing it in
+/// an `Arc`: [`set_outage`](Self::set_outage) fails every fetch, as if the
+/// backend were down, until it is turned off again. Random draws come from
+/// a [`seed`](Self::seed) if given, so runs on one thread repeat.
+///
+/// ```rust
+/// use std::sync::Arc;
+/// use std::time::Duration;
+/// use ttr_cache::test_util::{ChaosFetcher, Latency};
+/// use ttr_cache::{CacheLookup, MockClock, TTRCache, TTRCacheBuilder};
+///
+/// let backend = Arc::new(
+///     ChaosFetcher::new(|id: &u32| Some(id * 10)).latency(Latency::Fixed(Duration::from_millis(1))),
+/// );
+/// let clock = MockClock::new();
+/// let cache: TTRCache<_, _, _> = TTRCacheBuilder::new(Duration::from_secs(60))
+///     .clock(clock.clone())
+///     .build(Arc::clone(&backend));
+/// assert_eq!(cache.get(&1), Some(10));
+///
+/// // While the backend is down, the stale value keeps being served.
+/// backend.set_outage(true);
+/// clock.advance(Duration::from_secs(90));
+/// assert_eq!(cache.get(&1), Some(10));
+/// assert_eq!(cache.get(&2), None);
+/// assert_eq!(backend.injected_failures(), 2);
+///
+/// backend.set_outage(false);
+/// assert_eq!(cache.get(&1), Some(10));
+/// assert_eq!(cache.lookup(&1), CacheLookup::Fresh(10));
+/// assert_eq!(cache.get(&2), Some(20));
+/// ```
+pub struct ChaosFetcher<F> {
+    inner: F,
+    /// The failure rate, as `f64` bits.
+    failure_rate: AtomicU64,
+    latency: Mutex<Option<Latency>>,
+    outage: AtomicBool,
+    rng: AtomicU64,
+    injected: AtomicUsize,
+}
+
+impl<F> ChaosFetcher<F> {
+    /// Wraps `inner`, injecting no faults yet.
+    pub fn new(inner: F) -> Self {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u64(0);
+        ChaosFetcher {
+            inner,
+            failure_rate: AtomicU64::new(0f64.to_bits()),
+            latency: Mutex::new(None),
+            outage: AtomicBool::new(false),
+            rng: AtomicU64::new(hasher.finish() | 1),
+            injected: AtomicUsize::new(0),
+        }
+    }
+
+    /// Fails a random share of fetches, between 0 and 1.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `rate` is not between 0 and 1.
+    pub fn failure_rate(self, rate: f64) -> Self {
+        self.set_failure_rate(rate);
+        self
+    }
+
+    /// Delays every fetch by `latency`.
+    pub fn latency(self, latency: Latency) -> Self {
+        self.set_latency(Some(latency));
+        self
+    }
+
+    /// Draws failures and latencies from `seed`, for repeatable runs.
+    pub fn seed(self, seed: u64) -> Self {
+        self.rng.store(seed | 1, Ordering::Relaxed);
+        self
+    }
+
+    /// Changes the failure rate, once the fetcher is in use.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `rate` is not between 0 and 1.
+    pub fn set_failure_rate(&self, rate: f64) {
+        assert!(
+            (0.0..=1.0).contains(&rate),
+            "a failure rate is between 0 and 1"
+        );
+        self.failure_rate.store(rate.to_bits(), Ordering::Relaxed);
+    }
+
+    /// Changes the latency, or removes it with `None`, once the fetcher is
+    /// in use.
+    pub fn set_latency(&self, latency: Option<Latency>) {
+        *self.latency.lock().unwrap_or_else(PoisonError::into_inner) = latency;
+    }
+
+    /// Fails every fetch while `down`, whatever the failure rate.
+    pub fn set_outage(&self, down: bool) {
+        self.outage.store(down, Ordering::Relaxed);
+    }
+
+    /// How many fetches were failed on purpose.
+    pub fn injected_failures(&self) -> usize {
+        self.injected.load(Ordering::SeqCst)
+    }
+
+    /// The wrapped fetcher.
+    pub fn inner(&self) -> &F {
+        &self.inner
+    }
+
+    /// A random number in `[0, 1)`, by xorshift.
+    fn random_unit(&self) -> f64 {
+        let next = |mut state: u64| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+        let previous = self
+            .rng
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |state| {
+                Some(next(state))
+            })
+            .unwrap_or_else(|state| state);
+        (next(previous) >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Waits out the latency, then tells whether this fetch should fail.
+    fn disrupt(&self) -> bool {
+        let latency = *self.latency.lock().unwrap_or_else(PoisonError::into_inner);
+        let delay = match latency {
+            None => Duration::ZERO,
+            Some(Latency::Fixed(delay)) => delay,
+            Some(Latency::Uniform(low, high)) => {
+                low + high.saturating_sub(low).mul_f64(self.random_unit())
+            }
+            Some(Latency::Spikes { spike, rate, .. }) if self.random_unit() < rate => spike,
+            Some(Latency::Spikes { base, .. }) => base,
+        };
+        if !delay.is_zero() {
+            thread::sleep(delay);
+        }
+        let rate = f64::from_bits(self.failure_rate.load(Ordering::Relaxed));
+        let failed = self.outage.load(Ordering::Relaxed) || self.random_unit() < rate;
+        if failed {
+            self.injected.fetch_add(1, Ordering::SeqCst);
+        }
+        failed
+    }
+}
+
+impl<K, V, F: EntityFetcher<K, V>> EntityFetcher<K, V> for ChaosFetcher<F> {
+    fn fetch_entity(&self, key: &K) -> Option<V> {
+        if self.disrupt() {
+            return None;
+        }
+        self.inner.fetch_entity(key)
+    }
+
+    fn fetch_if_modified(&self, key: &K, current: &V) -> FetchOutcome<V> {
+        if self.disrupt() {
+            return FetchOutcome::NotFound;
+        }
+        self.inner.fetch_if_modified(key, current)
+    }
+
+    fn fetch_with_context(
+        &self,
+        key: &K,
+        current: Option<&V>,
+        context: &FetchContext,
+    ) -> FetchOutcome<V> {
+        if self.disrupt() {
+            return FetchOutcome::NotFound;
+        }
+        self.inner.fetch_with_context(key, current, context)
+    }
+}
+
+impl<K, V, E, F> TryEntityFetcher<K, V, E> for ChaosFetcher<F>
+where
+    F: TryEntityFetcher<K, V, E>,
+    E: From<InjectedFault>,
+{
+    fn try_fetch_entity(&self, key: &K) -> Result<Option<V>, E> {
+        if self.disrupt() {
+            return Err(InjectedFault.into());
+        }
+        self.inner.try_fetch_entity(key)
+    }
+
+    fn try_fetch_if_modified(&self, key: &K, current: &V) -> Result<FetchOutcome<V>, E> {
+        if self.disrupt() {
+            return Err(InjectedFault.into());
+        }
+        self.inner.try_fetch_if_modified(key, current)
+    }
+
+    fn try_fetch_with_context(
+        &self,
+        key: &K,
+        current: Option<&V>,
+        context: &FetchContext,
+    ) -> Result<FetchOutcome<V>, E> {
+        if self.disrupt() {
+            return Err(InjectedFault.into());
+        }
+        self.inner.try_fetch_with_context(key, current, context)
+    }
+}
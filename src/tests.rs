@@ -0,0 +1,162 @@
+//! Property tests of the cache's expiry and capacity invariants.
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use proptest::prelude::*;
+
+use crate::{MockClock, StaleIfError, TTRCache, TTRCacheBuilder};
+
+#[derive(Debug, Clone)]
+enum Op {
+    Get(u64),
+    Insert(u64),
+    Remove(u64),
+    Invalidate(u64),
+    Advance(u64),
+}
+
+fn ops() -> impl Strategy<Value = Vec<Op>> {
+    let op = prop_oneof![
+        3 => (0..16u64).prop_map(Op::Get),
+        1 => (0..16u64).prop_map(Op::Insert),
+        1 => (0..16u64).prop_map(Op::Remove),
+        1 => (0..16u64).prop_map(Op::Invalidate),
+        1 => (1..8u64).prop_map(Op::Advance),
+    ];
+    prop::collection::vec(op, 1..200)
+}
+
+/// A cache whose values are the second they were fetched or inserted at,
+/// served for at most 5 seconds past their TTR.
+fn timestamps(
+    capacity: usize,
+    configure: impl FnOnce(TTRCacheBuilder<u64, u64>) -> TTRCacheBuilder<u64, u64>,
+) -> (
+    TTRCache<u64, u64, impl crate::EntityFetcher<u64, u64>>,
+    MockClock,
+    Arc<AtomicU64>,
+) {
+    timestamps_with(move |builder| {
+        configure(
+            builder
+                .max_stale(Duration::from_secs(5))
+                .max_capacity(capacity),
+        )
+    })
+}
+
+/// A cache like [`timestamps`]' with only the settings `configure` gives it.
+fn timestamps_with(
+    configure: impl FnOnce(TTRCacheBuilder<u64, u64>) -> TTRCacheBuilder<u64, u64>,
+) -> (
+    TTRCache<u64, u64, impl crate::EntityFetcher<u64, u64>>,
+    MockClock,
+    Arc<AtomicU64>,
+) {
+    let clock = MockClock::new();
+    let now = Arc::new(AtomicU64::new(0));
+    let backend = Arc::clone(&now);
+    let builder = TTRCacheBuilder::new(Duration::from_secs(10)).clock(clock.clone());
+    let cache = configure(builder)
+        .build(move |key: &u64| (!key.is_multiple_of(3)).then(|| backend.load(Ordering::SeqCst)));
+    (cache, clock, now)
+}
+
+fn apply(
+    cache: &TTRCache<u64, u64, impl crate::EntityFetcher<u64, u64>>,
+    clock: &MockClock,
+    now: &AtomicU64,
+    op: &Op,
+) -> Option<u64> {
+    match *op {
+        Op::Get(key) => return cache.get(&key),
+        Op::Insert(key) => cache.insert(key, now.load(Ordering::SeqCst)),
+        Op::Remove(key) => drop(cache.remove(&key)),
+        Op::Invalidate(key) => drop(cache.invalidate(&key)),
+        Op::Advance(secs) => {
+            clock.advance(Duration::from_secs(secs));
+            now.fetch_add(secs, Ordering::SeqCst);
+        }
+    }
+    None
+}
+
+proptest! {
+    /// No value is served past its TTR and stale window.
+    #[test]
+    fn never_serves_past_hard_expiry(ops in ops()) {
+        let (cache, clock, now) = timestamps(64, |builder| builder);
+        for op in &ops {
+            if let Some(written_at) = apply(&cache, &clock, &now, op) {
+                prop_assert!(now.load(Ordering::SeqCst) - written_at <= 15);
+            }
+            #[cfg(feature = "test-util")]
+            cache.assert_invariants();
+        }
+    }
+
+    /// The cache never holds more entries than its capacity.
+    #[test]
+    fn never_exceeds_capacity(capacity in 1..8usize, ops in ops()) {
+        let (cache, clock, now) = timestamps(capacity, |builder| builder);
+        for op in &ops {
+            apply(&cache, &clock, &now, op);
+            prop_assert!(cache.len() <= capacity);
+        }
+    }
+
+    /// Ordered storage holds the same entries as the default maps, and
+    /// serves ranges of them in order.
+    #[test]
+    fn ordered_storage_matches_hashed(ops in ops(), from in 0..16u64) {
+        let (hashed, hashed_clock, hashed_now) = timestamps(64, |builder| builder.shards(4));
+        let (ordered, ordered_clock, ordered_now) =
+            timestamps(64, |builder| builder.shards(4).storage(BTreeMap::new));
+        for op in &ops {
+            prop_assert_eq!(
+                apply(&hashed, &hashed_clock, &hashed_now, op),
+                apply(&ordered, &ordered_clock, &ordered_now, op),
+            );
+        }
+        let mut expected: Vec<_> = hashed.iter().filter(|(key, _)| *key >= from).collect();
+        expected.sort_unstable();
+        prop_assert_eq!(ordered.range(from..).collect::<Vec<_>>(), expected);
+    }
+
+    /// `len` counts the entries `iter` yields, whichever setting expires
+    /// them before they are removed.
+    #[test]
+    fn len_matches_iter(ops in ops(), setting in 0..4u8) {
+        let (cache, clock, now) = timestamps_with(|builder| match setting {
+            0 => builder.max_stale(Duration::from_secs(5)),
+            1 => builder.expire_after_idle(Duration::from_secs(5)),
+            2 => builder.stale_if_error(StaleIfError::new(Duration::from_secs(5)).max_failures(2)),
+            _ => builder,
+        });
+        for op in &ops {
+            apply(&cache, &clock, &now, op);
+            prop_assert_eq!(cache.len(), cache.iter().count());
+        }
+    }
+
+    /// `len` only counts the weakly held values still alive, as `iter` does.
+    #[test]
+    fn len_matches_iter_with_weak_values(held in prop::collection::vec(any::<bool>(), 1..32)) {
+        let cache: TTRCache<usize, Arc<usize>, ()> = TTRCacheBuilder::new(Duration::from_secs(10))
+            .weak_values()
+            .build_cache_aside();
+        let mut alive = Vec::new();
+        for (key, &keep) in held.iter().enumerate() {
+            let value = Arc::new(key);
+            cache.insert(key, Arc::clone(&value));
+            if keep {
+                alive.push(value);
+            }
+        }
+        prop_assert_eq!(cache.len(), alive.len());
+        prop_assert_eq!(cache.len(), cache.iter().count());
+    }
+}
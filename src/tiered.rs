@@ -0,0 +1,68 @@
+//! Second storage tier behind the in-memory map.
+
+use crate::snapshot::PersistedEntry;
+
+/// Storage consulted when a key is not in memory, before the fetcher.
+///
+/// Typically backed by something that outlives the process or is shared
+/// between instances, such as Redis, an embedded database or files.
+/// Entries carry their fetch time and TTR, so they keep aging while stored.
+///
+/// ```rust
+/// use std::collections::HashMap;
+/// use std::sync::{Arc, Mutex};
+/// use std::time::Duration;
+/// use ttr_cache::{CacheStore, EntityFetcher, PersistedEntry, TTRCache, TTRCacheBuilder};
+///
+/// #[derive(Clone, Default)]
+/// struct Shelf(Arc<Mutex<HashMap<u32, PersistedEntry<u32, String>>>>);
+///
+/// impl CacheStore<u32, String> for Shelf {
+///     fn load(&self, key: &u32) -> Option<PersistedEntry<u32, String>> {
+///         self.0.lock().unwrap().get(key).cloned()
+///     }
+///
+///     fn store(&self, entry: PersistedEntry<u32, String>) {
+///         self.0.lock().unwrap().insert(entry.key, entry);
+///     }
+///
+///     fn remove(&self, key: &u32) {
+///         self.0.lock().unwrap().remove(key);
+///     }
+/// }
+///
+/// struct Backend;
+///
+/// impl EntityFetcher<u32, String> for Backend {
+///     fn fetch_entity(&self, key: &u32) -> Option<String> {
+///         Some(key.to_string())
+///     }
+/// }
+///
+/// let shelf = Shelf::default();
+/// let first: TTRCache<_, _, _> = TTRCacheBuilder::new(Duration::from_secs(60))
+///     .second_tier(shelf.clone())
+///     .build(Backend);
+/// first.get(&7);
+///
+/// let second: TTRCache<_, _, _> = TTRCacheBuilder::new(Duration::from_secs(60))
+///     .second_tier(shelf)
+///     .build(Backend);
+/// assert_eq!(second.get(&7), Some("7".to_string()));
+/// assert_eq!(second.stats().refreshes, 0);
+/// ```
+pub trait CacheStore<K, V> {
+    /// Returns the stored entry for `key`, if any.
+    fn load(&self, key: &K) -> Option<PersistedEntry<K, V>>;
+
+    /// Stores an entry, replacing any previous one for its key.
+    fn store(&self, entry: PersistedEntry<K, V>);
+
+    /// Removes the entry for `key`, if any.
+    fn remove(&self, key: &K);
+
+    /// Writes out anything buffered, on shutdown.
+    ///
+    /// Defaults to doing nothing, for stores that write synchronously.
+    fn flush(&self) {}
+}
@@ -0,0 +1,11 @@
+//! Time types used throughout the cache.
+//!
+//! These are the `std::time` types, except on `wasm32-unknown-unknown`,
+//! where `std::time` panics and [`web-time`](https://docs.rs/web-time)'s
+//! browser-backed equivalents are used instead. A [`Clock`](crate::Clock)
+//! implementation should name them through this module to build everywhere.
+
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+pub use std::time::{Instant, SystemTime};
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+pub use web_time::{Instant, SystemTime};
@@ -0,0 +1,24 @@
+//! Support for the `tracing` feature.
+
+use std::fmt;
+
+/// Formats keys in trace output, set by `TTRCacheBuilder::trace_keys`.
+pub(crate) type KeyFormatter<K> = fn(&K, &mut fmt::Formatter<'_>) -> fmt::Result;
+
+/// A key as a trace field.
+///
+/// Keys are not required to implement `Debug`, so without a formatter the
+/// field only shows that a key is there.
+pub(crate) struct TraceKey<'a, K> {
+    pub(crate) key: &'a K,
+    pub(crate) format: Option<KeyFormatter<K>>,
+}
+
+impl<K> fmt::Debug for TraceKey<'_, K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.format {
+            Some(format) => format(self.key, f),
+            None => f.write_str("_"),
+        }
+    }
+}
@@ -0,0 +1,84 @@
+//! Summary of a cache warm-up or bulk refresh.
+
+use crate::fetcher::FetchOutcome;
+
+/// Which keys a warm-up or bulk refresh managed to load.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct WarmReport<K> {
+    /// Keys that are cached afterwards.
+    pub loaded: Vec<K>,
+    /// Keys the fetcher found nothing for.
+    pub missing: Vec<K>,
+}
+
+impl<K> WarmReport<K> {
+    pub(crate) fn new() -> Self {
+        WarmReport {
+            loaded: Vec::new(),
+            missing: Vec::new(),
+        }
+    }
+
+    pub(crate) fn record(&mut self, key: K, loaded: bool) {
+        if loaded {
+            self.loaded.push(key);
+        } else {
+            self.missing.push(key);
+        }
+    }
+
+    pub(crate) fn merge(&mut self, other: WarmReport<K>) {
+        self.loaded.extend(other.loaded);
+        self.missing.extend(other.missing);
+    }
+
+    /// Returns `true` if every key was loaded.
+    pub fn is_complete(&self) -> bool {
+        self.missing.is_empty()
+    }
+}
+
+/// How the refresh of one key in a bulk refresh went.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum KeyRefresh {
+    /// A new value was fetched and stored.
+    Refreshed,
+    /// The fetcher reported the cached value still current.
+    Unchanged,
+    /// The fetch failed, panicked, timed out, was rejected by the validator,
+    /// or was not made because of the circuit breaker or minimum fetch
+    /// interval.
+    Failed,
+    /// The fetcher found nothing for the key.
+    Missing,
+}
+
+/// The outcome of refreshing one key, reported by
+/// [`TTRCache::refresh_keys`](crate::TTRCache::refresh_keys) as each key
+/// finishes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RefreshReport<K> {
+    /// The key refreshed.
+    pub key: K,
+    /// How it went.
+    pub outcome: KeyRefresh,
+}
+
+impl KeyRefresh {
+    /// The outcome of a fetch that returned `fetched`, with `validates`
+    /// checking a new value.
+    pub(crate) fn fetched<V>(
+        fetched: &FetchOutcome<V>,
+        validates: impl FnOnce(&V) -> bool,
+    ) -> Self {
+        match fetched {
+            FetchOutcome::Modified(value) if validates(value) => KeyRefresh::Refreshed,
+            FetchOutcome::Modified(_) => KeyRefresh::Failed,
+            FetchOutcome::NotModified => KeyRefresh::Unchanged,
+            FetchOutcome::NotFound => KeyRefresh::Missing,
+        }
+    }
+}
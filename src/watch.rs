@@ -0,0 +1,112 @@
+//! Latest-value watches on single keys.
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::task::{Context, Poll, Waker};
+
+struct State<V> {
+    value: Option<V>,
+    /// Bumped on every new value; a watch yields when it has not seen it.
+    version: u64,
+    waker: Option<Waker>,
+    closed: bool,
+}
+
+/// The store's end of a [`Watch`].
+pub(crate) struct WatchSlot<V> {
+    state: Mutex<State<V>>,
+}
+
+impl<V> WatchSlot<V> {
+    fn lock(&self) -> MutexGuard<'_, State<V>> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn wake(mut state: MutexGuard<'_, State<V>>) {
+        let waker = state.waker.take();
+        drop(state);
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+
+    /// Publishes a new value.
+    pub(crate) fn publish(&self, value: V) {
+        let mut state = self.lock();
+        state.value = Some(value);
+        state.version += 1;
+        Self::wake(state);
+    }
+
+    /// Ends the watch once the cache is gone.
+    pub(crate) fn close(&self) {
+        let mut state = self.lock();
+        state.closed = true;
+        Self::wake(state);
+    }
+}
+
+/// The value of one key as it changes, returned by `watch()`.
+///
+/// Only the latest value is kept: a watch that falls behind skips the values
+/// in between.
+pub struct Watch<V> {
+    slot: Arc<WatchSlot<V>>,
+    seen: u64,
+}
+
+impl<V: Clone> Watch<V> {
+    pub(crate) fn new(current: Option<V>) -> (Self, Arc<WatchSlot<V>>) {
+        let slot = Arc::new(WatchSlot {
+            state: Mutex::new(State {
+                version: u64::from(current.is_some()),
+                value: current,
+                waker: None,
+                closed: false,
+            }),
+        });
+        let watch = Watch {
+            slot: Arc::clone(&slot),
+            seen: 0,
+        };
+        (watch, slot)
+    }
+
+    /// Waits for a value this watch has not yielded yet.
+    ///
+    /// The first call yields the value cached when the watch was created, if
+    /// any. Resolves to `None` once the cache is dropped.
+    pub fn changed(&mut self) -> impl Future<Output = Option<V>> + '_ {
+        Changed { watch: self }
+    }
+
+    /// The latest value, without waiting or marking it as seen.
+    pub fn latest(&self) -> Option<V> {
+        self.slot.lock().value.clone()
+    }
+}
+
+struct Changed<'a, V> {
+    watch: &'a mut Watch<V>,
+}
+
+impl<V: Clone> Future for Changed<'_, V> {
+    type Output = Option<V>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<V>> {
+        let mut state = self.watch.slot.lock();
+        if state.version != self.watch.seen {
+            let version = state.version;
+            let value = state.value.clone();
+            drop(state);
+            self.watch.seen = version;
+            return Poll::Ready(value);
+        }
+        if state.closed {
+            return Poll::Ready(None);
+        }
+        state.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
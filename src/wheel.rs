@@ -0,0 +1,230 @@
+//! A hierarchical timer wheel for keys refreshed on a schedule.
+
+use std::collections::HashSet;
+use std::hash::Hash;
+use std::sync::{Condvar, Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+
+use crate::time::Instant;
+
+/// How far the wheel turns per step, the resolution of scheduled refreshes.
+pub(crate) const TICK: Duration = Duration::from_millis(100);
+
+/// Slots per level, as a power of two.
+const BITS: u32 = 6;
+const SLOTS: usize = 1 << BITS;
+
+/// Levels of slots, each a turn of the one below: the first covers 6.4
+/// seconds in ticks, the last about 19 days in 5-hour slots. Later timers
+/// wait in the last level and are filed again as it turns.
+const LEVELS: usize = 4;
+
+/// Keys due at a tick, each filed under a slot for its deadline: in the
+/// first level if it is due within a turn of it, or else in the level whose
+/// slots are as coarse as it is far off. When the wheel enters a slot above
+/// the first, the timers in it are filed again, into finer slots.
+///
+/// Scheduling and expiring a timer take constant time, however many are
+/// scheduled, and the wheel can tell when the next one is due.
+pub(crate) struct TimerWheel<K> {
+    levels: Vec<Vec<Vec<Timer<K>>>>,
+    /// The last tick the wheel turned to.
+    current: u64,
+    /// Keys with a timer, so each is scheduled at most once.
+    scheduled: HashSet<K>,
+}
+
+struct Timer<K> {
+    key: K,
+    /// The tick it fires at.
+    deadline: u64,
+}
+
+impl<K> TimerWheel<K> {
+    pub(crate) fn new() -> Self {
+        TimerWheel {
+            levels: (0..LEVELS)
+                .map(|_| (0..SLOTS).map(|_| Vec::new()).collect())
+                .collect(),
+            current: 0,
+            scheduled: HashSet::new(),
+        }
+    }
+
+    /// The slot a tick falls in at `level`.
+    fn slot(level: usize, tick: u64) -> usize {
+        ((tick >> (BITS * level as u32)) as usize) & (SLOTS - 1)
+    }
+
+    /// Files a timer under the slot for its deadline.
+    fn file(&mut self, timer: Timer<K>) {
+        let deadline = timer.deadline.max(self.current + 1);
+        let far = deadline - self.current;
+        let level = (1..LEVELS)
+            .take_while(|&level| far >= 1 << (BITS * level as u32))
+            .last()
+            .unwrap_or(0);
+        // Beyond the last level, the timer waits in its furthest slot.
+        let span = 1 << (BITS * LEVELS as u32);
+        let filed_at = deadline.min(self.current + span - 1);
+        self.levels[level][Self::slot(level, filed_at)].push(timer);
+    }
+
+    /// The tick the next timer is due at or filed again at, if any.
+    pub(crate) fn next_due(&self) -> Option<u64> {
+        (0..LEVELS)
+            .filter_map(|level| {
+                let shift = BITS * level as u32;
+                let base = self.current >> shift;
+                (1..=SLOTS as u64)
+                    .map(|ahead| base + ahead)
+                    .find(|&slot| !self.levels[level][Self::slot(0, slot)].is_empty())
+                    .map(|slot| slot << shift)
+            })
+            .min()
+    }
+}
+
+impl<K: Eq + Hash + Clone> TimerWheel<K> {
+    /// Makes `key` due at tick `deadline`, unless it already has a timer.
+    ///
+    /// Returns whether it was scheduled.
+    pub(crate) fn schedule(&mut self, key: K, deadline: u64) -> bool {
+        if !self.scheduled.insert(key.clone()) {
+            return false;
+        }
+        self.file(Timer { key, deadline });
+        true
+    }
+
+    /// Turns the wheel to tick `now`, returning the keys that came due.
+    pub(crate) fn advance(&mut self, now: u64) -> Vec<K> {
+        let mut due = Vec::new();
+        while self.current < now {
+            // Nothing can come due before the next slot with timers.
+            let Some(next) = self.next_due().filter(|&next| next <= now) else {
+                self.current = now;
+                break;
+            };
+            self.current = next;
+            // Coarser slots entered at this tick are filed again first.
+            let mut timers = Vec::new();
+            for level in (0..LEVELS).rev() {
+                let shift = BITS * level as u32;
+                if self.current & ((1 << shift) - 1) != 0 {
+                    continue;
+                }
+                let slot = &mut self.levels[level][Self::slot(level, self.current)];
+                timers.append(slot);
+            }
+            for timer in timers {
+                if timer.deadline <= self.current {
+                    self.scheduled.remove(&timer.key);
+                    due.push(timer.key);
+                } else {
+                    self.file(timer);
+                }
+            }
+        }
+        due
+    }
+}
+
+/// A timer wheel turned by a thread that sleeps until the next timer is
+/// due, or until an earlier one is scheduled.
+pub(crate) struct Timers<K> {
+    wheel: Mutex<TimerWheel<K>>,
+    woken: Condvar,
+    /// The wall time of tick zero.
+    origin: Instant,
+    /// The tick the turning thread sleeps until, if it is asleep.
+    sleeping_until: Mutex<Option<u64>>,
+}
+
+impl<K> Timers<K> {
+    pub(crate) fn new() -> Self {
+        Timers {
+            wheel: Mutex::new(TimerWheel::new()),
+            woken: Condvar::new(),
+            origin: Instant::now(),
+            sleeping_until: Mutex::new(None),
+        }
+    }
+
+    fn wheel(&self) -> MutexGuard<'_, TimerWheel<K>> {
+        self.wheel.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn sleeping_until(&self) -> MutexGuard<'_, Option<u64>> {
+        self.sleeping_until
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// The tick of the current wall time.
+    fn now(&self) -> u64 {
+        let elapsed = Instant::now().saturating_duration_since(self.origin);
+        u64::try_from(elapsed.as_nanos() / TICK.as_nanos()).unwrap_or(u64::MAX)
+    }
+
+    /// Wakes the turning thread, so it can notice it should stop.
+    pub(crate) fn wake(&self) {
+        let _wheel = self.wheel();
+        self.woken.notify_all();
+    }
+}
+
+impl<K: Eq + Hash + Clone> Timers<K> {
+    /// Makes `key` due `after` from now, rounded up to ticks, unless it
+    /// already has a timer.
+    pub(crate) fn schedule(&self, key: K, after: Duration) {
+        let ticks = u64::try_from(after.as_nanos().div_ceil(TICK.as_nanos()))
+            .unwrap_or(u64::MAX)
+            .max(1);
+        let deadline = self.now().saturating_add(ticks);
+        let mut wheel = self.wheel();
+        if !wheel.schedule(key, deadline) {
+            return;
+        }
+        if self
+            .sleeping_until()
+            .is_some_and(|sleeping_until| deadline < sleeping_until)
+        {
+            self.woken.notify_all();
+        }
+    }
+
+    /// Waits until some keys are due and returns them, or returns `None`
+    /// once `stopped` is true after a [`wake`](Self::wake).
+    pub(crate) fn wait_due(&self, stopped: impl Fn() -> bool) -> Option<Vec<K>> {
+        let mut wheel = self.wheel();
+        loop {
+            if stopped() {
+                return None;
+            }
+            let now = self.now();
+            let due = wheel.advance(now);
+            if !due.is_empty() {
+                return Some(due);
+            }
+            let next = wheel.next_due();
+            *self.sleeping_until() = Some(next.unwrap_or(u64::MAX));
+            wheel = match next {
+                Some(next) => {
+                    let at = TICK.saturating_mul(u32::try_from(next).unwrap_or(u32::MAX));
+                    let timeout =
+                        at.saturating_sub(Instant::now().saturating_duration_since(self.origin));
+                    self.woken
+                        .wait_timeout(wheel, timeout)
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .0
+                }
+                None => self
+                    .woken
+                    .wait(wheel)
+                    .unwrap_or_else(PoisonError::into_inner),
+            };
+            *self.sleeping_until() = None;
+        }
+    }
+}
@@ -0,0 +1,197 @@
+//! Asynchronous persistence of refreshed values to a durable store.
+
+use std::collections::HashMap;
+use std::error::Error as StdError;
+use std::hash::Hash;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::thread;
+use std::time::Duration;
+
+use crate::cache::THREADS;
+use crate::time::Instant;
+
+/// Attempts at writing a batch before it is dropped.
+const MAX_ATTEMPTS: u32 = 5;
+
+/// Durable storage written to after refreshes, off the lookup path.
+///
+/// Unlike a [`CacheStore`](crate::CacheStore), which is written through and
+/// read back, a write-behind sink only receives values: each successful
+/// refresh queues its value, and a worker thread hands the queue to
+/// [`write`](Self::write) in batches. See
+/// [`TTRCacheBuilder::write_behind`](crate::TTRCacheBuilder::write_behind).
+///
+/// ```rust
+/// use std::error::Error;
+/// use std::sync::{Arc, Mutex};
+/// use std::time::Duration;
+/// use ttr_cache::{TTRCache, TTRCacheBuilder, WriteBehind};
+///
+/// #[derive(Clone, Default)]
+/// struct Archive(Arc<Mutex<Vec<(u32, u64)>>>);
+///
+/// impl WriteBehind<u32, u64> for Archive {
+///     fn write(&self, entries: &[(u32, u64)]) -> Result<(), Box<dyn Error + Send + Sync>> {
+///         self.0.lock().unwrap().extend_from_slice(entries);
+///         Ok(())
+///     }
+/// }
+///
+/// let archive = Archive::default();
+/// let cache: TTRCache<_, _, _> = TTRCacheBuilder::new(Duration::from_secs(60))
+///     .write_behind(archive.clone(), 100, Duration::from_millis(10))
+///     .build(|id: &u32| Some(u64::from(*id) * 2));
+///
+/// cache.get(&21);
+/// assert!(cache.shutdown(Duration::from_secs(1)));
+/// assert_eq!(*archive.0.lock().unwrap(), [(21, 42)]);
+/// ```
+pub trait WriteBehind<K, V> {
+    /// Writes a batch of refreshed values, at most one per key.
+    ///
+    /// On error the batch is retried with backoff, and dropped after a few
+    /// attempts.
+    fn write(&self, entries: &[(K, V)]) -> Result<(), Box<dyn StdError + Send + Sync>>;
+}
+
+enum Message<K, V> {
+    Write(K, V),
+    /// Write everything queued, then acknowledge.
+    Flush(Sender<()>),
+}
+
+/// The queue of values waiting for a sink, drained by a worker thread.
+pub(crate) struct WriteBehindQueue<K, V> {
+    sender: Mutex<Sender<Message<K, V>>>,
+    /// Writes on the calling thread where threads are unavailable.
+    inline: Option<BoxedWriteBehind<K, V>>,
+}
+
+type BoxedWriteBehind<K, V> = Box<dyn WriteBehind<K, V> + Send + Sync>;
+
+impl<K, V> WriteBehindQueue<K, V>
+where
+    K: Eq + Hash + Clone + Send + 'static,
+    V: Clone + Send + 'static,
+{
+    /// Starts a worker writing to `sink` in batches of up to `max_batch`,
+    /// at least every `interval` while values are queued.
+    ///
+    /// The worker writes what is left and stops once the queue is dropped.
+    pub(crate) fn spawn(
+        sink: BoxedWriteBehind<K, V>,
+        max_batch: usize,
+        interval: Duration,
+    ) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        if !THREADS {
+            return WriteBehindQueue {
+                sender: Mutex::new(sender),
+                inline: Some(sink),
+            };
+        }
+        thread::spawn(move || drain(&*sink, &receiver, max_batch, interval));
+        WriteBehindQueue {
+            sender: Mutex::new(sender),
+            inline: None,
+        }
+    }
+}
+
+impl<K, V> WriteBehindQueue<K, V> {
+    /// Queues a refreshed value.
+    pub(crate) fn push(&self, key: K, value: V) {
+        if let Some(sink) = &self.inline {
+            // Without a worker, retrying would hold up the lookup.
+            let _ = sink.write(&[(key, value)]);
+            return;
+        }
+        let _ = self.sender().send(Message::Write(key, value));
+    }
+
+    /// Waits until everything queued so far has been written or dropped.
+    pub(crate) fn flush(&self) {
+        let (ack, done) = mpsc::channel();
+        if self.sender().send(Message::Flush(ack)).is_ok() {
+            let _ = done.recv();
+        }
+    }
+
+    fn sender(&self) -> MutexGuard<'_, Sender<Message<K, V>>> {
+        self.sender.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Collects queued values by key and writes them out in batches.
+fn drain<K, V>(
+    sink: &(dyn WriteBehind<K, V> + Send + Sync),
+    receiver: &Receiver<Message<K, V>>,
+    max_batch: usize,
+    interval: Duration,
+) where
+    K: Eq + Hash + Clone,
+    V: Clone,
+{
+    let mut pending = HashMap::new();
+    let mut due: Option<Instant> = None;
+    loop {
+        let message = match due {
+            None => receiver.recv().map_err(|_| RecvTimeoutError::Disconnected),
+            Some(due) => receiver.recv_timeout(due.saturating_duration_since(Instant::now())),
+        };
+        let flushed = match message {
+            Ok(Message::Write(key, value)) => {
+                due.get_or_insert_with(|| Instant::now() + interval);
+                pending.insert(key, value);
+                if pending.len() < max_batch {
+                    continue;
+                }
+                None
+            }
+            Ok(Message::Flush(ack)) => Some(ack),
+            Err(RecvTimeoutError::Timeout) => None,
+            Err(RecvTimeoutError::Disconnected) => {
+                write_pending(sink, &mut pending, max_batch, interval);
+                return;
+            }
+        };
+        write_pending(sink, &mut pending, max_batch, interval);
+        due = None;
+        if let Some(ack) = flushed {
+            let _ = ack.send(());
+        }
+    }
+}
+
+fn write_pending<K, V>(
+    sink: &(dyn WriteBehind<K, V> + Send + Sync),
+    pending: &mut HashMap<K, V>,
+    max_batch: usize,
+    backoff: Duration,
+) {
+    let batch: Vec<_> = pending.drain().collect();
+    for chunk in batch.chunks(max_batch) {
+        write(sink, chunk, backoff);
+    }
+}
+
+/// Writes a batch, retrying with exponential backoff from `backoff`.
+fn write<K, V>(sink: &(dyn WriteBehind<K, V> + Send + Sync), batch: &[(K, V)], backoff: Duration) {
+    for attempt in 0..MAX_ATTEMPTS {
+        match sink.write(batch) {
+            Ok(()) => return,
+            Err(_error) if attempt + 1 < MAX_ATTEMPTS => {
+                #[cfg(feature = "tracing")]
+                tracing::debug!(error = %_error, attempt, "write-behind failed, retrying");
+                if THREADS {
+                    thread::sleep(backoff * 2u32.pow(attempt));
+                }
+            }
+            Err(_error) => {
+                #[cfg(feature = "tracing")]
+                tracing::warn!(error = %_error, entries = batch.len(), "write-behind batch dropped");
+            }
+        }
+    }
+}